@@ -0,0 +1,14 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use voxel::magica::MagicaMesh;
+
+pub fn bench_mesh_magica_model(c: &mut Criterion) {
+    static LOGO: &[u8] = include_bytes!("../src/vox/logo.vox");
+    let top_chunk = voxel::magica::io::from_reader(std::io::Cursor::new(LOGO)).unwrap();
+    c.bench_function("mesh logo.vox", |b| {
+        b.iter(|| MagicaMesh::new(black_box(&top_chunk)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_mesh_magica_model);
+criterion_main!(benches);
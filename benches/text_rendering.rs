@@ -1,24 +1,34 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 use voxel;
+use voxel::text_rendering::{FormattedText, TextOptions};
+
+fn hello_world() -> FormattedText {
+    let color = voxel::sw_image::Pixel {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+    let mut text = FormattedText::new();
+    text.add_str("Hello, world.", color);
+    text
+}
 
 pub fn bench_render_text_with_cached_glyphs(c: &mut Criterion) {
     let mut fonts = voxel::resources::Fonts::init(true).unwrap();
-    let empty_cache = voxel::text_rendering::cache::GlyphCache::empty(14 << 6);
+    let text = hello_world();
     c.bench_function("render text, no cache", |b| {
         b.iter(|| {
-            let color = voxel::sw_image::Pixel {
-                r: 0,
-                g: 0,
-                b: 0,
-                a: 255,
-            };
+            // The cache fills itself as glyphs are rendered, so start from an empty one each time.
+            let mut empty_cache = voxel::text_rendering::cache::GlyphCache::empty(14 << 6);
             let face = &mut fonts.deja_vu;
             voxel::text_rendering::render_text(
-                black_box("Hello, world."),
+                black_box(&text),
                 black_box(face),
-                black_box(color),
-                &empty_cache,
+                14 << 6,
+                &TextOptions::default(),
+                &mut empty_cache,
             )
             .unwrap();
         })
@@ -26,18 +36,13 @@ pub fn bench_render_text_with_cached_glyphs(c: &mut Criterion) {
 
     c.bench_function("render text, with cache", |b| {
         b.iter(|| {
-            let color = voxel::sw_image::Pixel {
-                r: 0,
-                g: 0,
-                b: 0,
-                a: 255,
-            };
             let face = &mut fonts.deja_vu;
             voxel::text_rendering::render_text(
-                black_box("Hello, world."),
+                black_box(&text),
                 black_box(face),
-                black_box(color),
-                &fonts.deja_vu_cache,
+                14 << 6,
+                &TextOptions::default(),
+                &mut fonts.deja_vu_cache,
             )
             .unwrap();
         })
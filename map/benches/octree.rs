@@ -33,5 +33,42 @@ fn bench_octree_replace_volume(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench_octree_replace_volume);
+/// The volume of the single block at `(x, y, z)`, at the full depth of a chunk.
+fn block_at(x: u8, y: u8, z: u8) -> LocationCode {
+    (0..6).rev().fold(LocationCode::ROOT, |code, bit| {
+        let sub_cube = SubCube::from_xyz((x >> bit) & 1, (y >> bit) & 1, (z >> bit) & 1).unwrap();
+        code.push_sub_cube(sub_cube)
+    })
+}
+
+fn bench_octree_set_deep_volumes(c: &mut Criterion) {
+    // Every third block along each axis, so that no two blocks share a parent, & every one of
+    // them needs its own subdivisions.
+    let blocks = (0..64u8)
+        .step_by(3)
+        .flat_map(|x| (0..64u8).step_by(3).map(move |y| (x, y)))
+        .flat_map(|(x, y)| (0..64u8).step_by(3).map(move |z| block_at(x, y, z)))
+        .collect::<Vec<_>>();
+    let set_blocks = || {
+        let mut tree: BlockOctree<TestBlock, _> = BlockOctree::new(BlockDefs);
+        for block in blocks.iter() {
+            tree.set_volume(*block, TestBlock(1));
+        }
+        tree
+    };
+
+    let nodes = set_blocks().depth_first_all_levels().count();
+    println!(
+        "setting {} blocks at full depth results in {} nodes",
+        blocks.len(),
+        nodes
+    );
+    c.bench_function("octree set deep volumes", |b| b.iter(set_blocks));
+}
+
+criterion_group!(
+    benches,
+    bench_octree_replace_volume,
+    bench_octree_set_deep_volumes
+);
 criterion_main!(benches);
@@ -0,0 +1,56 @@
+#[macro_use]
+extern crate criterion;
+
+use std::path::{Path, PathBuf};
+
+use criterion::Criterion;
+
+use voxel_map::region::{ChunkCoord, Region};
+
+const CHUNK_COUNT: i64 = 100;
+
+fn temp_region_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "voxel-region-bench-{}-{}.sqlite",
+        std::process::id(),
+        name,
+    ))
+}
+
+fn create_region(path: &Path) -> Region {
+    let _ = std::fs::remove_file(path);
+    Region::create(path).unwrap()
+}
+
+fn bench_region_save_chunks(c: &mut Criterion) {
+    let chunks = (0..CHUNK_COUNT)
+        .map(|i| (ChunkCoord::new(i % 10, i / 10, 0), vec![i as u8; 4096]))
+        .collect::<Vec<_>>();
+
+    let path = temp_region_path("one_by_one");
+    let region = create_region(&path);
+    c.bench_function("region save chunks one by one", |b| {
+        b.iter(|| {
+            for (coord, data) in chunks.iter() {
+                region.save_chunk(coord, data).unwrap();
+            }
+        });
+    });
+    drop(region);
+    std::fs::remove_file(&path).unwrap();
+
+    let path = temp_region_path("batched");
+    let region = create_region(&path);
+    c.bench_function("region save chunks batched", |b| {
+        b.iter(|| {
+            region
+                .save_chunks(chunks.iter().map(|(c, d)| (c, d.as_slice())))
+                .unwrap();
+        });
+    });
+    drop(region);
+    std::fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(benches, bench_region_save_chunks);
+criterion_main!(benches);
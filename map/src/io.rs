@@ -3,12 +3,14 @@ use std::convert::TryFrom;
 use std::hash::{Hash, Hasher};
 use std::io::{self, Write};
 
-use serde::Serialize;
+use std::sync::Arc;
 
-use voxel_mod::ModuleBlockDefinition;
+use serde::{Deserialize, Serialize};
 
-use crate::octree::{LocationCode, OctreeNode, SubCube};
-use crate::Chunk;
+use voxel_mod::{ModuleBlockDefinition, ModuleRegistry};
+
+use crate::octree::{BlockOctree, LocationCode, OctreeNode, SubCube};
+use crate::{BlockInfo, Chunk, OctreeBlock, CHUNK_OCTREE_DEPTH};
 
 // Below, we construct a map from block definitions, to the ID we will give that type of block in
 // the encoded chunk. This wrapper does Eq & Hash on the address/pointer of the reference to that
@@ -54,13 +56,126 @@ struct ChunkOnDisk<'a> {
     blocks: Vec<u8>,
 }
 
+/// The owned counterpart of [`ChunkOnDisk`], for reading chunks back.
+#[derive(Deserialize)]
+struct ChunkFromDisk {
+    palette: Vec<Option<(String, String)>>,
+    #[serde(with = "serde_bytes")]
+    blocks: Vec<u8>,
+}
+
+/// Encode a chunk, as stored in a region file.
+pub fn encode_chunk(chunk: &Chunk) -> Vec<u8> {
+    serde_cbor::to_vec(&write_chunk_octree(chunk)).expect("a chunk should always serialize")
+}
+
+/// Decode a chunk written by [`encode_chunk`]. The blocks in the chunk's palette are looked up in
+/// `registry`.
+pub fn decode_chunk(data: &[u8], registry: &ModuleRegistry) -> Result<Chunk, DecodeChunkError> {
+    let on_disk = serde_cbor::from_slice::<ChunkFromDisk>(data)?;
+    let palette = on_disk
+        .palette
+        .into_iter()
+        .map(|entry| match entry {
+            None => Ok(None),
+            Some((module_id, block_id)) => {
+                let full_id = format!("{}:{}", module_id, block_id);
+                match registry.block_by_full_id(&full_id) {
+                    Some(block) => Ok(Some(block)),
+                    None => Err(DecodeChunkError::UnknownBlock(full_id)),
+                }
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut reader = OctreeReader {
+        data: &on_disk.blocks,
+        palette: &palette,
+        blocks: Vec::new(),
+    };
+    reader.read_volume(LocationCode::ROOT)?;
+    if !reader.data.is_empty() {
+        return Err(DecodeChunkError::TrailingData(reader.data.len()));
+    }
+    let octree = BlockOctree::from_blocks(BlockInfo, OctreeBlock::new(None), reader.blocks);
+    Ok(Chunk { octree })
+}
+
+/// Reads the octree encoded by [`write_chunk_octree`]: depth first, each volume is either a 0 &
+/// the palette index of the block filling it, or a 1 followed by its 8 sub-volumes. (A 2, rather
+/// than a 0, marks a block that's never merged; see [`Chunk::set_block_no_merge`].)
+struct OctreeReader<'a> {
+    data: &'a [u8],
+    palette: &'a [Option<Arc<ModuleBlockDefinition>>],
+    blocks: Vec<(LocationCode, OctreeBlock)>,
+}
+
+impl OctreeReader<'_> {
+    fn read_volume(&mut self, volume: LocationCode) -> Result<(), DecodeChunkError> {
+        match self.read_byte()? {
+            tag @ (0 | 2) => {
+                let block_id = read_varint(&mut self.data)?;
+                let block = usize::try_from(block_id)
+                    .ok()
+                    .and_then(|idx| self.palette.get(idx))
+                    .ok_or(DecodeChunkError::BadBlockId(block_id))?;
+                let block = OctreeBlock {
+                    block: block.clone(),
+                    no_merge: tag == 2,
+                };
+                self.blocks.push((volume, block));
+            }
+            1 => {
+                // A chunk's blocks are the smallest volume; they can't be subdivided.
+                if volume.depth() == CHUNK_OCTREE_DEPTH {
+                    return Err(DecodeChunkError::TooDeep);
+                }
+                for sub_cube in SubCube::all_sub_cubes() {
+                    self.read_volume(volume.push_sub_cube(sub_cube))?;
+                }
+            }
+            tag => return Err(DecodeChunkError::BadTag(tag)),
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, DecodeChunkError> {
+        let (&byte, rest) = self.data.split_first().ok_or(DecodeChunkError::Truncated)?;
+        self.data = rest;
+        Ok(byte)
+    }
+}
+
+/// An error decoding a chunk, from [`decode_chunk`].
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeChunkError {
+    #[error("CBOR error: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+    #[error("the chunk uses the block {0:?}, which isn't loaded")]
+    UnknownBlock(String),
+    #[error("the chunk's octree ends early")]
+    Truncated,
+    #[error("the chunk's octree has {0} bytes left over")]
+    TrailingData(usize),
+    #[error("the chunk's octree has an invalid tag {0}")]
+    BadTag(u8),
+    #[error("the chunk's octree has a block ID longer than 32 bits")]
+    BadVarint,
+    #[error("the chunk's octree refers to block {0}, which isn't in its palette")]
+    BadBlockId(u32),
+    #[error("the chunk's octree is subdivided below the size of a block")]
+    TooDeep,
+}
+
 fn write_chunk_octree(chunk: &Chunk) -> ChunkOnDisk {
     // Iterate through the blocks in the chunk, and assign integer IDs to the various types of
-    // blocks in this chunk.
+    // blocks in this chunk. This goes depth first, in the same order as the octree is encoded
+    // below, so that the palette's order (& thus the encoding) only depends on the chunk's
+    // contents, not on the order of the octree's hash map.
     let mut palette = Vec::<Option<&ModuleBlockDefinition>>::new();
     let mut block_ids = HashMap::<HashableRef<ModuleBlockDefinition>, u32>::new();
 
-    for (_, block) in chunk.blocks() {
+    for (_, block) in chunk.depth_first_blocks() {
         let block_ref = block.as_ref().map(|bdref| bdref.as_ref());
         block_ids.entry(HashableRef(block_ref)).or_insert_with(|| {
             let this_id = palette.len();
@@ -94,9 +209,9 @@ fn write_chunk_octree(chunk: &Chunk) -> ChunkOnDisk {
         match volume {
             OctreeNode::Present(vd) => {
                 let block_id = block_ids
-                    .get(&HashableRef(vd.0.as_ref().map(|arc| arc.as_ref())))
+                    .get(&HashableRef(vd.block.as_ref().map(|arc| arc.as_ref())))
                     .unwrap();
-                blocks.push(0);
+                blocks.push(if vd.no_merge { 2 } else { 0 });
                 write_varint(&mut blocks, *block_id).expect("vector writes cannot fail");
                 loop {
                     let (parent, sub_cube) = match current_location.sub_cube() {
@@ -127,10 +242,10 @@ fn write_chunk_octree(chunk: &Chunk) -> ChunkOnDisk {
 }
 
 /// Write a varint; this is not a CBOR varint, this is just used for encoding block IDs in the
-/// encoded octree. The varint is encoded as least-significant bits first (so, sort of
-/// little-endian), with the most-significant bit of each byte reserved: it is set if more bytes
-/// follow. Each byte thus carries 7 bits, of increasing significance. This function only handles
-/// u32s, as that's all the octree needs.
+/// encoded octree. The varint is encoded as most-significant bits first (so, sort of big-endian),
+/// with the most-significant bit of each byte reserved: it is set if more bytes follow. Each byte
+/// thus carries 7 bits, of decreasing significance. This function only handles u32s, as that's all
+/// the octree needs.
 fn write_varint<W: Write>(mut write: W, n: u32) -> io::Result<()> {
     if n < 0b0111_1111
     /* 7 bits */
@@ -171,6 +286,25 @@ fn write_varint<W: Write>(mut write: W, n: u32) -> io::Result<()> {
     }
 }
 
+/// Read a varint written by [`write_varint`]; one whose value doesn't fit in a u32 is an error.
+fn read_varint(data: &mut &[u8]) -> Result<u32, DecodeChunkError> {
+    let mut n = 0u32;
+    // A u32 takes at most 5 bytes.
+    for _ in 0..5 {
+        let (&byte, rest) = data.split_first().ok_or(DecodeChunkError::Truncated)?;
+        *data = rest;
+        // Shifting in another 7 bits would push some of `n`'s out the top.
+        if n > u32::MAX >> 7 {
+            return Err(DecodeChunkError::BadVarint);
+        }
+        n = (n << 7) | u32::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(DecodeChunkError::BadVarint)
+}
+
 /*
 /// Write a CBOR string
 fn cbor_write_string<W: Write>(w: W, s: &str) -> io::Result<()> {
@@ -181,10 +315,9 @@ fn cbor_write_string<W: Write>(w: W, s: &str) -> io::Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
     use std::sync::Arc;
 
-    use voxel_mod::Module;
+    use voxel_mod::{Module, ModuleRegistry};
 
     use crate::{Chunk, ChunkRelativeCoord};
 
@@ -199,16 +332,7 @@ dirt:
 "#;
 
     fn minimal_mod() -> Arc<Module> {
-        let block_defs = voxel_mod::block_defs::load_block_definitions(
-            (&MINIMAL_MOD_BLOCK_DEFS[1..]).as_bytes(),
-        )
-        .unwrap();
-        Module::new(
-            "test".to_owned(),
-            "[test]".to_owned(),
-            PathBuf::from("[internal]"),
-            block_defs,
-        )
+        Module::for_test(&MINIMAL_MOD_BLOCK_DEFS[1..])
     }
 
     #[test]
@@ -234,4 +358,55 @@ dirt:
 
         // This is the expected value of the above write.
     }
+
+    #[test]
+    fn test_varint_round_trip() {
+        // Either side of each length's limit:
+        let values = [0, 126, 127, 300, 16_383, 16_384, 1 << 21, 1 << 28, u32::MAX];
+        for &n in values.iter() {
+            let mut buf = Vec::new();
+            super::write_varint(&mut buf, n).unwrap();
+            let mut data = buf.as_slice();
+            assert_eq!(super::read_varint(&mut data).unwrap(), n);
+            assert!(data.is_empty());
+        }
+
+        // Five bytes can hold 35 bits, but anything past 32 is too large.
+        let mut data = &[0x9f, 0xff, 0xff, 0xff, 0x7f][..];
+        assert!(matches!(
+            super::read_varint(&mut data),
+            Err(super::DecodeChunkError::BadVarint),
+        ));
+        let mut data = &[0x8f, 0xff, 0xff, 0xff, 0x7f][..];
+        assert!(super::read_varint(&mut data).unwrap() == u32::MAX);
+    }
+
+    #[test]
+    fn test_chunk_round_trip() {
+        let minimal_mod = minimal_mod();
+        let dirt = minimal_mod.block_by_id("dirt").unwrap();
+        let mut registry = ModuleRegistry::new();
+        registry.register(minimal_mod).unwrap();
+
+        let mut chunk = Chunk::new();
+        let coords = [(0, 0, 0), (1, 0, 0), (63, 63, 63), (5, 40, 22)];
+        for &(x, y, z) in coords.iter() {
+            chunk.set_block(ChunkRelativeCoord::new(x, y, z), Some(dirt.clone()));
+        }
+        // A block that isn't merged stays that way.
+        chunk.set_block_no_merge(ChunkRelativeCoord::new(0, 1, 0), dirt.clone());
+        let encoded = super::encode_chunk(&chunk);
+        let decoded = super::decode_chunk(&encoded, &registry).unwrap();
+        assert!(decoded.octree == chunk.octree);
+        assert_eq!(decoded.count_non_empty(), coords.len() + 1);
+
+        // A chunk using blocks from a module that isn't loaded can't be decoded.
+        let err = match super::decode_chunk(&encoded, &ModuleRegistry::new()) {
+            Err(err) => err,
+            Ok(_) => panic!("decoded a chunk without its blocks"),
+        };
+        assert!(err.to_string().contains("test:dirt"), "{}", err);
+        // Nor can a truncated one.
+        assert!(super::decode_chunk(&encoded[..encoded.len() - 1], &registry).is_err());
+    }
 }
@@ -1,38 +1,73 @@
+use std::convert::TryFrom;
 use std::fmt;
 use std::sync::Arc;
 
 use voxel_mod::ModuleBlockDefinition;
 
 pub mod io;
+pub mod loader;
 /// An octree implementation for space-efficient map data.
 pub mod octree;
 pub mod region;
+#[cfg(test)]
+mod test_util;
+pub mod world;
 
-use octree::{LocationCode, SubCube};
+use octree::{LocationCode, OctreeNode};
+
+/// The box an octree node fills: its corner nearest the origin, & the length of its sides. (As
+/// returned by [`LocationCode::to_coords_and_size`].)
+pub type NodeBox = ((u32, u32, u32), u16);
 
 #[derive(Clone)]
-struct OctreeBlock(Option<Arc<ModuleBlockDefinition>>);
+struct OctreeBlock {
+    block: Option<Arc<ModuleBlockDefinition>>,
+    /// This instance is never merged with (or split into) its neighbours, even if its definition
+    /// is homogeneous; e.g., a block that's been rotated, or that has state of its own.
+    no_merge: bool,
+}
+
+impl OctreeBlock {
+    fn new(block: Option<Arc<ModuleBlockDefinition>>) -> OctreeBlock {
+        OctreeBlock {
+            block,
+            no_merge: false,
+        }
+    }
+}
 
 impl PartialEq for OctreeBlock {
     fn eq(&self, other: &OctreeBlock) -> bool {
-        match (&self.0, &other.0) {
-            (None, None) => true,
-            (Some(s), Some(o)) => Arc::ptr_eq(s, o),
-            _ => false,
-        }
+        same_block(self.block.as_ref(), other.block.as_ref()) && self.no_merge == other.no_merge
+    }
+}
+
+/// Whether two blocks are both empty, or both the same definition.
+fn same_block(
+    a: Option<&Arc<ModuleBlockDefinition>>,
+    b: Option<&Arc<ModuleBlockDefinition>>,
+) -> bool {
+    match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        _ => false,
     }
 }
 
 impl fmt::Debug for OctreeBlock {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self.0 {
+        match &self.block {
             Some(b) => {
                 write!(f, "OctreeBlock(Some(")?;
                 b.fmt(f)?;
-                write!(f, ")),")
+                write!(f, ")")?;
             }
-            None => write!(f, "OctreeBlock(None)"),
+            None => write!(f, "OctreeBlock(None")?,
+        }
+        if self.no_merge {
+            write!(f, ", no_merge")?;
         }
+        write!(f, ")")
     }
 }
 
@@ -43,13 +78,47 @@ struct BlockInfo;
 
 impl octree::BlockInfo<OctreeBlock> for BlockInfo {
     fn is_homogeneous(&self, block: &OctreeBlock) -> bool {
-        match &block.0 {
+        if block.no_merge {
+            return false;
+        }
+        match &block.block {
             Some(block) => block.definition().is_homogeneous(),
             None => true,
         }
     }
 }
 
+/// The coordinate of a block in the world.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct WorldCoord {
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+}
+
+impl WorldCoord {
+    pub fn new(x: i64, y: i64, z: i64) -> WorldCoord {
+        WorldCoord { x, y, z }
+    }
+
+    /// Split the coordinate into the chunk containing the block, & the block's position within
+    /// that chunk. (Chunks are aligned to multiples of [`CHUNK_SIDE_LENGTH`]; e.g., the block at
+    /// x = -1 is the last one in the chunk at x = -1.)
+    pub fn to_chunk_and_relative(self) -> (region::ChunkCoord, ChunkRelativeCoord) {
+        let side = i64::from(CHUNK_SIDE_LENGTH);
+        let chunk = region::ChunkCoord::new(
+            self.x.div_euclid(side),
+            self.y.div_euclid(side),
+            self.z.div_euclid(side),
+        );
+        // `rem_euclid` is in [0, side), so it always fits.
+        let relative = |n: i64| u8::try_from(n.rem_euclid(side)).unwrap();
+        let relative =
+            ChunkRelativeCoord::new(relative(self.x), relative(self.y), relative(self.z));
+        (chunk, relative)
+    }
+}
+
 /// A 3D cube representing a subsection of the world.
 ///
 /// Chunks are 64×64×64 cubes of blocks. (See [`CHUNK_SIDE_LENGTH`].)
@@ -65,8 +134,11 @@ pub struct ChunkRelativeCoord {
     z: u8,
 }
 
-/// The length of the side of a chunk.
-pub const CHUNK_SIDE_LENGTH: u8 = 64;
+/// How many times a chunk's octree can be subdivided, down to single blocks.
+pub const CHUNK_OCTREE_DEPTH: u8 = 6;
+
+/// The length of the side of a chunk: 2<sup>[`CHUNK_OCTREE_DEPTH`]</sup>.
+pub const CHUNK_SIDE_LENGTH: u8 = 1 << CHUNK_OCTREE_DEPTH;
 
 impl ChunkRelativeCoord {
     pub fn new(x: u8, y: u8, z: u8) -> ChunkRelativeCoord {
@@ -77,26 +149,46 @@ impl ChunkRelativeCoord {
         }
     }
 
+    /// The (up to) 6 coordinates sharing a face with this one; those that would be outside the
+    /// chunk are left out.
+    pub fn neighbors(self) -> impl Iterator<Item = ChunkRelativeCoord> {
+        const OFFSETS: [(i16, i16, i16); 6] = [
+            (-1, 0, 0),
+            (1, 0, 0),
+            (0, -1, 0),
+            (0, 1, 0),
+            (0, 0, -1),
+            (0, 0, 1),
+        ];
+        let ChunkRelativeCoord { x, y, z } = self;
+        let step = |c: u8, d: i16| {
+            u8::try_from(i16::from(c) + d)
+                .ok()
+                .filter(|&c| c < CHUNK_SIDE_LENGTH)
+        };
+        OFFSETS.iter().filter_map(move |&(dx, dy, dz)| {
+            Some(ChunkRelativeCoord {
+                x: step(x, dx)?,
+                y: step(y, dy)?,
+                z: step(z, dz)?,
+            })
+        })
+    }
+
     fn to_location_code(&self) -> LocationCode {
-        let mut code = LocationCode::ROOT;
-
-        // A chunk is 64x64x64; 64 is 2**6 so we need to do this 6 times:
-        for idx in 0..6 {
-            let shift = 6 + 1 - idx;
-            let xbit = self.x >> shift;
-            let ybit = self.y >> shift;
-            let zbit = self.z >> shift;
-            let sub_cube = SubCube::from_xyz(xbit, ybit, zbit).unwrap();
-            code = code.push_sub_cube(sub_cube);
-        }
-        code
+        LocationCode::from_coords(
+            u32::from(self.x),
+            u32::from(self.y),
+            u32::from(self.z),
+            CHUNK_OCTREE_DEPTH,
+        )
     }
 }
 
 impl Chunk {
     pub fn new() -> Chunk {
         Chunk {
-            octree: octree::BlockOctree::with_block(BlockInfo, OctreeBlock(None)),
+            octree: octree::BlockOctree::with_block(BlockInfo, OctreeBlock::new(None)),
         }
     }
 
@@ -106,16 +198,351 @@ impl Chunk {
         block: Option<Arc<ModuleBlockDefinition>>,
     ) {
         let location_code = chunk_coord.to_location_code();
-        self.octree.set_volume(location_code, OctreeBlock(block));
+        self.octree
+            .set_volume(location_code, OctreeBlock::new(block));
+    }
+
+    /// Like [`set_block`](Self::set_block), but the block is never merged with its neighbours
+    /// (even ones of the same definition), as if its definition weren't homogeneous.
+    pub fn set_block_no_merge(
+        &mut self,
+        chunk_coord: ChunkRelativeCoord,
+        block: Arc<ModuleBlockDefinition>,
+    ) {
+        let location_code = chunk_coord.to_location_code();
+        let block = OctreeBlock {
+            block: Some(block),
+            no_merge: true,
+        };
+        self.octree.set_volume(location_code, block);
+    }
+
+    /// The block at `chunk_coord`, or `None` if it's empty.
+    pub fn get_block(
+        &self,
+        chunk_coord: ChunkRelativeCoord,
+    ) -> Option<&Arc<ModuleBlockDefinition>> {
+        // The block is whichever volume containing it isn't subdivided.
+        chunk_coord
+            .to_location_code()
+            .from_root_to_here()
+            .find_map(|volume| match self.octree.get_volume(volume) {
+                Some(OctreeNode::Present(block)) => Some(block.block.as_ref()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    /// Replace the blocks connected to `start` (through their faces) that are the same as it with
+    /// `replacement`, without going past the chunk's edges. Empty space is filled just the same.
+    pub fn flood_fill(
+        &mut self,
+        start: ChunkRelativeCoord,
+        replacement: Option<Arc<ModuleBlockDefinition>>,
+    ) {
+        let target = self.get_block(start).cloned();
+        if same_block(target.as_ref(), replacement.as_ref()) {
+            return;
+        }
+        // Filled blocks no longer match the target, so nothing's visited twice.
+        let mut stack = vec![start];
+        while let Some(coord) = stack.pop() {
+            if !same_block(self.get_block(coord), target.as_ref()) {
+                continue;
+            }
+            self.set_block(coord, replacement.clone());
+            stack.extend(coord.neighbors());
+        }
+    }
+
+    /// Count the number of blocks in the chunk that are not empty.
+    pub fn count_non_empty(&self) -> usize {
+        self.octree
+            .iter()
+            .filter(|(_, block)| block.block.is_some())
+            .map(|(location_code, _)| {
+                let (_, size) = location_code.to_coords_and_size(u16::from(CHUNK_SIDE_LENGTH));
+                usize::from(size).pow(3)
+            })
+            .sum()
     }
 
     pub fn blocks(
         &self,
     ) -> impl Iterator<Item = (LocationCode, &Option<Arc<ModuleBlockDefinition>>)> {
-        self.octree.iter().map(|(k, v)| (k, &v.0))
+        self.octree.iter().map(|(k, v)| (k, &v.block))
+    }
+
+    /// Iterate through the blocks in the chunk, depth first. Each block fills the volume of its
+    /// `LocationCode`; see [`LocationCode::to_coords_and_size`].
+    pub fn depth_first_blocks(
+        &self,
+    ) -> impl Iterator<Item = (LocationCode, &Option<Arc<ModuleBlockDefinition>>)> {
+        self.octree.depth_first_blocks().map(|(k, v)| (k, &v.block))
+    }
+
+    /// Iterate through the chunk's octree, depth first, including the subdivided nodes above the
+    /// blocks; see [`octree::BlockOctree::depth_first_all_levels`].
+    pub fn depth_first_all_levels(
+        &self,
+    ) -> impl Iterator<
+        Item = (
+            LocationCode,
+            OctreeNode<&Option<Arc<ModuleBlockDefinition>>>,
+        ),
+    > {
+        self.octree.depth_first_all_levels().map(|(k, v)| match v {
+            OctreeNode::Present(block) => (k, OctreeNode::Present(&block.block)),
+            OctreeNode::Subdivided => (k, OctreeNode::Subdivided),
+        })
+    }
+
+    /// Iterate through the chunk's octree, depth first, like
+    /// [`depth_first_all_levels`](Self::depth_first_all_levels), with the box each node fills: its
+    /// chunk-relative corner & its size, in blocks. (E.g., for drawing the octree's structure.)
+    /// Subdivided nodes, like empty ones, have no block.
+    pub fn iter_boxes(
+        &self,
+    ) -> impl Iterator<Item = (LocationCode, NodeBox, Option<&Arc<ModuleBlockDefinition>>)> {
+        self.depth_first_all_levels().map(|(location_code, node)| {
+            let coords_and_size = location_code.to_coords_and_size(u16::from(CHUNK_SIDE_LENGTH));
+            let block = match node {
+                OctreeNode::Present(block) => block.as_ref(),
+                OctreeNode::Subdivided => None,
+            };
+            (location_code, coords_and_size, block)
+        })
     }
 
     pub(crate) fn get_octree(&self) -> &octree::BlockOctree<OctreeBlock, BlockInfo> {
         &self.octree
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use voxel_mod::{Module, ModuleBlockDefinition};
+
+    use super::octree::OctreeNode;
+    use super::region::ChunkCoord;
+    use super::{
+        Chunk, ChunkRelativeCoord, OctreeBlock, WorldCoord, CHUNK_OCTREE_DEPTH, CHUNK_SIDE_LENGTH,
+    };
+
+    static DIRT_BLOCK_DEFS: &str =
+        "dirt:\n  texture: dirt.png\n  color: \"#8f563b\"\n  homogeneous: true\n";
+
+    #[test]
+    fn test_world_coord_to_chunk_and_relative() {
+        let split = |x, y, z| WorldCoord::new(x, y, z).to_chunk_and_relative();
+
+        // The origin is the first block of the chunk at the origin:
+        let (chunk, relative) = split(0, 0, 0);
+        assert!(chunk == ChunkCoord::new(0, 0, 0));
+        assert!(relative == ChunkRelativeCoord::new(0, 0, 0));
+
+        // Either side of a chunk boundary:
+        let (chunk, relative) = split(63, 64, 127);
+        assert!(chunk == ChunkCoord::new(0, 1, 1));
+        assert!(relative == ChunkRelativeCoord::new(63, 0, 63));
+        let (chunk, relative) = split(64, 128, 128);
+        assert!(chunk == ChunkCoord::new(1, 2, 2));
+        assert!(relative == ChunkRelativeCoord::new(0, 0, 0));
+
+        // Negative coordinates round down, to the chunk "before" the origin:
+        let (chunk, relative) = split(-1, -64, -65);
+        assert!(chunk == ChunkCoord::new(-1, -1, -2));
+        assert!(relative == ChunkRelativeCoord::new(63, 0, 63));
+    }
+
+    #[test]
+    fn test_chunk_relative_coord_to_location_code() {
+        let side = u32::from(CHUNK_SIDE_LENGTH);
+        assert!(side == 1 << CHUNK_OCTREE_DEPTH);
+        for &(x, y, z) in [(0, 0, 0), (63, 63, 63), (5, 6, 7), (32, 0, 31)].iter() {
+            let loc = ChunkRelativeCoord::new(x, y, z).to_location_code();
+            assert!(loc.depth() == CHUNK_OCTREE_DEPTH);
+            let coords = (u32::from(x), u32::from(y), u32::from(z));
+            assert!(loc.to_coords_and_size(u16::from(CHUNK_SIDE_LENGTH)) == (coords, 1));
+        }
+    }
+
+    #[test]
+    fn test_iter_boxes() {
+        let module = Module::for_test(DIRT_BLOCK_DEFS);
+        let dirt = module.block_by_id("dirt").unwrap();
+
+        let mut chunk = Chunk::new();
+        let boxes = chunk.iter_boxes().collect::<Vec<_>>();
+        assert!(boxes.len() == 1);
+        assert!(boxes[0].1 == ((0, 0, 0), u16::from(CHUNK_SIDE_LENGTH)));
+
+        // A single block subdivides every level above it.
+        chunk.set_block(ChunkRelativeCoord::new(5, 6, 7), Some(dirt.clone()));
+        let boxes = chunk.iter_boxes().collect::<Vec<_>>();
+        let by_location = boxes
+            .iter()
+            .map(|&(location_code, coords_and_size, _)| (location_code, coords_and_size))
+            .collect::<HashMap<_, _>>();
+        for &(location_code, ((x, y, z), size), _) in boxes.iter() {
+            let parent = match location_code.containing_cube() {
+                Some(parent) => parent,
+                None => continue,
+            };
+            let ((px, py, pz), parent_size) = by_location[&parent];
+            assert!(size * 2 == parent_size);
+            for (c, pc) in [(x, px), (y, py), (z, pz)].iter() {
+                assert!(pc <= c && c + u32::from(size) <= pc + u32::from(parent_size));
+            }
+        }
+        let with_blocks = boxes
+            .iter()
+            .filter_map(|(_, coords_and_size, block)| block.map(|b| (*coords_and_size, b)))
+            .collect::<Vec<_>>();
+        assert!(with_blocks.len() == 1);
+        assert!(with_blocks[0].0 == ((5, 6, 7), 1));
+        assert!(std::sync::Arc::ptr_eq(with_blocks[0].1, &dirt));
+        // The root, & 7 children under each of 6 levels of subdivided nodes.
+        assert!(boxes.len() == 1 + 6 * 8);
+    }
+
+    #[test]
+    fn test_no_merge() {
+        let module = Module::for_test(DIRT_BLOCK_DEFS);
+        let dirt = module.block_by_id("dirt").unwrap();
+
+        // Fill the 2×2×2 volume at the origin with dirt, flagging the block at `flagged`, if any.
+        let fill = |flagged: Option<(u8, u8, u8)>| {
+            let mut chunk = Chunk::new();
+            for &x in [0, 1].iter() {
+                for &y in [0, 1].iter() {
+                    for &z in [0, 1].iter() {
+                        let coord = ChunkRelativeCoord::new(x, y, z);
+                        if flagged == Some((x, y, z)) {
+                            chunk.set_block_no_merge(coord, dirt.clone());
+                        } else {
+                            chunk.set_block(coord, Some(dirt.clone()));
+                        }
+                    }
+                }
+            }
+            chunk.octree.merge();
+            chunk
+        };
+        let volume = ChunkRelativeCoord::new(0, 0, 0)
+            .to_location_code()
+            .containing_cube()
+            .unwrap();
+
+        let merged = fill(None);
+        assert!(
+            merged.octree.get_volume(volume)
+                == Some(&OctreeNode::Present(OctreeBlock::new(Some(dirt.clone()))))
+        );
+
+        // The flagged block is the same definition, but isn't merged with its neighbours:
+        let unmerged = fill(Some((1, 0, 1)));
+        assert!(unmerged.octree.get_volume(volume) == Some(&OctreeNode::Subdivided));
+        assert!(unmerged.count_non_empty() == 8);
+        let flagged = ChunkRelativeCoord::new(1, 0, 1);
+        assert!(std::sync::Arc::ptr_eq(
+            unmerged.get_block(flagged).unwrap(),
+            &dirt
+        ));
+
+        // Replacing the flagged block with an ordinary one lets them merge again.
+        let mut chunk = unmerged;
+        chunk.set_block(flagged, Some(dirt.clone()));
+        chunk.octree.merge();
+        assert!(chunk.octree == merged.octree);
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let neighbors = |x, y, z| {
+            ChunkRelativeCoord::new(x, y, z)
+                .neighbors()
+                .map(|c| (c.x, c.y, c.z))
+                .collect::<Vec<_>>()
+        };
+        assert!(neighbors(5, 6, 7).len() == 6);
+        assert!(neighbors(5, 6, 7).contains(&(5, 5, 7)));
+        assert!(neighbors(5, 6, 7).contains(&(5, 6, 8)));
+        // At a corner, half of them are outside the chunk.
+        let last = CHUNK_SIDE_LENGTH - 1;
+        assert!(neighbors(0, 0, 0) == [(1, 0, 0), (0, 1, 0), (0, 0, 1)]);
+        assert!(
+            neighbors(last, last, last)
+                == [
+                    (last - 1, last, last),
+                    (last, last - 1, last),
+                    (last, last, last - 1)
+                ]
+        );
+    }
+
+    #[test]
+    fn test_flood_fill() {
+        let module = Module::for_test(
+            "\
+stone:\n  texture: stone.png\n  color: \"#808080\"\n  homogeneous: true
+water:\n  texture: water.png\n  color: \"#2040c0\"\n  homogeneous: true
+",
+        );
+        let stone = module.block_by_id("stone").unwrap();
+        let water = module.block_by_id("water").unwrap();
+
+        // A hollow 4×4×4 cube of stone, with a 2×2×2 cavity, & a separate pillar of stone.
+        let mut chunk = Chunk::new();
+        let shell = 10..14;
+        let cavity = 11..13;
+        for x in shell.clone() {
+            for y in shell.clone() {
+                for z in shell.clone() {
+                    if !(cavity.contains(&x) && cavity.contains(&y) && cavity.contains(&z)) {
+                        chunk.set_block(ChunkRelativeCoord::new(x, y, z), Some(stone.clone()));
+                    }
+                }
+            }
+        }
+        for y in 0..4 {
+            chunk.set_block(ChunkRelativeCoord::new(20, y, 20), Some(stone.clone()));
+        }
+        let stone_count = chunk.count_non_empty();
+
+        chunk.flood_fill(ChunkRelativeCoord::new(12, 11, 12), Some(water.clone()));
+        let is = |chunk: &Chunk, (x, y, z), block: &Arc<ModuleBlockDefinition>| {
+            matches!(
+                chunk.get_block(ChunkRelativeCoord::new(x, y, z)),
+                Some(b) if Arc::ptr_eq(b, block)
+            )
+        };
+        for x in cavity.clone() {
+            for y in cavity.clone() {
+                for z in cavity.clone() {
+                    assert!(is(&chunk, (x, y, z), &water));
+                }
+            }
+        }
+        // Nothing outside the cavity changed:
+        assert!(chunk.count_non_empty() == stone_count + 8);
+        assert!(is(&chunk, (10, 11, 12), &stone));
+        let empty =
+            |chunk: &Chunk, (x, y, z)| chunk.get_block(ChunkRelativeCoord::new(x, y, z)).is_none();
+        assert!(empty(&chunk, (9, 11, 12)));
+        assert!(empty(&chunk, (0, 0, 0)));
+
+        // Filling the shell leaves the water inside it, & the pillar that doesn't touch it.
+        chunk.flood_fill(ChunkRelativeCoord::new(10, 10, 10), None);
+        assert!(chunk.count_non_empty() == 4 + 8);
+        assert!(is(&chunk, (20, 3, 20), &stone));
+        assert!(is(&chunk, (11, 11, 11), &water));
+
+        // Filling a block with what's already there changes nothing.
+        chunk.flood_fill(ChunkRelativeCoord::new(20, 0, 20), Some(stone.clone()));
+        assert!(chunk.count_non_empty() == 4 + 8);
+    }
+}
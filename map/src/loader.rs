@@ -0,0 +1,115 @@
+//! Loading chunks from a region file on a background thread, so that the thread asking for them
+//! (e.g., the render loop) doesn't wait on the disk.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+
+use crate::region::{ChunkCoord, Region, RegionError};
+
+/// A chunk's data, as loaded from the region file (`None` if the region has no such chunk), or
+/// the error loading it.
+pub type LoadResult = Result<Option<Vec<u8>>, RegionError>;
+
+/// A thread that loads chunks from its own connection to a region file. (Region files are in WAL
+/// mode, so it can read while other connections write.)
+pub struct ChunkLoader {
+    requests: Option<Sender<ChunkCoord>>,
+    loaded: Receiver<(ChunkCoord, LoadResult)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ChunkLoader {
+    /// Start a thread loading chunks from `region`.
+    pub fn spawn(region: Region) -> ChunkLoader {
+        let (requests, requests_rx) = mpsc::channel();
+        let (loaded_tx, loaded) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("chunk loader".to_owned())
+            .spawn(move || load_requested_chunks(region, requests_rx, loaded_tx))
+            .expect("failed to start the chunk loading thread");
+        ChunkLoader {
+            requests: Some(requests),
+            loaded,
+            thread: Some(thread),
+        }
+    }
+
+    /// Ask for the chunk at `chunk_coord` to be loaded. Chunks are loaded in the order they're
+    /// requested.
+    pub fn request(&self, chunk_coord: ChunkCoord) {
+        // This only fails if the thread has panicked; that's reported when it's joined, on drop.
+        let _ = self.requests.as_ref().unwrap().send(chunk_coord);
+    }
+
+    /// A chunk that's finished loading, if there is one; this doesn't wait for one.
+    pub fn try_recv(&self) -> Option<(ChunkCoord, LoadResult)> {
+        self.loaded.try_recv().ok()
+    }
+}
+
+impl Drop for ChunkLoader {
+    fn drop(&mut self) {
+        // Closing the channel tells the thread to stop, once it's done with what's been requested.
+        drop(self.requests.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() && !std::thread::panicking() {
+                panic!("the chunk loading thread panicked");
+            }
+        }
+    }
+}
+
+fn load_requested_chunks(
+    region: Region,
+    requests: Receiver<ChunkCoord>,
+    loaded: Sender<(ChunkCoord, LoadResult)>,
+) {
+    for chunk_coord in requests {
+        let result = load_chunk(&region, &chunk_coord);
+        if loaded.send((chunk_coord, result)).is_err() {
+            // Nobody's listening anymore.
+            break;
+        }
+    }
+}
+
+fn load_chunk(region: &Region, chunk_coord: &ChunkCoord) -> LoadResult {
+    if region.chunk_exists(chunk_coord)? {
+        Ok(Some(region.load_chunk(chunk_coord)?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::ChunkLoader;
+    use crate::region::{ChunkCoord, Region};
+    use crate::test_util::TempRegion;
+
+    #[test]
+    fn test_load_in_background() {
+        let temp = TempRegion::create("load_in_background");
+        let saved = ChunkCoord::new(1, 2, 3);
+        temp.region.save_chunk(&saved, b"chunk data").unwrap();
+
+        let loader = ChunkLoader::spawn(Region::open(temp.path()).unwrap().unwrap());
+        let missing = ChunkCoord::new(0, 0, 0);
+        loader.request(saved.clone());
+        loader.request(missing.clone());
+
+        let mut results = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while results.len() < 2 {
+            assert!(Instant::now() < deadline, "the chunks never loaded");
+            match loader.try_recv() {
+                Some((chunk_coord, result)) => results.push((chunk_coord, result.unwrap())),
+                None => std::thread::sleep(Duration::from_millis(1)),
+            }
+        }
+        assert!(results[0] == (saved, Some(b"chunk data".to_vec())));
+        assert!(results[1] == (missing, None));
+    }
+}
@@ -1,8 +1,16 @@
 use std::convert::TryFrom;
 use std::fmt;
 
+/// One of the three axes of the octree's space.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
 /// Indicates a particular corner when dividing a cube into 8 smaller cudes.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum SubCube {
     LowerSw,
     LowerSe,
@@ -44,6 +52,24 @@ impl SubCube {
         }
     }
 
+    /// The position of this sub cube within its parent, as an `(x, y, z)` offset of 0 or 1 along
+    /// each axis. The inverse of [`from_xyz`](Self::from_xyz).
+    pub fn offset(self) -> (u8, u8, u8) {
+        let bits = self.to_bits();
+        (bits & 1, (bits >> 2) & 1, (bits >> 1) & 1)
+    }
+
+    /// The sub cube on the other side of the parent along `axis`; e.g., flipping `LowerSw` along
+    /// the X axis gives `LowerSe`.
+    pub fn flip(self, axis: Axis) -> SubCube {
+        let bit = match axis {
+            Axis::X => 0b001,
+            Axis::Z => 0b010,
+            Axis::Y => 0b100,
+        };
+        Self::from_bits(self.to_bits() ^ bit)
+    }
+
     pub(crate) fn to_bits(self) -> u8 {
         use SubCube::*;
 
@@ -140,7 +166,9 @@ impl LocationCode {
         }
     }
 
-    fn from_root_to_here_impl(self) -> FromRootToLocationCode {
+    /// The number of times the root has been subdivided to reach this volume. `ROOT` is at depth
+    /// 0.
+    pub fn depth(self) -> u8 {
         // We always have a leading 1, so we can count zeros to figure out the bits that form the
         // code:
         // (+1 for the leading 1)
@@ -149,9 +177,21 @@ impl LocationCode {
         let code_bits =
             u8::try_from(std::mem::size_of::<u32>() * 8).unwrap() - bits_not_part_of_code;
         // Divide to get number of SubCubes
-        let shifts = code_bits / 3;
+        code_bits / 3
+    }
+
+    /// Is `other` within this volume? (A volume contains itself.)
+    pub fn contains(self, other: LocationCode) -> bool {
+        match other.depth().checked_sub(self.depth()) {
+            // `self`'s code is a prefix of `other`'s.
+            Some(levels_below) => other.0 >> (u32::from(levels_below) * 3) == self.0,
+            None => false,
+        }
+    }
+
+    fn from_root_to_here_impl(self) -> FromRootToLocationCode {
         // +1 for the root
-        FromRootToLocationCode(self, shifts + 1)
+        FromRootToLocationCode(self, self.depth() + 1)
     }
 
     /// Iterate through the location codes from the `ROOT` code to this one, inclusively. (That is,
@@ -177,46 +217,52 @@ impl LocationCode {
         }
     }
 
-    fn to_details(mut self, mut size: u16) -> ((u32, u32, u32), u16) {
+    /// The smallest volume, at `(x, y, z)`, in an octree subdivided `depth` times (so whose sides
+    /// are 2<sup>`depth`</sup> long). The inverse of
+    /// [`to_coords_and_size`](Self::to_coords_and_size).
+    pub fn from_coords(x: u32, y: u32, z: u32, depth: u8) -> LocationCode {
+        let in_range = |n: u32| n.checked_shr(u32::from(depth)).unwrap_or(0) == 0;
+        if !(in_range(x) && in_range(y) && in_range(z)) {
+            panic!(
+                "({}, {}, {}) is outside an octree of depth {}",
+                x, y, z, depth
+            );
+        }
+
+        // Each subdivision takes the next bit of each coordinate, from most to least significant.
+        let mut code = LocationCode::ROOT;
+        for shift in (0..depth).rev() {
+            let bit = |n: u32| u8::try_from((n >> shift) & 1).unwrap();
+            code = code.push_sub_cube(SubCube::from_xyz(bit(x), bit(y), bit(z)).unwrap());
+        }
+        code
+    }
+
+    /// The position of the volume's corner nearest the origin, and the length of its side, in an
+    /// octree whose whole volume has sides of `side_length`.
+    pub fn to_coords_and_size(mut self, side_length: u16) -> ((u32, u32, u32), u16) {
         let mut x = 0;
         let mut y = 0;
         let mut z = 0;
+        let mut size = side_length;
 
-        while self.0 != 1 {
-            x <<= 1;
-            y <<= 1;
-            z <<= 1;
-            size >>= 1;
-
-            let (parent, sub_cube_code) = self.sub_cube().unwrap();
+        // Walking up from this volume to the root visits the least significant bit of each
+        // coordinate first.
+        let mut bit = 0;
+        while let Some((parent, sub_cube)) = self.sub_cube() {
             self = parent;
+            size >>= 1;
 
-            match sub_cube_code {
-                SubCube::LowerSw => (),
-                SubCube::LowerSe => x += 1,
-                SubCube::LowerNw => z += 1,
-                SubCube::LowerNe => {
-                    x += 1;
-                    z += 1;
-                }
-                SubCube::UpperSw => y += 1,
-                SubCube::UpperSe => {
-                    x += 1;
-                    y += 1;
-                }
-                SubCube::UpperNw => {
-                    y += 1;
-                    z += 1;
-                }
-                SubCube::UpperNe => {
-                    x += 1;
-                    y += 1;
-                    z += 1;
-                }
-            }
+            let bits = u32::from(sub_cube.to_bits());
+            x |= (bits & 1) << bit;
+            z |= ((bits >> 1) & 1) << bit;
+            y |= ((bits >> 2) & 1) << bit;
+            bit += 1;
         }
 
-        ((x, y, z), size)
+        // Those bits count in units of this volume's size.
+        let scale = u32::from(size);
+        ((x * scale, y * scale, z * scale), size)
     }
 }
 
@@ -272,7 +318,7 @@ impl std::iter::FusedIterator for FromRootToLocationCode {}
 
 #[cfg(test)]
 mod tests {
-    use super::{LocationCode, SubCube};
+    use super::{Axis, LocationCode, SubCube};
 
     #[test]
     fn test_location_code_from_root_to_here() {
@@ -297,6 +343,98 @@ mod tests {
         assert!(items == expect);
     }
 
+    #[test]
+    fn test_location_code_to_coords_and_size() {
+        assert!(LocationCode::ROOT.to_coords_and_size(64) == ((0, 0, 0), 64));
+
+        // The first sub cube picks the most significant bit of each coordinate:
+        let loc_code = LocationCode::ROOT
+            .push_sub_cube(SubCube::LowerSe)
+            .push_sub_cube(SubCube::UpperNw);
+        assert!(loc_code.to_coords_and_size(4) == ((2, 1, 1), 1));
+
+        let loc_code = LocationCode::ROOT
+            .push_sub_cube(SubCube::UpperNe)
+            .push_sub_cube(SubCube::LowerSw)
+            .push_sub_cube(SubCube::LowerNw);
+        assert!(loc_code.to_coords_and_size(64) == ((32, 32, 40), 8));
+    }
+
+    #[test]
+    fn test_location_code_from_coords() {
+        // An octree of depth 5 is 32×32×32.
+        assert!(LocationCode::from_coords(0, 0, 0, 0) == LocationCode::ROOT);
+        let lower_sw = |loc: LocationCode, times| {
+            (0..times).fold(loc, |loc, _| loc.push_sub_cube(SubCube::LowerSw))
+        };
+        assert!(LocationCode::from_coords(0, 0, 0, 5) == lower_sw(LocationCode::ROOT, 5));
+        let half_way = |sub_cube| lower_sw(LocationCode::ROOT.push_sub_cube(sub_cube), 4);
+        assert!(LocationCode::from_coords(16, 0, 0, 5) == half_way(SubCube::LowerSe));
+        assert!(LocationCode::from_coords(0, 16, 0, 5) == half_way(SubCube::UpperSw));
+        assert!(LocationCode::from_coords(0, 0, 16, 5) == half_way(SubCube::LowerNw));
+
+        for &(x, y, z) in [(31, 31, 31), (1, 2, 3), (17, 0, 30), (8, 24, 5)].iter() {
+            let loc = LocationCode::from_coords(x, y, z, 5);
+            assert!(loc.depth() == 5);
+            assert!(loc.to_coords_and_size(32) == ((x, y, z), 1));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_location_code_from_coords_out_of_range() {
+        LocationCode::from_coords(0, 32, 0, 5);
+    }
+
+    #[test]
+    fn test_location_code_depth_and_contains() {
+        let parent = LocationCode::ROOT.push_sub_cube(SubCube::LowerSe);
+        let child = parent.push_sub_cube(SubCube::UpperNw);
+        let deepest = (0..10).fold(LocationCode::ROOT, |loc, _| {
+            loc.push_sub_cube(SubCube::UpperNe)
+        });
+        assert!(LocationCode::ROOT.depth() == 0);
+        assert!(parent.depth() == 1);
+        assert!(child.depth() == 2);
+        assert!(deepest.depth() == 10);
+
+        for loc in [LocationCode::ROOT, parent, child, deepest] {
+            assert!(LocationCode::ROOT.contains(loc));
+            assert!(loc.contains(loc));
+        }
+        assert!(parent.contains(child));
+        assert!(!child.contains(parent));
+        assert!(!parent.contains(deepest));
+        assert!(!child.contains(LocationCode::ROOT));
+        let sibling = parent.push_sub_cube(SubCube::UpperNe);
+        assert!(!child.contains(sibling));
+    }
+
+    #[test]
+    fn test_sub_cube_flip_and_offset() {
+        assert_eq!(SubCube::LowerSw.flip(Axis::X), SubCube::LowerSe);
+        assert_eq!(SubCube::LowerSw.flip(Axis::Y), SubCube::UpperSw);
+        assert_eq!(SubCube::LowerSw.flip(Axis::Z), SubCube::LowerNw);
+        assert_eq!(SubCube::UpperNe.flip(Axis::X), SubCube::UpperNw);
+
+        for sub_cube in SubCube::all_sub_cubes() {
+            let (x, y, z) = sub_cube.offset();
+            assert_eq!(SubCube::from_xyz(x, y, z), Some(sub_cube));
+            for axis in [Axis::X, Axis::Y, Axis::Z] {
+                let flipped = sub_cube.flip(axis);
+                assert_ne!(flipped, sub_cube);
+                assert_eq!(flipped.flip(axis), sub_cube);
+                let (fx, fy, fz) = flipped.offset();
+                let expected = match axis {
+                    Axis::X => (1 - x, y, z),
+                    Axis::Y => (x, 1 - y, z),
+                    Axis::Z => (x, y, 1 - z),
+                };
+                assert_eq!((fx, fy, fz), expected);
+            }
+        }
+    }
+
     #[test]
     fn test_location_code_from_root_to_just_above_here() {
         let sub_area = LocationCode::ROOT.push_sub_cube(SubCube::LowerNe);
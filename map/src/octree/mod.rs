@@ -2,10 +2,10 @@ use std::collections::HashMap;
 
 mod location_code;
 
-pub use location_code::{LocationCode, SubCube};
+pub use location_code::{Axis, LocationCode, SubCube};
 
 /// A node in a block octree. Either subdivided into 8, or present with the block data.
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq)]
 pub enum OctreeNode<T> {
     /// This node is present; the given value is there.
     Present(T),
@@ -22,6 +22,16 @@ impl<T: Clone> Clone for OctreeNode<T> {
     }
 }
 
+/// Returned by the callback of [`BlockOctree::visit`], to say whether to visit the contents of a
+/// subdivided node.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Descend {
+    /// Visit the node's sub-volumes.
+    Into,
+    /// Skip the node's sub-volumes, and carry on with the next node.
+    Skip,
+}
+
 /// A struct containing information about blocks in the octree. It can either derive this info from
 /// the block itself, or from some sort of list of definitions, e.g., if many blocks share the same
 /// info.
@@ -55,6 +65,16 @@ impl<T: Clone, BI: Clone> Clone for BlockOctree<T, BI> {
     }
 }
 
+/// Trees are equal if they have the same structure & blocks, node for node; the block info isn't
+/// compared. N.b., this is structural: the same blocks, but subdivided differently, aren't equal.
+impl<T: Eq, BI> PartialEq for BlockOctree<T, BI> {
+    fn eq(&self, other: &Self) -> bool {
+        self.octree == other.octree
+    }
+}
+
+impl<T: Eq, BI> Eq for BlockOctree<T, BI> {}
+
 impl<T: Default, BI: BlockInfo<T>> BlockOctree<T, BI> {
     /// Create a new `BlockOctree`, with the volume filled with `T::default()`.
     pub fn new(block_info: BI) -> BlockOctree<T, BI> {
@@ -72,6 +92,22 @@ impl<T, BI: BlockInfo<T>> BlockOctree<T, BI> {
 }
 
 impl<T: Clone + Eq + PartialEq, BI: BlockInfo<T>> BlockOctree<T, BI> {
+    /// Create a `BlockOctree` filled with `root`, then set each of `blocks` in order (as
+    /// [`set_volume`](Self::set_volume) would; blocks that can't be set are skipped), and merge
+    /// the result.
+    pub fn from_blocks(
+        block_info: BI,
+        root: T,
+        blocks: impl IntoIterator<Item = (LocationCode, T)>,
+    ) -> BlockOctree<T, BI> {
+        let mut tree = Self::with_block(block_info, root);
+        for (volume, block) in blocks {
+            tree.set_volume(volume, block);
+        }
+        tree.merge();
+        tree
+    }
+
     /// Iterate through the contents of the tree, in no particular order.
     pub fn iter(&self) -> impl Iterator<Item = (LocationCode, &T)> {
         self.octree.iter().map(|(k, v)| (*k, v)).filter_map(|(k, v)| match v {
@@ -81,12 +117,13 @@ impl<T: Clone + Eq + PartialEq, BI: BlockInfo<T>> BlockOctree<T, BI> {
     }
 
     /// Iterate through the octree, depth first, returning intermediate levels even if the level is
-    /// subdivided.
-    /*
+    /// subdivided. A subdivided node comes just before its sub-volumes.
     pub fn depth_first_all_levels(&self) -> impl Iterator<Item = (LocationCode, &OctreeNode<T>)> {
-        unimplemented!()
+        DepthFirstAllLevelsIterator {
+            octree: &self.octree,
+            next_location: Some(LocationCode::ROOT),
+        }
     }
-    */
 
     /// Iterate through the contents of the tree, depth first.
     pub fn depth_first_blocks(&self) -> impl Iterator<Item = (LocationCode, &T)> {
@@ -96,6 +133,28 @@ impl<T: Clone + Eq + PartialEq, BI: BlockInfo<T>> BlockOctree<T, BI> {
         }
     }
 
+    /// Walk the tree depth first, calling `f` with each node, starting with the root. `f` decides
+    /// whether a subdivided node's sub-volumes are visited; its result for a present node is
+    /// ignored.
+    pub fn visit<F>(&self, mut f: F)
+    where
+        F: FnMut(LocationCode, &OctreeNode<T>) -> Descend,
+    {
+        self.visit_from(LocationCode::ROOT, &mut f);
+    }
+
+    fn visit_from<F>(&self, volume: LocationCode, f: &mut F)
+    where
+        F: FnMut(LocationCode, &OctreeNode<T>) -> Descend,
+    {
+        let node = self.octree.get(&volume).unwrap();
+        if let (Descend::Into, OctreeNode::Subdivided) = (f(volume, node), node) {
+            for sub_cube in SubCube::all_sub_cubes() {
+                self.visit_from(volume.push_sub_cube(sub_cube), f);
+            }
+        }
+    }
+
     pub fn get_volume(&self, volume: LocationCode) -> Option<&OctreeNode<T>> {
         self.octree.get(&volume)
     }
@@ -149,37 +208,55 @@ impl<T: Clone + Eq + PartialEq, BI: BlockInfo<T>> BlockOctree<T, BI> {
         true
     }
 
-    // Clear a volume from the tree. This leaves a void in the tree, which is an invariant of the
-    // tree! You must make sure the void gets filled in after calling this.
+    // Remove everything inside a subdivided volume, at every level, and set the whole volume to
+    // `data`.
     fn clear_subvolume_and_set(&mut self, volume: LocationCode, data: T) {
-        // LowerSw is the first sub cube in the chain of siblings that `next_sibling` iterates
-        // through.
-        let mut current_volume = volume.push_sub_cube(SubCube::LowerSw);
-        while current_volume != volume {
-            match self.octree.get(&current_volume).unwrap() {
-                OctreeNode::Subdivided => {
-                    current_volume = current_volume.push_sub_cube(SubCube::LowerSw);
-                }
-                OctreeNode::Present(_) => {
-                    self.octree.remove(&current_volume);
+        let mut to_remove = SubCube::all_sub_cubes()
+            .map(|sc| volume.push_sub_cube(sc))
+            .collect::<Vec<_>>();
+        while let Some(current_volume) = to_remove.pop() {
+            if let Some(OctreeNode::Subdivided) = self.octree.remove(&current_volume) {
+                to_remove
+                    .extend(SubCube::all_sub_cubes().map(|sc| current_volume.push_sub_cube(sc)));
+            }
+        }
+        self.octree.insert(volume, OctreeNode::Present(data));
+    }
 
-                    while current_volume != volume {
-                        let (parent, subcube) = current_volume
-                            .sub_cube()
-                            // We are always below `volume`, so we always have a parent volume.
-                            .unwrap();
+    /// Merge every subdivided volume whose sub-volumes are all the same homogeneous block back into
+    /// a single volume of that block, leaving the tree in its simplest form.
+    pub fn merge(&mut self) {
+        self.merge_subtree(LocationCode::ROOT);
+    }
 
-                        let next = subcube
-                            .next_sibling()
-                            .map(|sc| parent.push_sub_cube(sc))
-                            .unwrap_or_else(|| parent);
+    fn merge_subtree(&mut self, volume: LocationCode) {
+        match self.octree.get(&volume) {
+            Some(OctreeNode::Subdivided) => (),
+            _ => return,
+        }
+        // Bottom up, so that merged sub-volumes can merge again here.
+        for sub_cube in SubCube::all_sub_cubes() {
+            self.merge_subtree(volume.push_sub_cube(sub_cube));
+        }
 
-                        current_volume = next;
-                    }
-                }
+        let mut sub_volumes =
+            SubCube::all_sub_cubes().map(|sc| self.octree.get(&volume.push_sub_cube(sc)));
+        let first = match sub_volumes.next() {
+            Some(Some(OctreeNode::Present(block))) if self.block_info.is_homogeneous(block) => {
+                block
             }
+            _ => return,
+        };
+        let all_same = sub_volumes
+            .all(|node| matches!(node, Some(OctreeNode::Present(block)) if block == first));
+        if !all_same {
+            return;
         }
-        self.octree.insert(volume, OctreeNode::Present(data));
+        let block = first.clone();
+        for sub_cube in SubCube::all_sub_cubes() {
+            self.octree.remove(&volume.push_sub_cube(sub_cube));
+        }
+        self.octree.insert(volume, OctreeNode::Present(block));
     }
 
     fn subdivide(&mut self, volume: LocationCode, value: T) {
@@ -235,11 +312,33 @@ impl<'a, T> Iterator for DepthFirstIterator<'a, T> {
 
 impl<T> std::iter::FusedIterator for DepthFirstIterator<'_, T> {}
 
+struct DepthFirstAllLevelsIterator<'a, T> {
+    octree: &'a HashMap<LocationCode, OctreeNode<T>>,
+    next_location: Option<LocationCode>,
+}
+
+impl<'a, T> Iterator for DepthFirstAllLevelsIterator<'a, T> {
+    type Item = (LocationCode, &'a OctreeNode<T>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let this_location = self.next_location?;
+        let node = self.octree.get(&this_location).unwrap();
+        self.next_location = match node {
+            OctreeNode::Present(_) => DepthFirstIterator::<T>::next_sibling_of(this_location),
+            OctreeNode::Subdivided => Some(this_location.push_sub_cube(SubCube::LowerSw)),
+        };
+        Some((this_location, node))
+    }
+}
+
+impl<T> std::iter::FusedIterator for DepthFirstAllLevelsIterator<'_, T> {}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::fmt;
 
-    use super::{BlockOctree, LocationCode, SubCube};
+    use super::{BlockOctree, Descend, LocationCode, OctreeNode, SubCube};
 
     #[derive(Clone, Copy, Default, Eq, PartialEq)]
     struct TestBlock(u16);
@@ -251,7 +350,7 @@ mod tests {
         }
     }
 
-    #[derive(Debug)]
+    #[derive(Clone, Debug)]
     struct BlockDefs;
 
     impl super::BlockInfo<TestBlock> for BlockDefs {
@@ -286,4 +385,204 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_octree_eq() {
+        let lower_ne = LocationCode::ROOT.push_sub_cube(SubCube::LowerNe);
+        let upper_sw = LocationCode::ROOT
+            .push_sub_cube(SubCube::UpperSw)
+            .push_sub_cube(SubCube::UpperNe);
+
+        let mut tree: BlockOctree<TestBlock, BlockDefs> = BlockOctree::new(BlockDefs);
+        tree.set_volume(lower_ne, TestBlock(2));
+        tree.set_volume(upper_sw, TestBlock(3));
+        assert_eq!(tree, tree.clone());
+
+        // The same blocks, set in the other order:
+        let mut other = BlockOctree::new(BlockDefs);
+        other.set_volume(upper_sw, TestBlock(3));
+        other.set_volume(lower_ne, TestBlock(1));
+        assert_ne!(tree, other);
+        other.set_volume(lower_ne, TestBlock(2));
+        assert_eq!(tree, other);
+
+        assert_ne!(tree, BlockOctree::new(BlockDefs));
+    }
+
+    #[test]
+    fn test_octree_visit() {
+        let lower_sw = LocationCode::ROOT.push_sub_cube(SubCube::LowerSw);
+        let deeper = lower_sw.push_sub_cube(SubCube::UpperNe);
+        let tree = BlockOctree::from_blocks(BlockDefs, TestBlock(0), vec![(deeper, TestBlock(1))]);
+
+        let mut visited = Vec::new();
+        tree.visit(|loc, _| {
+            visited.push(loc);
+            Descend::Skip
+        });
+        assert_eq!(visited, [LocationCode::ROOT]);
+
+        let mut visited = Vec::new();
+        tree.visit(|loc, node| {
+            visited.push((loc, node.clone()));
+            Descend::Into
+        });
+        assert_eq!(visited.len(), 1 + 8 + 8);
+        assert_eq!(visited[0], (LocationCode::ROOT, OctreeNode::Subdivided));
+        assert_eq!(visited[1], (lower_sw, OctreeNode::Subdivided));
+        assert_eq!(visited[9], (deeper, OctreeNode::Present(TestBlock(1))));
+
+        // Skipping `lower_sw` skips its contents, but not its siblings:
+        let mut visited = Vec::new();
+        tree.visit(|loc, _| {
+            visited.push(loc);
+            if loc == lower_sw {
+                Descend::Skip
+            } else {
+                Descend::Into
+            }
+        });
+        assert_eq!(visited.len(), 1 + 8);
+        assert!(!visited.contains(&deeper));
+    }
+
+    #[test]
+    fn test_octree_depth_first_all_levels() {
+        let lower_sw = LocationCode::ROOT.push_sub_cube(SubCube::LowerSw);
+        let deeper = lower_sw.push_sub_cube(SubCube::UpperNe);
+        let tree = BlockOctree::from_blocks(BlockDefs, TestBlock(0), vec![(deeper, TestBlock(1))]);
+
+        // The same nodes, in the same order, as visiting every node:
+        let mut visited = Vec::new();
+        tree.visit(|loc, node| {
+            visited.push((loc, node.clone()));
+            Descend::Into
+        });
+        let all_levels = tree
+            .depth_first_all_levels()
+            .map(|(loc, node)| (loc, node.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(all_levels, visited);
+        assert_eq!(all_levels[9], (deeper, OctreeNode::Present(TestBlock(1))));
+    }
+
+    #[test]
+    fn test_octree_from_blocks() {
+        let lower_sw = LocationCode::ROOT
+            .push_sub_cube(SubCube::LowerSw)
+            .push_sub_cube(SubCube::LowerSw);
+        let lower_ne = LocationCode::ROOT.push_sub_cube(SubCube::LowerNe);
+        let upper_ne = LocationCode::ROOT.push_sub_cube(SubCube::UpperNe);
+        let blocks = vec![(lower_sw, TestBlock(1)), (lower_ne, TestBlock(2))]
+            .into_iter()
+            // Filling all of `upper_ne`'s sub-volumes one by one merges them into one:
+            .chain(SubCube::all_sub_cubes().map(|sc| (upper_ne.push_sub_cube(sc), TestBlock(5))));
+        let tree = BlockOctree::from_blocks(BlockDefs, TestBlock(0), blocks);
+
+        let items = tree.iter().map(|(l, b)| (l, *b)).collect::<HashMap<_, _>>();
+        assert_eq!(items.len(), 15);
+        assert_eq!(items.get(&lower_sw), Some(&TestBlock(1)));
+        assert_eq!(items.get(&lower_ne), Some(&TestBlock(2)));
+        assert_eq!(items.get(&upper_ne), Some(&TestBlock(5)));
+        let other_lower_sw = lower_sw
+            .containing_cube()
+            .unwrap()
+            .push_sub_cube(SubCube::UpperNe);
+        assert_eq!(items.get(&other_lower_sw), Some(&TestBlock(0)));
+
+        // Setting a volume back to what surrounds it merges all the way up:
+        let blocks = vec![(lower_sw, TestBlock(1)), (lower_sw, TestBlock(0))];
+        let tree = BlockOctree::from_blocks(BlockDefs, TestBlock(0), blocks);
+        assert_eq!(tree, BlockOctree::new(BlockDefs));
+    }
+
+    /// Check the tree's invariants: every node but the root is inside a subdivided node, every
+    /// subdivided node has all 8 sub-volumes, & the present nodes fill the whole volume exactly
+    /// once.
+    fn assert_well_formed(tree: &BlockOctree<TestBlock, BlockDefs>) {
+        for (&loc, node) in tree.octree.iter() {
+            if let Some(parent) = loc.containing_cube() {
+                assert_eq!(
+                    tree.octree.get(&parent),
+                    Some(&OctreeNode::Subdivided),
+                    "{:?} has no subdivided parent",
+                    loc
+                );
+            }
+            if let OctreeNode::Subdivided = node {
+                for sub_cube in SubCube::all_sub_cubes() {
+                    assert!(tree.octree.contains_key(&loc.push_sub_cube(sub_cube)));
+                }
+            }
+        }
+
+        const SIDE: u16 = 16;
+        let side = usize::from(SIDE);
+        let mut filled = vec![0u8; side * side * side];
+        for (loc, _) in tree.depth_first_blocks() {
+            let ((x, y, z), size) = loc.to_coords_and_size(SIDE);
+            let (x, y, z) = (x as usize, y as usize, z as usize);
+            let size = usize::from(size);
+            for x in x..x + size {
+                for y in y..y + size {
+                    for z in z..z + size {
+                        filled[(x * side + y) * side + z] += 1;
+                    }
+                }
+            }
+        }
+        assert!(filled.iter().all(|&n| n == 1));
+        assert_eq!(tree.depth_first_blocks().count(), tree.iter().count());
+    }
+
+    #[test]
+    fn test_octree_clear_subvolume() {
+        let lower_sw = LocationCode::ROOT.push_sub_cube(SubCube::LowerSw);
+        let upper_ne = LocationCode::ROOT.push_sub_cube(SubCube::UpperNe);
+        let lower_sw_upper_ne = lower_sw.push_sub_cube(SubCube::UpperNe);
+        // Subdivided to different depths in different octants, & within `lower_sw`:
+        let blocks = vec![
+            (lower_sw.push_sub_cube(SubCube::LowerSe), TestBlock(1)),
+            (
+                lower_sw_upper_ne.push_sub_cube(SubCube::LowerSw),
+                TestBlock(2),
+            ),
+            (
+                lower_sw_upper_ne
+                    .push_sub_cube(SubCube::UpperNe)
+                    .push_sub_cube(SubCube::LowerSe),
+                TestBlock(3),
+            ),
+            (upper_ne.push_sub_cube(SubCube::LowerSw), TestBlock(4)),
+        ];
+        let mut tree = BlockOctree::from_blocks(BlockDefs, TestBlock(0), blocks);
+        assert_well_formed(&tree);
+        assert_eq!(tree.depth_first_blocks().count(), 8 + 7 + 7 + 7 + 7);
+
+        // Clear a volume in the middle of `lower_sw`, then `lower_sw` itself:
+        assert!(tree.set_volume(lower_sw_upper_ne, TestBlock(5)));
+        assert_well_formed(&tree);
+        assert_eq!(tree.depth_first_blocks().count(), 8 + 7 + 7);
+        assert!(tree.set_volume(lower_sw, TestBlock(6)));
+        assert_well_formed(&tree);
+        assert_eq!(
+            tree.get_volume(lower_sw),
+            Some(&OctreeNode::Present(TestBlock(6)))
+        );
+
+        // Only `upper_ne`'s block is left; the tree is the same as if `lower_sw` never held more.
+        let expected = BlockOctree::from_blocks(
+            BlockDefs,
+            TestBlock(0),
+            vec![
+                (lower_sw, TestBlock(6)),
+                (upper_ne.push_sub_cube(SubCube::LowerSw), TestBlock(4)),
+            ],
+        );
+        assert_eq!(tree, expected);
+
+        // Clearing from the root leaves nothing else behind, either:
+        assert!(tree.set_volume(LocationCode::ROOT, TestBlock(7)));
+        assert_well_formed(&tree);
+        assert_eq!(tree.octree.len(), 1);
+    }
 }
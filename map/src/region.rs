@@ -1,5 +1,7 @@
 use std::io::{self, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
 
 use rusqlite::Connection;
 
@@ -10,31 +12,60 @@ pub struct ChunkCoord {
     z: i64,
 }
 
-#[derive(Copy, Clone)]
-enum ChunkCompression {
+impl ChunkCoord {
+    pub fn new(x: i64, y: i64, z: i64) -> ChunkCoord {
+        ChunkCoord { x, y, z }
+    }
+}
+
+/// How a chunk's data is compressed in the region file. This is stored alongside each chunk, so
+/// chunks compressed with different algorithms can be mixed within a region.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ChunkCompression {
+    /// The data is stored raw.
+    None,
+    #[default]
     Brotli,
+    Zstd,
 }
 
 impl ChunkCompression {
     fn from_int(encoded: u8) -> Option<ChunkCompression> {
         match encoded {
+            0 => Some(ChunkCompression::None),
             1 => Some(ChunkCompression::Brotli),
+            2 => Some(ChunkCompression::Zstd),
             _ => None,
         }
     }
 
     fn as_int(self) -> u8 {
         match self {
+            ChunkCompression::None => 0,
             ChunkCompression::Brotli => 1,
+            ChunkCompression::Zstd => 2,
         }
     }
 }
 
+/// The mimetype stored in the metadata of region files this version of the code understands.
+const REGION_MIMETYPE: &str = "application/vnd.voxel.region.v0";
+
+/// How long an operation on a region file waits for another connection's lock before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// A region file, which represents a large portion of the world; it contains "chunks".
 ///
-/// A region file, under the hood, is an SQLite database.
+/// A region file, under the hood, is an SQLite database. Reads go through a pool of read-only
+/// connections, so a `Region` can be shared between threads that load chunks at the same time;
+/// writes all go through a single connection, & take turns.
 pub struct Region {
-    connection: Connection,
+    path: PathBuf,
+    /// Read-only connections that aren't in use, kept for the next read. (These are declared
+    /// before the writer so that they're closed first; the last connection to close merges the
+    /// write-ahead log back into the file, & a read-only one can't.)
+    readers: Mutex<Vec<Connection>>,
+    writer: Mutex<Connection>,
 }
 
 impl Region {
@@ -46,9 +77,10 @@ impl Region {
         )
         .map_err(RegionErrorKind::Sqlite)?;
 
+        configure_connection(&connection).map_err(RegionErrorKind::Sqlite)?;
         run_schema_create(&connection).map_err(RegionErrorKind::Sqlite)?;
 
-        Ok(Region { connection })
+        Ok(Region::new(path, connection))
     }
 
     /// Open an existing region file at the given path.
@@ -61,14 +93,161 @@ impl Region {
         let connection =
             Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE)
                 .map_err(RegionErrorKind::Sqlite)?;
-        Ok(Some(Region { connection }))
+        configure_connection(&connection).map_err(RegionErrorKind::Sqlite)?;
+        let region = Region::new(path, connection);
+
+        let mimetype = region.mimetype()?;
+        if mimetype != REGION_MIMETYPE {
+            return Err(RegionErrorKind::WrongMimetype(mimetype).into());
+        }
+        Ok(Some(region))
+    }
+
+    fn new(path: &Path, writer: Connection) -> Region {
+        Region {
+            path: path.to_owned(),
+            readers: Mutex::new(Vec::new()),
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Where the region file is.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Run `read` on a read-only connection from the pool, opening a new one if every pooled
+    /// connection is in use. The connection goes back in the pool afterwards.
+    fn with_reader<T, E>(&self, read: impl FnOnce(&Connection) -> Result<T, E>) -> Result<T, E>
+    where
+        E: From<RegionErrorKind>,
+    {
+        let pooled = self.readers.lock().unwrap().pop();
+        let reader = match pooled {
+            Some(reader) => reader,
+            None => open_reader(&self.path).map_err(RegionErrorKind::Sqlite)?,
+        };
+        let result = read(&reader);
+        self.readers.lock().unwrap().push(reader);
+        result
+    }
+
+    /// The connection that all writes go through; holding it keeps other writes waiting.
+    fn writer(&self) -> MutexGuard<'_, Connection> {
+        self.writer.lock().unwrap()
+    }
+
+    /// The mimetype stored in the region file's metadata, identifying the file's format.
+    pub fn mimetype(&self) -> Result<String, RegionError> {
+        let raw_mimetype = self.with_reader(|reader| {
+            reader
+                .query_row(
+                    "SELECT value FROM metadata WHERE key = 'mimetype';",
+                    [],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )
+                .map_err(|err| match err {
+                    rusqlite::Error::QueryReturnedNoRows => RegionErrorKind::MissingMimetype,
+                    err => RegionErrorKind::Sqlite(err),
+                })
+        })?;
+        let mimetype = serde_cbor::from_slice(&raw_mimetype).map_err(RegionErrorKind::Cbor)?;
+        Ok(mimetype)
+    }
+
+    /// Check that the region file is intact: that SQLite finds nothing wrong with the database,
+    /// that it's a region file (by its mimetype), & that the chunks table is as this code expects.
+    /// Returns `Ok(false)` if any of that doesn't hold.
+    pub fn check_integrity(&self) -> Result<bool, RegionError> {
+        let checks = [
+            Region::sqlite_integrity_check,
+            Region::has_region_mimetype,
+            Region::has_expected_chunks_schema,
+        ];
+        for check in checks.iter() {
+            match check(self) {
+                Ok(true) => (),
+                Ok(false) => return Ok(false),
+                // SQLite gives up on some damage with an error, rather than describing it.
+                Err(RegionErrorKind::Sqlite(rusqlite::Error::SqliteFailure(err, _)))
+                    if err.code == rusqlite::ErrorCode::DatabaseCorrupt
+                        || err.code == rusqlite::ErrorCode::NotADatabase =>
+                {
+                    return Ok(false)
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(true)
+    }
+
+    /// `PRAGMA integrity_check` returns a single "ok" row if all is well, or rows describing
+    /// each problem found.
+    fn sqlite_integrity_check(&self) -> Result<bool, RegionErrorKind> {
+        self.with_reader(|reader| {
+            let mut statement = reader.prepare("PRAGMA integrity_check;")?;
+            let results = statement
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(results == ["ok"])
+        })
+    }
+
+    fn has_region_mimetype(&self) -> Result<bool, RegionErrorKind> {
+        match self.mimetype() {
+            Ok(mimetype) => Ok(mimetype == REGION_MIMETYPE),
+            Err(RegionError(RegionErrorKind::MissingMimetype))
+            | Err(RegionError(RegionErrorKind::Cbor(_))) => Ok(false),
+            // A missing metadata table is also just a sign that this isn't a region file.
+            Err(RegionError(RegionErrorKind::Sqlite(rusqlite::Error::SqliteFailure(
+                _,
+                Some(message),
+            )))) if message.starts_with("no such table") => Ok(false),
+            Err(RegionError(err)) => Err(err),
+        }
+    }
+
+    /// Whether the chunks table's columns (name, type, NOT NULL, & position in the primary key)
+    /// are those created by `region_file_schema.sql`.
+    fn has_expected_chunks_schema(&self) -> Result<bool, RegionErrorKind> {
+        const EXPECTED_COLUMNS: [(&str, &str, bool, i64); 5] = [
+            ("chunk_x", "int", true, 1),
+            ("chunk_y", "int", true, 2),
+            ("chunk_z", "int", true, 3),
+            ("compression", "int", true, 0),
+            ("chunk_data", "blob", true, 0),
+        ];
+        let columns = self.with_reader(|reader| {
+            let mut statement = reader.prepare("PRAGMA table_info(chunks);")?;
+            let columns = statement
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>("name")?,
+                        // (SQLite reports the types in upper case.)
+                        row.get::<_, String>("type")?.to_lowercase(),
+                        row.get::<_, bool>("notnull")?,
+                        row.get::<_, i64>("pk")?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok::<_, RegionErrorKind>(columns)
+        })?;
+        let matches = columns.len() == EXPECTED_COLUMNS.len()
+            && columns.iter().zip(EXPECTED_COLUMNS.iter()).all(
+                |((name, type_, notnull, pk), expected)| {
+                    (name.as_str(), type_.as_str(), *notnull, *pk) == *expected
+                },
+            );
+        Ok(matches)
     }
 
     /// Load a chunk from the region file. `chunk_coord` should contain a region-relative chunk
     /// coordinate.
-    pub fn load_chunk(&mut self, chunk_coord: &ChunkCoord) -> Result<Vec<u8>, RegionError> {
-        let (compression, compressed_chunk_data) = self.connection.query_row(
-            "\
+    pub fn load_chunk(&self, chunk_coord: &ChunkCoord) -> Result<Vec<u8>, RegionError> {
+        let (compression, compressed_chunk_data) = self.with_reader(|reader| {
+            reader
+                .query_row(
+                    "\
 SELECT compression, chunk_data
 FROM chunks
 WHERE
@@ -77,70 +256,188 @@ WHERE
     AND chunk_z = ?
 ;
 ",
-            [chunk_coord.x, chunk_coord.y, chunk_coord.z],
-            |row| {
-                Ok((
-                    ChunkCompression::from_int(row.get_unwrap::<_, u8>(0)).unwrap(),
-                    row.get_unwrap::<_, Vec<u8>>(1),
-                ))
-            },
-        ).map_err(RegionErrorKind::Sqlite)?;
-
-        let chunk_data = match compression {
-            ChunkCompression::Brotli => {
-                let mut buf = Vec::new();
-                {
-                    let mut decoder = brotli2::write::BrotliDecoder::new(&mut buf);
-                    decoder
-                        .write_all(&compressed_chunk_data)
-                        .map_err(RegionErrorKind::Io)?;
-                    decoder.finish().map_err(RegionErrorKind::Io)?;
-                }
-                buf
+                    [chunk_coord.x, chunk_coord.y, chunk_coord.z],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .map_err(RegionErrorKind::Sqlite)
+        })?;
+
+        decompress_chunk(compression, compressed_chunk_data)
+    }
+
+    /// Load every chunk within the box between `min` and `max` (inclusive), along with its
+    /// (region-relative) coordinate.
+    pub fn chunks_in_bounds(
+        &self,
+        min: ChunkCoord,
+        max: ChunkCoord,
+    ) -> Result<Vec<(ChunkCoord, Vec<u8>)>, RegionError> {
+        self.with_reader(|reader| {
+            let mut statement = reader
+                .prepare(
+                    "\
+SELECT chunk_x, chunk_y, chunk_z, compression, chunk_data
+FROM chunks
+WHERE
+    chunk_x BETWEEN ? AND ?
+    AND chunk_y BETWEEN ? AND ?
+    AND chunk_z BETWEEN ? AND ?
+ORDER BY chunk_x, chunk_y, chunk_z
+;
+",
+                )
+                .map_err(RegionErrorKind::Sqlite)?;
+            let rows = statement
+                .query_map([min.x, max.x, min.y, max.y, min.z, max.z], |row| {
+                    Ok((
+                        ChunkCoord {
+                            x: row.get(0)?,
+                            y: row.get(1)?,
+                            z: row.get(2)?,
+                        },
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                })
+                .map_err(RegionErrorKind::Sqlite)?;
+
+            let mut chunks = Vec::new();
+            for row in rows {
+                let (chunk_coord, compression, compressed_chunk_data) =
+                    row.map_err(RegionErrorKind::Sqlite)?;
+                chunks.push((
+                    chunk_coord,
+                    decompress_chunk(compression, compressed_chunk_data)?,
+                ));
             }
-        };
-        Ok(chunk_data)
+            Ok(chunks)
+        })
     }
 
-    /// Save a chunk to the region file.
+    /// Save a chunk to the region file, compressed with the default algorithm. (Or stored raw, if
+    /// compressing it doesn't make it any smaller.)
     pub fn save_chunk(
-        &mut self,
+        &self,
         chunk_coord: &ChunkCoord,
         chunk_data: &[u8],
     ) -> Result<(), RegionError> {
-        let compression = ChunkCompression::Brotli;
-        let compressed_data = {
-            let mut buf = Vec::new();
-            let mut encoder = brotli2::write::BrotliEncoder::new(&mut buf, 11);
-            encoder
-                .write_all(&chunk_data)
-                .map_err(RegionErrorKind::Io)?;
-            encoder.finish().map_err(RegionErrorKind::Io)?;
-            buf
-        };
+        self.save_chunk_with_compression(chunk_coord, chunk_data, ChunkCompression::default())
+    }
 
-        self.connection.execute(
-            "\
-INSERT INTO chunks
-(chunk_x, chunk_y, chunk_z, compression, chunk_data)
-VALUES (?, ?, ?, ?, ?)
-ON CONFLICT (chunk_x, chunk_y, chunk_z)
-DO UPDATE SET
-    compression = excluded.compression,
-    chunk_data = excluded.chunk_data
+    /// Save a chunk to the region file, compressed with the given algorithm. (Or stored raw, if
+    /// compressing it doesn't make it any smaller.)
+    pub fn save_chunk_with_compression(
+        &self,
+        chunk_coord: &ChunkCoord,
+        chunk_data: &[u8],
+        compression: ChunkCompression,
+    ) -> Result<(), RegionError> {
+        insert_chunk(&self.writer(), chunk_coord, chunk_data, compression)
+    }
+
+    /// Save many chunks to the region file, compressed with the default algorithm, in a single
+    /// transaction. This is much faster than saving them one by one.
+    pub fn save_chunks<'a>(
+        &self,
+        chunks: impl IntoIterator<Item = (&'a ChunkCoord, &'a [u8])>,
+    ) -> Result<(), RegionError> {
+        let mut writer = self.writer();
+        let transaction = writer.transaction().map_err(RegionErrorKind::Sqlite)?;
+        for (chunk_coord, chunk_data) in chunks {
+            insert_chunk(
+                &transaction,
+                chunk_coord,
+                chunk_data,
+                ChunkCompression::default(),
+            )?;
+        }
+        transaction.commit().map_err(RegionErrorKind::Sqlite)?;
+        Ok(())
+    }
+
+    /// Delete a chunk from the region file. Returns whether there was a chunk there to delete.
+    pub fn delete_chunk(&self, chunk_coord: &ChunkCoord) -> Result<bool, RegionError> {
+        let deleted = self
+            .writer()
+            .execute(
+                "\
+DELETE FROM chunks
+WHERE
+    chunk_x = ?
+    AND chunk_y = ?
+    AND chunk_z = ?
 ;
 ",
-            rusqlite::params![
-                chunk_coord.x,
-                chunk_coord.y,
-                chunk_coord.z,
-                compression.as_int(),
-                compressed_data,
-            ],
-        ).map_err(RegionErrorKind::Sqlite)?;
+                [chunk_coord.x, chunk_coord.y, chunk_coord.z],
+            )
+            .map_err(RegionErrorKind::Sqlite)?;
+        Ok(deleted != 0)
+    }
 
+    /// Rebuild the region file, giving back the space left by deleted chunks. (Otherwise, SQLite
+    /// keeps freed pages around for reuse, and the file never shrinks.)
+    pub fn vacuum(&self) -> Result<(), RegionError> {
+        let writer = self.writer();
+        writer
+            .execute_batch("VACUUM;")
+            .map_err(RegionErrorKind::Sqlite)?;
+        // In WAL mode, the rebuilt database lands in the log; the file itself only shrinks once
+        // the log is checkpointed back into it.
+        writer
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE);", [], |_| Ok(()))
+            .map_err(RegionErrorKind::Sqlite)?;
         Ok(())
     }
+
+    /// List the (region-relative) coordinates of every chunk stored in the region file.
+    pub fn list_chunks(&self) -> Result<Vec<ChunkCoord>, RegionError> {
+        self.with_reader(|reader| {
+            let mut statement = reader
+                .prepare(
+                    "\
+SELECT chunk_x, chunk_y, chunk_z
+FROM chunks
+ORDER BY chunk_x, chunk_y, chunk_z
+;
+",
+                )
+                .map_err(RegionErrorKind::Sqlite)?;
+            let coords = statement
+                .query_map([], |row| {
+                    Ok(ChunkCoord {
+                        x: row.get(0)?,
+                        y: row.get(1)?,
+                        z: row.get(2)?,
+                    })
+                })
+                .map_err(RegionErrorKind::Sqlite)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(RegionErrorKind::Sqlite)?;
+            Ok(coords)
+        })
+    }
+
+    /// Is there a chunk stored at `chunk_coord`?
+    pub fn chunk_exists(&self, chunk_coord: &ChunkCoord) -> Result<bool, RegionError> {
+        self.with_reader(|reader| {
+            reader
+                .query_row(
+                    "\
+SELECT EXISTS (
+    SELECT 1
+    FROM chunks
+    WHERE
+        chunk_x = ?
+        AND chunk_y = ?
+        AND chunk_z = ?
+);
+",
+                    [chunk_coord.x, chunk_coord.y, chunk_coord.z],
+                    |row| row.get(0),
+                )
+                .map_err(|err| RegionErrorKind::Sqlite(err).into())
+        })
+    }
 }
 
 /// An error while doing operations on a region file.
@@ -162,6 +459,119 @@ enum RegionErrorKind {
     ExpectedOneRow(i64, &'static str),
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
+    #[error("CBOR error: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+    #[error("the file has no mimetype in its metadata; is it a region file?")]
+    MissingMimetype,
+    #[error("expected a region file, but the file's mimetype is {0:?}")]
+    WrongMimetype(String),
+    #[error("a chunk is stored with an unknown compression ({0})")]
+    UnknownCompression(u8),
+}
+
+/// Decompress a chunk's data, as loaded (along with its `compression` column) from the region's
+/// database.
+fn decompress_chunk(
+    compression: u8,
+    compressed_chunk_data: Vec<u8>,
+) -> Result<Vec<u8>, RegionError> {
+    let compression = ChunkCompression::from_int(compression)
+        .ok_or(RegionErrorKind::UnknownCompression(compression))?;
+    let chunk_data = match compression {
+        ChunkCompression::None => compressed_chunk_data,
+        ChunkCompression::Brotli => {
+            let mut buf = Vec::new();
+            {
+                let mut decoder = brotli2::write::BrotliDecoder::new(&mut buf);
+                decoder
+                    .write_all(&compressed_chunk_data)
+                    .map_err(RegionErrorKind::Io)?;
+                decoder.finish().map_err(RegionErrorKind::Io)?;
+            }
+            buf
+        }
+        ChunkCompression::Zstd => {
+            zstd::decode_all(compressed_chunk_data.as_slice()).map_err(RegionErrorKind::Io)?
+        }
+    };
+    Ok(chunk_data)
+}
+
+/// Compress a chunk's data, to be stored in the region's database. Returns the compression actually
+/// used: if compressing doesn't shrink the data (e.g., it's tiny, or incompressible), it's stored
+/// raw instead, which is also cheaper to load.
+fn compress_chunk(
+    compression: ChunkCompression,
+    chunk_data: &[u8],
+) -> Result<(ChunkCompression, Vec<u8>), RegionError> {
+    let compressed_data = match compression {
+        ChunkCompression::None => chunk_data.to_vec(),
+        ChunkCompression::Brotli => {
+            let mut buf = Vec::new();
+            let mut encoder = brotli2::write::BrotliEncoder::new(&mut buf, 11);
+            encoder.write_all(chunk_data).map_err(RegionErrorKind::Io)?;
+            encoder.finish().map_err(RegionErrorKind::Io)?;
+            buf
+        }
+        ChunkCompression::Zstd => zstd::encode_all(chunk_data, 0).map_err(RegionErrorKind::Io)?,
+    };
+    if chunk_data.len() <= compressed_data.len() {
+        return Ok((ChunkCompression::None, chunk_data.to_vec()));
+    }
+    Ok((compression, compressed_data))
+}
+
+/// Compress a chunk & insert it into the region's database, replacing any existing chunk.
+fn insert_chunk(
+    connection: &Connection,
+    chunk_coord: &ChunkCoord,
+    chunk_data: &[u8],
+    compression: ChunkCompression,
+) -> Result<(), RegionError> {
+    let (compression, compressed_data) = compress_chunk(compression, chunk_data)?;
+
+    connection
+        .execute(
+            "\
+INSERT INTO chunks
+(chunk_x, chunk_y, chunk_z, compression, chunk_data)
+VALUES (?, ?, ?, ?, ?)
+ON CONFLICT (chunk_x, chunk_y, chunk_z)
+DO UPDATE SET
+    compression = excluded.compression,
+    chunk_data = excluded.chunk_data
+;
+",
+            rusqlite::params![
+                chunk_coord.x,
+                chunk_coord.y,
+                chunk_coord.z,
+                compression.as_int(),
+                compressed_data,
+            ],
+        )
+        .map_err(RegionErrorKind::Sqlite)?;
+
+    Ok(())
+}
+
+/// Set up a freshly opened connection to a region file.
+///
+/// WAL mode lets readers (e.g., a chunk-loading thread) carry on while another connection writes;
+/// the busy timeout makes conflicting writers wait their turn instead of failing immediately.
+fn configure_connection(connection: &Connection) -> rusqlite::Result<()> {
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    connection
+        .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    Ok(())
+}
+
+/// Open a read-only connection to a region file, for the pool of readers. (The file is already in
+/// WAL mode, set by the writer, so readers don't block it.)
+fn open_reader(path: &Path) -> rusqlite::Result<Connection> {
+    let connection = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(connection)
 }
 
 /// Create the SQL tables, etc. (schema) in a new SQLite region file.
@@ -170,8 +580,388 @@ fn run_schema_create(connection: &Connection) -> rusqlite::Result<()> {
 
     connection.execute_batch(RAW_SQL)?;
 
-    let mime = serde_cbor::to_vec(&"application/vnd.voxel.region.v0").unwrap();
+    let mime = serde_cbor::to_vec(&REGION_MIMETYPE).unwrap();
     connection.execute("INSERT INTO metadata VALUES ('mimetype', ?);", &[&mime])?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::path::Path;
+
+    use super::{ChunkCompression, ChunkCoord, Region, RegionErrorKind};
+    use crate::test_util::TempRegion;
+
+    /// How the chunk at `coord` is compressed in the region file.
+    fn stored_compression(region: &Region, coord: &ChunkCoord) -> ChunkCompression {
+        let compression = region
+            .writer()
+            .query_row(
+                "SELECT compression FROM chunks WHERE chunk_x = ? AND chunk_y = ? AND chunk_z = ?;",
+                [coord.x, coord.y, coord.z],
+                |row| row.get::<_, u8>(0),
+            )
+            .unwrap();
+        ChunkCompression::from_int(compression).unwrap()
+    }
+
+    #[test]
+    fn test_delete_chunk() {
+        let mut temp = TempRegion::create("delete_chunk");
+        let region = &mut temp.region;
+        let coord = ChunkCoord { x: 1, y: -2, z: 3 };
+
+        region.save_chunk(&coord, b"chunk data").unwrap();
+        assert!(region.load_chunk(&coord).unwrap() == b"chunk data");
+
+        assert!(region.delete_chunk(&coord).unwrap());
+        match region.load_chunk(&coord) {
+            Err(super::RegionError(RegionErrorKind::Sqlite(
+                rusqlite::Error::QueryReturnedNoRows,
+            ))) => (),
+            other => panic!("expected no chunk, got {:?}", other),
+        }
+        assert!(!region.delete_chunk(&coord).unwrap());
+    }
+
+    #[test]
+    fn test_vacuum() {
+        let temp = TempRegion::create("vacuum");
+        // The region's size on disk, counting what's still only in the write-ahead log.
+        let size_on_disk = |path: &Path| {
+            let mut wal_path = path.as_os_str().to_owned();
+            wal_path.push("-wal");
+            let file_size = |path: &Path| std::fs::metadata(path).map_or(0, |m| m.len());
+            file_size(path) + file_size(Path::new(&wal_path))
+        };
+        let coords = (0..100).map(|i| ChunkCoord::new(i, 0, 0)).collect::<Vec<_>>();
+        for coord in coords.iter() {
+            let chunk_data = vec![coord.x as u8; 4096];
+            temp.region
+                .save_chunk_with_compression(coord, &chunk_data, ChunkCompression::None)
+                .unwrap();
+        }
+        let full_size = size_on_disk(temp.path());
+
+        for coord in coords.iter() {
+            assert!(temp.region.delete_chunk(coord).unwrap());
+        }
+        temp.region.vacuum().unwrap();
+        let vacuumed_size = size_on_disk(temp.path());
+        assert!(
+            vacuumed_size < full_size / 10,
+            "{}B after vacuuming, from {}B",
+            vacuumed_size,
+            full_size
+        );
+        assert!(temp.region.list_chunks().unwrap().is_empty());
+        assert!(temp.region.check_integrity().unwrap());
+    }
+
+    #[test]
+    fn test_list_chunks() {
+        let mut temp = TempRegion::create("list_chunks");
+        let region = &mut temp.region;
+        let coords = [
+            ChunkCoord { x: 0, y: 0, z: 0 },
+            ChunkCoord { x: -1, y: 2, z: 0 },
+            ChunkCoord { x: 5, y: 0, z: -7 },
+        ];
+        assert!(region.list_chunks().unwrap().is_empty());
+        for coord in coords.iter() {
+            region.save_chunk(coord, b"chunk data").unwrap();
+        }
+
+        let mut listed = region.list_chunks().unwrap();
+        listed.sort_by_key(|c| (c.x, c.y, c.z));
+        let mut expected = coords.to_vec();
+        expected.sort_by_key(|c| (c.x, c.y, c.z));
+        assert!(listed == expected);
+
+        let missing = ChunkCoord { x: 1, y: 2, z: 0 };
+        assert!(region.chunk_exists(&coords[1]).unwrap());
+        assert!(!region.chunk_exists(&missing).unwrap());
+    }
+
+    #[test]
+    fn test_compression_round_trip() {
+        let mut temp = TempRegion::create("compression_round_trip");
+        let region = &mut temp.region;
+        let chunk_data = (0..4096).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        let algorithms = [
+            ChunkCompression::None,
+            ChunkCompression::Brotli,
+            ChunkCompression::Zstd,
+        ];
+        for (x, compression) in algorithms.iter().enumerate() {
+            let coord = ChunkCoord {
+                x: x as i64,
+                y: 0,
+                z: 0,
+            };
+            region
+                .save_chunk_with_compression(&coord, &chunk_data, *compression)
+                .unwrap();
+            assert!(stored_compression(region, &coord) == *compression);
+            assert!(region.load_chunk(&coord).unwrap() == chunk_data);
+        }
+    }
+
+    #[test]
+    fn test_incompressible_chunk_stored_raw() {
+        let mut temp = TempRegion::create("incompressible_chunk_stored_raw");
+        let region = &mut temp.region;
+        // Pseudo-random bytes (from a xorshift generator), which no algorithm can shrink.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let chunk_data = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        let algorithms = [ChunkCompression::Brotli, ChunkCompression::Zstd];
+        for (x, compression) in algorithms.iter().enumerate() {
+            let coord = ChunkCoord::new(x as i64, 0, 0);
+            region
+                .save_chunk_with_compression(&coord, &chunk_data, *compression)
+                .unwrap();
+            assert!(stored_compression(region, &coord) == ChunkCompression::None);
+            assert!(region.load_chunk(&coord).unwrap() == chunk_data);
+        }
+
+        // A tiny chunk only grows when compressed, too.
+        let coord = ChunkCoord::new(0, 1, 0);
+        region.save_chunk(&coord, b"x").unwrap();
+        assert!(stored_compression(region, &coord) == ChunkCompression::None);
+        assert!(region.load_chunk(&coord).unwrap() == b"x");
+    }
+
+    #[test]
+    fn test_save_chunks() {
+        let mut temp = TempRegion::create("save_chunks");
+        let region = &mut temp.region;
+        let chunks = (0..100)
+            .map(|i| (ChunkCoord::new(i % 10, i / 10, 0), vec![i as u8; 64]))
+            .collect::<Vec<_>>();
+        region
+            .save_chunks(chunks.iter().map(|(c, d)| (c, d.as_slice())))
+            .unwrap();
+
+        assert!(region.list_chunks().unwrap().len() == chunks.len());
+        for (coord, data) in chunks.iter() {
+            assert!(region.load_chunk(coord).unwrap() == *data);
+        }
+    }
+
+    #[test]
+    fn test_open_checks_mimetype() {
+        let temp = TempRegion::create("open_checks_mimetype");
+        assert!(temp.region.mimetype().unwrap() == super::REGION_MIMETYPE);
+        let reopened = Region::open(temp.path()).unwrap().unwrap();
+        assert!(reopened.mimetype().unwrap() == super::REGION_MIMETYPE);
+        drop(reopened);
+
+        let bogus = serde_cbor::to_vec(&"application/x-bogus").unwrap();
+        temp.region
+            .writer()
+            .execute(
+                "UPDATE metadata SET value = ? WHERE key = 'mimetype';",
+                [bogus],
+            )
+            .unwrap();
+        let err = match Region::open(temp.path()) {
+            Err(err) => err,
+            Ok(_) => panic!("opened a region file with the wrong mimetype"),
+        };
+        match &err.0 {
+            RegionErrorKind::WrongMimetype(mimetype) => assert!(mimetype == "application/x-bogus"),
+            other => panic!("expected a mimetype error, got {:?}", other),
+        }
+        assert!(err.to_string().contains("application/x-bogus"));
+    }
+
+    #[test]
+    fn test_check_integrity() {
+        let temp = TempRegion::create("check_integrity");
+        // Enough chunks to fill several pages of the database.
+        let chunks = (0..64)
+            .map(|i| (ChunkCoord::new(i, 0, 0), vec![i as u8; 1024]))
+            .collect::<Vec<_>>();
+        temp.region
+            .save_chunks(chunks.iter().map(|(c, d)| (c, d.as_slice())))
+            .unwrap();
+        assert!(temp.region.check_integrity().unwrap());
+
+        // A chunks table other than the expected one:
+        temp.region
+            .writer()
+            .execute_batch(
+                "\
+ALTER TABLE chunks RENAME TO old_chunks;
+CREATE TABLE chunks (chunk_x int, chunk_y int, chunk_z int, compression int, chunk_data blob);
+",
+            )
+            .unwrap();
+        assert!(!temp.region.check_integrity().unwrap());
+        temp.region
+            .writer()
+            .execute_batch("DROP TABLE chunks; ALTER TABLE old_chunks RENAME TO chunks;")
+            .unwrap();
+        assert!(temp.region.check_integrity().unwrap());
+
+        // Garbage written over the middle of the file. (The region is closed first, so that the
+        // write-ahead log is merged into the file.)
+        let (region, path) = temp.into_parts();
+        drop(region);
+        let mut contents = std::fs::read(path.path()).unwrap();
+        let page_size = 4096;
+        assert!(contents.len() > 4 * page_size);
+        for byte in &mut contents[2 * page_size..4 * page_size] {
+            *byte = 0x55;
+        }
+        std::fs::write(path.path(), contents).unwrap();
+        let region = Region::open(path.path()).unwrap().unwrap();
+        assert!(!region.check_integrity().unwrap());
+    }
+
+    #[test]
+    fn test_read_during_write() {
+        let temp = TempRegion::create("read_during_write");
+        let first = ChunkCoord::new(0, 0, 0);
+        let second = ChunkCoord::new(1, 0, 0);
+        temp.region.save_chunk(&first, b"first").unwrap();
+
+        let reader = Region::open(temp.path()).unwrap().unwrap();
+        {
+            let mut connection = reader.writer();
+            let read_transaction = connection.transaction().unwrap();
+            let count_chunks = || {
+                read_transaction
+                    .query_row("SELECT COUNT(*) FROM chunks;", [], |row| {
+                        row.get::<_, i64>(0)
+                    })
+                    .unwrap()
+            };
+            assert!(count_chunks() == 1);
+            // With a rollback journal, this write would be blocked by the open read.
+            temp.region.save_chunk(&second, b"second").unwrap();
+            // The reader carries on with its snapshot.
+            assert!(count_chunks() == 1);
+        }
+        assert!(reader.load_chunk(&second).unwrap() == b"second");
+    }
+
+    #[test]
+    fn test_chunks_in_bounds() {
+        let mut temp = TempRegion::create("chunks_in_bounds");
+        let region = &mut temp.region;
+        let mut chunks = Vec::new();
+        for x in 0..3 {
+            for y in 0..3 {
+                for z in 0..3 {
+                    let data = vec![u8::try_from(x * 9 + y * 3 + z).unwrap(); 16];
+                    chunks.push((ChunkCoord::new(x, y, z), data));
+                }
+            }
+        }
+        region
+            .save_chunks(chunks.iter().map(|(c, d)| (c, d.as_slice())))
+            .unwrap();
+
+        let found = region
+            .chunks_in_bounds(ChunkCoord::new(1, 0, 1), ChunkCoord::new(2, 1, 2))
+            .unwrap();
+        assert!(found.len() == 8);
+        for (coord, data) in found.iter() {
+            assert!((1..=2).contains(&coord.x));
+            assert!((0..=1).contains(&coord.y));
+            assert!((1..=2).contains(&coord.z));
+            assert!(chunks.contains(&(coord.clone(), data.clone())));
+        }
+    }
+
+    #[test]
+    fn test_unknown_compression() {
+        let mut temp = TempRegion::create("unknown_compression");
+        let region = &mut temp.region;
+        let coord = ChunkCoord::new(1, 2, 3);
+        region.save_chunk(&coord, b"chunk data").unwrap();
+        region
+            .writer()
+            .execute("UPDATE chunks SET compression = 99;", [])
+            .unwrap();
+
+        match region.load_chunk(&coord) {
+            Err(super::RegionError(RegionErrorKind::UnknownCompression(99))) => (),
+            other => panic!("expected an unknown compression, got {:?}", other),
+        }
+        match region.chunks_in_bounds(coord.clone(), coord) {
+            Err(super::RegionError(RegionErrorKind::UnknownCompression(99))) => (),
+            other => panic!("expected an unknown compression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        let temp = TempRegion::create("concurrent_reads");
+        let region = &temp.region;
+        let coords = (0..8).map(|x| ChunkCoord::new(x, 0, 0)).collect::<Vec<_>>();
+        let generation = |g: u8| coords.iter().map(move |c| (c, vec![g; 4096]));
+        let save_generation = |g: u8| {
+            let chunks = generation(g).collect::<Vec<_>>();
+            region
+                .save_chunks(chunks.iter().map(|(c, d)| (*c, d.as_slice())))
+                .unwrap();
+        };
+        save_generation(0);
+
+        // Each generation is saved in one transaction, so a read sees either all of it or none;
+        // & a reader never goes back to an older generation than one it's already seen.
+        const LAST_GENERATION: u8 = 20;
+        let done = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            let readers = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut seen = 0;
+                        while !done.load(std::sync::atomic::Ordering::Acquire) {
+                            for coord in coords.iter() {
+                                let data = region.load_chunk(coord).unwrap();
+                                assert!(data.len() == 4096);
+                                assert!(data.iter().all(|&b| b == data[0]));
+                                assert!(seen <= data[0] && data[0] <= LAST_GENERATION);
+                                seen = data[0];
+                            }
+                            let all = region
+                                .chunks_in_bounds(
+                                    ChunkCoord::new(0, 0, 0),
+                                    ChunkCoord::new(7, 0, 0),
+                                )
+                                .unwrap();
+                            assert!(all.len() == coords.len());
+                            let g = all[0].1[0];
+                            assert!(seen <= g);
+                            assert!(all.iter().all(|(_, d)| *d == vec![g; 4096]));
+                            seen = g;
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for g in 1..=LAST_GENERATION {
+                save_generation(g);
+            }
+            done.store(true, std::sync::atomic::Ordering::Release);
+            for reader in readers {
+                reader.join().unwrap();
+            }
+        });
+
+        for coord in coords.iter() {
+            assert!(region.load_chunk(coord).unwrap() == vec![LAST_GENERATION; 4096]);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 -- Metadata; currently, just the file version is here.
 CREATE TABLE metadata (
 	key varchar NOT NULL,
-	value blob NOT NULL,  -- CBOR
+	value blob NOT NULL  -- CBOR
 );
 
 -- 3D chunk data. This is the actual world data.
@@ -24,5 +24,5 @@ CREATE TABLE topography (
 	topo_x int NOT NULL,
 	topo_y int NOT NULL,
 	compression int NOT NULL,
-	topo_data blob NOT NULL,
+	topo_data blob NOT NULL
 );
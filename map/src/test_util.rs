@@ -0,0 +1,61 @@
+//! Helpers shared by the crate's tests.
+
+use std::path::{Path, PathBuf};
+
+use crate::region::Region;
+
+/// A region file in the temp directory, deleted on drop.
+pub(crate) struct TempRegion {
+    // (Fields drop in order, so the region is closed before the file is removed.)
+    pub(crate) region: Region,
+    path: TempPath,
+}
+
+impl TempRegion {
+    /// Create an empty region; `name` keeps it apart from other tests' regions.
+    pub(crate) fn create(name: &str) -> TempRegion {
+        let path = std::env::temp_dir().join(format!(
+            "voxel-map-test-{}-{}.sqlite",
+            std::process::id(),
+            name,
+        ));
+        remove_if_exists(&path);
+        let region = Region::create(&path).unwrap();
+        TempRegion {
+            region,
+            path: TempPath(path),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        self.path.path()
+    }
+
+    /// Hand out the region by value, along with the guard that deletes its file; keep the guard
+    /// until the region (& anything that reopens the file) is dropped.
+    pub(crate) fn into_parts(self) -> (Region, TempPath) {
+        (self.region, self.path)
+    }
+}
+
+/// A path in the temp directory, whose file is deleted on drop.
+pub(crate) struct TempPath(PathBuf);
+
+impl TempPath {
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        remove_if_exists(&self.0);
+    }
+}
+
+fn remove_if_exists(path: &Path) {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => panic!("{}", err),
+        _ => (),
+    }
+}
@@ -0,0 +1,254 @@
+//! The world: every chunk, loaded from (and saved back to) a region file as they're needed.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use voxel_mod::{ModuleBlockDefinition, ModuleRegistry};
+
+use crate::io::{decode_chunk, encode_chunk, DecodeChunkError};
+use crate::loader::ChunkLoader;
+use crate::region::{ChunkCoord, Region, RegionError};
+use crate::{Chunk, WorldCoord};
+
+/// The chunks of the world that have been loaded, backed by a region file. (For now, the whole
+/// world is the one region.)
+pub struct World {
+    region: Region,
+    registry: Arc<ModuleRegistry>,
+    chunks: HashMap<ChunkCoord, LoadedChunk>,
+    /// Started on the first [`request_chunk`](Self::request_chunk).
+    loader: Option<ChunkLoader>,
+    /// Chunks requested from the loader, that haven't been polled yet.
+    pending: HashSet<ChunkCoord>,
+}
+
+struct LoadedChunk {
+    chunk: Chunk,
+    /// Set when the chunk has been edited since it was loaded or saved.
+    dirty: bool,
+}
+
+impl World {
+    /// A world stored in `region`, whose chunks are made of blocks from `registry`.
+    pub fn new(region: Region, registry: Arc<ModuleRegistry>) -> World {
+        World {
+            region,
+            registry,
+            chunks: HashMap::new(),
+            loader: None,
+            pending: HashSet::new(),
+        }
+    }
+
+    /// The chunk at `chunk_coord`, loading it from the region file if it isn't already. A chunk
+    /// that's never been saved is empty.
+    pub fn get_or_load_chunk(&mut self, chunk_coord: &ChunkCoord) -> Result<&Chunk, WorldError> {
+        Ok(&self.loaded_chunk(chunk_coord)?.chunk)
+    }
+
+    /// Start loading the chunk at `chunk_coord` on a background thread, unless it's already loaded
+    /// (or loading). It's available once [`poll`](Self::poll) has returned it.
+    pub fn request_chunk(&mut self, chunk_coord: &ChunkCoord) -> Result<(), WorldError> {
+        if self.chunks.contains_key(chunk_coord) || self.pending.contains(chunk_coord) {
+            return Ok(());
+        }
+        self.loader()?.request(chunk_coord.clone());
+        self.pending.insert(chunk_coord.clone());
+        Ok(())
+    }
+
+    /// Take in whichever requested chunks have finished loading, without waiting for the rest.
+    /// Returns the coordinates of the chunks that are now loaded.
+    pub fn poll(&mut self) -> Result<Vec<ChunkCoord>, WorldError> {
+        let loader = match &self.loader {
+            Some(loader) => loader,
+            None => return Ok(Vec::new()),
+        };
+        let mut newly_loaded = Vec::new();
+        while let Some((chunk_coord, result)) = loader.try_recv() {
+            self.pending.remove(&chunk_coord);
+            // If the chunk was loaded some other way in the meantime (& maybe edited since), the
+            // copy in memory is the newer one.
+            if self.chunks.contains_key(&chunk_coord) {
+                continue;
+            }
+            let chunk = match result? {
+                Some(data) => decode_chunk(&data, &self.registry)?,
+                None => Chunk::new(),
+            };
+            let loaded = LoadedChunk {
+                chunk,
+                dirty: false,
+            };
+            self.chunks.insert(chunk_coord.clone(), loaded);
+            newly_loaded.push(chunk_coord);
+        }
+        Ok(newly_loaded)
+    }
+
+    /// Set the block at `coord`, in whichever chunk contains it. It isn't saved until
+    /// [`flush`](Self::flush).
+    pub fn set_block(
+        &mut self,
+        coord: WorldCoord,
+        block: Option<Arc<ModuleBlockDefinition>>,
+    ) -> Result<(), WorldError> {
+        let (chunk_coord, relative) = coord.to_chunk_and_relative();
+        let loaded = self.loaded_chunk(&chunk_coord)?;
+        loaded.chunk.set_block(relative, block);
+        loaded.dirty = true;
+        Ok(())
+    }
+
+    /// Save every chunk that's been edited since it was last saved.
+    pub fn flush(&mut self) -> Result<(), WorldError> {
+        let dirty = self
+            .chunks
+            .iter()
+            .filter(|(_, loaded)| loaded.dirty)
+            .map(|(chunk_coord, loaded)| (chunk_coord, encode_chunk(&loaded.chunk)))
+            .collect::<Vec<_>>();
+        if dirty.is_empty() {
+            return Ok(());
+        }
+        self.region.save_chunks(
+            dirty
+                .iter()
+                .map(|(chunk_coord, data)| (*chunk_coord, data.as_slice())),
+        )?;
+        for loaded in self.chunks.values_mut() {
+            loaded.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Give back the region file, e.g., to close it. Unsaved edits are lost; see
+    /// [`flush`](Self::flush).
+    pub fn into_region(self) -> Region {
+        self.region
+    }
+
+    fn loader(&mut self) -> Result<&ChunkLoader, WorldError> {
+        if self.loader.is_none() {
+            let path = self.region.path().to_owned();
+            let region = Region::open(&path)?.ok_or(WorldError::RegionMissing(path))?;
+            self.loader = Some(ChunkLoader::spawn(region));
+        }
+        Ok(self.loader.as_ref().unwrap())
+    }
+
+    fn loaded_chunk(&mut self, chunk_coord: &ChunkCoord) -> Result<&mut LoadedChunk, WorldError> {
+        if !self.chunks.contains_key(chunk_coord) {
+            let chunk = if self.region.chunk_exists(chunk_coord)? {
+                let data = self.region.load_chunk(chunk_coord)?;
+                decode_chunk(&data, &self.registry)?
+            } else {
+                Chunk::new()
+            };
+            let loaded = LoadedChunk {
+                chunk,
+                dirty: false,
+            };
+            self.chunks.insert(chunk_coord.clone(), loaded);
+        }
+        Ok(self.chunks.get_mut(chunk_coord).unwrap())
+    }
+}
+
+/// An error loading or saving the world's chunks.
+#[derive(Debug, thiserror::Error)]
+pub enum WorldError {
+    #[error("region error: {0}")]
+    Region(#[from] RegionError),
+    #[error("failed to decode a chunk: {0}")]
+    DecodeChunk(#[from] DecodeChunkError),
+    #[error("the region file {} has gone missing", .0.display())]
+    RegionMissing(PathBuf),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use voxel_mod::{Module, ModuleRegistry};
+
+    use super::World;
+    use crate::region::{ChunkCoord, Region};
+    use crate::test_util::TempRegion;
+    use crate::{ChunkRelativeCoord, WorldCoord};
+
+    fn registry() -> Arc<ModuleRegistry> {
+        let module = Module::for_test(
+            "dirt:\n  texture: dirt.png\n  color: \"#8f563b\"\n  homogeneous: true\n",
+        );
+        let mut registry = ModuleRegistry::new();
+        registry.register(module).unwrap();
+        Arc::new(registry)
+    }
+
+    #[test]
+    fn test_poll_loads_in_background() {
+        let (region, path) = TempRegion::create("poll_loads_in_background").into_parts();
+        let registry = registry();
+        let dirt = registry.block_by_full_id("test:dirt").unwrap();
+        let mut world = World::new(region, registry.clone());
+        world
+            .set_block(WorldCoord::new(-1, 0, 0), Some(dirt.clone()))
+            .unwrap();
+        world.flush().unwrap();
+        drop(world);
+
+        let mut world = World::new(Region::open(path.path()).unwrap().unwrap(), registry);
+        let chunk_coord = ChunkCoord::new(-1, 0, 0);
+        world.request_chunk(&chunk_coord).unwrap();
+        // Asking again while it's loading is fine; it's only loaded once.
+        world.request_chunk(&chunk_coord).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let loaded = loop {
+            let loaded = world.poll().unwrap();
+            if !loaded.is_empty() {
+                break loaded;
+            }
+            assert!(Instant::now() < deadline, "the chunk never loaded");
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert!(loaded == [chunk_coord.clone()]);
+        let chunk = world.get_or_load_chunk(&chunk_coord).unwrap();
+        let block = chunk.get_block(ChunkRelativeCoord::new(63, 0, 0)).unwrap();
+        assert!(Arc::ptr_eq(block, &dirt));
+        assert!(world.poll().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_blocks_persist_across_chunks() {
+        let (region, path) = TempRegion::create("blocks_persist_across_chunks").into_parts();
+        let registry = registry();
+        let dirt = registry.block_by_full_id("test:dirt").unwrap();
+
+        // Either side of the boundary between two chunks:
+        let mut world = World::new(region, registry.clone());
+        world
+            .set_block(WorldCoord::new(63, 1, 2), Some(dirt.clone()))
+            .unwrap();
+        world
+            .set_block(WorldCoord::new(64, 1, 2), Some(dirt.clone()))
+            .unwrap();
+        world.flush().unwrap();
+        drop(world.into_region());
+
+        let mut world = World::new(Region::open(path.path()).unwrap().unwrap(), registry);
+        let first = world.get_or_load_chunk(&ChunkCoord::new(0, 0, 0)).unwrap();
+        let block = first.get_block(ChunkRelativeCoord::new(63, 1, 2)).unwrap();
+        assert!(Arc::ptr_eq(block, &dirt));
+        assert_eq!(first.count_non_empty(), 1);
+        let second = world.get_or_load_chunk(&ChunkCoord::new(1, 0, 0)).unwrap();
+        let block = second.get_block(ChunkRelativeCoord::new(0, 1, 2)).unwrap();
+        assert!(Arc::ptr_eq(block, &dirt));
+        assert_eq!(second.count_non_empty(), 1);
+        // A chunk that was never saved is empty.
+        let empty = world.get_or_load_chunk(&ChunkCoord::new(2, 0, 0)).unwrap();
+        assert_eq!(empty.count_non_empty(), 0);
+    }
+}
@@ -0,0 +1,127 @@
+//! Encoding chunks, as they're stored in region files, & decoding them back.
+
+use std::sync::Arc;
+
+use voxel_map::io::{decode_chunk, encode_chunk};
+use voxel_map::{Chunk, ChunkRelativeCoord, CHUNK_SIDE_LENGTH};
+use voxel_mod::{Module, ModuleBlockDefinition, ModuleRegistry};
+
+static BLOCK_DEFS: &str = r#"
+dirt:
+  texture: dirt.png
+  color:
+    r: 143
+    g: 86
+    b: 59
+  homogeneous: true
+stone:
+  texture: stone.png
+  color:
+    r: 128
+    g: 128
+    b: 128
+  homogeneous: true
+sign:
+  texture: sign.png
+  color:
+    r: 200
+    g: 160
+    b: 90
+  homogeneous: false
+"#;
+
+fn registry() -> ModuleRegistry {
+    let module = Module::for_test(&BLOCK_DEFS[1..]);
+    let mut registry = ModuleRegistry::new();
+    registry.register(module).unwrap();
+    registry
+}
+
+fn block(registry: &ModuleRegistry, id: &str) -> Arc<ModuleBlockDefinition> {
+    registry.block_by_full_id(&format!("test:{}", id)).unwrap()
+}
+
+fn all_coords() -> impl Iterator<Item = ChunkRelativeCoord> {
+    let side = 0..CHUNK_SIDE_LENGTH;
+    side.clone()
+        .flat_map(move |x| {
+            side.clone()
+                .flat_map(move |y| (0..CHUNK_SIDE_LENGTH).map(move |z| (x, y, z)))
+        })
+        .map(|(x, y, z)| ChunkRelativeCoord::new(x, y, z))
+}
+
+/// Whether two chunks hold the same block everywhere, regardless of how their octrees are split.
+fn same_blocks(a: &Chunk, b: &Chunk) -> bool {
+    all_coords().all(|coord| match (a.get_block(coord), b.get_block(coord)) {
+        (None, None) => true,
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        _ => false,
+    })
+}
+
+/// A chunk with a few kinds of blocks, scattered singly & filling larger volumes, with empty space
+/// around & inside them.
+fn mixed_chunk(registry: &ModuleRegistry) -> Chunk {
+    let (dirt, stone, sign) = (
+        block(registry, "dirt"),
+        block(registry, "stone"),
+        block(registry, "sign"),
+    );
+    let mut chunk = Chunk::new();
+    // An 8×8×8 cube of stone, aligned so that it can be a single octree node, with a hole in it.
+    for x in 8..16 {
+        for y in 0..8 {
+            for z in 16..24 {
+                chunk.set_block(ChunkRelativeCoord::new(x, y, z), Some(stone.clone()));
+            }
+        }
+    }
+    chunk.set_block(ChunkRelativeCoord::new(9, 3, 17), None);
+    // A layer of dirt that isn't aligned to any octree node.
+    for x in 30..50 {
+        for z in 5..9 {
+            chunk.set_block(ChunkRelativeCoord::new(x, 20, z), Some(dirt.clone()));
+        }
+    }
+    for &(x, y, z) in [(0, 0, 0), (63, 63, 63), (31, 32, 33), (10, 3, 17)].iter() {
+        chunk.set_block(ChunkRelativeCoord::new(x, y, z), Some(sign.clone()));
+    }
+    chunk
+}
+
+#[test]
+fn test_chunk_round_trip() {
+    let registry = registry();
+    let chunk = mixed_chunk(&registry);
+    let encoded = encode_chunk(&chunk);
+    let decoded = decode_chunk(&encoded, &registry).unwrap();
+    assert!(same_blocks(&chunk, &decoded));
+    assert_eq!(decoded.count_non_empty(), chunk.count_non_empty());
+    // (One of the signs replaces a block of stone.)
+    assert_eq!(chunk.count_non_empty(), 8 * 8 * 8 - 1 + 20 * 4 + 3);
+}
+
+#[test]
+fn test_empty_chunk_round_trip() {
+    let registry = registry();
+    let chunk = Chunk::new();
+    let encoded = encode_chunk(&chunk);
+    let decoded = decode_chunk(&encoded, &registry).unwrap();
+    assert!(same_blocks(&chunk, &decoded));
+    assert_eq!(decoded.count_non_empty(), 0);
+    assert_eq!(encode_chunk(&decoded), encoded);
+}
+
+#[test]
+fn test_encoding_is_stable() {
+    // Decoding merges the octree, so the first re-encoding can be smaller; from then on, decoding
+    // & encoding again reproduces exactly the same bytes, however the chunk got its blocks.
+    let registry = registry();
+    let encoded =
+        encode_chunk(&decode_chunk(&encode_chunk(&mixed_chunk(&registry)), &registry).unwrap());
+    for _ in 0..4 {
+        let decoded = decode_chunk(&encoded, &registry).unwrap();
+        assert_eq!(encode_chunk(&decoded), encoded);
+    }
+}
@@ -5,6 +5,9 @@ use structopt::StructOpt;
 #[derive(StructOpt)]
 struct Args {
     path: PathBuf,
+    /// Also check that every block's texture exists.
+    #[structopt(long)]
+    validate_textures: bool,
 }
 
 fn main() {
@@ -12,4 +15,10 @@ fn main() {
 
     let module = voxel_mod::Module::load_from_path(args.path).unwrap();
     println!("{:#?}", module);
+    if args.validate_textures {
+        if let Err(err) = module.validate_textures() {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
 }
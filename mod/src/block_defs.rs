@@ -1,16 +1,104 @@
 use std::collections::HashMap;
 use std::io::Read;
+use std::path::Path;
 
-use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
 
-#[derive(Debug, Deserialize)]
+/// The formats that module & block definition files can be written in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /// The extensions, without the dot, of files in each format; the first is preferred.
+    pub const EXTENSIONS: [(&'static str, Format); 3] = [
+        ("yaml", Format::Yaml),
+        ("yml", Format::Yaml),
+        ("toml", Format::Toml),
+    ];
+
+    /// The format of the file at `path`, going by its extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?;
+        Format::EXTENSIONS
+            .iter()
+            .find(|(ext, _)| extension.eq_ignore_ascii_case(ext))
+            .map(|&(_, format)| format)
+    }
+
+    /// Deserialize a `T` written in this format.
+    pub fn parse<T: DeserializeOwned, R: Read>(self, mut reader: R) -> anyhow::Result<T> {
+        match self {
+            Format::Yaml => Ok(serde_yaml::from_reader(reader)?),
+            Format::Toml => {
+                let mut toml = String::new();
+                reader.read_to_string(&mut toml)?;
+                Ok(toml::from_str(&toml)?)
+            }
+        }
+    }
+}
+
+/// A color; in YAML, either `{r: 143, g: 86, b: 59}` or `"#8f563b"`. (Or the equivalent in TOML.)
+#[derive(Debug, Eq, PartialEq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
     pub b: u8,
 }
 
-#[derive(Debug, Deserialize)]
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum ColorYaml {
+            Components { r: u8, g: u8, b: u8 },
+            Hex(String),
+        }
+
+        match ColorYaml::deserialize(deserializer)? {
+            ColorYaml::Components { r, g, b } => Ok(Color { r, g, b }),
+            ColorYaml::Hex(hex) => parse_hex_color(&hex).ok_or_else(|| {
+                serde::de::Error::custom(format!(
+                    "invalid color {:?}; expected a \"#rrggbb\" hex string",
+                    hex
+                ))
+            }),
+        }
+    }
+}
+
+/// Parse a `#rrggbb` color.
+fn parse_hex_color(s: &str) -> Option<Color> {
+    let digits = s.strip_prefix('#')?;
+    if digits.len() != 6 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let component = |idx: usize| u8::from_str_radix(&digits[idx..idx + 2], 16).ok();
+    Some(Color {
+        r: component(0)?,
+        g: component(2)?,
+        b: component(4)?,
+    })
+}
+
+/// The shape of a block, within the space it occupies.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockShape {
+    /// Fills the whole block.
+    #[default]
+    Cube,
+    /// Fills the bottom half of the block.
+    Slab,
+    /// A slab, with a step filling the top half of the block's back (+Z) half.
+    Stairs,
+}
+
+#[derive(Debug, Deserialize, Eq, PartialEq)]
 pub struct BlockDefinition {
     /// Path to a texture for the block.
     // TODO: all sorts of things. Loading? Multiple textures per block? Texture mapping?
@@ -19,12 +107,44 @@ pub struct BlockDefinition {
     color: Color,
     /// Whether the block is homogeneous, and nearby blocks can be losslessly merged with it.
     homogeneous: bool,
+    /// How much light the block gives off, if any (e.g., lava, or a lamp).
+    #[serde(default)]
+    emission: Option<u8>,
+    /// The block's shape; a full cube, if not given.
+    #[serde(default)]
+    shape: BlockShape,
 }
 
 impl BlockDefinition {
+    /// Whether the block can be merged with nearby blocks. Blocks that aren't cubes never can be,
+    /// as a merged node is drawn as a single cube.
     #[inline]
     pub fn is_homogeneous(&self) -> bool {
-        self.homogeneous
+        self.homogeneous && self.shape == BlockShape::Cube
+    }
+
+    /// The path to the block's texture, relative to its module's directory.
+    #[inline]
+    pub fn texture(&self) -> &str {
+        &self.texture
+    }
+
+    /// The block's primitive color.
+    #[inline]
+    pub fn color(&self) -> &Color {
+        &self.color
+    }
+
+    /// How much light the block gives off; `None` for blocks that don't glow.
+    #[inline]
+    pub fn emission(&self) -> Option<u8> {
+        self.emission
+    }
+
+    /// The block's shape.
+    #[inline]
+    pub fn shape(&self) -> BlockShape {
+        self.shape
     }
 }
 
@@ -34,3 +154,96 @@ pub fn load_block_definitions<R: Read>(
 ) -> Result<HashMap<String, BlockDefinition>, serde_yaml::Error> {
     serde_yaml::from_reader(reader)
 }
+
+/// Load block definitions from a file in the given format.
+pub fn load_block_definitions_in_format<R: Read>(
+    reader: R,
+    format: Format,
+) -> anyhow::Result<HashMap<String, BlockDefinition>> {
+    format.parse(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Color;
+
+    #[test]
+    fn test_color_forms() {
+        let components: Color = serde_yaml::from_str("{r: 143, g: 86, b: 59}").unwrap();
+        let hex: Color = serde_yaml::from_str("\"#8f563b\"").unwrap();
+        let expected = Color {
+            r: 0x8f,
+            g: 0x56,
+            b: 0x3b,
+        };
+        assert!(components == expected);
+        assert!(hex == expected);
+        let upper: Color = serde_yaml::from_str("\"#8F563B\"").unwrap();
+        assert!(upper == expected);
+
+        for bad in [
+            "\"8f563b\"",
+            "\"#8f563\"",
+            "\"#8f563bff\"",
+            "\"#8g563b\"",
+            "\"#+f563b\"",
+        ]
+        .iter()
+        {
+            assert!(
+                serde_yaml::from_str::<Color>(bad).is_err(),
+                "{} parsed",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_block_with_hex_color() {
+        let defs = super::load_block_definitions(
+            "dirt:\n  texture: dirt.png\n  color: \"#8f563b\"\n  homogeneous: true\n".as_bytes(),
+        )
+        .unwrap();
+        assert!(defs["dirt"].color().r == 0x8f);
+    }
+
+    #[test]
+    fn test_block_shape() {
+        let defs = super::load_block_definitions(
+            "\
+dirt:
+  texture: dirt.png
+  color: \"#8f563b\"
+  homogeneous: true
+slab:
+  texture: stone.png
+  color: \"#808080\"
+  homogeneous: true
+  shape: slab
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        assert!(defs["dirt"].shape() == super::BlockShape::Cube);
+        assert!(defs["dirt"].is_homogeneous());
+        assert!(defs["slab"].shape() == super::BlockShape::Slab);
+        // Even though it says it is.
+        assert!(!defs["slab"].is_homogeneous());
+
+        let bad =
+            "dirt:\n  texture: a.png\n  color: \"#808080\"\n  homogeneous: true\n  shape: ball\n";
+        assert!(super::load_block_definitions(bad.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        use super::Format;
+        use std::path::Path;
+
+        assert!(Format::from_path(Path::new("a/module.yaml")) == Some(Format::Yaml));
+        assert!(Format::from_path(Path::new("module.YML")) == Some(Format::Yaml));
+        assert!(Format::from_path(Path::new("module.toml")) == Some(Format::Toml));
+        assert!(Format::from_path(Path::new("module.json")).is_none());
+        assert!(Format::from_path(Path::new("module")).is_none());
+    }
+}
@@ -1,4 +1,6 @@
 pub mod block_defs;
 mod module;
+mod registry;
 
 pub use module::{Module, ModuleBlockDefinition};
+pub use registry::ModuleRegistry;
@@ -1,12 +1,12 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock, Weak};
 
 use anyhow::Context;
 use serde::Deserialize;
 
-use super::block_defs::BlockDefinition;
+use super::block_defs::{BlockDefinition, Format};
 
 /// A "mod".
 ///
@@ -18,6 +18,8 @@ pub struct Module {
     display_name: String,
     path: PathBuf,
     block_defs: RwLock<HashMap<String, Arc<ModuleBlockDefinition>>>,
+    /// A reference to the module itself, for the blocks it (re)loads to point back at.
+    weak_self: Weak<Module>,
 }
 
 impl Module {
@@ -28,15 +30,27 @@ impl Module {
         path: PathBuf,
         block_defs: HashMap<String, BlockDefinition>,
     ) -> Arc<Module> {
-        let module = Arc::new(Module {
+        Arc::new_cyclic(|weak_module| Module {
             id,
             display_name,
             path,
-            block_defs: RwLock::new(HashMap::new()),
-        });
-        let block_defs = map_block_defs(block_defs, Arc::downgrade(&module));
-        module.block_defs.write().unwrap().extend(block_defs);
-        module
+            block_defs: RwLock::new(map_block_defs(block_defs, weak_module.clone()).collect()),
+            weak_self: weak_module.clone(),
+        })
+    }
+
+    /// A module with the id `test`, holding the blocks defined (in YAML) by `block_defs`; for
+    /// tests, which panics if the definitions don't load.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn for_test(block_defs: &str) -> Arc<Module> {
+        let block_defs = super::block_defs::load_block_definitions(block_defs.as_bytes())
+            .expect("failed to load the test module's block definitions");
+        Module::new(
+            "test".to_owned(),
+            "[test]".to_owned(),
+            PathBuf::from("[internal]"),
+            block_defs,
+        )
     }
 
     pub fn block_by_id(&self, id: &str) -> Option<Arc<ModuleBlockDefinition>> {
@@ -48,46 +62,151 @@ impl Module {
         &self.id
     }
 
+    /// The directory the module was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The IDs of all of the module's blocks, sorted.
+    pub fn block_ids(&self) -> Vec<String> {
+        let lock = self.block_defs.read().unwrap();
+        let mut ids = lock.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    /// All of the module's blocks, in no particular order.
+    pub(crate) fn blocks(&self) -> Vec<Arc<ModuleBlockDefinition>> {
+        let lock = self.block_defs.read().unwrap();
+        lock.values().cloned().collect()
+    }
+
+    /// Check that every block's texture exists, relative to the module's directory. The error
+    /// lists every missing texture, not just the first.
+    pub fn validate_textures(&self) -> anyhow::Result<()> {
+        let lock = self.block_defs.read().unwrap();
+        let mut missing = lock
+            .values()
+            .filter_map(|block| {
+                let texture_path = self.path.join(block.definition().texture());
+                match texture_path.is_file() {
+                    true => None,
+                    false => Some(format!(
+                        "block {:?}: {}",
+                        block.id(),
+                        texture_path.display()
+                    )),
+                }
+            })
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        missing.sort();
+        anyhow::bail!(
+            "module {:?} is missing {} block texture(s):\n  {}",
+            self.id,
+            missing.len(),
+            missing.join("\n  "),
+        )
+    }
+
+    /// Load the module in the directory at `path`: its `module` & `block-definitions` files, each
+    /// either YAML (`.yaml`/`.yml`) or TOML (`.toml`).
     pub fn load_from_path(path: PathBuf) -> anyhow::Result<Arc<Module>> {
-        let module_yaml: ModuleYaml = {
-            let module_path = path.join("module.yaml");
+        let module_def: ModuleDefinition = {
+            let (module_path, format) = find_definition_file(&path, "module")?;
             let module_file = File::open(&module_path).with_context(|| {
                 format!(
-                    "failed to open module definition YAML at {}",
+                    "failed to open module definition at {}",
                     module_path.display()
                 )
             })?;
-            serde_yaml::from_reader(module_file).with_context(|| {
+            format.parse(module_file).with_context(|| {
                 format!(
-                    "failed to parse module definition YAML at {}",
+                    "failed to parse module definition at {}",
                     module_path.display()
                 )
             })?
         };
 
-        let block_defs = {
-            let block_defs_path = path.join("block-definitions.yaml");
-            let block_defs_file = File::open(&block_defs_path).with_context(|| {
-                format!(
-                    "failed to open block definitions YAML at {}",
-                    block_defs_path.display()
-                )
-            })?;
-            super::block_defs::load_block_definitions(block_defs_file)
-                .with_context(|| {
-                    format!(
-                        "failed to parse block definition YAML at {}",
-                        block_defs_path.display()
-                    )
-                })?
-        };
+        let block_defs = read_block_defs(&path)?;
 
-        Ok(Module::new(module_yaml.id, module_yaml.name, path, block_defs))
+        Ok(Module::new(module_def.id, module_def.name, path, block_defs))
+    }
+
+    /// Re-read the module's block definitions from its directory, & swap them in.
+    ///
+    /// Blocks whose definitions are unchanged keep the same `Arc<ModuleBlockDefinition>`, so
+    /// anything holding them (e.g., chunks' octrees) stays in step with the module. Blocks whose
+    /// definitions changed are replaced; anything already holding the old definition keeps it.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let block_defs = read_block_defs(&self.path)?;
+        let mut lock = self.block_defs.write().unwrap();
+        let reloaded = block_defs
+            .into_iter()
+            .map(|(id, def)| match lock.get(&id) {
+                Some(existing) if existing.def == def => (id, existing.clone()),
+                _ => {
+                    let def = ModuleBlockDefinition {
+                        module: self.weak_self.clone(),
+                        id: id.clone(),
+                        def,
+                    };
+                    (id, Arc::new(def))
+                }
+            })
+            .collect();
+        *lock = reloaded;
+        Ok(())
     }
 }
 
+/// Find the file named `name` in the module directory `dir`, with any of the extensions of the
+/// formats we understand. It's an error if there's none, or more than one.
+fn find_definition_file(dir: &Path, name: &str) -> anyhow::Result<(PathBuf, Format)> {
+    let mut found = Format::EXTENSIONS
+        .iter()
+        .map(|(ext, format)| (dir.join(format!("{}.{}", name, ext)), *format))
+        .filter(|(path, _)| path.is_file());
+    match (found.next(), found.next()) {
+        (Some(file), None) => Ok(file),
+        (Some((first, _)), Some((second, _))) => anyhow::bail!(
+            "module at {} has more than one {:?} file: {} & {}",
+            dir.display(),
+            name,
+            first.display(),
+            second.display(),
+        ),
+        (None, _) => anyhow::bail!(
+            "module at {} has no {}.yaml (or .yml, or .toml)",
+            dir.display(),
+            name,
+        ),
+    }
+}
+
+/// Read the block definitions from the module at `path`.
+fn read_block_defs(path: &Path) -> anyhow::Result<HashMap<String, BlockDefinition>> {
+    let (block_defs_path, format) = find_definition_file(path, "block-definitions")?;
+    let block_defs_file = File::open(&block_defs_path).with_context(|| {
+        format!(
+            "failed to open block definitions at {}",
+            block_defs_path.display()
+        )
+    })?;
+    super::block_defs::load_block_definitions_in_format(block_defs_file, format).with_context(
+        || {
+            format!(
+                "failed to parse block definitions at {}",
+                block_defs_path.display()
+            )
+        },
+    )
+}
+
 #[derive(Deserialize)]
-struct ModuleYaml {
+struct ModuleDefinition {
     id: String,
     name: String,
 }
@@ -99,7 +218,6 @@ fn map_block_defs(
     defs.into_iter()
         .map(move |(id, def)| {
             let def = ModuleBlockDefinition {
-                // empty ref, initialized later when we have an Arc<Module>.
                 module: weak_module.clone(),
                 id: id.clone(),
                 def,
@@ -131,4 +249,225 @@ impl ModuleBlockDefinition {
     pub fn definition(&self) -> &BlockDefinition {
         &self.def
     }
+
+    /// How much light blocks of this definition give off, if any.
+    #[inline]
+    pub fn emission(&self) -> Option<u8> {
+        self.def.emission()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::Module;
+
+    static MINIMAL_MOD_BLOCK_DEFS: &str = r#"
+dirt:
+  texture: dirt.png
+  color:
+    r: 143
+    g: 86
+    b: 59
+  homogeneous: true
+"#;
+
+    #[test]
+    fn test_block_ids() {
+        let module = Module::new(
+            "test".to_owned(),
+            "Test".to_owned(),
+            PathBuf::from("[test]"),
+            crate::block_defs::load_block_definitions(&MINIMAL_MOD_BLOCK_DEFS.as_bytes()[1..])
+                .unwrap(),
+        );
+        assert!(module.block_ids() == ["dirt"]);
+
+        let empty = Module::new(
+            "empty".to_owned(),
+            "Empty".to_owned(),
+            PathBuf::from("[test]"),
+            Default::default(),
+        );
+        assert!(empty.block_ids().is_empty());
+    }
+
+    #[test]
+    fn test_block_emission() {
+        let block_defs = crate::block_defs::load_block_definitions(
+            "\
+dirt:
+  texture: dirt.png
+  color: \"#8f563b\"
+  homogeneous: true
+lava:
+  texture: lava.png
+  color: \"#ff6000\"
+  homogeneous: true
+  emission: 200
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        let module = Module::new(
+            "test".to_owned(),
+            "Test".to_owned(),
+            PathBuf::from("[test]"),
+            block_defs,
+        );
+        assert!(module.block_by_id("dirt").unwrap().emission().is_none());
+        assert!(module.block_by_id("lava").unwrap().emission() == Some(200));
+    }
+
+    #[test]
+    fn test_validate_textures() {
+        let dir = std::env::temp_dir().join(format!("voxel-mod-textures-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("module.yaml"), "id: test\nname: Test\n").unwrap();
+        std::fs::write(
+            dir.join("block-definitions.yaml"),
+            "\
+dirt:
+  texture: dirt.png
+  color: \"#8f563b\"
+  homogeneous: true
+stone:
+  texture: textures/stone.png
+  color: \"#808080\"
+  homogeneous: true
+",
+        )
+        .unwrap();
+        std::fs::write(dir.join("dirt.png"), "").unwrap();
+
+        let module = Module::load_from_path(dir.clone()).unwrap();
+        let err = module.validate_textures().unwrap_err().to_string();
+        assert!(err.contains("block \"stone\""), "{}", err);
+        assert!(err.contains("stone.png"), "{}", err);
+        assert!(!err.contains("dirt"), "{}", err);
+
+        std::fs::create_dir(dir.join("textures")).unwrap();
+        std::fs::write(dir.join("textures").join("stone.png"), "").unwrap();
+        module.validate_textures().unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("voxel-mod-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("module.yaml"), "id: test\nname: Test\n").unwrap();
+        let dirt = "\
+dirt:
+  texture: dirt.png
+  color: \"#8f563b\"
+  homogeneous: true
+";
+        let sand = "\
+sand:
+  texture: sand.png
+  color: \"#e0d090\"
+  homogeneous: true
+";
+        let block_defs_path = dir.join("block-definitions.yaml");
+        std::fs::write(&block_defs_path, format!("{}{}", dirt, sand)).unwrap();
+        let module = Module::load_from_path(dir.clone()).unwrap();
+        let old_dirt = module.block_by_id("dirt").unwrap();
+        let old_sand = module.block_by_id("sand").unwrap();
+
+        // Add stone, & change sand's color.
+        std::fs::write(
+            &block_defs_path,
+            format!(
+                "{}{}{}",
+                dirt,
+                sand.replace("e0d090", "f0e0a0"),
+                "stone:\n  texture: stone.png\n  color: \"#808080\"\n  homogeneous: true\n",
+            ),
+        )
+        .unwrap();
+        module.reload().unwrap();
+        assert!(module.block_ids() == ["dirt", "sand", "stone"]);
+        assert!(Arc::ptr_eq(&module.block_by_id("dirt").unwrap(), &old_dirt));
+        let new_sand = module.block_by_id("sand").unwrap();
+        assert!(!Arc::ptr_eq(&new_sand, &old_sand));
+        assert!(new_sand.definition().color().r == 0xf0);
+        let stone = module.block_by_id("stone").unwrap();
+        assert!(Arc::ptr_eq(&stone.module(), &module));
+
+        // A broken file leaves the module as it was.
+        std::fs::write(&block_defs_path, "dirt: [").unwrap();
+        assert!(module.reload().is_err());
+        assert!(module.block_ids() == ["dirt", "sand", "stone"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_toml() {
+        let dir = std::env::temp_dir().join(format!("voxel-mod-toml-{}", std::process::id()));
+        let yaml_dir = dir.join("yaml");
+        let toml_dir = dir.join("toml");
+        std::fs::create_dir_all(&yaml_dir).unwrap();
+        std::fs::create_dir_all(&toml_dir).unwrap();
+        std::fs::write(yaml_dir.join("module.yml"), "id: test\nname: Test\n").unwrap();
+        std::fs::write(
+            yaml_dir.join("block-definitions.yaml"),
+            "\
+dirt:
+  texture: dirt.png
+  color: {r: 143, g: 86, b: 59}
+  homogeneous: true
+lava:
+  texture: lava.png
+  color: \"#ff6000\"
+  homogeneous: true
+  emission: 200
+",
+        )
+        .unwrap();
+        std::fs::write(
+            toml_dir.join("module.toml"),
+            "id = \"test\"\nname = \"Test\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            toml_dir.join("block-definitions.toml"),
+            "\
+[dirt]
+texture = \"dirt.png\"
+color = {r = 143, g = 86, b = 59}
+homogeneous = true
+
+[lava]
+texture = \"lava.png\"
+color = \"#ff6000\"
+homogeneous = true
+emission = 200
+",
+        )
+        .unwrap();
+
+        let from_yaml = Module::load_from_path(yaml_dir.clone()).unwrap();
+        let from_toml = Module::load_from_path(toml_dir.clone()).unwrap();
+        assert!(from_toml.id() == from_yaml.id());
+        assert!(from_toml.display_name == from_yaml.display_name);
+        assert!(from_toml.block_ids() == ["dirt", "lava"]);
+        assert!(from_toml.block_ids() == from_yaml.block_ids());
+        for id in from_yaml.block_ids() {
+            let yaml_block = from_yaml.block_by_id(&id).unwrap();
+            let toml_block = from_toml.block_by_id(&id).unwrap();
+            assert!(yaml_block.definition() == toml_block.definition());
+        }
+
+        // Having both is ambiguous.
+        std::fs::write(toml_dir.join("module.yaml"), "id: other\nname: Other\n").unwrap();
+        let err = Module::load_from_path(toml_dir).unwrap_err().to_string();
+        assert!(err.contains("more than one"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
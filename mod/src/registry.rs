@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+
+use super::module::{Module, ModuleBlockDefinition};
+
+/// The loaded modules, by ID.
+#[derive(Debug, Default)]
+pub struct ModuleRegistry {
+    modules: HashMap<String, Arc<Module>>,
+}
+
+impl ModuleRegistry {
+    pub fn new() -> ModuleRegistry {
+        ModuleRegistry::default()
+    }
+
+    /// Add a module to the registry. It's an error if a module with the same ID is already
+    /// registered.
+    pub fn register(&mut self, module: Arc<Module>) -> anyhow::Result<()> {
+        if let Some(existing) = self.modules.get(module.id()) {
+            anyhow::bail!(
+                "module ID {:?} is used by both {} and {}",
+                module.id(),
+                existing.path().display(),
+                module.path().display(),
+            );
+        }
+        self.modules.insert(module.id().to_owned(), module);
+        Ok(())
+    }
+
+    /// Load each subdirectory of `path` as a module, and register it.
+    pub fn load_dir(&mut self, path: &Path) -> anyhow::Result<()> {
+        let mut module_paths = Vec::new();
+        let entries = std::fs::read_dir(path)
+            .with_context(|| format!("failed to list modules in {}", path.display()))?;
+        for entry in entries {
+            let entry =
+                entry.with_context(|| format!("failed to list modules in {}", path.display()))?;
+            if entry.file_type()?.is_dir() {
+                module_paths.push(entry.path());
+            }
+        }
+        // Load in a consistent order, so that which module is reported as a duplicate is, too.
+        module_paths.sort();
+        for module_path in module_paths {
+            let module = Module::load_from_path(module_path)?;
+            self.register(module)?;
+        }
+        Ok(())
+    }
+
+    pub fn module(&self, id: &str) -> Option<Arc<Module>> {
+        self.modules.get(id).cloned()
+    }
+
+    /// Every block, from every module, in no particular order.
+    pub fn iter_blocks(&self) -> impl Iterator<Item = Arc<ModuleBlockDefinition>> + '_ {
+        self.modules.values().flat_map(|module| module.blocks())
+    }
+
+    /// Look up a block by its full ID, `"module:block"`; e.g., `"test:dirt"`.
+    pub fn block_by_full_id(&self, full_id: &str) -> Option<Arc<ModuleBlockDefinition>> {
+        let (module_id, block_id) = full_id.split_once(':')?;
+        self.modules.get(module_id)?.block_by_id(block_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use super::ModuleRegistry;
+    use crate::block_defs::load_block_definitions;
+    use crate::Module;
+
+    fn module(id: &str, block_defs: &str) -> Arc<Module> {
+        Module::new(
+            id.to_owned(),
+            id.to_owned(),
+            PathBuf::from("[test]"),
+            load_block_definitions(block_defs.as_bytes()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_block_by_full_id() {
+        let mut registry = ModuleRegistry::new();
+        registry
+            .register(module(
+                "test",
+                "dirt:\n  texture: dirt.png\n  color: \"#8f563b\"\n  homogeneous: true\n",
+            ))
+            .unwrap();
+
+        let dirt = registry.block_by_full_id("test:dirt").unwrap();
+        assert!(dirt.id() == "dirt");
+        assert!(dirt.module().id() == "test");
+
+        assert!(registry.block_by_full_id("other:dirt").is_none());
+        assert!(registry.block_by_full_id("test:stone").is_none());
+        assert!(registry.block_by_full_id("dirt").is_none());
+    }
+
+    /// Write a module, with one block, to `dir`.
+    fn write_module(dir: &Path, id: &str, block_id: &str) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(
+            dir.join("module.yaml"),
+            format!("id: {}\nname: {}\n", id, id),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("block-definitions.yaml"),
+            format!(
+                "{}:\n  texture: {}.png\n  color: \"#808080\"\n  homogeneous: true\n",
+                block_id, block_id
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_load_dir() {
+        let dir = std::env::temp_dir().join(format!("voxel-mod-test-{}", std::process::id()));
+        write_module(&dir.join("a"), "first", "dirt");
+        write_module(&dir.join("b"), "second", "stone");
+        // Not a module; should be skipped.
+        std::fs::write(dir.join("README"), "").unwrap();
+
+        let mut registry = ModuleRegistry::new();
+        registry.load_dir(&dir).unwrap();
+        assert!(registry.module("first").unwrap().id() == "first");
+        assert!(registry.module("third").is_none());
+        assert!(registry.block_by_full_id("first:dirt").is_some());
+        assert!(registry.block_by_full_id("second:stone").is_some());
+        let mut blocks = registry
+            .iter_blocks()
+            .map(|block| format!("{}:{}", block.module().id(), block.id()))
+            .collect::<Vec<_>>();
+        blocks.sort();
+        assert!(blocks == ["first:dirt", "second:stone"]);
+
+        // A second module claiming an ID that's already taken is an error.
+        write_module(&dir.join("c"), "first", "sand");
+        let err = ModuleRegistry::new().load_dir(&dir).unwrap_err();
+        assert!(err.to_string().contains("\"first\""), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
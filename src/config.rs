@@ -0,0 +1,117 @@
+//! Settings loaded from a config file, for things that would otherwise be hard-coded.
+
+use std::path::Path;
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+/// The contents of the config file. Anything missing from the file takes its default value.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The window's initial size, in pixels.
+    pub window_width: u32,
+    pub window_height: u32,
+    /// Use the GPU with this name (as shown by `--list-devices`).
+    pub gpu_name: Option<String>,
+    /// Use the GPU with this UUID (as shown by `--list-devices`).
+    pub gpu_uuid: Option<Uuid>,
+    /// The vertical field of view, in degrees.
+    pub fov: f32,
+    /// How far the camera moves per step; holding W, or the left stick, moves it several steps a
+    /// second.
+    pub move_speed: f32,
+    /// How many degrees the camera turns per pixel of mouse motion.
+    pub mouse_sensitivity: f32,
+    /// The color of the background (the sky), as RGBA, each from 0 to 1.
+    pub clear_color: [f32; 4],
+}
+
+/// The default background: a blue sky.
+pub const DEFAULT_CLEAR_COLOR: [f32; 4] = [0.0, 0.25, 1.0, 1.0];
+
+/// The default vertical field of view, in degrees.
+pub const DEFAULT_FOV: f32 = 90.;
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            window_width: 800,
+            window_height: 600,
+            gpu_name: None,
+            gpu_uuid: None,
+            fov: DEFAULT_FOV,
+            move_speed: 1.,
+            mouse_sensitivity: 1.,
+            clear_color: DEFAULT_CLEAR_COLOR,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file at `path`; if there's no such file, the defaults are used.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        match std::fs::File::open(path) {
+            Ok(file) => Config::from_reader(std::io::BufReader::new(file)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(ConfigError::Read(err)),
+        }
+    }
+
+    /// Parse a config file's contents, as YAML.
+    pub fn from_reader<R: std::io::Read>(reader: R) -> Result<Config, ConfigError> {
+        Ok(serde_yaml::from_reader(reader)?)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read the config file: {0}")]
+    Read(#[source] std::io::Error),
+    #[error("failed to parse the config file: {0}")]
+    Parse(#[from] serde_yaml::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::from_reader(
+            "\
+window_width: 1920
+window_height: 1080
+gpu_name: Some GPU
+fov: 75
+mouse_sensitivity: 0.5
+clear_color: [0.5, 0.5, 0.5, 1]
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        let expected = Config {
+            window_width: 1920,
+            window_height: 1080,
+            gpu_name: Some("Some GPU".to_owned()),
+            fov: 75.,
+            mouse_sensitivity: 0.5,
+            clear_color: [0.5, 0.5, 0.5, 1.],
+            ..Config::default()
+        };
+        assert_eq!(config, expected);
+
+        // Setting nothing means all defaults; a typo'd setting is an error, not silently ignored.
+        assert_eq!(
+            Config::from_reader("{}".as_bytes()).unwrap(),
+            Config::default()
+        );
+        assert!(Config::from_reader("fvo: 75".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_missing_config_file() {
+        let config = Config::load(std::path::Path::new("no/such/config.yaml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+}
@@ -0,0 +1,150 @@
+//! Line segments drawn with the lines pipeline, for showing things like bounding boxes, rays &
+//! normals, as well as the scene's grid.
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, TypedBufferAccess};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::memory::allocator::MemoryAllocator;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+
+use crate::matrix::Vertex3d;
+use crate::Line;
+
+/// Collects line segments on the CPU, to be uploaded as [`DebugLines`].
+#[derive(Default)]
+pub(crate) struct DebugLinesBuilder {
+    vertices: Vec<Line>,
+}
+
+impl DebugLinesBuilder {
+    pub fn new() -> DebugLinesBuilder {
+        DebugLinesBuilder::default()
+    }
+
+    /// Add a line from `from` to `to`, of a single (RGB) color.
+    pub fn add_line(&mut self, from: Vertex3d, to: Vertex3d, color: [f32; 3]) -> &mut Self {
+        self.vertices.push(Line {
+            position: [from.x, from.y, from.z],
+            color,
+        });
+        self.vertices.push(Line {
+            position: [to.x, to.y, to.z],
+            color,
+        });
+        self
+    }
+
+    /// The number of lines added so far.
+    pub fn len(&self) -> usize {
+        self.vertices.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// Upload the lines into a vertex buffer, or `None` if there are none (as a buffer can't be
+    /// empty).
+    pub fn build(self, memory_allocator: &(impl MemoryAllocator + ?Sized)) -> Option<DebugLines> {
+        if self.is_empty() {
+            return None;
+        }
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(
+            memory_allocator,
+            BufferUsage {
+                vertex_buffer: true,
+                ..BufferUsage::empty()
+            },
+            false,
+            self.vertices.into_iter(),
+        )
+        .unwrap();
+        Some(DebugLines { vertex_buffer })
+    }
+}
+
+/// Line segments that have been uploaded to the GPU, two vertexes per line.
+pub(crate) struct DebugLines {
+    vertex_buffer: Arc<CpuAccessibleBuffer<[Line]>>,
+}
+
+impl DebugLines {
+    /// The number of lines.
+    pub fn len(&self) -> usize {
+        usize::try_from(self.vertex_buffer.len()).unwrap() / 2
+    }
+}
+
+pub(crate) trait DebugLinesAutoCmdExt {
+    fn draw_lines(
+        &mut self,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        lines: &DebugLines,
+    ) -> &mut Self;
+}
+
+impl<L> DebugLinesAutoCmdExt for AutoCommandBufferBuilder<L> {
+    fn draw_lines(
+        &mut self,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        lines: &DebugLines,
+    ) -> &mut AutoCommandBufferBuilder<L> {
+        let layout = pipeline.layout().clone();
+        self.bind_pipeline_graphics(pipeline)
+            .bind_descriptor_sets(PipelineBindPoint::Graphics, layout, 0, descriptor_set)
+            .bind_vertex_buffers(0, lines.vertex_buffer.clone())
+            .draw(
+                u32::try_from(lines.vertex_buffer.len()).unwrap(),
+                1, // instance_count
+                0, // first_vertex
+                0, // first_instance
+            )
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vulkano::buffer::TypedBufferAccess;
+    use vulkano::memory::allocator::StandardMemoryAllocator;
+
+    use super::DebugLinesBuilder;
+    use crate::matrix::Vertex3d;
+
+    #[test]
+    fn test_debug_lines() {
+        let mut builder = DebugLinesBuilder::new();
+        assert!(builder.is_empty());
+        builder
+            .add_line(
+                Vertex3d::new(0., 0., 0.),
+                Vertex3d::new(1., 0., 0.),
+                [1., 0., 0.],
+            )
+            .add_line(
+                Vertex3d::new(0., 0., 0.),
+                Vertex3d::new(0., 1., 0.),
+                [0., 1., 0.],
+            )
+            .add_line(
+                Vertex3d::new(0., 0., 0.),
+                Vertex3d::new(0., 0., 1.),
+                [0., 0., 1.],
+            );
+        assert_eq!(builder.len(), 3);
+        assert_eq!(builder.vertices[3].position, [0., 1., 0.]);
+        assert_eq!(builder.vertices[3].color, [0., 1., 0.]);
+
+        let (device, _) = crate::init::test_device!();
+        let memory_allocator = StandardMemoryAllocator::new_default(device);
+        assert!(DebugLinesBuilder::new().build(&memory_allocator).is_none());
+        let lines = builder.build(&memory_allocator).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines.vertex_buffer.len(), 6);
+    }
+}
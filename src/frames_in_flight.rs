@@ -0,0 +1,78 @@
+//! Tracking the frames that have been submitted to the GPU, so that the CPU can record the next
+//! frame while the GPU is still drawing the last, but without getting arbitrarily far ahead.
+
+use std::collections::VecDeque;
+
+/// The frames (e.g., the fences signalled when they finish) that might still be executing on the
+/// GPU, oldest first; at most `max` of them.
+pub struct FramesInFlight<F> {
+    frames: VecDeque<F>,
+    max: usize,
+}
+
+impl<F> FramesInFlight<F> {
+    /// Allow up to `max` frames in flight at once.
+    pub fn new(max: usize) -> FramesInFlight<F> {
+        assert!(
+            0 < max,
+            "FramesInFlight needs to allow at least 1 frame in flight"
+        );
+        FramesInFlight {
+            frames: VecDeque::with_capacity(max),
+            max,
+        }
+    }
+
+    /// Make room for another frame. If there are already `max` in flight, the oldest is removed
+    /// & returned; the caller must wait for it to finish before submitting the next.
+    pub fn make_room(&mut self) -> Option<F> {
+        match self.frames.len() < self.max {
+            true => None,
+            false => self.frames.pop_front(),
+        }
+    }
+
+    /// Track a newly submitted frame. There must be room for it; see
+    /// [`make_room`](Self::make_room).
+    pub fn push(&mut self, frame: F) {
+        assert!(
+            self.frames.len() < self.max,
+            "more than {} frames in flight",
+            self.max
+        );
+        self.frames.push_back(frame);
+    }
+
+    /// The most recently submitted frame, which the next frame should follow.
+    pub fn newest(&self) -> Option<&F> {
+        self.frames.back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FramesInFlight;
+
+    #[test]
+    fn test_frames_in_flight() {
+        let mut in_flight = FramesInFlight::new(2);
+        assert!(in_flight.newest().is_none());
+        for frame in 0..10u32 {
+            // Once the ring is full, each new frame waits on the oldest.
+            let waited_on = in_flight.make_room();
+            assert_eq!(waited_on, frame.checked_sub(2));
+            in_flight.push(frame);
+            assert!(in_flight.frames.len() <= in_flight.max);
+            assert_eq!(in_flight.newest(), Some(&frame));
+        }
+        assert_eq!(in_flight.frames.len(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_too_many_frames_in_flight() {
+        let mut in_flight = FramesInFlight::new(1);
+        in_flight.push(0);
+        in_flight.push(1);
+    }
+}
@@ -0,0 +1,145 @@
+//! Game controller input: the left stick moves the camera, and the right stick turns it.
+
+use log::{info, warn};
+use sdl2::controller::{Axis, GameController};
+use sdl2::GameControllerSubsystem;
+
+/// How far (as a fraction of full deflection) a stick must be pushed before it registers, so that
+/// sticks which don't quite return to center don't drift the camera.
+pub const DEADZONE: f32 = 0.15;
+
+/// How many steps (the configured `move_speed`) per second the camera moves with the left stick
+/// fully deflected.
+pub const STEPS_PER_SECOND: f32 = 4.;
+
+/// How many degrees the camera turns per second with the right stick fully deflected.
+pub const TURN_SPEED: f32 = 120.;
+
+/// Convert a raw SDL axis value to a deflection in [-1, 1], ignoring anything within `deadzone` of
+/// center. Past the deadzone, the deflection is rescaled to start from 0, so there's no jump at
+/// its edge.
+pub fn axis_to_delta(value: i16, deadzone: f32) -> f32 {
+    // i16::MIN is one further from center than i16::MAX.
+    let value = (f32::from(value) / f32::from(i16::MAX)).max(-1.);
+    let magnitude = value.abs();
+    if magnitude <= deadzone {
+        return 0.;
+    }
+    value.signum() * (magnitude - deadzone) / (1. - deadzone)
+}
+
+/// The sticks' positions, as last reported by SDL.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sticks {
+    left_x: i16,
+    left_y: i16,
+    right_x: i16,
+    right_y: i16,
+}
+
+impl Sticks {
+    pub fn axis_moved(&mut self, axis: Axis, value: i16) {
+        match axis {
+            Axis::LeftX => self.left_x = value,
+            Axis::LeftY => self.left_y = value,
+            Axis::RightX => self.right_x = value,
+            Axis::RightY => self.right_y = value,
+            Axis::TriggerLeft | Axis::TriggerRight => (),
+        }
+    }
+
+    /// The left stick's `(x, y)` deflection, past the deadzone. Y points down.
+    pub fn left(&self) -> (f32, f32) {
+        (
+            axis_to_delta(self.left_x, DEADZONE),
+            axis_to_delta(self.left_y, DEADZONE),
+        )
+    }
+
+    /// The right stick's `(x, y)` deflection, past the deadzone. Y points down.
+    pub fn right(&self) -> (f32, f32) {
+        (
+            axis_to_delta(self.right_x, DEADZONE),
+            axis_to_delta(self.right_y, DEADZONE),
+        )
+    }
+}
+
+/// How far to move the camera, as `(x, z)`, for the left stick's deflection over `dt` seconds, in
+/// steps of `step` long. Pushing the stick up moves along `bearing`, as the W key does.
+pub fn movement(stick: (f32, f32), bearing: f32, step: f32, dt: f32) -> (f32, f32) {
+    let speed = step * STEPS_PER_SECOND * dt;
+    let forward = -stick.1 * speed;
+    let strafe = stick.0 * speed;
+    (
+        forward * bearing.cos() - strafe * bearing.sin(),
+        forward * bearing.sin() + strafe * bearing.cos(),
+    )
+}
+
+/// The open game controllers; SDL only sends events for controllers that have been opened.
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    open: Vec<GameController>,
+    pub sticks: Sticks,
+}
+
+impl Controllers {
+    pub fn new(subsystem: GameControllerSubsystem) -> Controllers {
+        Controllers {
+            subsystem,
+            open: Vec::new(),
+            sticks: Sticks::default(),
+        }
+    }
+
+    /// Open the controller at joystick index `which`, as reported by a `ControllerDeviceAdded`
+    /// event. (SDL sends these for controllers already connected at startup, too.)
+    pub fn device_added(&mut self, which: u32) {
+        match self.subsystem.open(which) {
+            Ok(controller) => {
+                info!("Opened game controller {:?}.", controller.name());
+                self.open.push(controller);
+            }
+            Err(err) => warn!("failed to open game controller {}: {}", which, err),
+        }
+    }
+
+    /// Close the controller with instance ID `which`, as reported by a `ControllerDeviceRemoved`
+    /// event.
+    pub fn device_removed(&mut self, which: u32) {
+        self.open
+            .retain(|controller| controller.instance_id() != which);
+        if self.open.is_empty() {
+            // Don't leave the camera moving on the last reported stick positions.
+            self.sticks = Sticks::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_axis_to_delta() {
+        use super::axis_to_delta;
+
+        let deadzone = 0.25;
+        assert!(axis_to_delta(0, deadzone) == 0.);
+        assert!(axis_to_delta(i16::MAX / 4, deadzone) == 0.);
+        assert!(axis_to_delta(-i16::MAX / 4, deadzone) == 0.);
+        assert!(axis_to_delta(i16::MAX, deadzone) == 1.);
+        assert!(axis_to_delta(i16::MIN, deadzone) == -1.);
+        // Halfway between the deadzone & full deflection:
+        let half = axis_to_delta(i16::MAX / 8 * 5, deadzone);
+        assert!((half - 0.5).abs() < 0.001);
+        assert!(axis_to_delta(-(i16::MAX / 8 * 5), deadzone) == -half);
+    }
+
+    #[test]
+    fn test_movement() {
+        let (x, z) = super::movement((0., -1.), 0., 2., 0.5);
+        assert!((x - super::STEPS_PER_SECOND).abs() < 0.001);
+        assert!(z.abs() < 0.001);
+        assert!(super::movement((0., 0.), 1., 2., 0.5) == (0., 0.));
+    }
+}
@@ -6,19 +6,28 @@ use std::mem::ManuallyDrop;
 use std::sync::Arc;
 
 use ash::vk::Handle as AshHandle;
-use log::{debug, info, trace};
+use log::{debug, info, trace, warn};
 use sdl2::video::Window;
 use smallvec::SmallVec;
 use uuid::Uuid;
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
-use vulkano::device::{Device, Features, Queue, QueueCreateInfo};
-use vulkano::image::{ImageUsage, SwapchainImage};
+use vulkano::device::physical::{PhysicalDevice, SampleCounts};
+use vulkano::device::{Device, DeviceExtensions, Features, Queue, QueueCreateInfo};
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::view::{ImageView, ImageViewAbstract, ImageViewCreationError};
+use vulkano::image::{
+    AttachmentImage, ImageAccess, ImageError, ImageUsage, SampleCount, SwapchainImage,
+};
 use vulkano::instance::{self, Instance, InstanceExtensions};
 use vulkano::library::VulkanLibrary;
 use vulkano::memory::allocator::StandardMemoryAllocator;
-use vulkano::render_pass::RenderPass;
-use vulkano::swapchain::{Surface, SurfaceApi, Swapchain, SwapchainCreateInfo};
+use vulkano::render_pass::{
+    Framebuffer, FramebufferCreateInfo, FramebufferCreationError, RenderPass,
+};
+use vulkano::swapchain::{
+    ColorSpace, PresentMode, Surface, SurfaceApi, Swapchain, SwapchainCreateInfo,
+};
 use vulkano::VulkanObject;
 
 pub struct Init {
@@ -27,6 +36,8 @@ pub struct Init {
     pub vulkan_device: Arc<Device>,
     pub queue: Arc<Queue>,
     pub event_pump: sdl2::EventPump,
+    /// `None` if SDL's game controller support failed to initialize.
+    pub game_controller: Option<sdl2::GameControllerSubsystem>,
 
     surface: ManuallyDrop<Arc<Surface>>,
     window: ManuallyDrop<Window>,
@@ -60,17 +71,58 @@ impl Drop for Init {
     }
 }
 
-pub fn init_sdl_and_vulkan(select_device: Option<Uuid>) -> Init {
+/// Which GPU to use. Devices must match every field that is set; if none are, the first device
+/// found is used.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceSelection {
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+}
+
+impl DeviceSelection {
+    fn matches(&self, physical_device: &PhysicalDevice) -> bool {
+        let properties = physical_device.properties();
+        let uuid_matches = self.uuid.map_or(true, |uuid| {
+            properties
+                .device_uuid
+                .as_ref()
+                .map(|id| Uuid::from_slice(id).unwrap())
+                == Some(uuid)
+        });
+        let name_matches = self
+            .name
+            .as_ref()
+            .map_or(true, |name| *name == properties.device_name);
+        uuid_matches && name_matches
+    }
+}
+
+pub fn init_sdl_and_vulkan(
+    select_device: &DeviceSelection,
+    window_size: (u32, u32),
+    validation: bool,
+) -> Init {
     let sdl_context = sdl2::init().expect("Failed to initialize SDL.");
     debug!("SDL initialized.");
 
     // Event pump
     let event_pump = sdl_context.event_pump().unwrap();
 
+    let game_controller = match sdl_context.game_controller() {
+        Ok(subsystem) => Some(subsystem),
+        Err(err) => {
+            warn!(
+                "Failed to initialize SDL's game controller subsystem: {}",
+                err
+            );
+            None
+        }
+    };
+
     let video_subsystem = sdl_context.video().unwrap();
     trace!("SDL video subsystem initialized.");
     let window = video_subsystem
-        .window("Voxel", 800, 600)
+        .window("Voxel", window_size.0, window_size.1)
         .vulkan()
         .resizable()
         .build()
@@ -79,7 +131,16 @@ pub fn init_sdl_and_vulkan(select_device: Option<Uuid>) -> Init {
     let instance_extensions = window.vulkan_instance_extensions().unwrap();
     let instance_extensions = InstanceExtensions::from_iter(instance_extensions);
 
-    let (instance, device, queue) = init_vulkan(instance_extensions, select_device);
+    let device_extensions = DeviceExtensions {
+        khr_swapchain: true,
+        ..DeviceExtensions::empty()
+    };
+    let (instance, device, queue) = init_vulkan(
+        instance_extensions,
+        device_extensions,
+        select_device,
+        validation,
+    );
 
     trace!("Creating surface in SDL.");
     let surface_handle = window
@@ -120,12 +181,22 @@ pub fn init_sdl_and_vulkan(select_device: Option<Uuid>) -> Init {
         window: ManuallyDrop::new(window),
         surface,
         event_pump,
+        game_controller,
     }
 }
 
+/// The format of the depth buffer. Every implementation supports this as a depth attachment.
+const DEPTH_FORMAT: Format = Format::D16_UNORM;
+
 pub struct RenderDetails {
     pub swapchain: Arc<Swapchain>,
     pub swapchain_images: Vec<Arc<SwapchainImage>>,
+    /// The framebuffers for the swapchain images; rebuilt only when the swapchain is.
+    pub targets: FrameTargets,
+    /// The size of the swapchain images, in pixels.
+    pub dimensions: [u32; 2],
+    /// The number of samples per pixel the render pass (and so, every pipeline) uses.
+    pub samples: SampleCount,
     pub render_pass: Arc<RenderPass>,
     pub memory_allocator: Arc<StandardMemoryAllocator>,
     pub descriptor_set_allocator: StandardDescriptorSetAllocator,
@@ -138,21 +209,145 @@ pub enum RenderDetailsError {
     FailedToQuerySurfaceCapabilities(vulkano::device::physical::PhysicalDeviceError),
     #[error("failed to query surface formats: {0}")]
     FailedToQuerySurfaceFormats(vulkano::device::physical::PhysicalDeviceError),
+    #[error("failed to query surface present modes: {0}")]
+    FailedToQueryPresentModes(vulkano::device::physical::PhysicalDeviceError),
     #[error("the surface's .current_extent was None; we expect the surface to have an extent")]
     ExpectedSurfaceToHaveExtent,
     #[error("failed to create Swapchain: {0}")]
     FailedToCreateSwapchain(vulkano::swapchain::SwapchainCreationError),
     #[error("failed to create RenderPass: {0}")]
     FailedToCreateRenderPass(vulkano::render_pass::RenderPassCreationError),
+    #[error("failed to create depth buffer: {0}")]
+    FailedToCreateDepthBuffer(ImageError),
+    #[error("failed to create depth buffer view: {0}")]
+    FailedToCreateDepthBufferView(ImageViewCreationError),
+    #[error("failed to create multisampled color buffer: {0}")]
+    FailedToCreateMultisampledBuffer(ImageError),
+    #[error("failed to create multisampled color buffer view: {0}")]
+    FailedToCreateMultisampledBufferView(ImageViewCreationError),
+    #[error("failed to create swapchain image view: {0}")]
+    FailedToCreateSwapchainImageView(ImageViewCreationError),
+    #[error("failed to create offscreen color buffer: {0}")]
+    FailedToCreateOffscreenBuffer(ImageError),
+    #[error("failed to create offscreen color buffer view: {0}")]
+    FailedToCreateOffscreenBufferView(ImageViewCreationError),
+    #[error("failed to create Framebuffer: {0}")]
+    FailedToCreateFramebuffer(FramebufferCreationError),
+}
+
+/// The images, besides the swapchain image itself, that a frame is rendered into.
+pub struct FrameAttachments {
+    /// When multisampling, the image that's rendered into, & then resolved into the swapchain
+    /// image.
+    pub multisampled_color: Option<Arc<ImageView<AttachmentImage>>>,
+    pub depth: Arc<ImageView<AttachmentImage>>,
+}
+
+impl FrameAttachments {
+    /// The framebuffer's attachments, in the render pass's order, given the image the frame ends
+    /// up in (e.g., a swapchain image).
+    pub fn framebuffer_attachments(
+        &self,
+        color: Arc<dyn ImageViewAbstract>,
+    ) -> Vec<Arc<dyn ImageViewAbstract>> {
+        match &self.multisampled_color {
+            Some(multisampled_color) => vec![multisampled_color.clone(), color, self.depth.clone()],
+            None => vec![color, self.depth.clone()],
+        }
+    }
+
+    /// The values to clear the framebuffer's attachments to, in the render pass's order.
+    pub fn clear_values(&self, color: ClearValue) -> Vec<Option<ClearValue>> {
+        // The depth buffer is cleared to the far plane.
+        let depth = ClearValue::Depth(1.0);
+        match self.multisampled_color {
+            // The swapchain image is only resolved into, so it isn't cleared.
+            Some(_) => vec![Some(color), None, Some(depth)],
+            None => vec![Some(color), Some(depth)],
+        }
+    }
+}
+
+/// A framebuffer for each of a set of images that frames end up in (e.g., a swapchain's), along
+/// with the other images rendered into alongside each.
+pub struct FrameTargets {
+    /// A framebuffer for each image (at the same index).
+    pub framebuffers: Vec<Arc<Framebuffer>>,
+    /// The other images rendered into alongside each image (at the same index).
+    pub frame_attachments: Vec<FrameAttachments>,
+    /// Incremented each time the framebuffers are rebuilt.
+    generation: u64,
+}
+
+impl FrameTargets {
+    fn new<I: ImageAccess + 'static>(
+        render_pass: &Arc<RenderPass>,
+        memory_allocator: &StandardMemoryAllocator,
+        images: &[Arc<I>],
+        format: Format,
+        samples: SampleCount,
+    ) -> Result<FrameTargets, RenderDetailsError> {
+        let frame_attachments =
+            create_frame_attachments(memory_allocator, images, format, samples)?;
+        let framebuffers = create_framebuffers(render_pass, images, &frame_attachments)?;
+        Ok(FrameTargets {
+            framebuffers,
+            frame_attachments,
+            generation: 0,
+        })
+    }
+
+    /// Build the framebuffers afresh, for a new set of images (e.g., once the swapchain has been
+    /// recreated).
+    fn rebuild<I: ImageAccess + 'static>(
+        &mut self,
+        render_pass: &Arc<RenderPass>,
+        memory_allocator: &StandardMemoryAllocator,
+        images: &[Arc<I>],
+        format: Format,
+        samples: SampleCount,
+    ) -> Result<(), RenderDetailsError> {
+        let rebuilt = FrameTargets::new(render_pass, memory_allocator, images, format, samples)?;
+        self.framebuffers = rebuilt.framebuffers;
+        self.frame_attachments = rebuilt.frame_attachments;
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// The number of times the framebuffers have been rebuilt since they were first built.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
 }
 
 impl RenderDetails {
+    /// `samples` is the requested number of samples per pixel for multisampling; if the device
+    /// doesn't support it, we fall back to no multisampling. Likewise, if the surface doesn't
+    /// support `present_mode`, we fall back to FIFO (i.e., vsync).
     pub fn init(
         device: Arc<Device>,
         surface: Arc<Surface>,
+        samples: SampleCount,
+        present_mode: PresentMode,
     ) -> Result<RenderDetails, RenderDetailsError> {
         info!("Creating RenderDetails…");
 
+        let samples = {
+            let properties = device.physical_device().properties();
+            let supported = supported_sample_count(
+                samples,
+                properties.framebuffer_color_sample_counts,
+                properties.framebuffer_depth_sample_counts,
+            );
+            if supported != samples {
+                warn!(
+                    "{:?} multisampling isn't supported; falling back to {:?}",
+                    samples, supported
+                );
+            }
+            supported
+        };
+
         // Swapchain
         let (swapchain, images, format) = {
             trace!("Querying surface capabilities");
@@ -176,24 +371,36 @@ impl RenderDetails {
                 Some(limit) => min(max(2, caps.min_image_count), limit),
             };
 
-            // Just use the first format
-            // TODO: Do we need to be more aware of this value, or can we just render into whatever we
-            // get and not care? It seems like we'd *have* to care?
-            let (format, color_space) = supported_formats[0];
-            debug!("[TODO] Selected first format: {:?}", (format, color_space));
+            let (format, color_space) = choose_surface_format(&supported_formats);
+            debug!("Selected format: {:?}", (format, color_space));
 
             // TODO: figure this out
-            // The created swapchain will be used as a color attachment for rendering.
+            // The created swapchain will be used as a color attachment for rendering, and copied
+            // from for screenshots, where supported.
             let usage = ImageUsage {
                 color_attachment: true,
+                transfer_src: caps.supported_usage_flags.transfer_src,
                 ..ImageUsage::empty()
             };
 
+            let supported_present_modes = device
+                .physical_device()
+                .surface_present_modes(&surface)
+                .map_err(RenderDetailsError::FailedToQueryPresentModes)?;
+            let chosen_present_mode = choose_present_mode(present_mode, supported_present_modes);
+            if chosen_present_mode != present_mode {
+                warn!(
+                    "present mode {:?} isn't supported; falling back to {:?}",
+                    present_mode, chosen_present_mode
+                );
+            }
+
             let swapchain_create_info = SwapchainCreateInfo {
                 min_image_count: buffers_count,
                 image_format: Some(format),
                 image_color_space: color_space,
                 image_usage: usage,
+                present_mode: chosen_present_mode,
                 ..Default::default()
             };
             let (swapchain, images) =
@@ -203,33 +410,21 @@ impl RenderDetails {
             (swapchain, images, format)
         };
 
-        // Render pass
-        let render_pass = vulkano::single_pass_renderpass!(
-            device.clone(),
-            attachments: {
-                color: {
-                    load: Clear,
-                    store: Store,
-                    //format: vulkano::format::Format::R8G8B8A8Unorm,
-                    format: format,
-                    samples: 1,
-                }
-            },
-            pass: {
-                color: [color],
-                depth_stencil: {}
-            }
-        )
-        .map_err(RenderDetailsError::FailedToCreateRenderPass)?;
+        let render_pass = create_render_pass(device.clone(), format, samples)?;
 
         let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
         let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device.clone());
         let command_buffer_allocator =
             StandardCommandBufferAllocator::new(device, Default::default());
 
+        let targets = FrameTargets::new(&render_pass, &memory_allocator, &images, format, samples)?;
+        let dimensions = swapchain.image_extent();
         Ok(RenderDetails {
             swapchain,
             swapchain_images: images,
+            targets,
+            dimensions,
+            samples,
             render_pass,
             memory_allocator,
             descriptor_set_allocator,
@@ -237,31 +432,371 @@ impl RenderDetails {
         })
     }
 
-    pub fn recreate_swapchain(
-        &mut self,
-        init: &Init,
-    ) -> Result<bool, vulkano::swapchain::SwapchainCreationError> {
+    /// Recreate the swapchain (e.g., after the window is resized) at the window's current size.
+    /// Returns `false` if it can't be recreated right now, as the window has no area.
+    pub fn recreate_swapchain(&mut self, init: &Init) -> Result<bool, RenderDetailsError> {
         debug!("Recreating swap chain");
-        let create_info = self.swapchain.create_info();
+        let caps = self
+            .swapchain
+            .device()
+            .physical_device()
+            .surface_capabilities(self.swapchain.surface(), Default::default())
+            .map_err(RenderDetailsError::FailedToQuerySurfaceCapabilities)?;
+        let image_extent = match swapchain_extent(
+            init.window().vulkan_drawable_size(),
+            caps.min_image_extent,
+            caps.max_image_extent,
+        ) {
+            Some(image_extent) => image_extent,
+            None => return Ok(false),
+        };
+        let create_info = SwapchainCreateInfo {
+            image_extent,
+            ..self.swapchain.create_info()
+        };
         match self.swapchain.recreate(create_info) {
             Ok((new_swapchain, new_images)) => {
+                self.targets.rebuild(
+                    &self.render_pass,
+                    &self.memory_allocator,
+                    &new_images,
+                    new_swapchain.image_format(),
+                    self.samples,
+                )?;
+                self.dimensions = new_swapchain.image_extent();
                 self.swapchain = new_swapchain;
                 self.swapchain_images = new_images;
                 Ok(true)
             }
             // These happen. Examples ignore them. What exactly is going on here?
             //Err(vulkano::swapchain::SwapchainCreationError::UnsupportedDimensions) => Ok(false),
-            Err(err) => Err(err),
+            Err(err) => Err(RenderDetailsError::FailedToCreateSwapchain(err)),
         }
     }
 }
 
-fn init_vulkan(
-    ext: InstanceExtensions,
-    select_device: Option<Uuid>,
-) -> (Arc<Instance>, Arc<Device>, Arc<Queue>) {
+/// The render pass every frame is drawn with, into an image of `format`, with `samples` samples
+/// per pixel. (The attachment order here must match `FrameAttachments`.)
+fn create_render_pass(
+    device: Arc<Device>,
+    format: Format,
+    samples: SampleCount,
+) -> Result<Arc<RenderPass>, RenderDetailsError> {
+    if samples == SampleCount::Sample1 {
+        vulkano::single_pass_renderpass!(
+            device,
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: Store,
+                    //format: vulkano::format::Format::R8G8B8A8Unorm,
+                    format: format,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: DEPTH_FORMAT,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth}
+            }
+        )
+    } else {
+        vulkano::single_pass_renderpass!(
+            device,
+            attachments: {
+                multisampled_color: {
+                    load: Clear,
+                    store: DontCare,
+                    format: format,
+                    samples: samples as u32,
+                },
+                color: {
+                    load: DontCare,
+                    store: Store,
+                    format: format,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: DEPTH_FORMAT,
+                    samples: samples as u32,
+                }
+            },
+            pass: {
+                color: [multisampled_color],
+                depth_stencil: {depth},
+                resolve: [color],
+            }
+        )
+    }
+    .map_err(RenderDetailsError::FailedToCreateRenderPass)
+}
+
+/// Create a framebuffer for each of the images (e.g., the swapchain's), along with its other
+/// attachments.
+fn create_framebuffers<I: ImageAccess + 'static>(
+    render_pass: &Arc<RenderPass>,
+    images: &[Arc<I>],
+    frame_attachments: &[FrameAttachments],
+) -> Result<Vec<Arc<Framebuffer>>, RenderDetailsError> {
+    debug!("Building framebuffers");
+    images
+        .iter()
+        .zip(frame_attachments.iter())
+        .map(|(image, attachments)| {
+            let image_view = ImageView::new_default(image.clone())
+                .map_err(RenderDetailsError::FailedToCreateSwapchainImageView)?;
+            Framebuffer::new(
+                render_pass.clone(),
+                FramebufferCreateInfo {
+                    attachments: attachments.framebuffer_attachments(image_view),
+                    ..Default::default()
+                },
+            )
+            .map_err(RenderDetailsError::FailedToCreateFramebuffer)
+        })
+        .collect()
+}
+
+/// The size to make the swapchain's images, given the window's drawable size & the surface's
+/// limits, or `None` if the window has no area (e.g., it's minimized).
+fn swapchain_extent(
+    drawable_size: (u32, u32),
+    min_extent: [u32; 2],
+    max_extent: [u32; 2],
+) -> Option<[u32; 2]> {
+    let (width, height) = drawable_size;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some([
+        width.clamp(min_extent[0], max_extent[0]),
+        height.clamp(min_extent[1], max_extent[1]),
+    ])
+}
+
+/// Pick the format to render to the surface in: an 8-bit sRGB format, if the surface supports
+/// one, so that colors are displayed correctly; otherwise, the first format.
+fn choose_surface_format(formats: &[(Format, ColorSpace)]) -> (Format, ColorSpace) {
+    const PREFERRED: [(Format, ColorSpace); 2] = [
+        (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear),
+        (Format::R8G8B8A8_SRGB, ColorSpace::SrgbNonLinear),
+    ];
+    PREFERRED
+        .iter()
+        .find(|preferred| formats.contains(preferred))
+        .copied()
+        .unwrap_or(formats[0])
+}
+
+/// Pick the present mode to use: `requested`, if it's among the `supported` modes; otherwise,
+/// FIFO, which every surface supports.
+fn choose_present_mode(
+    requested: PresentMode,
+    mut supported: impl Iterator<Item = PresentMode>,
+) -> PresentMode {
+    if supported.any(|mode| mode == requested) {
+        requested
+    } else {
+        PresentMode::Fifo
+    }
+}
+
+/// Pick the sample count to use: `requested`, if the device supports it for both color & depth
+/// attachments; otherwise, no multisampling.
+fn supported_sample_count(
+    requested: SampleCount,
+    color_counts: SampleCounts,
+    depth_counts: SampleCounts,
+) -> SampleCount {
+    let supports = |counts: SampleCounts| match requested {
+        SampleCount::Sample1 => counts.sample1,
+        SampleCount::Sample2 => counts.sample2,
+        SampleCount::Sample4 => counts.sample4,
+        SampleCount::Sample8 => counts.sample8,
+        SampleCount::Sample16 => counts.sample16,
+        SampleCount::Sample32 => counts.sample32,
+        SampleCount::Sample64 => counts.sample64,
+    };
+    if supports(color_counts) && supports(depth_counts) {
+        requested
+    } else {
+        SampleCount::Sample1
+    }
+}
+
+/// Create the images to go with each of the images (e.g., the swapchain's).
+fn create_frame_attachments<I: ImageAccess>(
+    memory_allocator: &StandardMemoryAllocator,
+    images: &[Arc<I>],
+    format: Format,
+    samples: SampleCount,
+) -> Result<Vec<FrameAttachments>, RenderDetailsError> {
+    images
+        .iter()
+        .map(|image| {
+            let dimensions = image.dimensions().width_height();
+            create_frame_attachment(memory_allocator, dimensions, format, samples)
+        })
+        .collect()
+}
+
+/// Create the images to go with an image of `dimensions` & `format` that a frame is rendered into.
+fn create_frame_attachment(
+    memory_allocator: &StandardMemoryAllocator,
+    dimensions: [u32; 2],
+    format: Format,
+    samples: SampleCount,
+) -> Result<FrameAttachments, RenderDetailsError> {
+    let multisampled_color = if samples == SampleCount::Sample1 {
+        None
+    } else {
+        let image =
+            AttachmentImage::transient_multisampled(memory_allocator, dimensions, samples, format)
+                .map_err(RenderDetailsError::FailedToCreateMultisampledBuffer)?;
+        let view = ImageView::new_default(image)
+            .map_err(RenderDetailsError::FailedToCreateMultisampledBufferView)?;
+        Some(view)
+    };
+    let depth_image = AttachmentImage::transient_multisampled(
+        memory_allocator,
+        dimensions,
+        samples,
+        DEPTH_FORMAT,
+    )
+    .map_err(RenderDetailsError::FailedToCreateDepthBuffer)?;
+    let depth = ImageView::new_default(depth_image)
+        .map_err(RenderDetailsError::FailedToCreateDepthBufferView)?;
+    Ok(FrameAttachments {
+        multisampled_color,
+        depth,
+    })
+}
+
+/// The format of the image an [`Offscreen`] target renders into. (Which screenshots can read.)
+const OFFSCREEN_FORMAT: Format = Format::R8G8B8A8_SRGB;
+
+/// Everything needed to render frames into an image of our own, rather than a swapchain's; i.e.,
+/// without a window.
+pub struct Offscreen {
+    /// The image each frame ends up in; it can be copied out (e.g., by a screenshot).
+    pub image: Arc<AttachmentImage>,
+    pub framebuffer: Arc<Framebuffer>,
+    pub frame_attachments: FrameAttachments,
+    pub dimensions: [u32; 2],
+    /// The number of samples per pixel the render pass (and so, every pipeline) uses.
+    pub samples: SampleCount,
+    pub render_pass: Arc<RenderPass>,
+    pub memory_allocator: Arc<StandardMemoryAllocator>,
+    pub descriptor_set_allocator: StandardDescriptorSetAllocator,
+    pub command_buffer_allocator: StandardCommandBufferAllocator,
+}
+
+impl Offscreen {
+    /// Like [`RenderDetails::init`], if the device doesn't support `samples`, we fall back to no
+    /// multisampling.
+    pub fn init(
+        device: Arc<Device>,
+        dimensions: [u32; 2],
+        samples: SampleCount,
+    ) -> Result<Offscreen, RenderDetailsError> {
+        let samples = {
+            let properties = device.physical_device().properties();
+            supported_sample_count(
+                samples,
+                properties.framebuffer_color_sample_counts,
+                properties.framebuffer_depth_sample_counts,
+            )
+        };
+        let render_pass = create_render_pass(device.clone(), OFFSCREEN_FORMAT, samples)?;
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+        let image = AttachmentImage::with_usage(
+            &memory_allocator,
+            dimensions,
+            OFFSCREEN_FORMAT,
+            ImageUsage {
+                color_attachment: true,
+                transfer_src: true,
+                ..ImageUsage::empty()
+            },
+        )
+        .map_err(RenderDetailsError::FailedToCreateOffscreenBuffer)?;
+        let image_view = ImageView::new_default(image.clone())
+            .map_err(RenderDetailsError::FailedToCreateOffscreenBufferView)?;
+        let frame_attachments =
+            create_frame_attachment(&memory_allocator, dimensions, OFFSCREEN_FORMAT, samples)?;
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: frame_attachments.framebuffer_attachments(image_view),
+                ..Default::default()
+            },
+        )
+        .map_err(RenderDetailsError::FailedToCreateFramebuffer)?;
+        Ok(Offscreen {
+            image,
+            framebuffer,
+            frame_attachments,
+            dimensions,
+            samples,
+            render_pass,
+            memory_allocator,
+            descriptor_set_allocator: StandardDescriptorSetAllocator::new(device.clone()),
+            command_buffer_allocator: StandardCommandBufferAllocator::new(
+                device,
+                Default::default(),
+            ),
+        })
+    }
+}
+
+/// Describe each of the physical devices (GPUs) available, without needing a window or surface.
+pub fn list_physical_devices() -> Vec<String> {
+    let instance = create_instance(InstanceExtensions::empty(), false);
+    enumerate_physical_devices(&instance)
+        .iter()
+        .map(|physical_device| describe_physical_device(physical_device))
+        .collect()
+}
+
+/// The name of Khronos' validation layer, which checks our use of the Vulkan API.
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// The layers to enable for validation, given the names of the `available` ones: the validation
+/// layer, if it's installed. If it isn't, we warn & carry on without it.
+fn validation_layers<I: IntoIterator<Item = String>>(available: I) -> Vec<String> {
+    match available.into_iter().any(|name| name == VALIDATION_LAYER) {
+        true => vec![VALIDATION_LAYER.to_owned()],
+        false => {
+            warn!(
+                "validation was requested, but {} isn't installed; continuing without it",
+                VALIDATION_LAYER,
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// Create the instance; with `validation`, the validation layer is enabled if it's available.
+fn create_instance(ext: InstanceExtensions, validation: bool) -> Arc<Instance> {
     let vk_library = VulkanLibrary::new().expect("failed to init VulkanLibrary");
-    let instance = Instance::new(
+    let enabled_layers = match validation {
+        true => validation_layers(
+            vk_library
+                .layer_properties()
+                .expect("failed to list Vulkan layers")
+                .map(|layer| layer.name().to_owned()),
+        ),
+        false => Vec::new(),
+    };
+    debug!("Enabled Vulkan layers: {:?}", enabled_layers);
+    Instance::new(
         vk_library,
         instance::InstanceCreateInfo {
             application_name: Some("Voxel".to_owned()),
@@ -272,51 +807,128 @@ fn init_vulkan(
                 patch: 0,
             },
             enabled_extensions: ext,
-            enabled_layers: Vec::new(),
+            enabled_layers,
             engine_name: None,
             engine_version: Default::default(),
             max_api_version: Default::default(),
             ..Default::default()
         },
     )
-    .expect("failed to create Vulkan instance");
+    .expect("failed to create Vulkan instance")
+}
 
-    let physical_devices = instance
+fn enumerate_physical_devices(instance: &Arc<Instance>) -> SmallVec<[Arc<PhysicalDevice>; 2]> {
+    instance
         .enumerate_physical_devices()
         .expect("failed to enumerate physical devices")
-        .collect::<SmallVec<[_; 2]>>();
+        .collect()
+}
+
+/// A human-readable description of a physical device: its name, UUID, type & API version.
+fn describe_physical_device(physical_device: &PhysicalDevice) -> String {
+    let properties = physical_device.properties();
+    let device_id = match properties.device_uuid {
+        Some(b) => Cow::from(Uuid::from_slice(&b).unwrap().to_string()),
+        None => Cow::from("None"),
+    };
+    format!(
+        "{}\n  ID: {}\n  type: {:?}\n  API version: {:?}",
+        properties.device_name,
+        device_id,
+        properties.device_type,
+        physical_device.api_version(),
+    )
+}
+
+/// A device (& a graphics queue) for tests that need one, without any window or surface; `None`
+/// if there is no Vulkan implementation, or no device, available.
+#[cfg(test)]
+pub(crate) fn headless_device() -> Option<(Arc<Device>, Arc<Queue>)> {
+    let vk_library = VulkanLibrary::new().ok()?;
+    let instance = Instance::new(vk_library, Default::default()).ok()?;
+    let physical_device = instance.enumerate_physical_devices().ok()?.next()?;
+    let queue_family_index = physical_device
+        .queue_family_properties()
+        .iter()
+        .position(|q| q.queue_flags.graphics)?;
+    let fill_mode_non_solid = physical_device.supported_features().fill_mode_non_solid;
+    let sampler_anisotropy = physical_device.supported_features().sampler_anisotropy;
+    let (device, mut queues) = Device::new(
+        physical_device,
+        vulkano::device::DeviceCreateInfo {
+            enabled_features: Features {
+                fill_mode_non_solid,
+                sampler_anisotropy,
+                ..Features::empty()
+            },
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index: queue_family_index.try_into().unwrap(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    )
+    .ok()?;
+    Some((device, queues.next().unwrap()))
+}
+
+/// The [`headless_device`] (& its queue) for a test; if there isn't one, the test returns early,
+/// i.e., it's skipped.
+#[cfg(test)]
+macro_rules! test_device {
+    () => {
+        match crate::init::headless_device() {
+            Some(device) => device,
+            None => {
+                eprintln!("no Vulkan device available; skipping");
+                return;
+            }
+        }
+    };
+}
+#[cfg(test)]
+pub(crate) use test_device;
+
+/// A single-sampled render pass, like `RenderDetails` uses, rendering to an R8G8B8A8 image.
+#[cfg(test)]
+pub(crate) fn test_render_pass(device: Arc<Device>) -> Arc<RenderPass> {
+    create_render_pass(device, Format::R8G8B8A8_UNORM, SampleCount::Sample1).unwrap()
+}
+
+/// Initialize Vulkan without a window, e.g., to render offscreen.
+pub fn init_headless(
+    select_device: &DeviceSelection,
+    validation: bool,
+) -> (Arc<Instance>, Arc<Device>, Arc<Queue>) {
+    init_vulkan(
+        InstanceExtensions::empty(),
+        DeviceExtensions::empty(),
+        select_device,
+        validation,
+    )
+}
+
+fn init_vulkan(
+    ext: InstanceExtensions,
+    device_extensions: DeviceExtensions,
+    select_device: &DeviceSelection,
+    validation: bool,
+) -> (Arc<Instance>, Arc<Device>, Arc<Queue>) {
+    let instance = create_instance(ext, validation);
+
+    let physical_devices = enumerate_physical_devices(&instance);
     for physical_device in physical_devices.iter() {
-        let properties = physical_device.properties();
-        let device_id = match properties.device_uuid {
-            Some(b) => Cow::from(Uuid::from_slice(&b).unwrap().to_string()),
-            None => Cow::from("None"),
-        };
         debug!(
-            "Physical device: {} / {:?}\n  ID: {}\n  type: {:?}\n  API version: {:?}",
-            properties.device_name,
-            physical_device,
-            device_id,
-            properties.device_type,
-            physical_device.api_version(),
+            "Physical device: {}",
+            describe_physical_device(physical_device)
         );
     }
 
-    let physical_device = if let Some(id) = select_device {
-        physical_devices
-            .into_iter()
-            .filter(|pd| {
-                pd.properties()
-                    .device_uuid
-                    .as_ref()
-                    .map(|id| Uuid::from_slice(id).unwrap())
-                    == Some(id)
-            })
-            .next()
-    } else {
-        physical_devices.into_iter().next()
-    };
-    let physical_device = physical_device.expect("Failed to select Vulkan physical device");
-    debug!("Selected first device: {:?}", physical_device);
+    let physical_device = physical_devices
+        .into_iter()
+        .find(|pd| select_device.matches(pd))
+        .expect("Failed to select Vulkan physical device");
+    debug!("Selected device: {:?}", physical_device);
 
     for family in physical_device.queue_family_properties() {
         debug!(
@@ -332,16 +944,27 @@ fn init_vulkan(
         .position(|q| q.queue_flags.graphics)
         .expect("Failed to find a queue family that supported graphics");
 
+    // Wireframe rendering needs this; without it, we just can't draw in wireframe.
+    let fill_mode_non_solid = physical_device.supported_features().fill_mode_non_solid;
+    if !fill_mode_non_solid {
+        warn!("the device doesn't support fill_mode_non_solid; wireframe rendering is unavailable");
+    }
+    // Textures are filtered anisotropically if this is supported, & merely trilinearly if not.
+    let sampler_anisotropy = physical_device.supported_features().sampler_anisotropy;
+    if !sampler_anisotropy {
+        warn!("the device doesn't support sampler_anisotropy; textures won't be filtered anisotropically");
+    }
+
     let (device, queue) = {
-        let device_extensions = vulkano::device::DeviceExtensions {
-            khr_swapchain: true,
-            ..vulkano::device::DeviceExtensions::empty()
-        };
         let (device, mut queues) = Device::new(
             physical_device,
             vulkano::device::DeviceCreateInfo {
                 enabled_extensions: device_extensions,
-                enabled_features: Features::empty(),
+                enabled_features: Features {
+                    fill_mode_non_solid,
+                    sampler_anisotropy,
+                    ..Features::empty()
+                },
                 queue_create_infos: vec![QueueCreateInfo {
                     queue_family_index: queue_family_index.try_into().unwrap(),
                     ..Default::default()
@@ -357,3 +980,185 @@ fn init_vulkan(
     info!("Vulkan initialized.");
     (instance, device, queue)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+    use vulkano::command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, PrimaryCommandBufferAbstract,
+        RenderPassBeginInfo, SubpassContents,
+    };
+    use vulkano::device::physical::SampleCounts;
+    use vulkano::format::{ClearValue, Format};
+    use vulkano::image::{AttachmentImage, ImageUsage, SampleCount};
+    use vulkano::memory::allocator::StandardMemoryAllocator;
+    use vulkano::swapchain::{ColorSpace, PresentMode};
+    use vulkano::sync::GpuFuture;
+
+    #[test]
+    fn test_swapchain_extent() {
+        let extent = |size| super::swapchain_extent(size, [1, 1], [4096, 4096]);
+        assert!(extent((800, 600)) == Some([800, 600]));
+        // After a resize, the new size is used:
+        assert!(extent((1024, 768)) == Some([1024, 768]));
+        assert!(extent((8000, 600)) == Some([4096, 600]));
+        assert!(extent((0, 600)).is_none());
+    }
+
+    #[test]
+    fn test_choose_surface_format() {
+        let unorm = (Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear);
+        let bgra_srgb = (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear);
+        let rgba_srgb = (Format::R8G8B8A8_SRGB, ColorSpace::SrgbNonLinear);
+        let bgra_srgb_extended = (Format::B8G8R8A8_SRGB, ColorSpace::ExtendedSrgbLinear);
+
+        let choose = super::choose_surface_format;
+        assert!(choose(&[unorm, rgba_srgb, bgra_srgb]) == bgra_srgb);
+        assert!(choose(&[unorm, rgba_srgb]) == rgba_srgb);
+        assert!(choose(&[bgra_srgb_extended, unorm]) == bgra_srgb_extended);
+        assert!(choose(&[unorm]) == unorm);
+    }
+
+    #[test]
+    fn test_choose_present_mode() {
+        let supported = [PresentMode::Fifo, PresentMode::Mailbox];
+        let choose = |requested| super::choose_present_mode(requested, supported.iter().copied());
+        assert!(choose(PresentMode::Mailbox) == PresentMode::Mailbox);
+        assert!(choose(PresentMode::Fifo) == PresentMode::Fifo);
+        assert!(choose(PresentMode::Immediate) == PresentMode::Fifo);
+    }
+
+    #[test]
+    fn test_supported_sample_count() {
+        let up_to_4 = SampleCounts {
+            sample1: true,
+            sample2: true,
+            sample4: true,
+            ..SampleCounts::empty()
+        };
+        let up_to_8 = SampleCounts {
+            sample8: true,
+            ..up_to_4
+        };
+        let supported = |requested| super::supported_sample_count(requested, up_to_8, up_to_4);
+        assert!(supported(SampleCount::Sample4) == SampleCount::Sample4);
+        assert!(supported(SampleCount::Sample2) == SampleCount::Sample2);
+        // Depth attachments don't support 8×, so neither can the render pass.
+        assert!(supported(SampleCount::Sample8) == SampleCount::Sample1);
+        assert!(supported(SampleCount::Sample16) == SampleCount::Sample1);
+    }
+
+    #[test]
+    fn test_framebuffers_only_rebuilt_with_new_images() {
+        let (device, queue) = crate::init::test_device!();
+        let memory_allocator = StandardMemoryAllocator::new_default(device.clone());
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(device.clone(), Default::default());
+        let render_pass = super::test_render_pass(device.clone());
+        // Stand-ins for a swapchain's images.
+        let images = |dimensions| {
+            (0..2)
+                .map(|_| {
+                    AttachmentImage::with_usage(
+                        &memory_allocator,
+                        dimensions,
+                        Format::R8G8B8A8_UNORM,
+                        ImageUsage {
+                            color_attachment: true,
+                            ..ImageUsage::empty()
+                        },
+                    )
+                    .unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut targets = super::FrameTargets::new(
+            &render_pass,
+            &memory_allocator,
+            &images([16, 16]),
+            Format::R8G8B8A8_UNORM,
+            SampleCount::Sample1,
+        )
+        .unwrap();
+        let framebuffers = targets.framebuffers.clone();
+
+        // Drawing frames uses the framebuffers as they are.
+        for frame in 0..4 {
+            let idx = frame % framebuffers.len();
+            let mut builder = AutoCommandBufferBuilder::primary(
+                &command_buffer_allocator,
+                queue.queue_family_index(),
+                CommandBufferUsage::OneTimeSubmit,
+            )
+            .unwrap();
+            builder
+                .begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values: targets.frame_attachments[idx]
+                            .clear_values(ClearValue::Float([0.0, 0.0, 0.0, 1.0])),
+                        ..RenderPassBeginInfo::framebuffer(targets.framebuffers[idx].clone())
+                    },
+                    SubpassContents::Inline,
+                )
+                .unwrap()
+                .end_render_pass()
+                .unwrap();
+            builder
+                .build()
+                .unwrap()
+                .execute(queue.clone())
+                .unwrap()
+                .then_signal_fence_and_flush()
+                .unwrap()
+                .wait(None)
+                .unwrap();
+        }
+        assert!(targets.generation() == 0);
+        assert!(targets
+            .framebuffers
+            .iter()
+            .zip(&framebuffers)
+            .all(|(current, original)| Arc::ptr_eq(current, original)));
+
+        // As `RenderDetails::recreate_swapchain` does, with the new swapchain's images:
+        targets
+            .rebuild(
+                &render_pass,
+                &memory_allocator,
+                &images([32, 24]),
+                Format::R8G8B8A8_UNORM,
+                SampleCount::Sample1,
+            )
+            .unwrap();
+        assert!(targets.generation() == 1);
+        assert!(targets.framebuffers.len() == 2);
+        assert!(targets
+            .framebuffers
+            .iter()
+            .all(|framebuffer| framebuffer.extent() == [32, 24]));
+    }
+
+    #[test]
+    fn test_validation_layers() {
+        let layers =
+            |names: &[&str]| super::validation_layers(names.iter().map(|name| name.to_string()));
+        let validation = vec![super::VALIDATION_LAYER.to_owned()];
+        assert!(layers(&["VK_LAYER_MESA_overlay", super::VALIDATION_LAYER]) == validation);
+        // Without the layer installed, we fall back to none rather than failing.
+        assert!(layers(&["VK_LAYER_MESA_overlay"]).is_empty());
+        assert!(layers(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_create_instance_with_validation() {
+        // Whether or not the validation layer is installed, requesting it mustn't stop the
+        // instance from being created.
+        if vulkano::library::VulkanLibrary::new().is_err() {
+            return;
+        }
+        let instance = super::create_instance(vulkano::instance::InstanceExtensions::empty(), true);
+        assert!(instance.enabled_layers().len() <= 1);
+    }
+}
@@ -0,0 +1,114 @@
+//! Whether input flies the camera around, or is left for interacting with the UI (e.g., a menu),
+//! with the cursor visible.
+
+/// What the mouse & keyboard are controlling.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InputMode {
+    /// Mouse motion turns the camera, & the movement keys move it.
+    Fly,
+    /// The cursor is shown, & the camera is left alone.
+    Ui,
+}
+
+/// The input mode, along with whether the mouse is captured (in relative mode) while flying.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InputState {
+    mode: InputMode,
+    /// Whether flying captures the mouse; this is kept across trips into UI mode & back.
+    capture_mouse: bool,
+}
+
+impl InputState {
+    /// Start out flying, with the mouse captured.
+    pub fn new() -> InputState {
+        InputState {
+            mode: InputMode::Fly,
+            capture_mouse: true,
+        }
+    }
+
+    pub fn mode(&self) -> InputMode {
+        self.mode
+    }
+
+    /// Switch between flying & the UI.
+    pub fn toggle_ui(&mut self) {
+        self.mode = match self.mode {
+            InputMode::Fly => InputMode::Ui,
+            InputMode::Ui => InputMode::Fly,
+        };
+    }
+
+    /// Capture or release the mouse while flying. (The UI never captures it, so this does nothing
+    /// there.)
+    pub fn toggle_capture_mouse(&mut self) {
+        if self.mode == InputMode::Fly {
+            self.capture_mouse = !self.capture_mouse;
+        }
+    }
+
+    /// Whether the mouse should be in SDL's relative mode, which hides the cursor.
+    pub fn relative_mouse_mode(&self) -> bool {
+        self.mode == InputMode::Fly && self.capture_mouse
+    }
+
+    /// Whether mouse motion should turn the camera.
+    pub fn look_enabled(&self) -> bool {
+        self.relative_mouse_mode()
+    }
+
+    /// Whether a gamepad's look stick should turn the camera. Unlike the mouse, it needn't be
+    /// captured to.
+    pub fn gamepad_look_enabled(&self) -> bool {
+        self.mode == InputMode::Fly
+    }
+
+    /// Whether the movement keys (& a gamepad's movement stick & buttons) should move the camera.
+    pub fn movement_enabled(&self) -> bool {
+        self.mode == InputMode::Fly
+    }
+}
+
+impl Default for InputState {
+    fn default() -> Self {
+        InputState::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputMode, InputState};
+
+    #[test]
+    fn test_input_mode_transitions() {
+        let mut input = InputState::new();
+        assert!(input.mode() == InputMode::Fly);
+        assert!(input.relative_mouse_mode() && input.look_enabled() && input.movement_enabled());
+        assert!(input.gamepad_look_enabled());
+
+        input.toggle_ui();
+        assert!(input.mode() == InputMode::Ui);
+        assert!(!input.relative_mouse_mode());
+        assert!(!input.look_enabled());
+        assert!(!input.gamepad_look_enabled());
+        assert!(!input.movement_enabled());
+        // The UI always shows the cursor.
+        input.toggle_capture_mouse();
+        assert!(!input.relative_mouse_mode());
+
+        input.toggle_ui();
+        assert!(input == InputState::new());
+
+        // Releasing the mouse while flying stops looking (with the mouse, not a gamepad), but not
+        // moving, & is remembered through a trip into the UI.
+        input.toggle_capture_mouse();
+        assert!(!input.relative_mouse_mode() && !input.look_enabled());
+        assert!(input.gamepad_look_enabled() && input.movement_enabled());
+        input.toggle_ui();
+        input.toggle_ui();
+        assert!(input.mode() == InputMode::Fly);
+        assert!(!input.relative_mouse_mode());
+        input.toggle_capture_mouse();
+        assert!(input == InputState::new());
+    }
+}
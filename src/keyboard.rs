@@ -0,0 +1,98 @@
+//! Keyboard movement: the camera moves for as long as a movement key is held, rather than once
+//! per key press (which, held down, would be at the mercy of key repeat).
+
+use sdl2::keyboard::{KeyboardState, Scancode};
+
+/// How many steps per second the camera moves while W is held; the same as with the left stick
+/// fully deflected.
+pub const STEPS_PER_SECOND: f32 = crate::gamepad::STEPS_PER_SECOND;
+
+/// How far Q, Z, Left & Right move the camera per step, regardless of the configured step.
+pub const NUDGE: f32 = 0.5;
+
+/// Which of the movement keys are held.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MovementKeys {
+    /// W: along the camera's bearing.
+    pub forward: bool,
+    /// Q
+    pub up: bool,
+    /// Z
+    pub down: bool,
+    /// Left: towards -X.
+    pub left: bool,
+    /// Right: towards +X.
+    pub right: bool,
+}
+
+impl MovementKeys {
+    /// The movement keys held, per SDL's keyboard state. (These are scancodes, so it's the keys in
+    /// the same place, whatever the keyboard layout.)
+    pub fn from_keyboard_state(state: &KeyboardState<'_>) -> MovementKeys {
+        MovementKeys {
+            forward: state.is_scancode_pressed(Scancode::W),
+            up: state.is_scancode_pressed(Scancode::Q),
+            down: state.is_scancode_pressed(Scancode::Z),
+            left: state.is_scancode_pressed(Scancode::Left),
+            right: state.is_scancode_pressed(Scancode::Right),
+        }
+    }
+}
+
+/// How far to move the camera, as `(x, y, z)`, with `keys` held for `dt` seconds, in steps of
+/// `step` long. Keys in opposite directions cancel out.
+pub fn movement(keys: MovementKeys, bearing: f32, step: f32, dt: f32) -> (f32, f32, f32) {
+    let steps = STEPS_PER_SECOND * dt;
+    let axis = |positive: bool, negative: bool| f32::from(i8::from(positive) - i8::from(negative));
+    let forward = axis(keys.forward, false) * step * steps;
+    (
+        forward * bearing.cos() + axis(keys.right, keys.left) * NUDGE * steps,
+        axis(keys.up, keys.down) * NUDGE * steps,
+        forward * bearing.sin(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{movement, MovementKeys, NUDGE, STEPS_PER_SECOND};
+
+    #[test]
+    fn test_movement() {
+        let close = |a: (f32, f32, f32), b: (f32, f32, f32)| {
+            (a.0 - b.0).abs() < 0.001 && (a.1 - b.1).abs() < 0.001 && (a.2 - b.2).abs() < 0.001
+        };
+        assert!(movement(MovementKeys::default(), 1., 2., 0.5) == (0., 0., 0.));
+
+        let forward = MovementKeys {
+            forward: true,
+            ..MovementKeys::default()
+        };
+        let moved = movement(forward, 0., 2., 0.5);
+        assert!(close(moved, (STEPS_PER_SECOND, 0., 0.)));
+        // Twice as long moves twice as far.
+        let (x, _, _) = movement(forward, 0., 2., 1.);
+        assert!((x - 2. * moved.0).abs() < 0.001);
+        let moved = movement(forward, std::f32::consts::FRAC_PI_2, 2., 0.5);
+        assert!(close(moved, (0., 0., STEPS_PER_SECOND)));
+
+        // Several keys at once:
+        let up_and_left = MovementKeys {
+            up: true,
+            left: true,
+            ..MovementKeys::default()
+        };
+        let steps = STEPS_PER_SECOND * 0.25;
+        assert!(close(
+            movement(up_and_left, 1., 2., 0.25),
+            (-NUDGE * steps, NUDGE * steps, 0.)
+        ));
+        let opposed = MovementKeys {
+            up: true,
+            down: true,
+            left: true,
+            right: true,
+            ..MovementKeys::default()
+        };
+        assert!(movement(opposed, 1., 2., 0.25) == (0., 0., 0.));
+    }
+}
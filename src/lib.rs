@@ -2,63 +2,94 @@ use std::convert::{TryFrom, TryInto};
 use std::sync::Arc;
 
 use bytemuck::{Pod, Zeroable};
-use log::{debug, info, trace};
-use sdl2::event::Event;
+use log::{debug, error, info, trace, warn};
+use sdl2::controller::Button;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use smallvec::SmallVec;
+use sdl2::mouse::MouseButton;
+use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 use vulkano::buffer::cpu_access::CpuAccessibleBuffer;
 use vulkano::buffer::cpu_pool::CpuBufferPool;
-use vulkano::buffer::{BufferUsage, TypedBufferAccess};
+use vulkano::buffer::{BufferContents, BufferUsage, TypedBufferAccess};
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
-use vulkano::command_buffer::{AutoCommandBufferBuilder, RenderPassBeginInfo, SubpassContents};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract,
+    RenderPassBeginInfo, SubpassContents,
+};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
 use vulkano::format::ClearValue;
 use vulkano::image::view::ImageView;
-use vulkano::image::SwapchainImage;
+use vulkano::image::{ImmutableImage, SampleCount, SwapchainImage};
 use vulkano::memory::allocator::StandardMemoryAllocator;
 use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
 use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{PolygonMode, RasterizationState};
 use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
 use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
 use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
 use vulkano::render_pass::{Framebuffer, RenderPass, Subpass};
+use vulkano::sampler::{
+    Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode, LOD_CLAMP_NONE,
+};
 use vulkano::shader::ShaderModule;
-use vulkano::swapchain::{AcquireError, Swapchain, SwapchainPresentInfo};
+use vulkano::swapchain::{AcquireError, PresentMode, Swapchain, SwapchainPresentInfo};
+use vulkano::sync::future::FenceSignalFuture;
 use vulkano::sync::{FlushError, GpuFuture};
 
 mod camera;
+mod config;
+mod debug_lines;
+mod frames_in_flight;
+mod gamepad;
 mod init;
+mod input_mode;
+mod keyboard;
 pub mod magica;
 mod matrix;
 mod model_util;
 mod png;
+pub mod rendered_chunk;
 pub mod resources;
+mod screenshot;
+mod secondary;
 pub mod sw_image;
 pub mod text_rendering;
 mod timing;
 
-use matrix::Matrix;
+use debug_lines::{DebugLines, DebugLinesBuilder};
+use matrix::{Matrix, Vertex3d};
 
-#[derive(Clone, Default)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 struct Position {
     x: f32,
     y: f32,
     z: f32,
 }
 
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 struct Look {
     rotation_horz: f32,
     rotation_vert: f32,
 }
 
 impl Look {
-    fn cursor_moved(&mut self, xrel: i32, yrel: i32) {
+    fn cursor_moved(&mut self, xrel: i32, yrel: i32, config: &CameraConfig) {
+        let per_pixel = degrees_to_radians(config.sensitivity);
+        self.turn((xrel as f32) * per_pixel, (yrel as f32) * per_pixel, config);
+    }
+
+    /// Turn by the given angles, in radians. Positive `vert` looks down, unless `config.invert_y`
+    /// is set.
+    fn turn(&mut self, horz: f32, vert: f32, config: &CameraConfig) {
         const NINETY_DEG: f32 = std::f32::consts::PI / 2.; // N.b., it's in radians.
 
-        self.rotation_horz += (xrel as f32) * degrees_to_radians(1.);
-        self.rotation_vert += (yrel as f32) * degrees_to_radians(1.);
+        let vert = if config.invert_y { -vert } else { vert };
+        self.rotation_horz += horz;
+        self.rotation_vert += vert;
 
         if self.rotation_vert < -NINETY_DEG {
             self.rotation_vert = -NINETY_DEG;
@@ -66,12 +97,8 @@ impl Look {
             self.rotation_vert = NINETY_DEG;
         }
 
-        if self.rotation_horz < -std::f32::consts::PI * 2. {
-            self.rotation_horz += std::f32::consts::PI * 2.;
-        }
-        if std::f32::consts::PI * 2. < self.rotation_horz {
-            self.rotation_horz -= std::f32::consts::PI * 2.;
-        }
+        // However far the mouse moved, keep the bearing in [0, 2π).
+        self.rotation_horz = self.rotation_horz.rem_euclid(std::f32::consts::TAU);
     }
 }
 
@@ -84,14 +111,297 @@ impl Default for Look {
     }
 }
 
+/// Where the camera starts when there's no saved camera to restore.
+fn initial_camera() -> (Position, Look) {
+    let position = Position {
+        y: 1.5,
+        ..Position::default()
+    };
+    (position, Look::default())
+}
+
+/// The camera, as saved between runs.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct SavedCamera {
+    position: Position,
+    look: Look,
+}
+
+/// The file the camera is saved to on exit, and restored from on startup.
+const SAVED_CAMERA_PATH: &str = "camera.yaml";
+
+/// Load the camera saved at `path`, falling back to [`initial_camera`] if there isn't one, or it
+/// can't be read.
+fn load_camera(path: &std::path::Path) -> (Position, Look) {
+    let saved = std::fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|yaml| Ok(serde_yaml::from_str::<SavedCamera>(&yaml)?));
+    match saved {
+        Ok(saved) => (saved.position, saved.look),
+        Err(err) => {
+            if path.exists() {
+                warn!(
+                    "failed to restore the camera from {}: {:#}",
+                    path.display(),
+                    err
+                );
+            }
+            initial_camera()
+        }
+    }
+}
+
+fn save_camera(path: &std::path::Path, position: &Position, look: &Look) -> anyhow::Result<()> {
+    let saved = SavedCamera {
+        position: position.clone(),
+        look: look.clone(),
+    };
+    std::fs::write(path, serde_yaml::to_string(&saved)?)?;
+    Ok(())
+}
+
+/// How mouse motion turns the camera.
+#[derive(Clone, Copy)]
+struct CameraConfig {
+    /// Degrees turned per pixel of mouse motion.
+    sensitivity: f32,
+    /// Flip vertical mouse motion, so that moving the mouse up looks down.
+    invert_y: bool,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            sensitivity: 1.,
+            invert_y: false,
+        }
+    }
+}
+
 fn degrees_to_radians(degrees: f32) -> f32 {
     degrees * std::f32::consts::PI / 180.
 }
 
+/// The narrowest & widest vertical fields of view allowed, in degrees.
+const MIN_FOV: f32 = 30.;
+const MAX_FOV: f32 = 120.;
+/// How many degrees each notch of the scroll wheel zooms in or out by.
+const ZOOM_PER_NOTCH: f32 = 5.;
+
+/// The camera's vertical field of view: the configured one, narrowed by however far the scroll
+/// wheel has zoomed in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FieldOfView {
+    /// In degrees.
+    base: f32,
+    /// How many degrees narrower than `base` the view currently is.
+    zoom: f32,
+}
+
+impl FieldOfView {
+    fn new(degrees: f32) -> FieldOfView {
+        FieldOfView {
+            base: degrees.clamp(MIN_FOV, MAX_FOV),
+            zoom: 0.,
+        }
+    }
+
+    /// Zoom in for each notch the wheel scrolled up (positive), and back out for each notch down,
+    /// but never out past the configured field of view.
+    fn scrolled(&mut self, notches: i32) {
+        let max_zoom = self.base - MIN_FOV;
+        self.zoom = (self.zoom + notches as f32 * ZOOM_PER_NOTCH).clamp(0., max_zoom);
+    }
+
+    fn reset_zoom(&mut self) {
+        self.zoom = 0.;
+    }
+
+    fn degrees(&self) -> f32 {
+        self.base - self.zoom
+    }
+
+    fn radians(&self) -> f32 {
+        degrees_to_radians(self.degrees())
+    }
+}
+
+/// How many frames the frame rate is averaged over.
+const FPS_WINDOW: usize = 120;
+
+/// The text of the HUD: where the camera is, where it's looking, and the frame rate (if it's been
+/// measured yet).
+fn hud_text(position: &Position, look: &Look, fps_counter: &timing::FpsCounter) -> String {
+    let fps = match (fps_counter.average_fps(), fps_counter.frame_time_ms()) {
+        (Some(fps), Some(frame_time_ms)) => format!("{:.1} FPS ({:.2} ms)", fps, frame_time_ms),
+        _ => "… FPS".to_owned(),
+    };
+    format!(
+        "x: {:.2}, y: {:.2}, z: {:.2}\nlook: {:.1}° horz, {:.1}° vert\n{}",
+        position.x,
+        position.y,
+        position.z,
+        look.rotation_horz.to_degrees(),
+        look.rotation_vert.to_degrees(),
+        fps,
+    )
+}
+
+/// The size of the HUD's glyph atlas; plenty for the handful of sizes & glyphs the HUD uses.
+const HUD_ATLAS_SIZE: u32 = 512;
+
+/// Lay out the HUD's text, as quads drawn from `atlas`.
+fn layout_hud(
+    text: &str,
+    resources: &mut resources::Fonts,
+    atlas: &mut text_rendering::atlas::GlyphAtlas,
+) -> Vec<text_rendering::atlas::GlyphQuad> {
+    let mut formatted = text_rendering::FormattedText::new();
+    formatted.add_str(
+        text,
+        sw_image::Pixel {
+            r: 0,
+            g: 255,
+            b: 0,
+            a: 255,
+        },
+    );
+    atlas
+        .layout_text(
+            &formatted,
+            &mut resources.deja_vu,
+            14 << 6,
+            &text_rendering::TextOptions::default(),
+            &mut resources.deja_vu_cache,
+        )
+        .unwrap()
+}
+
+/// Shown large, under the HUD's text, while the mouse & keyboard are left for the UI.
+const UI_MODE_BANNER: &str = "UI mode (Tab to fly)";
+
+/// Where the HUD's text & its banner are drawn, in pixels from the top-left corner of the screen.
+const HUD_ORIGIN: (u32, u32) = (32, 5);
+const HUD_BANNER_ORIGIN: (u32, u32) = (32, 64);
+
+/// Lay out large text for the HUD, as quads of distance fields drawn from `atlas` (which stay
+/// crisp at sizes the HUD's regular text would blur at).
+fn layout_hud_banner(
+    text: &str,
+    resources: &mut resources::Fonts,
+    atlas: &mut text_rendering::atlas::GlyphAtlas,
+    sdf_cache: &mut text_rendering::sdf::SdfGlyphCache,
+) -> Vec<text_rendering::atlas::SdfGlyphQuad> {
+    let mut formatted = text_rendering::FormattedText::new();
+    formatted.add_str(
+        text,
+        sw_image::Pixel {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        },
+    );
+    atlas
+        .layout_text_sdf(
+            &formatted,
+            &mut resources.deja_vu,
+            32 << 6,
+            &text_rendering::TextOptions::default(),
+            sdf_cache,
+        )
+        .unwrap()
+}
+
 #[derive(StructOpt)]
 struct Args {
+    /// The config file to load settings from; defaults are used for anything it doesn't set, or if
+    /// it doesn't exist.
+    #[structopt(long, default_value = "voxel.yaml", parse(from_os_str))]
+    config: std::path::PathBuf,
     #[structopt(long)]
     use_gpu_with_uuid: Option<uuid::Uuid>,
+    /// List the available GPUs (e.g., for --use-gpu-with-uuid), and exit.
+    #[structopt(long)]
+    list_devices: bool,
+    /// Print the chunks of the given MagicaVoxel .vox file as JSON, and exit.
+    #[structopt(long, parse(from_os_str))]
+    dump_vox: Option<std::path::PathBuf>,
+    /// The number of samples per pixel to use for multisample anti-aliasing (1, 2, 4, 8, …).
+    #[structopt(long, default_value = "4")]
+    msaa_samples: u32,
+    /// How frames are presented: "fifo" (vsync), "fifo-relaxed", "mailbox", or "immediate".
+    #[structopt(long, default_value = "fifo", parse(try_from_str = parse_present_mode))]
+    present_mode: PresentMode,
+    /// Limit the frame rate to this many frames per second; 0 means uncapped.
+    #[structopt(long)]
+    max_fps: Option<u32>,
+    /// How many degrees the camera turns per pixel of mouse motion. Overrides the config file.
+    #[structopt(long)]
+    mouse_sensitivity: Option<f32>,
+    /// Invert vertical mouse motion.
+    #[structopt(long)]
+    invert_y: bool,
+    /// The vertical field of view, in degrees (30 to 120). Overrides the config file.
+    #[structopt(long)]
+    fov: Option<f32>,
+    /// Render a single frame, without a window, to the PNG file given by --output, and exit.
+    #[structopt(long, requires = "output")]
+    headless: bool,
+    /// Where --headless writes its frame.
+    #[structopt(long, parse(from_os_str))]
+    output: Option<std::path::PathBuf>,
+    /// How many frames the CPU may submit before waiting for the GPU to finish the oldest.
+    #[structopt(long, default_value = "2", parse(try_from_str = parse_frames_in_flight))]
+    frames_in_flight: usize,
+    /// Enable the Vulkan validation layer, if it's installed, to check for API misuse. Slow.
+    #[structopt(long)]
+    validation: bool,
+}
+
+impl Args {
+    fn camera_config(&self, config: &config::Config) -> CameraConfig {
+        CameraConfig {
+            sensitivity: self.mouse_sensitivity.unwrap_or(config.mouse_sensitivity),
+            invert_y: self.invert_y,
+        }
+    }
+
+    fn fov(&self, config: &config::Config) -> FieldOfView {
+        FieldOfView::new(self.fov.unwrap_or(config.fov))
+    }
+
+    /// The options to start rendering with, from the config file & any overrides.
+    fn render_options(&self, config: &config::Config) -> RenderOptions {
+        RenderOptions {
+            fov: self.fov(config),
+            ..RenderOptions::new(config)
+        }
+    }
+}
+
+/// Capture the mouse, or show the cursor, as `input` needs.
+fn apply_input_state(mouse: &sdl2::mouse::MouseUtil, input: &input_mode::InputState) {
+    mouse.set_relative_mouse_mode(input.relative_mouse_mode());
+    mouse.show_cursor(!input.relative_mouse_mode());
+}
+
+fn parse_present_mode(s: &str) -> Result<PresentMode, String> {
+    match s {
+        "fifo" => Ok(PresentMode::Fifo),
+        "fifo-relaxed" => Ok(PresentMode::FifoRelaxed),
+        "mailbox" => Ok(PresentMode::Mailbox),
+        "immediate" => Ok(PresentMode::Immediate),
+        _ => Err(format!("unknown present mode {:?}", s)),
+    }
+}
+
+fn parse_frames_in_flight(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("at least one frame must be allowed in flight".to_owned()),
+        Ok(frames) => Ok(frames),
+        Err(err) => Err(err.to_string()),
+    }
 }
 
 pub fn main() {
@@ -99,147 +409,292 @@ pub fn main() {
     let args = Args::from_args();
     info!("voxel started.");
 
+    if args.list_devices {
+        for device in init::list_physical_devices() {
+            println!("{}", device);
+        }
+        return;
+    }
+
+    if let Some(path) = &args.dump_vox {
+        let vox =
+            magica::io::load_file(path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        let stdout = std::io::stdout();
+        magica::dump::dump_json(stdout.lock(), &vox)
+            .unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        println!();
+        return;
+    }
+
+    let config = config::Config::load(&args.config).unwrap_or_else(|err| {
+        panic!("{}: {}", args.config.display(), err);
+    });
+
+    let device_selection = init::DeviceSelection {
+        uuid: args.use_gpu_with_uuid.or(config.gpu_uuid),
+        name: config.gpu_name.clone(),
+    };
+    let msaa_samples = SampleCount::try_from(args.msaa_samples)
+        .expect("--msaa-samples must be a power of two, from 1 to 64");
+
+    if args.headless {
+        // (--output is required with --headless.)
+        let output = args.output.as_ref().unwrap();
+        let (_instance, device, queue) = init::init_headless(&device_selection, args.validation);
+        let image = render_headless(
+            device,
+            queue,
+            [config.window_width, config.window_height],
+            msaa_samples,
+            &args.render_options(&config),
+        )
+        .unwrap_or_else(|err| panic!("failed to render headlessly: {:#}", err));
+        let file = std::fs::File::create(output)
+            .unwrap_or_else(|err| panic!("{}: {}", output.display(), err));
+        image
+            .write_png(std::io::BufWriter::new(file))
+            .unwrap_or_else(|err| panic!("{}: {}", output.display(), err));
+        info!("rendered a frame to {}", output.display());
+        return;
+    }
+
     info!("init_sdl_and_vulkan()");
-    let mut init = init::init_sdl_and_vulkan(args.use_gpu_with_uuid);
+    let mut init = init::init_sdl_and_vulkan(
+        &device_selection,
+        (config.window_width, config.window_height),
+        args.validation,
+    );
     info!("init_render_details()");
-    let mut render_details =
-        init::RenderDetails::init(init.vulkan_device.clone(), init.surface().clone()).unwrap();
+    let mut render_details = init::RenderDetails::init(
+        init.vulkan_device.clone(),
+        init.surface().clone(),
+        msaa_samples,
+        args.present_mode,
+    )
+    .unwrap();
 
     info!("Loading resources…");
-    let mut resources = resources::Fonts::init(false).unwrap();
+    let mut resources = resources::Fonts::init(false)
+        .unwrap_or_else(|err| panic!("failed to load resources: {:#}", err));
     info!("Loaded resources.");
 
-    let fov_vert = 90. * std::f32::consts::PI / 180.;
-    let fov_horz = fov_vert * (1. as f32) / (1. as f32);
+    let mut render_options = args.render_options(&config);
+    let fov = render_options.fov.radians();
     println!(
         "{:#?}",
-        matrix::projection::perspective_fov_both(fov_horz, fov_vert, 0.1, 10.)
+        matrix::projection::perspective_fov_both(fov, fov, 0.1, 10.)
     );
 
-    let vs = vs::load(init.vulkan_device.clone()).expect("failed to create shader module");
-    let fs = fs::load(init.vulkan_device.clone()).expect("failed to create shader module");
-
-    let lines_vs =
-        lines::vs::load(init.vulkan_device.clone()).expect("failed to create shader module");
-    let lines_fs =
-        lines::fs::load(init.vulkan_device.clone()).expect("failed to create shader module");
-
-    let blit_vs =
-        blit::vs::load(init.vulkan_device.clone()).expect("failed to create shader module");
-    let blit_fs =
-        blit::fs::load(init.vulkan_device.clone()).expect("failed to create shader module");
-
-    let magica_shaders = magica::MagicaShaders::load(init.vulkan_device.clone());
-    let magica_model = {
-        static MODEL: &'static [u8] = include_bytes!("vox/logo.vox");
-        let top_chunk = magica::io::from_reader(std::io::Cursor::new(MODEL)).unwrap();
-        magica::MagicaModel::new(&render_details.memory_allocator, &top_chunk).unwrap()
-    };
+    let shaders = Shaders::load(init.vulkan_device.clone());
+    let magica_model = logo_model(&render_details.memory_allocator);
+    let demo_module = demo_module();
+    let mut demo_chunk =
+        rendered_chunk::RenderedChunk::new(demo_chunk(&demo_module), [-16.0, -1.0, -16.0]);
 
+    let scene_buffers = SceneBuffers::new(&render_details.memory_allocator);
     let uniform_buffer_pool =
         CpuBufferPool::uniform_buffer(render_details.memory_allocator.clone());
     let blit_uniform_buffer_pool =
         CpuBufferPool::uniform_buffer(render_details.memory_allocator.clone());
 
-    let mut previous_frame_end: Option<Box<dyn GpuFuture>> =
-        Some(Box::new(vulkano::sync::now(init.vulkan_device.clone())));
+    // Each frame's resources (its uniform subbuffers, descriptor sets, etc.) are kept alive by
+    // its command buffer until its fence is signalled, so they can't be overwritten while the GPU
+    // is still using them.
+    let mut frames_in_flight =
+        frames_in_flight::FramesInFlight::<Arc<FrameFence>>::new(args.frames_in_flight);
     let mut swapchain_needs_recreating = false;
     let mut timer = timing::Timer::start();
-    let mut frames = 0;
+    let mut fps_counter = timing::FpsCounter::new(FPS_WINDOW);
     let start = std::time::Instant::now();
-    let mut rotation: Look = Default::default();
-    let mut position: Position = Default::default();
-    position.y = 1.5;
-    let mut pipelines = Pipelines::new(
+    let camera_config = args.camera_config(&config);
+    let camera_path = std::path::Path::new(SAVED_CAMERA_PATH);
+    let (mut position, mut rotation) = load_camera(camera_path);
+    let mut hud = hud_text(&position, &rotation, &fps_counter);
+    let mut hud_atlas = text_rendering::atlas::GlyphAtlas::new(HUD_ATLAS_SIZE, HUD_ATLAS_SIZE);
+    let mut hud_atlas_texture = AtlasTexture::new();
+    let mut hud_quads = layout_hud(&hud, &mut resources, &mut hud_atlas);
+    let mut hud_sdf_cache = text_rendering::sdf::SdfGlyphCache::new();
+    let mut hud_banner_quads = Vec::new();
+    let mut pipelines = shaders.pipelines(
         init.vulkan_device.clone(),
         render_details.render_pass.clone(),
-        &vs,
-        &fs,
-        &lines_vs,
-        &lines_fs,
-        &blit_vs,
-        &blit_fs,
-        &magica_shaders,
+        render_details.samples,
     );
 
-    init.sdl_context.mouse().set_relative_mouse_mode(true);
-    let mut rel_mouse = true;
-
-    'running: loop {
+    let mut input = input_mode::InputState::new();
+    apply_input_state(&init.sdl_context.mouse(), &input);
+    let mut take_screenshot = false;
+    let mut controllers = init.game_controller.clone().map(gamepad::Controllers::new);
+    let mut last_frame = std::time::Instant::now();
+
+    let lost = 'running: loop {
+        let frame_timer = timing::Timer::start();
+        let dt = {
+            let now = std::time::Instant::now();
+            let dt = (now - last_frame).as_secs_f32();
+            last_frame = now;
+            dt
+        };
         for event in init.event_pump.poll_iter() {
             match event {
                 Event::MouseMotion { xrel, yrel, .. } => {
                     println!("Mouse motion: {:?}, {:?}", xrel, yrel);
-                    if rel_mouse {
-                        rotation.cursor_moved(xrel, yrel);
+                    if input.look_enabled() {
+                        rotation.cursor_moved(xrel, yrel, &camera_config);
                     }
                 }
+                Event::MouseWheel { y, .. } => {
+                    render_options.fov.scrolled(y);
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Middle,
+                    ..
+                } => {
+                    render_options.fov.reset_zoom();
+                }
+                // The movement keys are polled, below, for as long as they're held.
+                Event::KeyDown {
+                    keycode:
+                        Some(Keycode::W | Keycode::Q | Keycode::Z | Keycode::Left | Keycode::Right),
+                    ..
+                } => (),
                 Event::KeyDown {
-                    keycode: Some(Keycode::W),
+                    keycode: Some(Keycode::M),
                     ..
                 } => {
-                    let bearing = rotation.rotation_horz;
-                    let y_change = bearing.sin();
-                    let x_change = bearing.cos();
-                    position.x += x_change;
-                    position.z += y_change;
+                    input.toggle_capture_mouse();
+                    apply_input_state(&init.sdl_context.mouse(), &input);
                 }
                 Event::KeyDown {
-                    keycode: Some(Keycode::Q),
+                    keycode: Some(Keycode::Tab),
                     ..
                 } => {
-                    position.y += 0.5;
+                    input.toggle_ui();
+                    apply_input_state(&init.sdl_context.mouse(), &input);
+                    hud_banner_quads = match input.mode() {
+                        input_mode::InputMode::Ui => layout_hud_banner(
+                            UI_MODE_BANNER,
+                            &mut resources,
+                            &mut hud_atlas,
+                            &mut hud_sdf_cache,
+                        ),
+                        input_mode::InputMode::Fly => Vec::new(),
+                    };
                 }
                 Event::KeyDown {
-                    keycode: Some(Keycode::Z),
+                    keycode: Some(Keycode::F),
                     ..
                 } => {
-                    position.y -= 0.5;
+                    if pipelines.wireframe.is_some() {
+                        render_options.wireframe = !render_options.wireframe;
+                    } else {
+                        warn!("wireframe rendering isn't supported on this device");
+                    }
                 }
                 Event::KeyDown {
-                    keycode: Some(Keycode::Left),
+                    keycode: Some(Keycode::G),
                     ..
                 } => {
-                    position.x -= 0.5;
+                    render_options.grid = !render_options.grid;
                 }
                 Event::KeyDown {
-                    keycode: Some(Keycode::Right),
+                    keycode: Some(Keycode::H),
                     ..
                 } => {
-                    position.x += 0.5;
+                    render_options.hud = !render_options.hud;
                 }
                 Event::KeyDown {
-                    keycode: Some(Keycode::M),
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    take_screenshot = true;
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(controllers) = controllers.as_mut() {
+                        controllers.device_added(which);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(controllers) = controllers.as_mut() {
+                        controllers.device_removed(which);
+                    }
+                }
+                Event::ControllerAxisMotion { axis, value, .. } => {
+                    if let Some(controllers) = controllers.as_mut() {
+                        controllers.sticks.axis_moved(axis, value);
+                    }
+                }
+                Event::ControllerButtonDown {
+                    button: Button::A, ..
+                } => {
+                    if input.movement_enabled() {
+                        position.y += 0.5;
+                    }
+                }
+                Event::ControllerButtonDown {
+                    button: Button::B, ..
+                } => {
+                    if input.movement_enabled() {
+                        position.y -= 0.5;
+                    }
+                }
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..),
                     ..
                 } => {
-                    rel_mouse = !rel_mouse;
-                    init.sdl_context.mouse().set_relative_mouse_mode(rel_mouse);
+                    swapchain_needs_recreating = true;
                 }
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => break 'running,
+                } => break 'running None,
                 event => {
                     println!("Unknown event: {:?}", event);
                 }
             }
         }
 
+        if input.movement_enabled() {
+            let keys =
+                keyboard::MovementKeys::from_keyboard_state(&init.event_pump.keyboard_state());
+            let (dx, dy, dz) =
+                keyboard::movement(keys, rotation.rotation_horz, config.move_speed, dt);
+            position.x += dx;
+            position.y += dy;
+            position.z += dz;
+        }
+
+        if let Some(controllers) = controllers.as_ref() {
+            if input.movement_enabled() {
+                let (dx, dz) = gamepad::movement(
+                    controllers.sticks.left(),
+                    rotation.rotation_horz,
+                    config.move_speed,
+                    dt,
+                );
+                position.x += dx;
+                position.z += dz;
+            }
+            if input.gamepad_look_enabled() {
+                let (turn_x, turn_y) = controllers.sticks.right();
+                let per_second = degrees_to_radians(gamepad::TURN_SPEED);
+                rotation.turn(
+                    turn_x * per_second * dt,
+                    turn_y * per_second * dt,
+                    &camera_config,
+                );
+            }
+        }
+
         if swapchain_needs_recreating {
             match render_details.recreate_swapchain(&init) {
                 Ok(true) => {
                     swapchain_needs_recreating = false;
-                    pipelines = Pipelines::new(
+                    pipelines = shaders.pipelines(
                         init.vulkan_device.clone(),
                         render_details.render_pass.clone(),
-                        &vs,
-                        &fs,
-                        &lines_vs,
-                        &lines_fs,
-                        &blit_vs,
-                        &blit_fs,
-                        &magica_shaders,
+                        render_details.samples,
                     );
                 }
                 // These happen. Examples ignore them. What exactly is going on here?
@@ -248,19 +703,44 @@ pub fn main() {
             }
         }
 
+        let new_hud = hud_text(&position, &rotation, &fps_counter);
+        if new_hud != hud {
+            hud_quads = layout_hud(&new_hud, &mut resources, &mut hud_atlas);
+            hud = new_hud;
+        }
+
+        // Only rebuild the chunk's buffers after it's been edited.
+        demo_chunk.remesh_if_dirty(|chunk, origin| {
+            magica::MagicaModel::from_chunk(&render_details.memory_allocator, chunk, origin)
+        });
+
+        if let Some(oldest_frame) = frames_in_flight.make_room() {
+            trace!(target: "render_frame", "waiting for the oldest frame in flight");
+            if let Err(err) = oldest_frame.wait(None) {
+                match RendererOutput::from_flush_error(err) {
+                    RendererOutput::Lost(lost) => break 'running Some(lost),
+                    _ => swapchain_needs_recreating = true,
+                }
+            }
+        }
+        let previous_frame_end = match frames_in_flight.newest() {
+            Some(frame) => frame.clone().boxed(),
+            None => vulkano::sync::now(init.vulkan_device.clone()).boxed(),
+        };
         let output = render_frame(
             &init.vulkan_device,
             &init.queue,
-            previous_frame_end
-                .take()
-                .unwrap_or_else(|| Box::new(vulkano::sync::now(init.vulkan_device.clone()))),
+            previous_frame_end,
             &render_details.swapchain,
             &render_details.swapchain_images,
-            &render_details.render_pass,
+            &render_details.targets.framebuffers,
+            &render_details.targets.frame_attachments,
             &render_details.memory_allocator,
             &render_details.descriptor_set_allocator,
             &render_details.command_buffer_allocator,
             &pipelines,
+            &render_options,
+            &scene_buffers,
             &uniform_buffer_pool,
             &blit_uniform_buffer_pool,
             (std::time::Instant::now() - start).as_secs_f32(),
@@ -273,43 +753,254 @@ pub fn main() {
                 rotation.rotation_horz,
                 rotation.rotation_vert,
             ),
-            &mut resources,
+            &hud_atlas,
+            &mut hud_atlas_texture,
+            &hud_quads,
+            &hud_banner_quads,
             &magica_model,
+            demo_chunk.model(),
+            take_screenshot,
         );
         match output {
-            RendererOutput::Rendering(future) => {
-                previous_frame_end = Some(future);
-                frames += 1;
+            RendererOutput::Rendering(fence, screenshot) => {
+                frames_in_flight.push(fence);
+                take_screenshot = false;
+                if let Some(screenshot) = screenshot {
+                    match screenshot::save(&screenshot) {
+                        Ok(path) => info!("saved a screenshot to {}", path.display()),
+                        Err(err) => warn!("failed to save a screenshot: {:#}", err),
+                    }
+                }
             }
             RendererOutput::SwapchainNeedsRecreating => swapchain_needs_recreating = true,
+            RendererOutput::Lost(lost) => break 'running Some(lost),
         }
 
-        if let Some(previous_frame_end) = previous_frame_end.as_mut() {
-            previous_frame_end.cleanup_finished();
+        if 2. <= timer.elapsed_secs_f64() {
+            if let Some(fps) = fps_counter.average_fps() {
+                debug!("{:.3} FPS (over the last {} frames)", fps, FPS_WINDOW);
+            }
+            timer.reset();
         }
 
-        if frames & 0x3 == 0 {
-            let mark = timer.mark();
-            if 2 <= mark.as_secs() {
-                let fps = frames as f64 / mark.as_secs_f64();
-                debug!(
-                    "{:.3} FPS ({} frames over {}s)",
-                    fps,
-                    frames,
-                    mark.as_secs_f64()
-                );
-                frames = 0;
-                timer = timing::Timer::start();
+        if let Some(max_fps) = args.max_fps {
+            if let Some(sleep) = timing::frame_limit_sleep(frame_timer.mark(), max_fps) {
+                std::thread::sleep(sleep);
             }
         }
+        fps_counter.record(frame_timer.mark());
+    };
 
-        //::std::thread::sleep(::std::time::Duration::new(0, 1_000_000_000u32 / 60));
+    if let Err(err) = save_camera(camera_path, &position, &rotation) {
+        warn!("failed to save the camera: {:#}", err);
+    }
+    if let Some(lost) = lost {
+        error!("{}; shutting down", lost);
+        std::process::exit(1);
     }
 }
 
+/// The instanced model drawn in the scene.
+fn logo_model(
+    memory_allocator: &StandardMemoryAllocator,
+) -> magica::instanced::InstancedMagicaModel {
+    static MODEL: &'static [u8] = include_bytes!("vox/logo.vox");
+    let top_chunk = magica::io::from_reader(std::io::Cursor::new(MODEL)).unwrap();
+    magica::instanced::InstancedMagicaModel::new(memory_allocator, &top_chunk).unwrap()
+}
+
+/// Render a single frame of the scene, from the initial camera, into a `dimensions`-sized image,
+/// without a window. The HUD's text (which would only show the frame rate, etc.) is left out.
+fn render_headless(
+    device: Arc<vulkano::device::Device>,
+    queue: Arc<vulkano::device::Queue>,
+    dimensions: [u32; 2],
+    samples: SampleCount,
+    options: &RenderOptions,
+) -> anyhow::Result<sw_image::SwImage> {
+    let offscreen = init::Offscreen::init(device.clone(), dimensions, samples)?;
+    let pipelines = Shaders::load(device.clone()).pipelines(
+        device.clone(),
+        offscreen.render_pass.clone(),
+        offscreen.samples,
+    );
+    let magica_model = logo_model(&offscreen.memory_allocator);
+    let demo_module = demo_module();
+    let mut demo_chunk =
+        rendered_chunk::RenderedChunk::new(demo_chunk(&demo_module), [-16.0, -1.0, -16.0]);
+    demo_chunk.remesh_if_dirty(|chunk, origin| {
+        magica::MagicaModel::from_chunk(&offscreen.memory_allocator, chunk, origin)
+    });
+    let scene_buffers = SceneBuffers::new(&offscreen.memory_allocator);
+    let uniform_buffer_pool = CpuBufferPool::uniform_buffer(offscreen.memory_allocator.clone());
+    let blit_uniform_buffer_pool =
+        CpuBufferPool::uniform_buffer(offscreen.memory_allocator.clone());
+    let hud_atlas = text_rendering::atlas::GlyphAtlas::new(HUD_ATLAS_SIZE, HUD_ATLAS_SIZE);
+    let (position, look) = initial_camera();
+
+    let mut builder = AutoCommandBufferBuilder::primary(
+        &offscreen.command_buffer_allocator,
+        queue.queue_family_index(),
+        vulkano::command_buffer::CommandBufferUsage::OneTimeSubmit,
+    )?;
+    record_frame(
+        &mut builder,
+        &device,
+        &queue,
+        &offscreen.framebuffer,
+        &offscreen.frame_attachments,
+        &offscreen.memory_allocator,
+        &offscreen.descriptor_set_allocator,
+        &offscreen.command_buffer_allocator,
+        &pipelines,
+        options,
+        &scene_buffers,
+        &uniform_buffer_pool,
+        &blit_uniform_buffer_pool,
+        0.,
+        camera::camera(
+            position.x,
+            position.y,
+            position.z,
+            look.rotation_horz,
+            look.rotation_vert,
+        ),
+        &hud_atlas,
+        &mut AtlasTexture::new(),
+        &[],
+        &[],
+        &magica_model,
+        demo_chunk.model(),
+    );
+    let screenshot = screenshot::Screenshot::record(
+        &mut builder,
+        &*offscreen.memory_allocator,
+        offscreen.image.clone(),
+    )?;
+    builder
+        .build()?
+        .execute(queue)?
+        .then_signal_fence_and_flush()?
+        .wait(None)?;
+    Ok(screenshot.into_image()?)
+}
+
+/// Block definitions for the demo chunk.
+static DEMO_BLOCK_DEFS: &str = "\
+grass:
+  texture: grass.png
+  color:
+    r: 86
+    g: 140
+    b: 62
+  homogeneous: true
+stone:
+  texture: stone.png
+  color:
+    r: 128
+    g: 128
+    b: 136
+  homogeneous: true
+";
+
+/// A module holding the blocks the demo chunk is built from.
+fn demo_module() -> Arc<voxel_mod::Module> {
+    let block_defs =
+        voxel_mod::block_defs::load_block_definitions(DEMO_BLOCK_DEFS.as_bytes()).unwrap();
+    voxel_mod::Module::new(
+        "demo".to_owned(),
+        "Demo".to_owned(),
+        std::path::PathBuf::from("[internal]"),
+        block_defs,
+    )
+}
+
+/// A floor of grass with a few stone pillars on it, to draw until chunks are loaded from a map.
+fn demo_chunk(module: &voxel_mod::Module) -> voxel_map::Chunk {
+    let grass = module.block_by_id("grass").unwrap();
+    let stone = module.block_by_id("stone").unwrap();
+    let mut chunk = voxel_map::Chunk::new();
+    for x in 0..32 {
+        for z in 0..32 {
+            let coord = voxel_map::ChunkRelativeCoord::new(x, 0, z);
+            chunk.set_block(coord, Some(grass.clone()));
+        }
+    }
+    for &(x, z, height) in &[(4, 4, 3), (26, 8, 5), (10, 24, 2)] {
+        for y in 1..=height {
+            let coord = voxel_map::ChunkRelativeCoord::new(x, y, z);
+            chunk.set_block(coord, Some(stone.clone()));
+        }
+    }
+    chunk
+}
+
+/// How far each arm of the crosshair reaches from the center of the screen, in pixels.
+const CROSSHAIR_ARM_LENGTH: f32 = 8.;
+const CROSSHAIR_COLOR: [f32; 3] = [1., 1., 1.];
+
+/// The crosshair, as a horizontal & a vertical line crossing at the center of a screen of
+/// `dimensions`. The positions are in pixels, for [`matrix::screen_matrix`].
+fn crosshair_lines(dimensions: [u32; 2]) -> [Line; 4] {
+    let center_x = dimensions[0] as f32 / 2.;
+    let center_y = dimensions[1] as f32 / 2.;
+    let point = |x, y| Line {
+        position: [x, y, 0.],
+        color: CROSSHAIR_COLOR,
+    };
+    [
+        point(center_x - CROSSHAIR_ARM_LENGTH, center_y),
+        point(center_x + CROSSHAIR_ARM_LENGTH, center_y),
+        point(center_x, center_y - CROSSHAIR_ARM_LENGTH),
+        point(center_x, center_y + CROSSHAIR_ARM_LENGTH),
+    ]
+}
+
+/// Signalled once the GPU has finished a frame.
+type FrameFence = FenceSignalFuture<Box<dyn GpuFuture>>;
+
 enum RendererOutput {
-    Rendering(Box<dyn GpuFuture>),
+    /// The frame was submitted; also, the frame's image, if a screenshot was asked for.
+    Rendering(Arc<FrameFence>, Option<sw_image::SwImage>),
     SwapchainNeedsRecreating,
+    /// Rendering can't carry on; the app should shut down.
+    Lost(RenderLost),
+}
+
+impl RendererOutput {
+    /// What to do after failing to acquire the next swapchain image.
+    fn from_acquire_error(err: AcquireError) -> RendererOutput {
+        match err {
+            AcquireError::OutOfDate | AcquireError::FullScreenExclusiveModeLost => {
+                RendererOutput::SwapchainNeedsRecreating
+            }
+            AcquireError::DeviceLost => RendererOutput::Lost(RenderLost::DeviceLost),
+            AcquireError::SurfaceLost => RendererOutput::Lost(RenderLost::SurfaceLost),
+            err => panic!("Failed to acquire next image: {}", err),
+        }
+    }
+
+    /// What to do after failing to submit (or wait for) a frame.
+    fn from_flush_error(err: FlushError) -> RendererOutput {
+        match err {
+            FlushError::OutOfDate | FlushError::FullScreenExclusiveModeLost => {
+                RendererOutput::SwapchainNeedsRecreating
+            }
+            FlushError::DeviceLost => RendererOutput::Lost(RenderLost::DeviceLost),
+            FlushError::SurfaceLost => RendererOutput::Lost(RenderLost::SurfaceLost),
+            err => panic!("then_signal_fence_and_flush failed: {:?}", err),
+        }
+    }
+}
+
+/// Why rendering had to stop: the GPU (e.g., after a driver reset, or switching GPUs) or the
+/// window's surface went away.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, thiserror::Error)]
+enum RenderLost {
+    #[error("the Vulkan device was lost")]
+    DeviceLost,
+    #[error("the window's surface was lost")]
+    SurfaceLost,
 }
 
 #[repr(C)]
@@ -319,6 +1010,12 @@ struct UniformBufferObject {
     view: Matrix,
     proj: Matrix,
     t: f32,
+    /// The light that reaches surfaces facing away from `light_dir`.
+    ambient: f32,
+    // std140 aligns `light_dir`, a vec3, to 16 bytes.
+    _padding: [f32; 2],
+    /// The direction the light travels in; a unit vector.
+    light_dir: [f32; 3],
 }
 
 #[repr(C)]
@@ -327,64 +1024,359 @@ struct BlitUniform {
     proj: Matrix,
 }
 
-fn screen_quad_to_triangle_fan(
-    pos: (u32, u32),
-    size: (u32, u32),
-) -> SmallVec<[BlitImageVertex; 4]> {
-    let mut vertexes = smallvec::SmallVec::new();
-    vertexes.push(BlitImageVertex {
-        position: [pos.0, pos.1 + size.1],
-        texture_coord: [0., 1.],
-    });
-    vertexes.push(BlitImageVertex {
-        position: [pos.0, pos.1],
-        texture_coord: [0., 0.],
-    });
-    vertexes.push(BlitImageVertex {
-        position: [pos.0 + size.0, pos.1 + size.1],
-        texture_coord: [1., 1.],
-    });
-    vertexes.push(BlitImageVertex {
-        position: [pos.0 + size.0, pos.1],
-        texture_coord: [1., 0.],
-    });
+/// Build a triangle strip for the blit pipeline that draws each of `quads` from an atlas of
+/// `atlas_size`, with the text's top-left corner at `origin`.
+///
+/// The quads are joined by repeating the last vertex of one & the first of the next, which makes
+/// degenerate (invisible) triangles, so that all of them can be drawn at once.
+fn glyph_quads_to_triangle_strip(
+    quads: &[text_rendering::atlas::GlyphQuad],
+    atlas_size: (u32, u32),
+    origin: (u32, u32),
+) -> Vec<BlitImageVertex> {
+    let mut vertexes = Vec::with_capacity(quads.len() * 6);
+    for quad in quads {
+        let size = (quad.region.width, quad.region.height);
+        let position = (quad.x, quad.y);
+        push_blit_quad(
+            &mut vertexes,
+            atlas_size,
+            origin,
+            position,
+            size,
+            &quad.region,
+            quad.color,
+        );
+    }
     vertexes
 }
 
+/// Like [`glyph_quads_to_triangle_strip`], but for the distance fields of
+/// [`text_rendering::atlas::GlyphAtlas::layout_text_sdf`], which are drawn scaled, for the blit
+/// SDF pipeline.
+fn sdf_quads_to_triangle_strip(
+    quads: &[text_rendering::atlas::SdfGlyphQuad],
+    atlas_size: (u32, u32),
+    origin: (u32, u32),
+) -> Vec<BlitImageVertex> {
+    let mut vertexes = Vec::with_capacity(quads.len() * 6);
+    for quad in quads {
+        let size = (quad.width, quad.height);
+        let position = (quad.x, quad.y);
+        push_blit_quad(
+            &mut vertexes,
+            atlas_size,
+            origin,
+            position,
+            size,
+            &quad.region,
+            quad.color,
+        );
+    }
+    vertexes
+}
+
+/// Append a quad of `size`, at `position` from `origin`, that draws `region` of the atlas.
+fn push_blit_quad(
+    vertexes: &mut Vec<BlitImageVertex>,
+    atlas_size: (u32, u32),
+    origin: (u32, u32),
+    position: (i32, i32),
+    size: (u32, u32),
+    region: &text_rendering::atlas::AtlasRegion,
+    color: sw_image::Pixel,
+) {
+    // Glyphs can't overhang the text's top-left corner, but the screen's coordinates are unsigned,
+    // so clamp to be sure.
+    let offset = |origin: u32, by: i32| match u32::try_from(by) {
+        Ok(by) => origin + by,
+        Err(_) => origin.saturating_sub(by.unsigned_abs()),
+    };
+    let (x, y) = (offset(origin.0, position.0), offset(origin.1, position.1));
+    let (width, height) = size;
+    let (u, v) = (
+        region.x as f32 / atlas_size.0 as f32,
+        region.y as f32 / atlas_size.1 as f32,
+    );
+    let (u_end, v_end) = (
+        (region.x + region.width) as f32 / atlas_size.0 as f32,
+        (region.y + region.height) as f32 / atlas_size.1 as f32,
+    );
+    let color = [color.r, color.g, color.b, color.a].map(|c| f32::from(c) / 255.);
+    let corner = |position: [u32; 2], texture_coord: [f32; 2]| BlitImageVertex {
+        position,
+        texture_coord,
+        color,
+    };
+    let corners = [
+        corner([x, y + height], [u, v_end]),
+        corner([x, y], [u, v]),
+        corner([x + width, y + height], [u_end, v_end]),
+        corner([x + width, y], [u_end, v]),
+    ];
+    if let Some(last) = vertexes.last().copied() {
+        vertexes.push(last);
+        vertexes.push(corners[0]);
+    }
+    vertexes.extend_from_slice(&corners);
+}
+
+/// The GPU's copy of a glyph atlas, which is only re-uploaded when glyphs get added to the atlas.
+struct AtlasTexture {
+    /// The atlas's generation when it was last uploaded, & the uploaded image.
+    uploaded: Option<(u64, Arc<ImageView<ImmutableImage>>)>,
+}
+
+impl AtlasTexture {
+    fn new() -> AtlasTexture {
+        AtlasTexture { uploaded: None }
+    }
+
+    /// The atlas's image, uploading it (as part of the commands in `builder`) if it has changed.
+    fn view<L>(
+        &mut self,
+        atlas: &text_rendering::atlas::GlyphAtlas,
+        memory_allocator: &StandardMemoryAllocator,
+        builder: &mut AutoCommandBufferBuilder<L>,
+    ) -> Arc<ImageView<ImmutableImage>> {
+        match &self.uploaded {
+            Some((generation, view)) if *generation == atlas.generation() => return view.clone(),
+            _ => (),
+        }
+        trace!(target: "render_frame", "uploading glyph atlas");
+        let view = sw_image::upload_to_gpu(atlas.image(), memory_allocator, builder);
+        self.uploaded = Some((atlas.generation(), view.clone()));
+        view
+    }
+}
+
+/// The most anisotropic filtering to use, if the device allows even more; more costs more
+/// samples, for little visible difference.
+const MAX_ANISOTROPY: f32 = 16.;
+
+/// A sampler for [`sw_image::upload_to_gpu`]'s images: trilinear, and anisotropic if the device supports it.
+fn texture_sampler(device: &Arc<vulkano::device::Device>) -> Arc<Sampler> {
+    let anisotropy = if device.enabled_features().sampler_anisotropy {
+        let max_anisotropy = device.physical_device().properties().max_sampler_anisotropy;
+        Some(max_anisotropy.min(MAX_ANISOTROPY))
+    } else {
+        None
+    };
+    Sampler::new(
+        device.clone(),
+        SamplerCreateInfo {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_mode: SamplerMipmapMode::Linear,
+            address_mode: [SamplerAddressMode::Repeat; 3],
+            lod: 0.0..=LOD_CLAMP_NONE,
+            anisotropy,
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+thread_local! {
+    /// The number of buffers [`vertex_buffer`] has created on this thread.
+    static VERTEX_BUFFERS_CREATED: std::cell::Cell<usize> = std::cell::Cell::new(0);
+}
+
+/// A (host-visible) vertex buffer holding `vertices`.
+fn vertex_buffer<T, I>(
+    memory_allocator: &StandardMemoryAllocator,
+    vertices: I,
+) -> Arc<CpuAccessibleBuffer<[T]>>
+where
+    [T]: BufferContents,
+    I: IntoIterator<Item = T>,
+    I::IntoIter: ExactSizeIterator,
+{
+    #[cfg(test)]
+    VERTEX_BUFFERS_CREATED.with(|created| created.set(created.get() + 1));
+    CpuAccessibleBuffer::from_iter(
+        memory_allocator,
+        BufferUsage {
+            vertex_buffer: true,
+            ..BufferUsage::empty()
+        },
+        false,
+        vertices,
+    )
+    .unwrap()
+}
+
+/// Vertex buffers for the parts of the scene that never change, so they're only created once.
+struct SceneBuffers {
+    triangle: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    grid_lines: DebugLines,
+}
+
+impl SceneBuffers {
+    fn new(memory_allocator: &StandardMemoryAllocator) -> SceneBuffers {
+        let triangle = vertex_buffer(
+            memory_allocator,
+            vec![
+                /*
+                Vertex { position: [-0.5, -0.5] },
+                Vertex { position: [ 0.0,  0.5] },
+                Vertex { position: [ 0.5, -0.25] },
+                */
+                /*
+                Vertex { position: [-4., -4.] },
+                Vertex { position: [ 0.0,  4.] },
+                Vertex { position: [ 4., -2.] },
+                */
+                Vertex {
+                    position: [-4., 0.],
+                },
+                Vertex { position: [0., 4.] },
+                Vertex { position: [4., 0.] },
+            ],
+        );
+
+        let mut grid = DebugLinesBuilder::new();
+        for i in -10i8..=10 {
+            let i = f32::from(i);
+            grid.add_line(
+                Vertex3d::new(i, 0., -10.),
+                Vertex3d::new(i, 0., 10.),
+                [1., 0., 0.],
+            );
+            grid.add_line(
+                Vertex3d::new(10., 0., i),
+                Vertex3d::new(-10., 0., i),
+                [0., 0., 1.],
+            );
+        }
+        // The Axis indicators:
+        let origin = Vertex3d::new(0., 1., 0.);
+        grid.add_line(origin.clone(), Vertex3d::new(1., 1., 0.), [1., 0., 0.]);
+        grid.add_line(origin.clone(), Vertex3d::new(0., 2., 0.), [0., 1., 0.]);
+        grid.add_line(origin, Vertex3d::new(0., 1., 1.), [0., 0., 1.]);
+        let grid_lines = grid.build(memory_allocator).unwrap();
+
+        SceneBuffers {
+            triangle,
+            grid_lines,
+        }
+    }
+}
+
+/// The shader modules that the [`Pipelines`] are built from.
+struct Shaders {
+    normal_vs: Arc<ShaderModule>,
+    normal_fs: Arc<ShaderModule>,
+    lines_vs: Arc<ShaderModule>,
+    lines_fs: Arc<ShaderModule>,
+    blit_vs: Arc<ShaderModule>,
+    blit_fs: Arc<ShaderModule>,
+    blit_sdf_fs: Arc<ShaderModule>,
+    magica: magica::MagicaShaders,
+}
+
+impl Shaders {
+    fn load(device: Arc<vulkano::device::Device>) -> Shaders {
+        Shaders {
+            normal_vs: vs::load(device.clone()).expect("failed to create shader module"),
+            normal_fs: fs::load(device.clone()).expect("failed to create shader module"),
+            lines_vs: lines::vs::load(device.clone()).expect("failed to create shader module"),
+            lines_fs: lines::fs::load(device.clone()).expect("failed to create shader module"),
+            blit_vs: blit::vs::load(device.clone()).expect("failed to create shader module"),
+            blit_fs: blit::fs::load(device.clone()).expect("failed to create shader module"),
+            blit_sdf_fs: blit::sdf_fs::load(device.clone())
+                .expect("failed to create shader module"),
+            magica: magica::MagicaShaders::load(device),
+        }
+    }
+
+    fn pipelines(
+        &self,
+        device: Arc<vulkano::device::Device>,
+        render_pass: Arc<RenderPass>,
+        samples: SampleCount,
+    ) -> Pipelines {
+        Pipelines::new(
+            device,
+            render_pass,
+            samples,
+            &self.normal_vs,
+            &self.normal_fs,
+            &self.lines_vs,
+            &self.lines_fs,
+            &self.blit_vs,
+            &self.blit_fs,
+            &self.blit_sdf_fs,
+            &self.magica,
+        )
+    }
+}
+
 /// A container for the various Vulkan graphics pipelines we create.
 struct Pipelines {
     normal_pipeline: Arc<GraphicsPipeline>,
     lines_pipeline: Arc<GraphicsPipeline>,
     blit_pipeline: Arc<GraphicsPipeline>,
+    /// Like the blit pipeline, but for drawing glyphs' distance fields (see
+    /// [`text_rendering::sdf`]), which stay crisp when scaled.
+    blit_sdf_pipeline: Arc<GraphicsPipeline>,
     magica_pipeline: Arc<GraphicsPipeline>,
+    magica_instanced_pipeline: Arc<GraphicsPipeline>,
+    /// Wireframe variants of the normal & magica pipelines, if the device supports them.
+    wireframe: Option<WireframePipelines>,
+}
+
+struct WireframePipelines {
+    normal_pipeline: Arc<GraphicsPipeline>,
+    magica_pipeline: Arc<GraphicsPipeline>,
+    magica_instanced_pipeline: Arc<GraphicsPipeline>,
 }
 
 impl Pipelines {
     fn new(
         device: Arc<vulkano::device::Device>,
         render_pass: Arc<RenderPass>,
+        samples: SampleCount,
         normal_vs: &ShaderModule,
         normal_fs: &ShaderModule,
         lines_vs: &ShaderModule,
         lines_fs: &ShaderModule,
         blit_vs: &ShaderModule,
         blit_fs: &ShaderModule,
+        blit_sdf_fs: &ShaderModule,
         magica_shaders: &magica::MagicaShaders,
     ) -> Pipelines {
-        let normal_pipeline = GraphicsPipeline::start()
-            // Defines what kind of vertex input is expected.
-            .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
-            // The vertex shader.
-            .vertex_shader(normal_vs.entry_point("main").unwrap(), ())
-            // Defines the viewport (explanations below).
-            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
-            // The fragment shader.
-            .fragment_shader(normal_fs.entry_point("main").unwrap(), ())
-            // This graphics pipeline object concerns the first pass of the render pass.
-            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-            // Now that everything is specified, we call `build`.
-            .build(device.clone())
-            .unwrap();
+        // Every pipeline must match the render pass's sample count.
+        let multisample_state = MultisampleState {
+            rasterization_samples: samples,
+            ..Default::default()
+        };
+
+        let build_normal_pipeline = |polygon_mode| {
+            GraphicsPipeline::start()
+                // Defines what kind of vertex input is expected.
+                .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
+                // The vertex shader.
+                .vertex_shader(normal_vs.entry_point("main").unwrap(), ())
+                // Defines the viewport (explanations below).
+                .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                // The fragment shader.
+                .fragment_shader(normal_fs.entry_point("main").unwrap(), ())
+                .multisample_state(multisample_state.clone())
+                .rasterization_state(RasterizationState {
+                    polygon_mode,
+                    ..Default::default()
+                })
+                // Nearer geometry hides what's behind it, regardless of draw order.
+                .depth_stencil_state(DepthStencilState::simple_depth_test())
+                // This graphics pipeline object concerns the first pass of the render pass.
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                // Now that everything is specified, we call `build`.
+                .build(device.clone())
+                .unwrap()
+        };
+        let normal_pipeline = build_normal_pipeline(PolygonMode::Fill);
 
         let lines_pipeline = GraphicsPipeline::start()
             // Defines what kind of vertex input is expected.
@@ -395,6 +1387,8 @@ impl Pipelines {
             .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
             // The fragment shader.
             .fragment_shader(lines_fs.entry_point("main").unwrap(), ())
+            .multisample_state(multisample_state.clone())
+            .depth_stencil_state(DepthStencilState::simple_depth_test())
             // This graphics pipeline object concerns the first pass of the render pass.
             .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
             .input_assembly_state(InputAssemblyState::new().topology(PrimitiveTopology::LineList))
@@ -402,95 +1396,284 @@ impl Pipelines {
             .build(device.clone())
             .unwrap();
 
-        let blit_pipeline = GraphicsPipeline::start()
-            // Defines what kind of vertex input is expected.
-            .vertex_input_state(BuffersDefinition::new().vertex::<BlitImageVertex>())
-            // The vertex shader.
-            .vertex_shader(blit_vs.entry_point("main").unwrap(), ())
-            // Defines the viewport (explanations below).
-            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
-            // The fragment shader.
-            .fragment_shader(blit_fs.entry_point("main").unwrap(), ())
-            // This graphics pipeline object concerns the first pass of the render pass.
-            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-            .color_blend_state(ColorBlendState::default().blend_alpha())
-            .input_assembly_state(
-                InputAssemblyState::new().topology(PrimitiveTopology::TriangleStrip),
-            )
-            // Now that everything is specified, we call `build`.
-            .build(device.clone())
-            .unwrap();
+        let build_blit_pipeline = |blit_fs: &ShaderModule| {
+            GraphicsPipeline::start()
+                // Defines what kind of vertex input is expected.
+                .vertex_input_state(BuffersDefinition::new().vertex::<BlitImageVertex>())
+                // The vertex shader.
+                .vertex_shader(blit_vs.entry_point("main").unwrap(), ())
+                // Defines the viewport (explanations below).
+                .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                // The fragment shader.
+                .fragment_shader(blit_fs.entry_point("main").unwrap(), ())
+                .multisample_state(multisample_state.clone())
+                // This graphics pipeline object concerns the first pass of the render pass.
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .color_blend_state(ColorBlendState::default().blend_alpha())
+                .input_assembly_state(
+                    InputAssemblyState::new().topology(PrimitiveTopology::TriangleStrip),
+                )
+                // Now that everything is specified, we call `build`.
+                .build(device.clone())
+                .unwrap()
+        };
+        let blit_pipeline = build_blit_pipeline(blit_fs);
+        let blit_sdf_pipeline = build_blit_pipeline(blit_sdf_fs);
 
-        let magica_pipeline = magica::build_pipeline(device, render_pass, magica_shaders);
+        let build_magica_pipeline = |polygon_mode| {
+            magica::build_pipeline(
+                device.clone(),
+                render_pass.clone(),
+                multisample_state.clone(),
+                polygon_mode,
+                magica_shaders,
+            )
+        };
+        let magica_pipeline = build_magica_pipeline(PolygonMode::Fill);
+        let build_magica_instanced_pipeline = |polygon_mode| {
+            magica::instanced::build_pipeline(
+                device.clone(),
+                render_pass.clone(),
+                multisample_state.clone(),
+                polygon_mode,
+                magica_shaders,
+            )
+        };
+        let magica_instanced_pipeline = build_magica_instanced_pipeline(PolygonMode::Fill);
+
+        let wireframe = if device.enabled_features().fill_mode_non_solid {
+            Some(WireframePipelines {
+                normal_pipeline: build_normal_pipeline(PolygonMode::Line),
+                magica_pipeline: build_magica_pipeline(PolygonMode::Line),
+                magica_instanced_pipeline: build_magica_instanced_pipeline(PolygonMode::Line),
+            })
+        } else {
+            None
+        };
 
         Pipelines {
             normal_pipeline,
             lines_pipeline,
             blit_pipeline,
+            blit_sdf_pipeline,
             magica_pipeline,
+            magica_instanced_pipeline,
+            wireframe,
+        }
+    }
+
+    /// The pipeline to draw the normal geometry with; in wireframe, if requested & supported.
+    fn normal(&self, wireframe: bool) -> &Arc<GraphicsPipeline> {
+        match &self.wireframe {
+            Some(wireframe_pipelines) if wireframe => &wireframe_pipelines.normal_pipeline,
+            _ => &self.normal_pipeline,
+        }
+    }
+
+    /// The pipeline to draw magica models with; in wireframe, if requested & supported.
+    fn magica(&self, wireframe: bool) -> &Arc<GraphicsPipeline> {
+        match &self.wireframe {
+            Some(wireframe_pipelines) if wireframe => &wireframe_pipelines.magica_pipeline,
+            _ => &self.magica_pipeline,
+        }
+    }
+
+    /// The pipeline to draw instanced magica models with; in wireframe, if requested & supported.
+    fn magica_instanced(&self, wireframe: bool) -> &Arc<GraphicsPipeline> {
+        match &self.wireframe {
+            Some(wireframe_pipelines) if wireframe => {
+                &wireframe_pipelines.magica_instanced_pipeline
+            }
+            _ => &self.magica_instanced_pipeline,
+        }
+    }
+}
+
+/// Toggleable parts of how the scene is drawn.
+#[derive(Clone, Debug, PartialEq)]
+struct RenderOptions {
+    /// Draw the geometry as wireframe; ignored if the device doesn't support it.
+    wireframe: bool,
+    /// Draw the debug grid.
+    grid: bool,
+    /// The color the background is cleared to, as RGBA.
+    clear_color: [f32; 4],
+    /// The camera's vertical field of view.
+    fov: FieldOfView,
+    /// Draw the HUD: the crosshair, & the text.
+    hud: bool,
+}
+
+impl RenderOptions {
+    fn new(config: &config::Config) -> RenderOptions {
+        RenderOptions {
+            clear_color: config.clear_color,
+            fov: FieldOfView::new(config.fov),
+            ..RenderOptions::default()
+        }
+    }
+
+    fn clear_value(&self) -> ClearValue {
+        ClearValue::Float(self.clear_color)
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            wireframe: false,
+            grid: true,
+            clear_color: config::DEFAULT_CLEAR_COLOR,
+            fov: FieldOfView::new(config::DEFAULT_FOV),
+            hud: true,
+        }
+    }
+}
+
+fn render_frame(
+    device: &Arc<vulkano::device::Device>,
+    queue: &Arc<vulkano::device::Queue>,
+    previous_frame_end: Box<dyn GpuFuture>,
+    swapchain: &Arc<Swapchain>,
+    swapchain_images: &[Arc<SwapchainImage>],
+    framebuffers: &[Arc<Framebuffer>],
+    frame_attachments: &[init::FrameAttachments],
+    memory_allocator: &StandardMemoryAllocator,
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    command_buffer_allocator: &StandardCommandBufferAllocator,
+    pipelines: &Pipelines,
+    options: &RenderOptions,
+    scene_buffers: &SceneBuffers,
+    uniform_buffer_pool: &CpuBufferPool<UniformBufferObject>,
+    blit_uniform_buffer_pool: &CpuBufferPool<BlitUniform>,
+    t: f32,
+    position: &Position,
+    look: &Look,
+    view: Matrix,
+    hud_atlas: &text_rendering::atlas::GlyphAtlas,
+    hud_atlas_texture: &mut AtlasTexture,
+    hud_quads: &[text_rendering::atlas::GlyphQuad],
+    hud_banner_quads: &[text_rendering::atlas::SdfGlyphQuad],
+    magica_model: &magica::instanced::InstancedMagicaModel,
+    chunk_model: Option<&magica::MagicaModel>,
+    take_screenshot: bool,
+) -> RendererOutput {
+    trace!(target: "render_frame", "acquire_next_image");
+    let (image_index, _, acquire_future) = {
+        match vulkano::swapchain::acquire_next_image(swapchain.clone(), None) {
+            Ok(r) => r,
+            Err(err) => return RendererOutput::from_acquire_error(err),
+        }
+    };
+
+    let framebuffer = &framebuffers[usize::try_from(image_index).unwrap()];
+    let attachments = &frame_attachments[usize::try_from(image_index).unwrap()];
+
+    let mut builder = AutoCommandBufferBuilder::primary(
+        command_buffer_allocator,
+        queue.queue_family_index(),
+        vulkano::command_buffer::CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+    record_frame(
+        &mut builder,
+        device,
+        queue,
+        framebuffer,
+        attachments,
+        memory_allocator,
+        descriptor_set_allocator,
+        command_buffer_allocator,
+        pipelines,
+        options,
+        scene_buffers,
+        uniform_buffer_pool,
+        blit_uniform_buffer_pool,
+        t,
+        view,
+        hud_atlas,
+        hud_atlas_texture,
+        hud_quads,
+        hud_banner_quads,
+        magica_model,
+        chunk_model,
+    );
+
+    let screenshot = if take_screenshot {
+        let image = swapchain_images[usize::try_from(image_index).unwrap()].clone();
+        match screenshot::Screenshot::record(&mut builder, memory_allocator, image) {
+            Ok(screenshot) => Some(screenshot),
+            Err(err) => {
+                warn!("can't take a screenshot: {}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    trace!(target: "render_frame", "build command buffer");
+    let command_buffer = builder.build().unwrap();
+
+    trace!(target: "render_frame", "scheduling command buffer");
+    let result = previous_frame_end
+        .join(acquire_future)
+        .then_execute(queue.clone(), command_buffer)
+        .expect("then_execute failed")
+        .then_swapchain_present(
+            queue.clone(),
+            SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), image_index),
+        )
+        .boxed()
+        .then_signal_fence_and_flush();
+    match result {
+        Ok(future) => {
+            let screenshot = screenshot.and_then(|screenshot| {
+                // The copy must finish before the screenshot can be read.
+                future.wait(None).expect("waiting for the frame failed");
+                screenshot
+                    .into_image()
+                    .map_err(|err| warn!("can't read the screenshot: {}", err))
+                    .ok()
+            });
+            RendererOutput::Rendering(Arc::new(future), screenshot)
         }
+        Err(err) => RendererOutput::from_flush_error(err),
     }
 }
 
-fn render_frame(
+/// Record drawing a frame into `framebuffer` (whose other attachments are `attachments`).
+fn record_frame(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
     device: &Arc<vulkano::device::Device>,
     queue: &Arc<vulkano::device::Queue>,
-    previous_frame_end: Box<dyn GpuFuture>,
-    swapchain: &Arc<Swapchain>,
-    swapchain_images: &[Arc<SwapchainImage>],
-    render_pass: &Arc<RenderPass>,
+    framebuffer: &Arc<Framebuffer>,
+    attachments: &init::FrameAttachments,
     memory_allocator: &StandardMemoryAllocator,
     descriptor_set_allocator: &StandardDescriptorSetAllocator,
     command_buffer_allocator: &StandardCommandBufferAllocator,
     pipelines: &Pipelines,
+    options: &RenderOptions,
+    scene_buffers: &SceneBuffers,
     uniform_buffer_pool: &CpuBufferPool<UniformBufferObject>,
     blit_uniform_buffer_pool: &CpuBufferPool<BlitUniform>,
     t: f32,
-    position: &Position,
-    look: &Look,
     view: Matrix,
-    resources: &mut resources::Fonts,
-    magica_model: &magica::MagicaModel,
-) -> RendererOutput {
-    trace!(target: "render_frame", "Building framebuffers");
-    let framebuffers = swapchain_images
-        .iter()
-        .map(|image| {
-            let image_view = ImageView::new_default(image.clone()).unwrap();
-            let fb = Framebuffer::new(
-                render_pass.clone(),
-                vulkano::render_pass::FramebufferCreateInfo {
-                    attachments: vec![image_view],
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-            fb
-        })
-        .collect::<Vec<_>>();
-
-    trace!(target: "render_frame", "acquire_next_image");
-    let (image_index, _, acquire_future) = {
-        match vulkano::swapchain::acquire_next_image(swapchain.clone(), None) {
-            Ok(r) => r,
-            Err(AcquireError::OutOfDate) => return RendererOutput::SwapchainNeedsRecreating,
-            Err(err) => panic!("Failed to acquire next image: {}", err),
-        }
-    };
-
-    let framebuffer = &framebuffers[usize::try_from(image_index).unwrap()];
-
+    hud_atlas: &text_rendering::atlas::GlyphAtlas,
+    hud_atlas_texture: &mut AtlasTexture,
+    hud_quads: &[text_rendering::atlas::GlyphQuad],
+    hud_banner_quads: &[text_rendering::atlas::SdfGlyphQuad],
+    magica_model: &magica::instanced::InstancedMagicaModel,
+    chunk_model: Option<&magica::MagicaModel>,
+) {
+    let wireframe = options.wireframe;
     let dimensions = framebuffer.extent();
 
-    let mut builder = AutoCommandBufferBuilder::primary(
-        command_buffer_allocator,
-        queue.queue_family_index(),
-        vulkano::command_buffer::CommandBufferUsage::OneTimeSubmit,
-    )
-    .unwrap();
-
-    let fov_vert = 90. * std::f32::consts::PI / 180.;
     let aspect = (dimensions[0] as f32) / (dimensions[1] as f32);
+    let proj = matrix::projection::perspective_fov(options.fov.radians(), aspect, 0.1, 80.);
+    // Skip drawing the chunk if none of it is on screen.
+    let planes = matrix::projection::frustum_planes(&(proj * view));
+    let chunk_model = chunk_model.filter(|model| model.in_frustum(&planes));
     let ubo = UniformBufferObject {
         model: Matrix::from([
             [0.0, 0.0, 0.0, 0.0],
@@ -499,7 +1682,7 @@ fn render_frame(
             [0.0, 0.0, 0.0, 0.0],
         ]),
         view,
-        proj: matrix::projection::perspective_fov(fov_vert, aspect, 0.1, 80.),
+        proj,
         /*
         proj: Matrix::from([
             [0.0, 0.0, 0.0, 0.0],
@@ -509,12 +1692,23 @@ fn render_frame(
         ]),
         */
         t,
+        ambient: magica::AMBIENT_LIGHT,
+        _padding: [0.0; 2],
+        light_dir: magica::light_direction(t),
     };
     let subbuffer_normal = Arc::new(uniform_buffer_pool.from_data(ubo.clone()).unwrap());
-    let subbuffer_lines = Arc::new(uniform_buffer_pool.from_data(ubo).unwrap());
+    let subbuffer_lines = Arc::new(uniform_buffer_pool.from_data(ubo.clone()).unwrap());
+    // The crosshair is drawn in pixels, straight onto the screen.
+    let crosshair_ubo = UniformBufferObject {
+        view: Matrix::identity(),
+        proj: crate::matrix::screen_matrix(dimensions[0], dimensions[1]),
+        ..ubo
+    };
+    let subbuffer_crosshair = Arc::new(uniform_buffer_pool.from_data(crosshair_ubo).unwrap());
+    let subbuffer_magica = Arc::new(uniform_buffer_pool.from_data(ubo).unwrap());
 
     let descriptor_set_normal = {
-        let layout = pipelines.normal_pipeline.layout().set_layouts()[0].clone();
+        let layout = pipelines.normal(wireframe).layout().set_layouts()[0].clone();
         {
             let write_descriptor_set = WriteDescriptorSet::buffer(0, subbuffer_normal);
             PersistentDescriptorSet::new(
@@ -537,6 +1731,26 @@ fn render_frame(
             .unwrap()
         }
     };
+    let descriptor_set_crosshair = {
+        let layout = pipelines.lines_pipeline.layout().set_layouts()[0].clone();
+        let write_descriptor_set = WriteDescriptorSet::buffer(0, subbuffer_crosshair);
+        PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            layout,
+            std::iter::once(write_descriptor_set),
+        )
+        .unwrap()
+    };
+    let descriptor_set_magica = {
+        let layout = pipelines.magica(wireframe).layout().set_layouts()[0].clone();
+        let write_descriptor_set = WriteDescriptorSet::buffer(0, subbuffer_magica);
+        PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            layout,
+            std::iter::once(write_descriptor_set),
+        )
+        .unwrap()
+    };
 
     trace!(target: "render_frame", "AutoCommandBufferBuilder");
 
@@ -546,235 +1760,162 @@ fn render_frame(
         depth_range: 0.0..1.0,
     };
 
-    // Don't need to do this every frame!
-    let vertex_buffer = CpuAccessibleBuffer::from_iter(
-        memory_allocator,
-        BufferUsage {
-            vertex_buffer: true,
-            ..BufferUsage::empty()
-        },
-        false,
-        vec![
-            /*
-            Vertex { position: [-0.5, -0.5] },
-            Vertex { position: [ 0.0,  0.5] },
-            Vertex { position: [ 0.5, -0.25] },
-            */
-            /*
-            Vertex { position: [-4., -4.] },
-            Vertex { position: [ 0.0,  4.] },
-            Vertex { position: [ 4., -2.] },
-            */
-            Vertex {
-                position: [-4., 0.],
-            },
-            Vertex { position: [0., 4.] },
-            Vertex { position: [4., 0.] },
-        ]
-        .into_iter(),
-    )
-    .unwrap();
-
-    let lines = {
-        let mut lines = vec![];
-        for i in -10i8..=10 {
-            lines.push(Line {
-                position: [f32::from(i), 0., -10.0],
-                color: [1., 0., 0.],
-            });
-            lines.push(Line {
-                position: [f32::from(i), 0., 10.0],
-                color: [1., 0., 0.],
-            });
-            lines.push(Line {
-                position: [10.0, 0., f32::from(i)],
-                color: [0., 0., 1.],
-            });
-            lines.push(Line {
-                position: [-10.0, 0., f32::from(i)],
-                color: [0., 0., 1.],
-            });
-        }
-        // The Axis indicators:
-        // X
-        lines.push(Line {
-            position: [0., 1., 0.],
-            color: [1., 0., 0.],
-        });
-        lines.push(Line {
-            position: [1., 1., 0.],
-            color: [1., 0., 0.],
-        });
-        // Y
-        lines.push(Line {
-            position: [0., 1., 0.],
-            color: [0., 1., 0.],
-        });
-        lines.push(Line {
-            position: [0., 2., 0.],
-            color: [0., 1., 0.],
-        });
-        // Z
-        lines.push(Line {
-            position: [0., 1., 0.],
-            color: [0., 0., 1.],
-        });
-        lines.push(Line {
-            position: [0., 1., 1.],
-            color: [0., 0., 1.],
-        });
-
-        lines
+    let atlas_view = hud_atlas_texture.view(hud_atlas, memory_allocator, builder);
+    let atlas_size = (hud_atlas.image().width(), hud_atlas.image().height());
+    let blits = glyph_quads_to_triangle_strip(hud_quads, atlas_size, HUD_ORIGIN);
+    let sdf_blits = sdf_quads_to_triangle_strip(hud_banner_quads, atlas_size, HUD_BANNER_ORIGIN);
+    // Rebuilt each frame, so that it stays centered when the window is resized.
+    let crosshair_vert_buf = vertex_buffer(memory_allocator, crosshair_lines(dimensions));
+    // There's nothing to draw (nor can we make an empty buffer) if the HUD is blank.
+    let blits_vert_buf = match blits.is_empty() {
+        true => None,
+        false => Some(vertex_buffer(memory_allocator, blits)),
     };
-
-    let lines_vert_buf = CpuAccessibleBuffer::from_iter(
-        memory_allocator,
-        BufferUsage {
-            vertex_buffer: true,
-            ..BufferUsage::empty()
-        },
-        false,
-        lines.into_iter(),
-    )
-    .unwrap();
-
-    let (image, (image_w, image_h)) = {
-        let t_image = text_rendering::render_text("Hello, world.", &mut resources.deja_vu, sw_image::Pixel { r: 0, g: 255, b: 0, a: 255}, &resources.deja_vu_cache).unwrap();
-        let rgba_pixel_data = CpuAccessibleBuffer::from_iter(
-            memory_allocator,
-            BufferUsage {
-                transfer_src: true,
-                ..BufferUsage::empty()
-            },
-            false, // host_cached
-            t_image.pixels().map(|p| (p.r, p.g, p.b, p.a)),
-        )
-        .unwrap();
-        let width = t_image.width();
-        let height = t_image.height();
-        let dimensions = vulkano::image::ImageDimensions::Dim2d {
-            width,
-            height,
-            array_layers: 1,
+    let sdf_blits_vert_buf = match sdf_blits.is_empty() {
+        true => None,
+        false => Some(vertex_buffer(memory_allocator, sdf_blits)),
+    };
+    // Both draw from the atlas, with the same uniforms.
+    let blit_descriptor_set = |pipeline: &GraphicsPipeline| {
+        let blit_uniform = BlitUniform {
+            proj: crate::matrix::screen_matrix(dimensions[0], dimensions[1]),
         };
-        let image = vulkano::image::ImmutableImage::from_buffer(
-            memory_allocator,
-            rgba_pixel_data,
-            dimensions,
-            vulkano::image::MipmapsCount::One,
-            vulkano::format::Format::R8G8B8A8_UNORM,
-            //vulkano::image::ImageLayout::ShaderReadOnlyOptimal,
-            &mut builder,
+        let subbuffer_blit = blit_uniform_buffer_pool.from_data(blit_uniform).unwrap();
+        let layout = pipeline.layout().set_layouts()[0].clone();
+        let write_buffer = WriteDescriptorSet::buffer(0, subbuffer_blit);
+        let sampler = texture_sampler(device);
+        let write_sampler = WriteDescriptorSet::image_view_sampler(1, atlas_view.clone(), sampler);
+        PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            layout,
+            [write_buffer, write_sampler],
         )
-        .unwrap();
-        (image, (width, height))
+        .unwrap()
     };
+    let descriptor_set_blits = blit_descriptor_set(&pipelines.blit_pipeline);
+    let descriptor_set_sdf_blits = blit_descriptor_set(&pipelines.blit_sdf_pipeline);
 
-    let blits_vert_buf = {
-        let blits = screen_quad_to_triangle_fan((32, 5), (image_w, image_h));
-
-        CpuAccessibleBuffer::from_iter(
-            memory_allocator,
-            BufferUsage {
-                vertex_buffer: true,
-                ..BufferUsage::empty()
-            },
-            false,
-            blits.into_iter(),
+    use debug_lines::DebugLinesAutoCmdExt;
+    use magica::instanced::InstancedMagicaAutoCmdExt;
+    use magica::MagicaAutoCmdExt;
+    // The draws are recorded into secondary command buffers, the chunks' on several threads.
+    let subpass = Subpass::from(framebuffer.render_pass().clone(), 0).unwrap();
+    let secondary_builder = || {
+        secondary::builder(
+            command_buffer_allocator,
+            queue.queue_family_index(),
+            subpass.clone(),
+            viewport.clone(),
         )
-        .unwrap()
     };
-    let descriptor_set_blits = {
-        let blit_uniform = BlitUniform {
-            proj: crate::matrix::screen_matrix(dimensions[0], dimensions[1]),
-        };
-        let subbuffer_blit = blit_uniform_buffer_pool.from_data(blit_uniform).unwrap();
-        let layout = pipelines.blit_pipeline.layout().set_layouts()[0].clone();
-        {
-            let write_buffer = WriteDescriptorSet::buffer(0, subbuffer_blit);
-            let sampler = vulkano::sampler::Sampler::new(
-                device.clone(),
-                vulkano::sampler::SamplerCreateInfo::simple_repeat_linear_no_mipmap(),
+    let mut secondaries = Vec::new();
+
+    trace!(target: "render_frame", "record scene");
+    let mut scene_builder = secondary_builder();
+    scene_builder
+        .bind_pipeline_graphics(pipelines.normal(wireframe).clone())
+        .bind_descriptor_sets(
+            PipelineBindPoint::Graphics,
+            pipelines.normal(wireframe).layout().clone(),
+            0,
+            descriptor_set_normal,
+        )
+        .bind_vertex_buffers(0, scene_buffers.triangle.clone())
+        .draw(scene_buffers.triangle.len().try_into().unwrap(), 1, 0, 0)
+        .unwrap();
+    if options.grid {
+        scene_builder.draw_lines(
+            pipelines.lines_pipeline.clone(),
+            descriptor_set_lines,
+            &scene_buffers.grid_lines,
+        );
+    }
+    scene_builder.draw_magica_instanced(
+        pipelines.magica_instanced(wireframe).clone(),
+        descriptor_set_magica.clone(),
+        magica_model,
+    );
+    secondaries.push(scene_builder.build().unwrap());
+
+    trace!(target: "render_frame", "record chunks");
+    let chunk_models = chunk_model.into_iter().collect::<Vec<_>>();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    secondaries.extend(secondary::record_in_parallel(
+        &chunk_models,
+        threads,
+        |models| {
+            let mut chunk_builder = secondary_builder();
+            for model in models {
+                chunk_builder.draw_magica(
+                    pipelines.magica(wireframe).clone(),
+                    descriptor_set_magica.clone(),
+                    descriptor_set_allocator,
+                    model,
+                );
+            }
+            chunk_builder.build().unwrap()
+        },
+    ));
+
+    // The HUD is drawn last, over everything else.
+    if options.hud {
+        let mut hud_builder = secondary_builder();
+        hud_builder
+            .bind_pipeline_graphics(pipelines.lines_pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                pipelines.lines_pipeline.layout().clone(),
+                0,
+                descriptor_set_crosshair,
             )
+            .bind_vertex_buffers(0, crosshair_vert_buf.clone())
+            .draw(crosshair_vert_buf.len().try_into().unwrap(), 1, 0, 0)
             .unwrap();
-            let image_view = vulkano::image::view::ImageView::new_default(image.clone()).unwrap();
-            let write_sampler = WriteDescriptorSet::image_view_sampler(1, image_view, sampler);
-            PersistentDescriptorSet::new(
-                descriptor_set_allocator,
-                layout,
-                [write_buffer, write_sampler],
-            )
-            .unwrap()
+        if let Some(blits_vert_buf) = blits_vert_buf {
+            hud_builder
+                .bind_pipeline_graphics(pipelines.blit_pipeline.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    pipelines.blit_pipeline.layout().clone(),
+                    0,
+                    descriptor_set_blits,
+                )
+                .bind_vertex_buffers(0, blits_vert_buf.clone())
+                .draw(blits_vert_buf.len().try_into().unwrap(), 1, 0, 0)
+                .unwrap();
         }
-    };
+        if let Some(sdf_blits_vert_buf) = sdf_blits_vert_buf {
+            hud_builder
+                .bind_pipeline_graphics(pipelines.blit_sdf_pipeline.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    pipelines.blit_sdf_pipeline.layout().clone(),
+                    0,
+                    descriptor_set_sdf_blits,
+                )
+                .bind_vertex_buffers(0, sdf_blits_vert_buf.clone())
+                .draw(sdf_blits_vert_buf.len().try_into().unwrap(), 1, 0, 0)
+                .unwrap();
+        }
+        secondaries.push(hud_builder.build().unwrap());
+    }
 
-    use magica::MagicaAutoCmdExt;
     trace!(target: "render_frame", "begin_render_pass");
     builder
         .begin_render_pass(
             {
                 let mut rpbi = RenderPassBeginInfo::framebuffer(framebuffer.clone());
                 // A shade of blue, to clear the color attachment of the framebuffer to.
-                rpbi.clear_values
-                    .push(Some(ClearValue::Float([0.0, 0.25, 1.0, 1.0])));
+                rpbi.clear_values = attachments.clear_values(options.clear_value());
                 rpbi
             },
-            SubpassContents::Inline,
-        )
-        .unwrap()
-        .set_viewport(0, [viewport])
-        .bind_pipeline_graphics(pipelines.normal_pipeline.clone())
-        .bind_descriptor_sets(
-            PipelineBindPoint::Graphics,
-            pipelines.normal_pipeline.layout().clone(),
-            0,
-            descriptor_set_normal,
-        )
-        .bind_vertex_buffers(0, vertex_buffer.clone())
-        .draw(vertex_buffer.len().try_into().unwrap(), 1, 0, 0)
-        .unwrap()
-        .bind_pipeline_graphics(pipelines.lines_pipeline.clone())
-        .bind_descriptor_sets(
-            PipelineBindPoint::Graphics,
-            pipelines.lines_pipeline.layout().clone(),
-            0,
-            descriptor_set_lines,
+            SubpassContents::SecondaryCommandBuffers,
         )
-        .bind_vertex_buffers(0, lines_vert_buf.clone())
-        .draw(lines_vert_buf.len().try_into().unwrap(), 1, 0, 0)
         .unwrap()
-        .draw_magica(pipelines.magica_pipeline.clone(), magica_model)
-        .bind_pipeline_graphics(pipelines.blit_pipeline.clone())
-        .bind_descriptor_sets(
-            PipelineBindPoint::Graphics,
-            pipelines.blit_pipeline.layout().clone(),
-            0,
-            descriptor_set_blits,
-        )
-        .bind_vertex_buffers(0, blits_vert_buf.clone())
-        .draw(blits_vert_buf.len().try_into().unwrap(), 1, 0, 0)
+        .execute_commands_from_vec(secondaries)
         .unwrap()
         .end_render_pass()
         .unwrap();
-
-    trace!(target: "render_frame", "build command buffer");
-    let command_buffer = builder.build().unwrap();
-
-    trace!(target: "render_frame", "scheduling command buffer");
-    let result = previous_frame_end
-        .join(acquire_future)
-        .then_execute(queue.clone(), command_buffer)
-        .expect("then_execute failed")
-        .then_swapchain_present(
-            queue.clone(),
-            SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), image_index),
-        )
-        .then_signal_fence_and_flush();
-    match result {
-        Ok(future) => RendererOutput::Rendering(Box::new(future)),
-        Err(FlushError::OutOfDate) => RendererOutput::SwapchainNeedsRecreating,
-        Err(err) => panic!("then_signal_fence_and_flush failed: {:?}", err),
-    }
 }
 
 mod vs {
@@ -870,11 +2011,14 @@ layout(binding = 0) uniform BlitUniform {
 
 layout(location = 0) in uvec2 position;
 layout(location = 1) in vec2 texture_coord;
+layout(location = 2) in vec4 color;
 layout(location = 0) out vec2 out_texture_coord;
+layout(location = 1) out vec4 out_color;
 
 void main() {
     gl_Position = ubo.proj * vec4(position.x, position.y, 0.0, 1.0);
     out_texture_coord = texture_coord;
+    out_color = color;
 }"
         }
     }
@@ -886,12 +2030,38 @@ void main() {
 #version 450
 
 layout(location = 0) in vec2 texCoord;
+layout(location = 1) in vec4 color;
 layout(location = 0) out vec4 f_color;
 layout(set = 0, binding = 1) uniform sampler2D texSampler;
 
 void main() {
     //f_color = vec4(0.0, 1.0, 0.0, 1.0);
-    f_color = texture(texSampler, texCoord);
+    // The texture is tinted by the vertex color; e.g., white glyphs become colored text.
+    f_color = texture(texSampler, texCoord) * color;
+}"
+        }
+    }
+
+    /// Like `fs`, but the texture's alpha is a distance field (see `text_rendering::sdf`), with
+    /// the glyph's edge at 0.5.
+    pub mod sdf_fs {
+        vulkano_shaders::shader! {
+            ty: "fragment",
+            src: "
+#version 450
+
+layout(location = 0) in vec2 texCoord;
+layout(location = 1) in vec4 color;
+layout(location = 0) out vec4 f_color;
+layout(set = 0, binding = 1) uniform sampler2D texSampler;
+
+void main() {
+    float field = texture(texSampler, texCoord).a;
+    // Anti-alias the edge over about a pixel, however much the field has been scaled: fwidth() is
+    // how much the field changes from one pixel to the next.
+    float smoothing = 0.5 * fwidth(field);
+    float alpha = smoothstep(0.5 - smoothing, 0.5 + smoothing, field);
+    f_color = vec4(color.rgb, color.a * alpha);
 }"
         }
     }
@@ -910,9 +2080,11 @@ vulkano::impl_vertex!(Vertex, position);
 struct BlitImageVertex {
     position: [u32; 2],
     texture_coord: [f32; 2],
+    /// The texture is multiplied by this color.
+    color: [f32; 4],
 }
 
-vulkano::impl_vertex!(BlitImageVertex, position, texture_coord);
+vulkano::impl_vertex!(BlitImageVertex, position, texture_coord, color);
 
 #[repr(C)]
 #[derive(Default, Clone, Copy, Pod, Zeroable)]
@@ -942,3 +2114,521 @@ impl From<sw_image::Pixel> for VulkanPixel {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use vulkano::image::SampleCount;
+
+    use super::sw_image::Pixel;
+    use super::text_rendering::atlas::{AtlasRegion, GlyphQuad, SdfGlyphQuad};
+    use super::timing::FpsCounter;
+    use super::{
+        blit, fs, lines, magica, vs, CameraConfig, FieldOfView, Look, Pipelines, Position,
+        RenderOptions,
+    };
+
+    #[test]
+    fn test_texture_sampler() {
+        let (device, _) = crate::init::test_device!();
+        // The sampler must be creatable whether or not the device supports anisotropy.
+        super::texture_sampler(&device);
+    }
+
+    #[test]
+    fn test_hud_text() {
+        let position = Position {
+            x: 1.0,
+            y: -2.5,
+            z: 10.25,
+        };
+        let look = Look {
+            rotation_horz: std::f32::consts::PI,
+            rotation_vert: -std::f32::consts::PI / 4.,
+        };
+        let mut fps_counter = FpsCounter::new(4);
+        fps_counter.record(Duration::from_millis(20));
+        fps_counter.record(Duration::from_millis(30));
+        assert_eq!(
+            super::hud_text(&position, &look, &fps_counter),
+            "x: 1.00, y: -2.50, z: 10.25\nlook: 180.0° horz, -45.0° vert\n40.0 FPS (25.00 ms)",
+        );
+        assert_eq!(
+            super::hud_text(&Position::default(), &Look::default(), &FpsCounter::new(4)),
+            "x: 0.00, y: 0.00, z: 0.00\nlook: 0.0° horz, 0.0° vert\n… FPS",
+        );
+    }
+
+    #[test]
+    fn test_save_and_load_camera() {
+        let path =
+            std::env::temp_dir().join(format!("voxel-test-camera-{}.yaml", std::process::id()));
+        let position = Position {
+            x: 3.5,
+            y: -1.25,
+            z: 100.,
+        };
+        let look = Look {
+            rotation_horz: 1.5,
+            rotation_vert: -0.25,
+        };
+        super::save_camera(&path, &position, &look).unwrap();
+        assert!(super::load_camera(&path) == (position, look));
+
+        // A corrupt file, or no file at all, means the initial camera.
+        std::fs::write(&path, "position: [").unwrap();
+        assert!(super::load_camera(&path) == super::initial_camera());
+        std::fs::remove_file(&path).unwrap();
+        assert!(super::load_camera(&path) == super::initial_camera());
+    }
+
+    #[test]
+    fn test_look_wraps_horizontally() {
+        let config = CameraConfig::default();
+        let in_range = |look: &Look| (0. ..=std::f32::consts::TAU).contains(&look.rotation_horz);
+        let mut look = Look::default();
+        look.cursor_moved(1_000_000, 0, &config);
+        assert!(in_range(&look));
+        look.cursor_moved(-3_000_000, 0, &config);
+        assert!(in_range(&look));
+
+        let mut look = Look::default();
+        look.cursor_moved(-90, 0, &config);
+        assert!((look.rotation_horz - 270f32.to_radians()).abs() < 1e-5);
+        look.cursor_moved(720, 0, &config);
+        assert!((look.rotation_horz - 270f32.to_radians()).abs() < 1e-5);
+
+        // Pitch is still clamped:
+        look.cursor_moved(0, 1_000_000, &config);
+        assert_eq!(look.rotation_vert, std::f32::consts::PI / 2.);
+        look.cursor_moved(0, -1_000_000, &config);
+        assert_eq!(look.rotation_vert, -std::f32::consts::PI / 2.);
+    }
+
+    #[test]
+    fn test_look_sensitivity() {
+        let moved = |xrel, yrel, config: &CameraConfig| {
+            let mut look = Look::default();
+            look.cursor_moved(xrel, yrel, config);
+            (look.rotation_horz, look.rotation_vert)
+        };
+        let config = CameraConfig::default();
+        let doubled = CameraConfig {
+            sensitivity: 2.,
+            ..config
+        };
+        let (horz, vert) = moved(10, 5, &config);
+        assert!((horz - 10f32.to_radians()).abs() < 1e-6);
+        assert!((vert - 5f32.to_radians()).abs() < 1e-6);
+        let (doubled_horz, doubled_vert) = moved(10, 5, &doubled);
+        assert!((doubled_horz - 2. * horz).abs() < 1e-6);
+        assert!((doubled_vert - 2. * vert).abs() < 1e-6);
+
+        let inverted = CameraConfig {
+            invert_y: true,
+            ..config
+        };
+        let (inverted_horz, inverted_vert) = moved(10, 5, &inverted);
+        assert_eq!(inverted_horz, horz);
+        assert_eq!(inverted_vert, -vert);
+    }
+
+    #[test]
+    fn test_fov_zoom() {
+        let projection = |fov: &FieldOfView| {
+            super::matrix::projection::perspective_fov(fov.radians(), 4. / 3., 0.1, 80.)
+        };
+        let mut fov = FieldOfView::new(90.);
+        let wide = projection(&fov);
+        fov.scrolled(3);
+        assert_eq!(fov.degrees(), 75.);
+        let narrow = projection(&fov);
+        assert!(wide != narrow);
+        // A narrower view magnifies the scene:
+        assert!(wide.row(1)[1].abs() < narrow.row(1)[1].abs());
+
+        // Zooming in stops at the narrowest FoV, & zooming out at the configured one:
+        fov.scrolled(100);
+        assert_eq!(fov.degrees(), super::MIN_FOV);
+        fov.scrolled(-100);
+        assert_eq!(fov.degrees(), 90.);
+        fov.scrolled(2);
+        fov.reset_zoom();
+        assert!(projection(&fov) == wide);
+
+        assert_eq!(FieldOfView::new(10.).degrees(), super::MIN_FOV);
+        assert_eq!(FieldOfView::new(170.).degrees(), super::MAX_FOV);
+    }
+
+    #[test]
+    fn test_render_options_default() {
+        let options = RenderOptions::default();
+        assert!(options.grid);
+        assert!(!options.wireframe);
+        assert!(options.hud);
+        assert_eq!(options.clear_color, super::config::DEFAULT_CLEAR_COLOR);
+        assert_eq!(options.fov.degrees(), super::config::DEFAULT_FOV);
+        // The default config gives the default options.
+        assert!(RenderOptions::new(&Default::default()) == options);
+    }
+
+    #[test]
+    fn test_lost_device_shuts_down() {
+        use vulkano::swapchain::AcquireError;
+        use vulkano::sync::FlushError;
+
+        use super::{RenderLost, RendererOutput};
+
+        let lost = |output: RendererOutput| match output {
+            RendererOutput::Lost(lost) => Some(lost),
+            _ => None,
+        };
+        // Losing the device or surface stops rendering, rather than panicking:
+        let acquire_errors = vec![
+            (AcquireError::DeviceLost, RenderLost::DeviceLost),
+            (AcquireError::SurfaceLost, RenderLost::SurfaceLost),
+        ];
+        for (err, expected) in acquire_errors {
+            assert_eq!(
+                lost(RendererOutput::from_acquire_error(err)),
+                Some(expected)
+            );
+        }
+        let flush_errors = vec![
+            (FlushError::DeviceLost, RenderLost::DeviceLost),
+            (FlushError::SurfaceLost, RenderLost::SurfaceLost),
+        ];
+        for (err, expected) in flush_errors {
+            assert_eq!(lost(RendererOutput::from_flush_error(err)), Some(expected));
+        }
+
+        // While an out of date swapchain is just recreated.
+        let output = RendererOutput::from_acquire_error(AcquireError::OutOfDate);
+        assert!(matches!(output, RendererOutput::SwapchainNeedsRecreating));
+        let output = RendererOutput::from_flush_error(FlushError::OutOfDate);
+        assert!(matches!(output, RendererOutput::SwapchainNeedsRecreating));
+    }
+
+    #[test]
+    fn test_clear_color() {
+        use vulkano::format::{ClearValue, Format};
+        use vulkano::image::view::ImageView;
+        use vulkano::image::AttachmentImage;
+        use vulkano::memory::allocator::StandardMemoryAllocator;
+
+        let config = super::config::Config {
+            clear_color: [0.5, 0.25, 0.125, 1.],
+            ..Default::default()
+        };
+        let options = RenderOptions::new(&config);
+        assert_eq!(options.clear_color, config.clear_color);
+
+        let (device, _) = crate::init::test_device!();
+        let memory_allocator = StandardMemoryAllocator::new_default(device);
+        let depth =
+            AttachmentImage::transient(&memory_allocator, [8, 8], Format::D16_UNORM).unwrap();
+        let attachments = crate::init::FrameAttachments {
+            multisampled_color: None,
+            depth: ImageView::new_default(depth).unwrap(),
+        };
+        let clear_values = attachments.clear_values(options.clear_value());
+        assert!(matches!(
+            clear_values[0],
+            Some(ClearValue::Float(color)) if color == [0.5, 0.25, 0.125, 1.]
+        ));
+    }
+
+    #[test]
+    fn test_crosshair_lines() {
+        use super::matrix::Vertex3d;
+
+        for &(width, height) in &[(800, 600), (1920, 1080), (31, 17)] {
+            let lines = super::crosshair_lines([width, height]);
+            let screen = super::matrix::screen_matrix(width, height);
+            let to_ndc = |line: &super::Line| {
+                let [x, y, z] = line.position;
+                screen * Vertex3d { x, y, z }
+            };
+            // A horizontal line, then a vertical one, each centered on the middle of the screen:
+            let ends = lines.iter().map(to_ndc).collect::<Vec<_>>();
+            assert!(ends[0].y == ends[1].y && ends[0].x < ends[1].x);
+            assert!(ends[2].x == ends[3].x && ends[2].y < ends[3].y);
+            for pair in ends.chunks(2) {
+                assert!(((pair[0].x + pair[1].x) / 2.).abs() < 1e-6);
+                assert!(((pair[0].y + pair[1].y) / 2.).abs() < 1e-6);
+            }
+            // … & always the same size, in pixels:
+            assert_eq!(lines[1].position[0] - lines[0].position[0], 16.);
+            assert_eq!(lines[3].position[1] - lines[2].position[1], 16.);
+        }
+    }
+
+    #[test]
+    fn test_glyph_quads_to_triangle_strip() {
+        let quad = |x, region_x| GlyphQuad {
+            x,
+            y: 2,
+            region: AtlasRegion {
+                x: region_x,
+                y: 0,
+                width: 8,
+                height: 16,
+                left: 0,
+                top: -12,
+            },
+            color: Pixel {
+                r: 255,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+        };
+        let strip =
+            super::glyph_quads_to_triangle_strip(&[quad(0, 0), quad(9, 32)], (64, 32), (10, 20));
+        let positions = strip.iter().map(|v| v.position).collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [
+                [10, 38],
+                [10, 22],
+                [18, 38],
+                [18, 22],
+                // Joining the two quads:
+                [18, 22],
+                [19, 38],
+                [19, 38],
+                [19, 22],
+                [27, 38],
+                [27, 22],
+            ],
+        );
+        assert_eq!(strip[7].texture_coord, [0.5, 0.]);
+        assert_eq!(strip[8].texture_coord, [0.625, 0.5]);
+        assert!(strip.iter().all(|v| v.color == [1., 0., 0., 1.]));
+    }
+
+    #[test]
+    fn test_parse_frames_in_flight() {
+        assert_eq!(super::parse_frames_in_flight("3"), Ok(3));
+        assert!(super::parse_frames_in_flight("0").is_err());
+        assert!(super::parse_frames_in_flight("-1").is_err());
+    }
+
+    #[test]
+    fn test_sdf_quads_to_triangle_strip() {
+        // Distance fields are drawn at the quad's size, not the region's.
+        let quad = SdfGlyphQuad {
+            x: 1,
+            y: -2,
+            width: 12,
+            height: 24,
+            region: AtlasRegion {
+                x: 32,
+                y: 16,
+                width: 32,
+                height: 16,
+                left: 0,
+                top: 0,
+            },
+            color: Pixel {
+                r: 255,
+                g: 255,
+                b: 255,
+                a: 255,
+            },
+        };
+        let strip = super::sdf_quads_to_triangle_strip(&[quad], (64, 32), (10, 20));
+        let positions = strip.iter().map(|v| v.position).collect::<Vec<_>>();
+        assert_eq!(positions, [[11, 42], [11, 18], [23, 42], [23, 18]]);
+        assert_eq!(strip[0].texture_coord, [0.5, 1.]);
+        assert_eq!(strip[3].texture_coord, [1., 0.5]);
+    }
+
+    #[test]
+    fn test_render_headless() {
+        let (device, queue) = crate::init::test_device!();
+        let image = super::render_headless(
+            device,
+            queue,
+            [64, 48],
+            SampleCount::Sample4,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert_eq!((image.width(), image.height()), (64, 48));
+    }
+
+    #[test]
+    fn test_frames_reuse_scene_buffers() {
+        use vulkano::buffer::cpu_pool::CpuBufferPool;
+        use vulkano::command_buffer::{
+            AutoCommandBufferBuilder, CommandBufferUsage, PrimaryCommandBufferAbstract,
+        };
+        use vulkano::sync::GpuFuture;
+
+        let created = || super::VERTEX_BUFFERS_CREATED.with(|created| created.get());
+        let (device, queue) = crate::init::test_device!();
+        let offscreen =
+            crate::init::Offscreen::init(device.clone(), [64, 48], SampleCount::Sample1).unwrap();
+        let pipelines = super::Shaders::load(device.clone()).pipelines(
+            device.clone(),
+            offscreen.render_pass.clone(),
+            offscreen.samples,
+        );
+        let magica_model = super::logo_model(&offscreen.memory_allocator);
+        let before = created();
+        let scene_buffers = super::SceneBuffers::new(&offscreen.memory_allocator);
+        assert!(created() - before == 1);
+        let uniform_buffer_pool = CpuBufferPool::uniform_buffer(offscreen.memory_allocator.clone());
+        let blit_uniform_buffer_pool =
+            CpuBufferPool::uniform_buffer(offscreen.memory_allocator.clone());
+        let hud_atlas = super::text_rendering::atlas::GlyphAtlas::new(
+            super::HUD_ATLAS_SIZE,
+            super::HUD_ATLAS_SIZE,
+        );
+        let mut hud_atlas_texture = super::AtlasTexture::new();
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &offscreen.command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        let before = created();
+        for frame in 0..3 {
+            super::record_frame(
+                &mut builder,
+                &device,
+                &queue,
+                &offscreen.framebuffer,
+                &offscreen.frame_attachments,
+                &offscreen.memory_allocator,
+                &offscreen.descriptor_set_allocator,
+                &offscreen.command_buffer_allocator,
+                &pipelines,
+                &RenderOptions::default(),
+                &scene_buffers,
+                &uniform_buffer_pool,
+                &blit_uniform_buffer_pool,
+                frame as f32,
+                crate::matrix::Matrix::identity(),
+                &hud_atlas,
+                &mut hud_atlas_texture,
+                &[],
+                &[],
+                &magica_model,
+                None,
+            );
+        }
+        // Only the crosshair's vertices (the HUD being blank) are uploaded afresh each frame; the
+        // triangle's & the grid's are drawn from the scene buffers.
+        assert!(created() - before == 3);
+        builder
+            .build()
+            .unwrap()
+            .execute(queue)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_depth_tested_pipelines_build() {
+        let (device, _queue) = crate::init::test_device!();
+        let render_pass = crate::init::test_render_pass(device.clone());
+        assert!(render_pass.subpasses()[0]
+            .depth_stencil_attachment
+            .is_some());
+
+        let pipelines = super::Shaders::load(device.clone()).pipelines(
+            device,
+            render_pass,
+            SampleCount::Sample1,
+        );
+        for pipeline in &[
+            &pipelines.normal_pipeline,
+            &pipelines.lines_pipeline,
+            &pipelines.magica_pipeline,
+            &pipelines.magica_instanced_pipeline,
+        ] {
+            let depth_stencil_state = pipeline.depth_stencil_state().unwrap();
+            assert!(depth_stencil_state.depth.is_some());
+        }
+    }
+
+    #[test]
+    fn test_multisampled_pipelines_build() {
+        let (device, _queue) = crate::init::test_device!();
+        let properties = device.physical_device().properties();
+        if !(properties.framebuffer_color_sample_counts.sample4
+            && properties.framebuffer_depth_sample_counts.sample4)
+        {
+            eprintln!("4x multisampling isn't supported; skipping");
+            return;
+        }
+        let offscreen =
+            crate::init::Offscreen::init(device.clone(), [64, 48], SampleCount::Sample4).unwrap();
+        assert!(offscreen.samples == SampleCount::Sample4);
+        assert!(offscreen.frame_attachments.multisampled_color.is_some());
+
+        let pipelines = super::Shaders::load(device.clone()).pipelines(
+            device,
+            offscreen.render_pass.clone(),
+            SampleCount::Sample4,
+        );
+        for pipeline in &[
+            &pipelines.normal_pipeline,
+            &pipelines.lines_pipeline,
+            &pipelines.blit_pipeline,
+            &pipelines.blit_sdf_pipeline,
+            &pipelines.magica_pipeline,
+            &pipelines.magica_instanced_pipeline,
+        ] {
+            let multisample_state = pipeline.multisample_state().unwrap();
+            assert!(multisample_state.rasterization_samples == SampleCount::Sample4);
+        }
+    }
+
+    #[test]
+    fn test_wireframe_pipelines_build() {
+        let (device, _queue) = crate::init::test_device!();
+        let render_pass = crate::init::test_render_pass(device.clone());
+
+        let pipelines = Pipelines::new(
+            device.clone(),
+            render_pass,
+            SampleCount::Sample1,
+            &vs::load(device.clone()).unwrap(),
+            &fs::load(device.clone()).unwrap(),
+            &lines::vs::load(device.clone()).unwrap(),
+            &lines::fs::load(device.clone()).unwrap(),
+            &blit::vs::load(device.clone()).unwrap(),
+            &blit::fs::load(device.clone()).unwrap(),
+            &blit::sdf_fs::load(device.clone()).unwrap(),
+            &magica::MagicaShaders::load(device.clone()),
+        );
+        assert_eq!(
+            pipelines.wireframe.is_some(),
+            device.enabled_features().fill_mode_non_solid,
+        );
+        // Without wireframe support, asking for it falls back to the filled pipelines.
+        if pipelines.wireframe.is_none() {
+            assert!(Arc::ptr_eq(
+                pipelines.normal(true),
+                &pipelines.normal_pipeline
+            ));
+            assert!(Arc::ptr_eq(
+                pipelines.magica(true),
+                &pipelines.magica_pipeline
+            ));
+            assert!(Arc::ptr_eq(
+                pipelines.magica_instanced(true),
+                &pipelines.magica_instanced_pipeline
+            ));
+        }
+    }
+}
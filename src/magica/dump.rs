@@ -0,0 +1,117 @@
+//! Dumping a parsed .vox file as JSON, for inspecting unfamiliar models.
+
+use std::io::{self, Write};
+
+use serde_json::{json, Value};
+
+use super::io::{Chunk, ChunkData, Color, MaterialType};
+
+/// Write the chunk hierarchy under `chunk` to `w`, as pretty-printed JSON.
+///
+/// Each chunk is an object with its `id` (e.g., `"MAIN"`), the fields parsed out of it, & its
+/// `children`, if it has any. Voxel data is summarized by its count, rather than listed.
+pub fn dump_json<W: Write>(w: W, chunk: &Chunk) -> io::Result<()> {
+    serde_json::to_writer_pretty(w, &chunk_to_json(chunk))?;
+    Ok(())
+}
+
+fn chunk_to_json(chunk: &Chunk) -> Value {
+    let mut object = match &chunk.data {
+        ChunkData::Main => json!({ "id": "MAIN" }),
+        ChunkData::Size {
+            size_x,
+            size_y,
+            size_z,
+        } => json!({
+            "id": "SIZE",
+            "size": [size_x, size_y, size_z],
+        }),
+        ChunkData::Xyzi { voxels } => json!({
+            "id": "XYZI",
+            "voxel_count": voxels.len(),
+        }),
+        ChunkData::Rgba { palette } => json!({
+            "id": "RGBA",
+            "palette": palette.iter().copied().map(color_to_hex).collect::<Vec<_>>(),
+        }),
+        ChunkData::Matl(matl) => json!({
+            "id": "MATL",
+            "material_id": matl.material_id,
+            "type": matl.material_type.as_ref().map(material_type_name),
+            "weight": matl.weight,
+            "rough": matl.rough,
+            "spec": matl.spec,
+            "ior": matl.ior,
+            "att": matl.att,
+            "flux": matl.flux,
+            "extra": matl.extra,
+        }),
+        ChunkData::Unknown(unknown) => json!({
+            "id": unknown.chunk_id.to_string(),
+            "content_len": unknown.content.len(),
+        }),
+    };
+    if !chunk.children.is_empty() {
+        object["children"] = chunk.children.iter().map(chunk_to_json).collect();
+    }
+    object
+}
+
+/// A color as `#rrggbbaa`, which is easier to read (& to paste into a color picker) than the
+/// components listed out.
+fn color_to_hex(color: Color) -> String {
+    format!(
+        "#{:02x}{:02x}{:02x}{:02x}",
+        color.r, color.g, color.b, color.a
+    )
+}
+
+fn material_type_name(material_type: &MaterialType) -> &'static str {
+    match material_type {
+        MaterialType::Diffuse => "diffuse",
+        MaterialType::Metal => "metal",
+        MaterialType::Glass => "glass",
+        MaterialType::Emit => "emit",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use serde_json::Value;
+
+    use super::dump_json;
+    use crate::magica::io::from_reader;
+
+    #[test]
+    fn test_dump_logo() {
+        let logo = from_reader(Cursor::new(&include_bytes!("../vox/logo.vox")[..])).unwrap();
+        let mut dumped = Vec::new();
+        dump_json(&mut dumped, &logo).unwrap();
+        let json = serde_json::from_slice::<Value>(&dumped).unwrap();
+
+        assert_eq!(json["id"], "MAIN");
+        let children = json["children"].as_array().unwrap();
+        let child = |id: &str| {
+            children
+                .iter()
+                .find(|child| child["id"] == id)
+                .unwrap_or_else(|| panic!("MAIN has no {} child", id))
+        };
+        let voxels = crate::magica::find_xyzi_data(&logo).unwrap();
+        assert_eq!(child("XYZI")["voxel_count"], voxels.len());
+        assert!(child("XYZI").get("voxels").is_none());
+        let palette = child("RGBA")["palette"].as_array().unwrap();
+        assert_eq!(palette.len(), 256);
+        let first = crate::magica::find_rgba_data(&logo).unwrap()[0];
+        assert_eq!(
+            palette[0],
+            format!(
+                "#{:02x}{:02x}{:02x}{:02x}",
+                first.r, first.g, first.b, first.a
+            )
+        );
+        assert_eq!(child("SIZE")["size"].as_array().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,76 @@
+//! Conversion of MagicaVoxel models into map chunks.
+
+use std::sync::Arc;
+
+use voxel_map::{ChunkRelativeCoord, CHUNK_SIDE_LENGTH};
+use voxel_mod::ModuleBlockDefinition;
+
+use super::io::{Chunk, Color};
+
+/// Build a map chunk out of the voxels in a loaded MagicaVoxel model.
+///
+/// `resolve_block` maps each voxel's palette index (and the color at that index) to the block to
+/// place in the chunk; returning `None` leaves that voxel empty. The model must fit within a
+/// single chunk; models larger than [`CHUNK_SIDE_LENGTH`] on any axis are an error.
+pub fn to_map_chunk<F>(top_chunk: &Chunk, mut resolve_block: F) -> anyhow::Result<voxel_map::Chunk>
+where
+    F: FnMut(u8, Color) -> Option<Arc<ModuleBlockDefinition>>,
+{
+    let voxels = super::find_xyzi_data(top_chunk)?;
+    let palette = super::find_rgba_data(top_chunk)?;
+
+    let mut chunk = voxel_map::Chunk::new();
+    for voxel in voxels {
+        if CHUNK_SIDE_LENGTH <= voxel.x
+            || CHUNK_SIDE_LENGTH <= voxel.y
+            || CHUNK_SIDE_LENGTH <= voxel.z
+        {
+            anyhow::bail!(
+                "voxel at ({}, {}, {}) lies outside a {}³ chunk",
+                voxel.x,
+                voxel.y,
+                voxel.z,
+                CHUNK_SIDE_LENGTH,
+            );
+        }
+        let color = palette
+            .get(usize::from(voxel.color_index))
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("no palette entry for index {}", voxel.color_index))?;
+        let block = resolve_block(voxel.color_index, color);
+        chunk.set_block(ChunkRelativeCoord::new(voxel.x, voxel.y, voxel.z), block);
+    }
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use voxel_mod::Module;
+
+    use super::super::io::from_reader;
+
+    static LOGO: &[u8] = include_bytes!("../vox/logo.vox");
+
+    static MINIMAL_MOD_BLOCK_DEFS: &str = r#"
+dirt:
+  texture: dirt.png
+  color:
+    r: 143
+    g: 86
+    b: 59
+  homogeneous: true
+"#;
+
+    #[test]
+    fn test_import_logo() {
+        let logo = from_reader(Cursor::new(LOGO)).expect("logo.vox should parse");
+        let voxel_count = super::super::find_xyzi_data(&logo).unwrap().len();
+
+        let module = Module::for_test(&MINIMAL_MOD_BLOCK_DEFS[1..]);
+        let dirt = module.block_by_id("dirt").unwrap();
+        let chunk = super::to_map_chunk(&logo, |_, _| Some(dirt.clone())).unwrap();
+        assert!(chunk.count_non_empty() == voxel_count);
+    }
+}
@@ -0,0 +1,347 @@
+//! Rather than expanding every voxel into its own cube of vertexes (as [`MagicaModel`] does), an
+//! [`InstancedMagicaModel`] uploads a single unit cube, and a buffer with one instance — a
+//! position & color — per voxel; the cube is then drawn once per instance. Each voxel costs one
+//! small instance, rather than 24 vertexes & 36 indexes.
+//!
+//! [`MagicaModel`]: super::MagicaModel
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, TypedBufferAccess};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::Device;
+use vulkano::memory::allocator::MemoryAllocator;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{PolygonMode, RasterizationState};
+use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use vulkano::render_pass::{RenderPass, Subpass};
+
+use super::io::{Chunk, Color, Voxel};
+use super::{find_rgba_data, find_xyzi_data, push_cube, CubeVertex, MagicaShaders};
+use crate::model_util::{IndexBuffer, ModelBuilder};
+
+/// A MagicaVoxel model that's been uploaded to the GPU, as instances of a cube.
+pub struct InstancedMagicaModel {
+    cube_vertex_buffer: Arc<CpuAccessibleBuffer<[CubeMeshVertex]>>,
+    cube_index_buffer: IndexBuffer,
+    instance_buffer: Arc<CpuAccessibleBuffer<[VoxelInstance]>>,
+}
+
+impl InstancedMagicaModel {
+    pub fn new(
+        memory_allocator: &(impl MemoryAllocator + ?Sized),
+        top_chunk: &Chunk,
+    ) -> anyhow::Result<InstancedMagicaModel> {
+        let voxels = find_xyzi_data(top_chunk)?;
+        let palette = find_rgba_data(top_chunk)?;
+        let instances = voxel_instances(voxels, palette)?;
+        if instances.is_empty() {
+            anyhow::bail!("model has no voxels to draw");
+        }
+
+        let (cube_vertex_buffer, cube_index_buffer) = unit_cube().into_gpu(
+            memory_allocator,
+            |((x, y, z, (), _), normal)| CubeMeshVertex {
+                position: [f32::from(x), f32::from(y), f32::from(z)],
+                normal: normal.map(f32::from),
+            },
+            false,
+        );
+        let instance_buffer = CpuAccessibleBuffer::from_iter(
+            memory_allocator,
+            BufferUsage {
+                vertex_buffer: true,
+                ..BufferUsage::empty()
+            },
+            false,
+            instances,
+        )
+        .unwrap();
+
+        Ok(InstancedMagicaModel {
+            cube_vertex_buffer,
+            cube_index_buffer,
+            instance_buffer,
+        })
+    }
+}
+
+/// The mesh of a cube with sides of 1, with its corner at the origin. (Every voxel shares it, so
+/// none are occluded by their neighbors.)
+fn unit_cube() -> ModelBuilder<CubeVertex<()>> {
+    let mut model_builder = ModelBuilder::new();
+    push_cube(&mut model_builder, (0, 0, 0), 1, 1, (), &|_| false);
+    model_builder
+}
+
+/// An instance for each voxel that's visible, i.e., that isn't hidden on all six sides by other
+/// voxels.
+fn voxel_instances(voxels: &[Voxel], palette: &[Color]) -> anyhow::Result<Vec<VoxelInstance>> {
+    let occupied = voxels
+        .iter()
+        .map(|voxel| (voxel.x, voxel.y, voxel.z))
+        .collect::<HashSet<_>>();
+    let is_occupied = |voxel: &Voxel, (dx, dy, dz): (i16, i16, i16)| {
+        let neighbor = (
+            u8::try_from(i16::from(voxel.x) + dx),
+            u8::try_from(i16::from(voxel.y) + dy),
+            u8::try_from(i16::from(voxel.z) + dz),
+        );
+        match neighbor {
+            (Ok(x), Ok(y), Ok(z)) => occupied.contains(&(x, y, z)),
+            // Nothing's beyond the edge of the model.
+            _ => false,
+        }
+    };
+    let neighbors = [
+        (-1, 0, 0),
+        (1, 0, 0),
+        (0, -1, 0),
+        (0, 1, 0),
+        (0, 0, -1),
+        (0, 0, 1),
+    ];
+
+    let mut instances = Vec::new();
+    for voxel in voxels {
+        if neighbors.iter().all(|n| is_occupied(voxel, *n)) {
+            continue;
+        }
+        let color = palette.get(usize::from(voxel.color_index)).ok_or_else(|| {
+            anyhow::anyhow!("no palette entry for color index {}", voxel.color_index)
+        })?;
+        instances.push(VoxelInstance {
+            offset: [f32::from(voxel.x), f32::from(voxel.y), f32::from(voxel.z)],
+            color: [u32::from(color.r), u32::from(color.g), u32::from(color.b)],
+        });
+    }
+    Ok(instances)
+}
+
+pub(crate) fn build_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    multisample_state: MultisampleState,
+    polygon_mode: PolygonMode,
+    shaders: &MagicaShaders,
+) -> Arc<GraphicsPipeline> {
+    GraphicsPipeline::start()
+        // Binding 0 is the cube, per vertex; binding 1, the voxels, per instance.
+        .vertex_input_state(
+            BuffersDefinition::new()
+                .vertex::<CubeMeshVertex>()
+                .instance::<VoxelInstance>(),
+        )
+        .vertex_shader(shaders.instanced_vs.entry_point("main").unwrap(), ())
+        .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+        // Instances are lit & colored just as expanded models are.
+        .fragment_shader(shaders.fs.entry_point("main").unwrap(), ())
+        .multisample_state(multisample_state)
+        .rasterization_state(RasterizationState {
+            polygon_mode,
+            ..Default::default()
+        })
+        .depth_stencil_state(DepthStencilState::simple_depth_test())
+        .render_pass(Subpass::from(render_pass, 0).unwrap())
+        .build(device)
+        .unwrap()
+}
+
+pub(crate) trait InstancedMagicaAutoCmdExt {
+    fn draw_magica_instanced(
+        &mut self,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        model: &InstancedMagicaModel,
+    ) -> &mut Self;
+}
+
+impl<L> InstancedMagicaAutoCmdExt for AutoCommandBufferBuilder<L> {
+    fn draw_magica_instanced(
+        &mut self,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        model: &InstancedMagicaModel,
+    ) -> &mut AutoCommandBufferBuilder<L> {
+        let layout = pipeline.layout().clone();
+        self.bind_pipeline_graphics(pipeline)
+            .bind_descriptor_sets(PipelineBindPoint::Graphics, layout, 0, descriptor_set)
+            .bind_vertex_buffers(
+                0,
+                (
+                    model.cube_vertex_buffer.clone(),
+                    model.instance_buffer.clone(),
+                ),
+            );
+        model.cube_index_buffer.bind(self);
+        let instance_count = u32::try_from(model.instance_buffer.len()).unwrap();
+        self.draw_indexed(
+            u32::try_from(model.cube_index_buffer.len()).unwrap(),
+            instance_count,
+            0, // first_index
+            0, // vertex_offset
+            0, // first_instance
+        )
+        .unwrap()
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Zeroable, Pod)]
+struct CubeMeshVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+}
+
+vulkano::impl_vertex!(CubeMeshVertex, position, normal);
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug, Zeroable, Pod)]
+struct VoxelInstance {
+    /// The corner of the voxel nearest the origin.
+    offset: [f32; 3],
+    color: [u32; 3],
+}
+
+vulkano::impl_vertex!(VoxelInstance, offset, color);
+
+pub(super) mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "\
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    float t;
+    float ambient;
+    vec3 light_dir;
+} ubo;
+
+// Per vertex, of the cube:
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+// Per instance, i.e., voxel:
+layout(location = 2) in vec3 offset;
+layout(location = 3) in uvec3 color;
+
+layout(location = 0) out vec3 color_out;
+layout(location = 1) out vec3 normal_out;
+layout(location = 2) out float ao_out;
+
+void main() {
+    gl_Position = ubo.proj * ubo.view * vec4(position + offset, 1.0);
+    color_out = vec3(color.r / 255.0, color.g / 255.0, color.b / 255.0);
+    normal_out = normal;
+    ao_out = 1.0;
+}"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vulkano::buffer::TypedBufferAccess;
+    use vulkano::memory::allocator::StandardMemoryAllocator;
+
+    use super::super::io::{Color, Voxel};
+    use super::{CubeMeshVertex, VoxelInstance};
+
+    fn voxel(x: u8, y: u8, z: u8, color_index: u8) -> Voxel {
+        Voxel {
+            x,
+            y,
+            z,
+            color_index,
+        }
+    }
+
+    #[test]
+    fn test_voxel_instances() {
+        let palette = [
+            Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+            Color {
+                r: 10,
+                g: 20,
+                b: 30,
+                a: 255,
+            },
+        ];
+        // A solid 3×3×3 cube, whose middle is hidden, plus a lone voxel.
+        let mut voxels = Vec::new();
+        for x in 0..3 {
+            for y in 0..3 {
+                for z in 0..3 {
+                    voxels.push(voxel(x, y, z, 0));
+                }
+            }
+        }
+        voxels.push(voxel(10, 0, 0, 1));
+
+        let instances = super::voxel_instances(&voxels, &palette).unwrap();
+        // The 26 voxels on the cube's surface, & the lone one.
+        assert_eq!(instances.len(), 27);
+        assert!(!instances.iter().any(|i| i.offset == [1., 1., 1.]));
+        let lone = instances.last().unwrap();
+        assert_eq!(lone.offset, [10., 0., 0.]);
+        assert_eq!(lone.color, [10, 20, 30]);
+
+        // A color index with no palette entry is an error.
+        assert!(super::voxel_instances(&[voxel(0, 0, 0, 2)], &palette).is_err());
+    }
+
+    #[test]
+    fn test_instanced_buffers_are_smaller() {
+        let vox_file = include_bytes!("../vox/logo.vox");
+        let top_chunk = super::super::io::from_reader(std::io::Cursor::new(&vox_file[..])).unwrap();
+        let voxels = super::find_xyzi_data(&top_chunk).unwrap();
+        let palette = super::find_rgba_data(&top_chunk).unwrap();
+
+        // Indexes are at least 16 bits.
+        let index_size = std::mem::size_of::<u16>();
+        let expanded = super::super::mesh_voxels(voxels);
+        let expanded_size = expanded.vertexes().len()
+            * std::mem::size_of::<super::super::MagicaVertex>()
+            + expanded.index_count() * index_size;
+        let cube = super::unit_cube();
+        let instances = super::voxel_instances(voxels, palette).unwrap();
+        let instanced_size = cube.vertexes().len() * std::mem::size_of::<CubeMeshVertex>()
+            + cube.index_count() * index_size
+            + instances.len() * std::mem::size_of::<VoxelInstance>();
+        assert!(
+            instanced_size * 4 < expanded_size,
+            "instanced: {}B, expanded: {}B",
+            instanced_size,
+            expanded_size
+        );
+    }
+
+    #[test]
+    fn test_instanced_model_through_allocator() {
+        let (device, _) = crate::init::test_device!();
+        let memory_allocator = StandardMemoryAllocator::new_default(device);
+        let vox_file = include_bytes!("../vox/logo.vox");
+        let top_chunk = super::super::io::from_reader(std::io::Cursor::new(&vox_file[..])).unwrap();
+        let model = super::InstancedMagicaModel::new(&memory_allocator, &top_chunk).unwrap();
+        assert_eq!(model.cube_vertex_buffer.len(), 6 * 4);
+        assert_eq!(model.cube_index_buffer.len(), 6 * 6);
+        // One instance per visible voxel.
+        let voxels = super::find_xyzi_data(&top_chunk).unwrap();
+        let palette = super::find_rgba_data(&top_chunk).unwrap();
+        let visible = super::voxel_instances(voxels, palette).unwrap().len();
+        assert_eq!(model.instance_buffer.len(), visible as u64);
+    }
+}
@@ -7,9 +7,36 @@ use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use std::io::{self, Read, Seek};
+use std::path::Path;
+
+/// The default limit on the number of chunks [`from_reader`] will parse out of a single file.
+///
+/// Real files have, at most, a few thousand chunks; this is generous enough to never matter for
+/// legitimate files, while still bounding the work done on a malicious one.
+pub const DEFAULT_MAX_CHUNKS: usize = 1 << 20;
 
 /// Read a MagicaVoxel .VOX file from the given `Read`
-pub fn from_reader<R: Read + Seek>(mut reader: R) -> io::Result<Chunk> {
+///
+/// Malformed files are reported as `InvalidData` (or `UnexpectedEof`) errors; parsing should never
+/// panic, whatever the input. (The remaining `expect`s below are on internal invariants, not on
+/// the file's contents.)
+pub fn from_reader<R: Read + Seek>(reader: R) -> io::Result<Chunk> {
+    from_reader_with_max_chunks(reader, DEFAULT_MAX_CHUNKS)
+}
+
+/// Read the MagicaVoxel .VOX file at `path`; see [`from_reader`].
+pub fn load_file<P: AsRef<Path>>(path: P) -> io::Result<Chunk> {
+    let file = std::fs::File::open(path)?;
+    from_reader(io::BufReader::new(file))
+}
+
+/// Read a MagicaVoxel .VOX file from the given `Read`, parsing at most `max_chunks` chunks.
+///
+/// If the file contains more chunks than that, an `InvalidData` error is returned.
+pub fn from_reader_with_max_chunks<R: Read + Seek>(
+    mut reader: R,
+    max_chunks: usize,
+) -> io::Result<Chunk> {
     parse_header(&mut reader)?;
 
     let mut chunk_stack = Vec::<ParseState>::new();
@@ -96,8 +123,11 @@ pub fn from_reader<R: Read + Seek>(mut reader: R) -> io::Result<Chunk> {
             chunk,
         });
         steps += 1;
-        if steps > 400 {
-            panic!("...");
+        if steps > max_chunks {
+            return Err(invalid_data(format!(
+                "file contained more than the maximum of {} chunks",
+                max_chunks
+            )));
         }
     };
 
@@ -112,7 +142,7 @@ struct ParseState {
 /// Read the b"VOX [version]" header.
 fn parse_header<R: Read>(mut reader: R) -> io::Result<()> {
     let mut buf = [0u8; 8];
-    let bytes_read = reader.read_exact(&mut buf)?;
+    reader.read_exact(&mut buf)?;
     if &buf[..4] != b"VOX " {
         Err(invalid_data(".vox magic not found"))
     } else if 150
@@ -153,20 +183,19 @@ pub struct UnknownChunk {
 
 #[derive(Debug)]
 pub struct MatlChunk {
-    material_id: i32,
+    pub material_id: i32,
     // TODO: even _type doesn't seem to always be present. What's a material with no type?
-    material_type: Option<MaterialType>,
-    // TODO: these don't always seem to be present; the docs on the format don't say anything about
-    // when to expect them.
-    /*
-    weight: Option<f64>,
-    rough: Option<f64>,
-    spec: Option<f64>,
-    ior: Option<f64>,
-    att: Option<f64>,
-    flux: Option<f64>,
-    */
-    extra: HashMap<String, String>,
+    pub material_type: Option<MaterialType>,
+    // These don't always seem to be present; the docs on the format don't say anything about when
+    // to expect them, so each is `None` if it's missing.
+    pub weight: Option<f64>,
+    pub rough: Option<f64>,
+    pub spec: Option<f64>,
+    pub ior: Option<f64>,
+    pub att: Option<f64>,
+    pub flux: Option<f64>,
+    /// Any other properties in the chunk's DICT, unparsed.
+    pub extra: HashMap<String, String>,
 }
 
 #[derive(Debug)]
@@ -203,6 +232,37 @@ pub struct Color {
     pub a: u8,
 }
 
+impl Color {
+    /// An opaque color.
+    pub fn from_rgb([r, g, b]: [u8; 3]) -> Color {
+        Color { r, g, b, a: 255 }
+    }
+
+    /// The color's RGB components, from 0 to 1.
+    pub fn to_f32_rgb(self) -> [f32; 3] {
+        [self.r, self.g, self.b].map(channel_to_f32)
+    }
+
+    /// The color's RGBA components, from 0 to 1.
+    pub fn to_f32_rgba(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a].map(channel_to_f32)
+    }
+
+    /// The color with the given RGBA components, from 0 to 1; out-of-range components are clamped.
+    pub fn from_f32(rgba: [f32; 4]) -> Color {
+        let [r, g, b, a] = rgba.map(channel_from_f32);
+        Color { r, g, b, a }
+    }
+}
+
+fn channel_to_f32(c: u8) -> f32 {
+    f32::from(c) / 255.
+}
+
+fn channel_from_f32(c: f32) -> u8 {
+    (c.clamp(0., 1.) * 255.).round() as u8
+}
+
 #[derive(Clone, Debug)]
 pub struct Voxel {
     pub x: u8,
@@ -230,11 +290,34 @@ impl fmt::Debug for ChunkId {
                 _ => write!(f, "0x{:02x}", b)?,
             }
         }
-        write!(f, "[")?;
+        write!(f, "]")?;
+        Ok(())
+    }
+}
+
+/// Shows the ID as the ASCII string it (usually) is, e.g., `MAIN`; other bytes are escaped, as in
+/// a Rust byte string.
+impl fmt::Display for ChunkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            for escaped in std::ascii::escape_default(b) {
+                write!(f, "{}", char::from(escaped))?;
+            }
+        }
         Ok(())
     }
 }
 
+impl ChunkId {
+    /// The chunk ID spelled by `s`, which must be exactly 4 ASCII characters; e.g., `"XYZI"`.
+    pub fn from_ascii(s: &str) -> Option<ChunkId> {
+        if !s.is_ascii() {
+            return None;
+        }
+        s.as_bytes().try_into().ok().map(ChunkId)
+    }
+}
+
 impl PartialEq<[u8]> for ChunkId {
     fn eq(&self, other: &[u8]) -> bool {
         self.0 == other
@@ -272,12 +355,9 @@ fn parse_chunk<R: Read>(
     chunk_id: ChunkId,
     chunk_content_len: u32,
 ) -> io::Result<Chunk> {
-    let content_len = chunk_content_len
-        .try_into()
-        .expect("could not convert chunk content length into usize");
-    let mut content = Vec::<u8>::with_capacity(content_len);
-    content.resize(content_len, 0);
-    reader.read_exact(&mut content)?;
+    let content_len = usize::try_from(chunk_content_len)
+        .map_err(|_| invalid_data("chunk content length too big for usize"))?;
+    let content = read_exact_vec(&mut reader, content_len)?;
     let chunk_data = match &chunk_id.0 {
         b"MAIN" => {
             if content.len() != 0 {
@@ -323,7 +403,7 @@ fn parse_chunk<R: Read>(
             );
             if u64::from(n_voxels) * 4
                 != u64::try_from(content.len() - 4)
-                    .expect("content length should have fit in a u64")
+                    .map_err(|_| invalid_data("YXZI chunk's content length too big for u64"))?
             {
                 return Err(invalid_data(
                     "YXZI chunk's numVoxels did not match the remaining data's size",
@@ -347,10 +427,6 @@ fn parse_chunk<R: Read>(
                     color_index,
                 });
             }
-            assert!(
-                u64::try_from(voxels.len()).expect("voxels.len() should have fit in a u64")
-                    == u64::from(n_voxels)
-            );
             ChunkData::Xyzi { voxels }
         }
         b"RGBA" => {
@@ -366,7 +442,6 @@ fn parse_chunk<R: Read>(
                 let color = Color { r, g, b, a };
                 palette.push(color);
             }
-            assert!(palette.len() == 256);
             ChunkData::Rgba { palette }
         }
         b"MATL" => {
@@ -388,20 +463,31 @@ fn parse_chunk<R: Read>(
                     })
                     .transpose()?
             };
-            /*
-            let weight = dict
-                .remove("_weight");
-            let rough = dict
-                .remove("_rough")
-                .ok_or_else(|| invalid_data("MATL chunk DICT missing _rough"))?;
-            let spec = dict
-                .remove("_spec")
-                .ok_or_else(|| invalid_data("MATL chunk DICT missing _spec"))?;
-            ChunkData::Unknown(UnknownChunk { chunk_id, content })
-            */
+            // The numeric properties are stored as strings, e.g. `"0.5"`.
+            let mut property = |key: &str| {
+                dict.remove(key)
+                    .map(|value| {
+                        value.parse::<f64>().map_err(|_| {
+                            invalid_data(format!("MATL chunk's {} was {:?}", key, value))
+                        })
+                    })
+                    .transpose()
+            };
+            let weight = property("_weight")?;
+            let rough = property("_rough")?;
+            let spec = property("_spec")?;
+            let ior = property("_ior")?;
+            let att = property("_att")?;
+            let flux = property("_flux")?;
             ChunkData::Matl(Box::new(MatlChunk {
                 material_id,
                 material_type,
+                weight,
+                rough,
+                spec,
+                ior,
+                att,
+                flux,
                 extra: dict,
             }))
         }
@@ -430,14 +516,29 @@ fn read_string(mut read: impl Read) -> io::Result<String> {
     log::trace!("read_string");
     let buffer_len = read_u32_as_usize(&mut read)?;
     log::trace!("done read size ({})", buffer_len);
-    let mut data = Vec::with_capacity(buffer_len);
-    data.resize(buffer_len, 0);
-    log::trace!("about to read_exact");
-    read.read_exact(&mut data)?;
+    let data = read_exact_vec(&mut read, buffer_len)?;
     log::trace!("done");
     String::from_utf8(data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
 }
 
+/// Read exactly `len` bytes into a new `Vec`.
+///
+/// Lengths in a .vox file come from the file itself, so we can't trust them enough to allocate
+/// the entire buffer up front: a corrupt length would have us attempt a multi-gigabyte
+/// allocation. Instead, the buffer grows only as data is actually read.
+fn read_exact_vec(read: impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let len_u64 = u64::try_from(len).map_err(|_| invalid_data("length too big for u64"))?;
+    let mut data = Vec::new();
+    read.take(len_u64).read_to_end(&mut data)?;
+    if data.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "file ended before the end of the data",
+        ));
+    }
+    Ok(data)
+}
+
 fn read_u32_as_usize(mut read: impl Read) -> io::Result<usize> {
     let mut buf = [0u8; 4];
     read.read_exact(&mut buf)?;
@@ -453,9 +554,10 @@ fn read_i32(mut read: impl Read) -> io::Result<i32> {
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
     use std::io::Cursor;
 
-    use super::from_reader;
+    use super::{from_reader, from_reader_with_max_chunks, ChunkId};
 
     static LOGO: &[u8] = include_bytes!("../vox/logo.vox");
 
@@ -465,6 +567,221 @@ mod tests {
         println!("Logo: {:#?}", logo);
     }
 
+    #[test]
+    fn test_load_file() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/vox/logo.vox");
+        let logo = super::load_file(&path).expect("logo.vox should load");
+        let voxels = super::super::find_xyzi_data(&logo).unwrap();
+        assert!(voxels.len() == 45);
+
+        let err = super::load_file(path.with_file_name("missing.vox"))
+            .expect_err("a missing file shouldn't load");
+        assert!(err.kind() == std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_truncated_files() {
+        // Every proper prefix of the file should fail to parse, but not panic. (Checking every
+        // length is slow, so this checks a spread of them, plus the lengths just shy of the end.)
+        let lengths = (0..LOGO.len())
+            .step_by(37)
+            .chain(LOGO.len() - 16..LOGO.len());
+        for len in lengths {
+            let result = from_reader(Cursor::new(&LOGO[..len]));
+            assert!(result.is_err(), "truncation to {}B parsed successfully", len);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_lengths() {
+        // Overwrite each 4-byte, aligned word in the file with a huge value; this hits every
+        // chunk length, voxel count, etc. in the file. The parser should never panic (nor try to
+        // allocate 4 GiB.)
+        for offset in (8..LOGO.len() - 4).step_by(4) {
+            let mut corrupt = LOGO.to_vec();
+            corrupt[offset..offset + 4].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+            let _ = from_reader(Cursor::new(&corrupt));
+        }
+    }
+
+    /// A small, seedable PRNG (xorshift64*), so that failures are reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        /// A number in `0..n`.
+        fn below(&mut self, n: usize) -> usize {
+            usize::try_from(self.next() % u64::try_from(n).unwrap()).unwrap()
+        }
+    }
+
+    /// Corrupt `LOGO` in a few random ways: flipping bytes, overwriting words with extreme
+    /// values, deleting or duplicating runs of bytes, and truncating it.
+    fn mutate(rng: &mut Rng) -> Vec<u8> {
+        let mut data = LOGO.to_vec();
+        for _ in 0..1 + rng.below(4) {
+            if data.len() < 8 {
+                break;
+            }
+            let at = rng.below(data.len() - 4);
+            match rng.below(5) {
+                0 => data[at] ^= 1 << rng.below(8),
+                1 => {
+                    let words = [0u32, 1, 12, 0x7fff_ffff, 0xffff_ffff];
+                    let word = words[rng.below(words.len())];
+                    data[at..at + 4].copy_from_slice(&word.to_le_bytes());
+                }
+                2 => {
+                    let len = std::cmp::min(rng.below(64), data.len() - at);
+                    data.drain(at..at + len);
+                }
+                3 => {
+                    let len = std::cmp::min(rng.below(64), data.len() - at);
+                    let run = data[at..at + len].to_vec();
+                    data.splice(at..at, run);
+                }
+                _ => data.truncate(at),
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_mutated_files_never_panic() {
+        let mut rng = Rng(0x5eed);
+        for iteration in 0..2000 {
+            let data = mutate(&mut rng);
+            let result = std::panic::catch_unwind(|| from_reader(Cursor::new(&data)));
+            assert!(
+                result.is_ok(),
+                "iteration {}: parsing a mutated logo.vox ({}B) panicked",
+                iteration,
+                data.len(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_bad_voxel_count() {
+        let mut file = Vec::new();
+        file.extend_from_slice(b"VOX ");
+        file.extend_from_slice(&150u32.to_le_bytes());
+        file.extend_from_slice(b"MAIN");
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&(12u32 + 8).to_le_bytes());
+        file.extend_from_slice(b"XYZI");
+        file.extend_from_slice(&8u32.to_le_bytes());
+        file.extend_from_slice(&0u32.to_le_bytes());
+        // Claims 2 voxels, but only has data for one:
+        file.extend_from_slice(&2u32.to_le_bytes());
+        file.extend_from_slice(&[0, 0, 0, 1]);
+        let err = from_reader(Cursor::new(&file)).expect_err("voxel count mismatch should fail");
+        assert!(err.kind() == std::io::ErrorKind::InvalidData);
+    }
+
+    /// The content of a MATL chunk, with the given DICT.
+    fn matl_content(material_id: i32, dict: &[(&str, &str)]) -> Vec<u8> {
+        let mut content = Vec::new();
+        content.extend_from_slice(&material_id.to_le_bytes());
+        content.extend_from_slice(&u32::try_from(dict.len()).unwrap().to_le_bytes());
+        for string in dict.iter().flat_map(|(k, v)| [k, v]) {
+            content.extend_from_slice(&u32::try_from(string.len()).unwrap().to_le_bytes());
+            content.extend_from_slice(string.as_bytes());
+        }
+        content
+    }
+
+    #[test]
+    fn test_matl_properties() {
+        let content = matl_content(
+            7,
+            &[("_type", "_metal"), ("_rough", "0.25"), ("_plastic", "1")],
+        );
+        let chunk = super::parse_chunk(
+            Cursor::new(&content),
+            ChunkId(*b"MATL"),
+            u32::try_from(content.len()).unwrap(),
+        )
+        .unwrap();
+        let matl = match chunk.data {
+            super::ChunkData::Matl(matl) => matl,
+            other => panic!("expected a MATL chunk, got {:?}", other),
+        };
+        assert!(matl.material_id == 7);
+        assert!(matches!(
+            matl.material_type,
+            Some(super::MaterialType::Metal)
+        ));
+        assert!(matl.rough == Some(0.25));
+        // Missing properties are just absent:
+        assert!(matl.weight.is_none());
+        assert!(matl.ior.is_none());
+        // & unknown ones are kept as they are.
+        assert!(matl.extra.get("_plastic").map(String::as_str) == Some("1"));
+
+        let content = matl_content(7, &[("_rough", "very")]);
+        let err = super::parse_chunk(
+            Cursor::new(&content),
+            ChunkId(*b"MATL"),
+            u32::try_from(content.len()).unwrap(),
+        )
+        .expect_err("a non-numeric _rough should fail");
+        assert!(err.kind() == std::io::ErrorKind::InvalidData);
+    }
+
+    /// Build a .vox file with a MAIN chunk holding `children` empty, unknown chunks.
+    fn vox_with_empty_children(children: u32) -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend_from_slice(b"VOX ");
+        file.extend_from_slice(&150u32.to_le_bytes());
+        file.extend_from_slice(b"MAIN");
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&(children * 12).to_le_bytes());
+        for _ in 0..children {
+            file.extend_from_slice(b"NOPE");
+            file.extend_from_slice(&0u32.to_le_bytes());
+            file.extend_from_slice(&0u32.to_le_bytes());
+        }
+        file
+    }
+
+    #[test]
+    fn test_max_chunks() {
+        let file = vox_with_empty_children(5);
+        let chunk = from_reader_with_max_chunks(Cursor::new(&file), 5)
+            .expect("5 chunks should be within a limit of 5");
+        assert!(chunk.children.len() == 5);
+
+        let err = from_reader_with_max_chunks(Cursor::new(&file), 4)
+            .expect_err("5 chunks should exceed a limit of 4");
+        assert!(err.kind() == std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_chunk_id_display_and_from_ascii() {
+        let main = ChunkId::from_ascii("MAIN").unwrap();
+        assert!(main == ChunkId(*b"MAIN"));
+        assert!(main.to_string() == "MAIN");
+        let xyzi = ChunkId::from_ascii("XYZI").unwrap();
+        assert!(xyzi == ChunkId(*b"XYZI"));
+        assert!(xyzi.to_string() == "XYZI");
+
+        let odd = ChunkId([b'n', 0xe9, 0, b'\\']);
+        assert!(odd.to_string() == "n\\xe9\\x00\\\\");
+        assert!(format!("{:?}", odd) == "[b'n', 0xe9, 0x00, 0x5c]");
+
+        assert!(ChunkId::from_ascii("MAI").is_none());
+        assert!(ChunkId::from_ascii("MAINS").is_none());
+        // Four bytes, but not ASCII:
+        assert!(ChunkId::from_ascii("nét").is_none());
+    }
+
     #[test]
     fn test_show_sizes() {
         println!("ChunkData: {}B", std::mem::size_of::<super::ChunkData>());
@@ -506,4 +823,32 @@ mod tests {
         assert!(color.g == 128);
         assert!(color.b == 254);
     }
+
+    #[test]
+    fn test_color_f32() {
+        use super::Color;
+
+        let color = Color {
+            r: 0,
+            g: 255,
+            b: 0,
+            a: 255,
+        };
+        assert!(color.to_f32_rgb() == [0., 1., 0.]);
+        assert!(color.to_f32_rgba() == [0., 1., 0., 1.]);
+        assert!(Color::from_f32(color.to_f32_rgba()) == color);
+        // Every channel value survives the round trip, not just the boundaries.
+        for c in 0..=255 {
+            let color = Color {
+                r: c,
+                g: 255 - c,
+                b: c,
+                a: c,
+            };
+            assert!(Color::from_f32(color.to_f32_rgba()) == color);
+        }
+        // Out-of-range components are clamped.
+        let clamped = Color::from_f32([-0.5, 1.5, 0.5, 1.]);
+        assert!(clamped.r == 0 && clamped.g == 255 && clamped.b == 128);
+    }
 }
@@ -1,72 +1,524 @@
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::hash::Hash;
 use std::sync::Arc;
 
 use bytemuck::{Pod, Zeroable};
-use vulkano::buffer::CpuAccessibleBuffer;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
 use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
 use vulkano::device::Device;
 use vulkano::memory::allocator::MemoryAllocator;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{PolygonMode, RasterizationState};
 use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
 use vulkano::pipeline::graphics::viewport::ViewportState;
-use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
 use vulkano::render_pass::{RenderPass, Subpass};
 use vulkano::shader::ShaderModule;
 
+/// Dump parsed MagicaVoxel files as JSON
+pub mod dump;
+/// Convert MagicaVoxel models into map chunks
+pub mod import;
+/// Draw MagicaVoxel models as instances of a single cube
+pub mod instanced;
 /// Load MagicaVoxel files
 pub mod io;
+mod obj;
 
+pub use obj::export_obj;
+
+use crate::matrix::Vertex3d;
+use crate::model_util::ModelBuilder;
 use io::{Chunk, ChunkData, Color, Voxel};
+use voxel_map::octree::{LocationCode, OctreeNode};
+use voxel_map::ChunkRelativeCoord;
+use voxel_mod::block_defs::BlockShape;
 
 /// A MagicaVoxel model that's been uploaded to the GPU, and can be rendered.
 pub struct MagicaModel {
     vertex_buffer: Arc<CpuAccessibleBuffer<[MagicaVertex]>>,
     index_buffer: crate::model_util::IndexBuffer,
+    /// The colors the vertexes' `color_index`es refer to.
+    palette: Arc<CpuAccessibleBuffer<[PaletteColor]>>,
+    /// The model's bounding box, as `(min, max)`, if known.
+    bounds: Option<(Vertex3d, Vertex3d)>,
+}
+
+/// The mesh of a MagicaVoxel model, built on the CPU, before it's uploaded to the GPU with
+/// [`MagicaModel::from_mesh`].
+pub struct MagicaMesh<'a> {
+    model_builder: ModelBuilder<CubeVertex<u8>>,
+    palette: &'a [Color],
+    bounds: Option<VoxelBounds>,
+}
+
+impl MagicaMesh<'_> {
+    pub fn new(top_chunk: &Chunk) -> anyhow::Result<MagicaMesh<'_>> {
+        let voxels = find_xyzi_data(top_chunk)?;
+        let palette = find_rgba_data(top_chunk)?;
+        Ok(MagicaMesh {
+            model_builder: mesh_voxels(voxels),
+            palette,
+            bounds: voxel_bounds(voxels),
+        })
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.model_builder.vertexes().len()
+    }
+
+    pub fn index_count(&self) -> usize {
+        self.model_builder.index_count()
+    }
 }
 
 impl MagicaModel {
     pub fn new(memory_allocator: &(impl MemoryAllocator + ?Sized), top_chunk: &Chunk) -> anyhow::Result<MagicaModel> {
-        let voxels = find_xyzi_data(&top_chunk)?;
-        let palette = find_rgba_data(&top_chunk)?;
-        let mut model_builder = crate::model_util::ModelBuilder::new();
-        for voxel in voxels {
-            log::debug!("dump Voxel: {:?}", voxel);
-            for side in CUBE_VERTEXES.iter() {
-                let side_vertexes = [
-                    // Triangle 1
-                    side[0],
-                    side[1],
-                    side[2],
-                    // Triangle 2
-                    side[0],
-                    side[2],
-                    side[3],
-                ];
-                for vertex in side_vertexes {
-                    let x = u16::from(voxel.x) + u16::from(vertex.0);
-                    let y = u16::from(voxel.y) + u16::from(vertex.1);
-                    let z = u16::from(voxel.z) + u16::from(vertex.2);
-                    model_builder.push_vertex((x, y, z, voxel.color_index));
-                }
-            }
-        }
+        Ok(Self::from_mesh(memory_allocator, MagicaMesh::new(top_chunk)?))
+    }
 
+    /// Upload a mesh built by [`MagicaMesh::new`].
+    pub fn from_mesh(
+        memory_allocator: &(impl MemoryAllocator + ?Sized),
+        mesh: MagicaMesh<'_>,
+    ) -> MagicaModel {
+        let MagicaMesh {
+            model_builder,
+            palette,
+            bounds,
+        } = mesh;
         let (vertex_buffer, index_buffer) = model_builder.into_gpu(
             memory_allocator,
-            |(x, y, z, color_idx)| MagicaVertex {
+            |((x, y, z, color_idx, ao), normal)| MagicaVertex {
                 position: [f32::from(x), y as f32, z as f32],
-                color: palette
-                    .get(usize::from(color_idx))
-                    .map(|c| [u32::from(c.r), u32::from(c.g), u32::from(c.b)])
-                    .expect("palette should contain a color for every index"),
+                color_index: u32::from(color_idx),
+                normal: normal.map(f32::from),
+                ao: ao_fraction(ao),
             },
             false,
         );
+        // Voxels are unit cubes, so the far corner is 1 past the furthest voxel's.
+        let bounds = bounds.map(|(min, max)| {
+            (
+                Vertex3d::new(f32::from(min.0), f32::from(min.1), f32::from(min.2)),
+                Vertex3d::new(
+                    f32::from(max.0) + 1.,
+                    f32::from(max.1) + 1.,
+                    f32::from(max.2) + 1.,
+                ),
+            )
+        });
+
+        MagicaModel {
+            vertex_buffer,
+            index_buffer,
+            palette: upload_palette(memory_allocator, vox_palette(palette)),
+            bounds,
+        }
+    }
+
+    /// Mesh the non-empty blocks of a map chunk, colored by their block definitions, with the
+    /// chunk's corner at `origin`. `None` if the chunk is entirely empty, as there's nothing to
+    /// draw.
+    pub fn from_chunk(
+        memory_allocator: &(impl MemoryAllocator + ?Sized),
+        chunk: &voxel_map::Chunk,
+        origin: [f32; 3],
+    ) -> Option<MagicaModel> {
+        Self::from_chunk_mesh(memory_allocator, mesh_chunk(chunk), origin)
+    }
+
+    /// Like [`from_chunk`](Self::from_chunk), but in less detail the further the chunk is from
+    /// `camera`; see [`lod_depth`].
+    pub fn from_chunk_lod(
+        memory_allocator: &(impl MemoryAllocator + ?Sized),
+        chunk: &voxel_map::Chunk,
+        origin: [f32; 3],
+        camera: [f32; 3],
+    ) -> Option<MagicaModel> {
+        let model_builder = mesh_chunk_lod(chunk, origin, camera);
+        Self::from_chunk_mesh(memory_allocator, model_builder, origin)
+    }
 
-        Ok(MagicaModel {
+    fn from_chunk_mesh(
+        memory_allocator: &(impl MemoryAllocator + ?Sized),
+        model_builder: ModelBuilder<CubeVertex<[u8; 3]>>,
+        origin: [f32; 3],
+    ) -> Option<MagicaModel> {
+        if model_builder.vertexes().is_empty() {
+            return None;
+        }
+        let (palette, color_indexes) = chunk_palette(model_builder.vertexes());
+        let (vertex_buffer, index_buffer) = model_builder.into_gpu(
+            memory_allocator,
+            |((x, y, z, color, ao), normal)| MagicaVertex {
+                position: [
+                    origin[0] + f32::from(x) / f32::from(BLOCK_STEPS),
+                    origin[1] + f32::from(y) / f32::from(BLOCK_STEPS),
+                    origin[2] + f32::from(z) / f32::from(BLOCK_STEPS),
+                ],
+                color_index: color_indexes[&color],
+                normal: normal.map(f32::from),
+                ao: ao_fraction(ao),
+            },
+            false,
+        );
+        let side = f32::from(voxel_map::CHUNK_SIDE_LENGTH);
+        let palette = palette
+            .into_iter()
+            .map(|color| Color::from_rgb(color).to_f32_rgba())
+            .collect();
+        Some(MagicaModel {
             vertex_buffer,
             index_buffer,
+            palette: upload_palette(memory_allocator, palette),
+            bounds: Some((
+                Vertex3d::new(origin[0], origin[1], origin[2]),
+                Vertex3d::new(origin[0] + side, origin[1] + side, origin[2] + side),
+            )),
         })
     }
+
+    /// Whether any of the model might be visible in the frustum with the given planes (see
+    /// [`crate::matrix::projection::frustum_planes`]). Models without known bounds always might be.
+    pub fn in_frustum(&self, planes: &[[f32; 4]; 6]) -> bool {
+        match &self.bounds {
+            Some((min, max)) => {
+                crate::matrix::projection::aabb_in_frustum(planes, min.clone(), max.clone())
+            }
+            None => true,
+        }
+    }
+}
+
+/// A vertex of a cube, before upload: its position, some color (or a key for one), & its ambient
+/// occlusion (see [`corner_ao`]), and the normal of its face.
+type CubeVertex<C> = ((u16, u16, u16, C, u8), [i8; 3]);
+
+/// The steps each block is divided into, along each axis, in the positions of chunk meshes'
+/// vertexes; so that blocks whose [`BlockShape`] isn't a cube can be made of half blocks.
+const BLOCK_STEPS: u16 = 2;
+
+/// The ambient occlusion of a face's corner, from 0 (darkest) to 3 (unoccluded), by which of the
+/// blocks around it, in front of the face, are occupied: the blocks to either side of the corner,
+/// & the one diagonally between them. (If both sides are occupied, the diagonal is hidden anyway.)
+fn corner_ao(side1: bool, side2: bool, diagonal: bool) -> u8 {
+    if side1 && side2 {
+        0
+    } else {
+        3 - u8::from(side1) - u8::from(side2) - u8::from(diagonal)
+    }
+}
+
+/// A [`corner_ao`], as the fraction of the light reaching the corner, for the shaders.
+fn ao_fraction(ao: u8) -> f32 {
+    f32::from(ao) / 3.
+}
+
+/// Add the faces of the cube with its corner nearest the origin at `corner`, and sides of `size`,
+/// with its vertexes' positions in `1 / steps` of a block. Each face's corners are occluded by the
+/// blocks for which `occupied` is true.
+fn push_cube<C: Copy + Eq + Hash, O: Fn([i32; 3]) -> bool>(
+    model_builder: &mut ModelBuilder<CubeVertex<C>>,
+    corner: (u16, u16, u16),
+    size: u16,
+    steps: u16,
+    color: C,
+    occupied: &O,
+) {
+    let min = [corner.0, corner.1, corner.2].map(i32::from);
+    let size_i32 = i32::from(size);
+    for (side, normal) in CUBE_VERTEXES.iter().zip(CUBE_NORMALS.iter()) {
+        let axis = normal.iter().position(|&n| n != 0).unwrap();
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        // The layer of blocks just in front of the face.
+        let layer = match normal[axis] {
+            1 => min[axis] + size_i32,
+            _ => min[axis] - 1,
+        };
+        let [a, b, c, d] = side.map(|vertex| {
+            let offset = [vertex.0, vertex.1, vertex.2];
+            // Along each of the face's axes, the block just past this corner of the face, & the
+            // block just within it.
+            let outside = |i: usize| match offset[i] {
+                1 => min[i] + size_i32,
+                _ => min[i] - 1,
+            };
+            let inside = |i: usize| match offset[i] {
+                1 => min[i] + size_i32 - 1,
+                _ => min[i],
+            };
+            let in_front = |u_coord, v_coord| {
+                let mut block = [0; 3];
+                block[axis] = layer;
+                block[u] = u_coord;
+                block[v] = v_coord;
+                occupied(block)
+            };
+            let ao = corner_ao(
+                in_front(outside(u), inside(v)),
+                in_front(inside(u), outside(v)),
+                in_front(outside(u), outside(v)),
+            );
+            let x = (corner.0 + u16::from(vertex.0) * size) * steps;
+            let y = (corner.1 + u16::from(vertex.1) * size) * steps;
+            let z = (corner.2 + u16::from(vertex.2) * size) * steps;
+            (x, y, z, color, ao)
+        });
+        // Split the face along the diagonal between its brighter corners, so that a single
+        // occluded corner's shadow doesn't stretch across the face.
+        if a.4 + c.4 < b.4 + d.4 {
+            model_builder.push_triangle_with_normal([a, b, d], *normal);
+            model_builder.push_triangle_with_normal([b, c, d], *normal);
+        } else {
+            model_builder.push_triangle_with_normal([a, b, c], *normal);
+            model_builder.push_triangle_with_normal([a, c, d], *normal);
+        }
+    }
+}
+
+/// Add the faces of the box between the corners `min` & `max`, with their positions in whatever
+/// units the model's are. Its faces aren't occluded.
+fn push_box<C: Copy + Eq + Hash>(
+    model_builder: &mut ModelBuilder<CubeVertex<C>>,
+    min: [u16; 3],
+    max: [u16; 3],
+    color: C,
+) {
+    for (side, normal) in CUBE_VERTEXES.iter().zip(CUBE_NORMALS.iter()) {
+        let [a, b, c, d] = side.map(|vertex| {
+            let offset = [vertex.0, vertex.1, vertex.2];
+            let [x, y, z] = [0, 1, 2].map(|i| match offset[i] {
+                1 => max[i],
+                _ => min[i],
+            });
+            (x, y, z, color, 3)
+        });
+        model_builder.push_triangle_with_normal([a, b, c], *normal);
+        model_builder.push_triangle_with_normal([a, c, d], *normal);
+    }
+}
+
+/// The boxes making up a block of `shape`, as their `(min, max)` corners in [`BLOCK_STEPS`],
+/// relative to the block's corner.
+fn shape_boxes(shape: BlockShape) -> &'static [([u16; 3], [u16; 3])] {
+    const FULL: u16 = BLOCK_STEPS;
+    const HALF: u16 = BLOCK_STEPS / 2;
+    match shape {
+        BlockShape::Cube => &[([0, 0, 0], [FULL, FULL, FULL])],
+        BlockShape::Slab => &[([0, 0, 0], [FULL, HALF, FULL])],
+        BlockShape::Stairs => &[
+            ([0, 0, 0], [FULL, HALF, FULL]),
+            ([0, HALF, HALF], [FULL, FULL, FULL]),
+        ],
+    }
+}
+
+/// Mesh every voxel as a cube, keyed by its palette index.
+fn mesh_voxels(voxels: &[Voxel]) -> ModelBuilder<CubeVertex<u8>> {
+    let occupied = voxels
+        .iter()
+        .map(|voxel| [voxel.x, voxel.y, voxel.z].map(i32::from))
+        .collect::<HashSet<_>>();
+    let is_occupied = |block: [i32; 3]| occupied.contains(&block);
+    let mut model_builder = ModelBuilder::new();
+    for voxel in voxels {
+        log::debug!("dump Voxel: {:?}", voxel);
+        let corner = (u16::from(voxel.x), u16::from(voxel.y), u16::from(voxel.z));
+        push_cube(
+            &mut model_builder,
+            corner,
+            1,
+            1,
+            voxel.color_index,
+            &is_occupied,
+        );
+    }
+    model_builder
+}
+
+/// Mesh every non-empty block in `chunk` as a cube of its block's color, or in its block's shape.
+/// Octree nodes that span several blocks become a single, larger cube. Vertexes are positioned in
+/// [`BLOCK_STEPS`].
+fn mesh_chunk(chunk: &voxel_map::Chunk) -> ModelBuilder<CubeVertex<[u8; 3]>> {
+    mesh_chunk_to_depth(chunk, CHUNK_OCTREE_DEPTH)
+}
+
+/// Write the mesh of `chunk`, as drawn by [`MagicaModel::from_chunk`], as an OBJ file; see
+/// [`export_obj`].
+pub fn export_chunk_obj<W: std::io::Write>(w: W, chunk: &voxel_map::Chunk) -> std::io::Result<()> {
+    export_model_obj(w, &mesh_chunk(chunk), BLOCK_STEPS)
+}
+
+/// Write the mesh of a MagicaVoxel model, as drawn by [`MagicaModel::new`], as an OBJ file; see
+/// [`export_obj`].
+pub fn export_vox_obj<W: std::io::Write>(w: W, top_chunk: &Chunk) -> anyhow::Result<()> {
+    let voxels = find_xyzi_data(top_chunk)?;
+    export_model_obj(w, &mesh_voxels(voxels), 1)?;
+    Ok(())
+}
+
+/// Write a model, with its vertexes' positions in `1 / steps` of a block, as an OBJ file.
+fn export_model_obj<W: std::io::Write, C>(
+    w: W,
+    model_builder: &ModelBuilder<CubeVertex<C>>,
+    steps: u16,
+) -> std::io::Result<()> {
+    let vertices = model_builder
+        .vertexes()
+        .iter()
+        .map(|((x, y, z, _, _), _)| [*x, *y, *z].map(|c| f32::from(c) / f32::from(steps)))
+        .collect::<Vec<_>>();
+    export_obj(w, &vertices, model_builder.indexes())
+}
+
+/// The depth of a chunk's octree at which nodes are single blocks.
+const CHUNK_OCTREE_DEPTH: u8 = voxel_map::CHUNK_SIDE_LENGTH.trailing_zeros() as u8;
+
+/// Chunks closer to the camera than this are meshed in full detail.
+const FULL_DETAIL_DISTANCE: f32 = 64.;
+
+/// How deep into a chunk's octree to mesh, for a chunk `distance` away from the camera: full
+/// detail up close, and one level less each time the distance doubles.
+pub fn lod_depth(distance: f32) -> u8 {
+    let levels_dropped = (distance / FULL_DETAIL_DISTANCE).max(1.).log2();
+    CHUNK_OCTREE_DEPTH.saturating_sub(levels_dropped as u8)
+}
+
+/// Mesh `chunk`, with its corner at `origin`, in as much detail as its distance from `camera`
+/// calls for; see [`lod_depth`].
+fn mesh_chunk_lod(
+    chunk: &voxel_map::Chunk,
+    origin: [f32; 3],
+    camera: [f32; 3],
+) -> ModelBuilder<CubeVertex<[u8; 3]>> {
+    let half_side = f32::from(voxel_map::CHUNK_SIDE_LENGTH) / 2.;
+    let distance = (0..3)
+        .map(|axis| (origin[axis] + half_side - camera[axis]).powi(2))
+        .sum::<f32>()
+        .sqrt();
+    mesh_chunk_to_depth(chunk, lod_depth(distance))
+}
+
+/// Mesh `chunk` like [`mesh_chunk`], but without descending past `max_depth` in its octree: a
+/// subdivided node at that depth becomes a single cube of the average color of the blocks in it,
+/// if it has any.
+fn mesh_chunk_to_depth(
+    chunk: &voxel_map::Chunk,
+    max_depth: u8,
+) -> ModelBuilder<CubeVertex<[u8; 3]>> {
+    let side_length = u16::from(voxel_map::CHUNK_SIDE_LENGTH);
+    // Occlusion is always by the blocks at full detail. (Nothing's occupied outside the chunk.)
+    let is_occupied = |block: [i32; 3]| match block.map(u8::try_from) {
+        [Ok(x), Ok(y), Ok(z)] if [x, y, z].iter().all(|&c| c < voxel_map::CHUNK_SIDE_LENGTH) => {
+            chunk.get_block(ChunkRelativeCoord::new(x, y, z)).is_some()
+        }
+        _ => false,
+    };
+    let mut model_builder = ModelBuilder::new();
+    let mut push_block = |location_code: LocationCode, color: [u8; 3], shape: BlockShape| {
+        let ((x, y, z), size) = location_code.to_coords_and_size(side_length);
+        let corner = (
+            u16::try_from(x).unwrap(),
+            u16::try_from(y).unwrap(),
+            u16::try_from(z).unwrap(),
+        );
+        match shape {
+            BlockShape::Cube => push_cube(
+                &mut model_builder,
+                corner,
+                size,
+                BLOCK_STEPS,
+                color,
+                &is_occupied,
+            ),
+            // Only cubes merge, so this is a single block.
+            shape => {
+                let corner = [corner.0, corner.1, corner.2].map(|c| c * BLOCK_STEPS);
+                for (min, max) in shape_boxes(shape) {
+                    push_box(
+                        &mut model_builder,
+                        [0, 1, 2].map(|i| corner[i] + min[i]),
+                        [0, 1, 2].map(|i| corner[i] + max[i]),
+                        color,
+                    );
+                }
+            }
+        }
+    };
+    // The subdivided node at `max_depth` being averaged, and the sum of its blocks' colors
+    // (weighted by their volume), & their volume. Its blocks all follow it, depth first.
+    let mut averaging: Option<(LocationCode, [u64; 3], u64)> = None;
+    for (location_code, node) in chunk.depth_first_all_levels() {
+        if let Some((lod_node, sums, volume)) = &mut averaging {
+            if lod_node.contains(location_code) {
+                if let OctreeNode::Present(Some(block)) = node {
+                    let (_, size) = location_code.to_coords_and_size(side_length);
+                    let block_volume = u64::from(size).pow(3);
+                    let color = block.definition().color();
+                    for (sum, c) in sums.iter_mut().zip([color.r, color.g, color.b]) {
+                        *sum += u64::from(c) * block_volume;
+                    }
+                    *volume += block_volume;
+                }
+                continue;
+            }
+            if let Some(color) = average_color(*sums, *volume) {
+                push_block(*lod_node, color, BlockShape::Cube);
+            }
+            averaging = None;
+        }
+        match node {
+            OctreeNode::Present(Some(block)) => {
+                let definition = block.definition();
+                let color = definition.color();
+                push_block(
+                    location_code,
+                    [color.r, color.g, color.b],
+                    definition.shape(),
+                );
+            }
+            OctreeNode::Present(None) => (),
+            OctreeNode::Subdivided => {
+                if max_depth <= location_code.depth() {
+                    averaging = Some((location_code, [0; 3], 0));
+                }
+            }
+        }
+    }
+    if let Some((lod_node, sums, volume)) = averaging {
+        if let Some(color) = average_color(sums, volume) {
+            push_block(lod_node, color, BlockShape::Cube);
+        }
+    }
+    model_builder
+}
+
+/// The average color, from the sum of each component over `volume` blocks; `None` if there were
+/// no blocks.
+fn average_color(sums: [u64; 3], volume: u64) -> Option<[u8; 3]> {
+    match volume {
+        0 => None,
+        _ => Some(sums.map(|sum| u8::try_from(sum / volume).unwrap())),
+    }
+}
+
+/// The palette of a chunk's mesh: each distinct color of its vertexes, in the order they're first
+/// used, & the index of each color in it.
+fn chunk_palette(vertexes: &[CubeVertex<[u8; 3]>]) -> (Vec<[u8; 3]>, HashMap<[u8; 3], u32>) {
+    let mut palette = Vec::new();
+    let mut indexes = HashMap::new();
+    for ((_, _, _, color, _), _) in vertexes {
+        indexes.entry(*color).or_insert_with(|| {
+            palette.push(*color);
+            u32::try_from(palette.len() - 1).unwrap()
+        });
+    }
+    (palette, indexes)
 }
 
 #[rustfmt::skip]
@@ -115,6 +567,48 @@ static CUBE_VERTEXES: &[[(u8, u8, u8); 4]] = &[
     ],
 ];
 
+/// The outward-facing normal of each face in `CUBE_VERTEXES`, in the same order.
+static CUBE_NORMALS: &[[i8; 3]] = &[
+    [0, -1, 0],
+    [0, 0, -1],
+    [0, 0, 1],
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+];
+
+/// The fraction of a model's color that is lit regardless of the light's direction.
+pub(super) const AMBIENT_LIGHT: f32 = 0.3;
+
+/// The direction the light shining on models travels in at time `t`: down at an angle, and
+/// circling around the Y axis.
+pub(super) fn light_direction(t: f32) -> [f32; 3] {
+    let (sin, cos) = (t * 0.5).sin_cos();
+    let [x, y, z] = [cos, -2.0, sin];
+    let len = (x * x + y * y + z * z).sqrt();
+    [x / len, y / len, z / len]
+}
+
+/// The `(min, max)` corners of a box of voxels, by their coordinates, inclusive.
+pub type VoxelBounds = ((u8, u8, u8), (u8, u8, u8));
+
+/// The extents of the voxels in a loaded Magica file, as the `(min, max)` voxel coordinates
+/// (inclusive) along each axis. `None` if the model has no voxels.
+pub fn bounding_box(top_chunk: &Chunk) -> anyhow::Result<Option<VoxelBounds>> {
+    Ok(voxel_bounds(find_xyzi_data(top_chunk)?))
+}
+
+fn voxel_bounds(voxels: &[Voxel]) -> Option<VoxelBounds> {
+    let first = voxels.first()?;
+    let start = ((first.x, first.y, first.z), (first.x, first.y, first.z));
+    Some(voxels.iter().fold(start, |(min, max), voxel| {
+        (
+            (min.0.min(voxel.x), min.1.min(voxel.y), min.2.min(voxel.z)),
+            (max.0.max(voxel.x), max.1.max(voxel.y), max.2.max(voxel.z)),
+        )
+    }))
+}
+
 /// Get the voxel data from the loaded Magica file.
 fn find_xyzi_data(top_chunk: &Chunk) -> anyhow::Result<&[Voxel]> {
     if !matches!(top_chunk.data, ChunkData::Main) {
@@ -152,19 +646,29 @@ fn find_rgba_data(top_chunk: &Chunk) -> anyhow::Result<&[Color]> {
 pub(super) struct MagicaShaders {
     vs: Arc<ShaderModule>,
     fs: Arc<ShaderModule>,
+    /// The vertex shader for [`instanced::InstancedMagicaModel`]s; they share `fs`.
+    instanced_vs: Arc<ShaderModule>,
 }
 
 impl MagicaShaders {
     pub(super) fn load(device: Arc<Device>) -> MagicaShaders {
         let vs = vs::load(device.clone()).expect("failed to load vertex shader");
         let fs = fs::load(device.clone()).expect("failed to load fragment shader");
-        MagicaShaders { vs, fs }
+        let instanced_vs =
+            instanced::vs::load(device.clone()).expect("failed to load instanced vertex shader");
+        MagicaShaders {
+            vs,
+            fs,
+            instanced_vs,
+        }
     }
 }
 
 pub(super) fn build_pipeline(
     device: Arc<Device>,
     render_pass: Arc<RenderPass>,
+    multisample_state: MultisampleState,
+    polygon_mode: PolygonMode,
     shaders: &MagicaShaders,
 ) -> Arc<GraphicsPipeline> {
     GraphicsPipeline::start()
@@ -176,6 +680,12 @@ pub(super) fn build_pipeline(
         .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
         // The fragment shader.
         .fragment_shader(shaders.fs.entry_point("main").unwrap(), ())
+        .multisample_state(multisample_state)
+        .rasterization_state(RasterizationState {
+            polygon_mode,
+            ..Default::default()
+        })
+        .depth_stencil_state(DepthStencilState::simple_depth_test())
         // This graphics pipeline object concerns the first pass of the render pass.
         .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
         // Now that everything is specified, we call `build`.
@@ -184,35 +694,98 @@ pub(super) fn build_pipeline(
 }
 
 pub(super) trait MagicaAutoCmdExt {
-    fn draw_magica(&mut self, pipeline: Arc<GraphicsPipeline>, model: &MagicaModel) -> &mut Self;
+    /// Draw `model`. `descriptor_set` is set 0, the uniforms; the model's palette is bound as set
+    /// 1, in a descriptor set from `descriptor_set_allocator`.
+    fn draw_magica(
+        &mut self,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        model: &MagicaModel,
+    ) -> &mut Self;
 }
 
 impl<L> MagicaAutoCmdExt for AutoCommandBufferBuilder<L> {
-    fn draw_magica(&mut self, pipeline: Arc<GraphicsPipeline>, model: &MagicaModel) -> &mut AutoCommandBufferBuilder<L> {
-        self
-            .bind_pipeline_graphics(pipeline)
+    fn draw_magica(
+        &mut self,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        model: &MagicaModel,
+    ) -> &mut AutoCommandBufferBuilder<L> {
+        let layout = pipeline.layout().clone();
+        let palette_set = PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            layout.set_layouts()[1].clone(),
+            [WriteDescriptorSet::buffer(0, model.palette.clone())],
+        )
+        .unwrap();
+        self.bind_pipeline_graphics(pipeline)
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                layout,
+                0,
+                vec![descriptor_set, palette_set],
+            )
             .bind_vertex_buffers(0, model.vertex_buffer.clone());
         model.index_buffer.bind(self);
-        self
-            .draw_indexed(
-                u32::try_from(model.index_buffer.len()).unwrap(),
-                1, // instance_count
-                0, // first_index
-                0, // vertex_offset
-                0, // first_instance
-            )
-            .unwrap()
+        self.draw_indexed(
+            u32::try_from(model.index_buffer.len()).unwrap(),
+            1, // instance_count
+            0, // first_index
+            0, // vertex_offset
+            0, // first_instance
+        )
+        .unwrap()
     }
 }
 
+/// A color in a [`MagicaModel`]'s palette, as RGBA; each is a `vec4` in the shader, anyway, as a
+/// storage buffer's `vec3`s are 16 bytes apart.
+type PaletteColor = [f32; 4];
+
+/// The number of colors in a MagicaVoxel model's palette; a voxel's color is a `u8` index into it.
+const VOX_PALETTE_LEN: usize = 256;
+
+/// A MagicaVoxel model's palette, as uploaded: always [`VOX_PALETTE_LEN`] colors, so that every
+/// color index is in range. Colors the file doesn't have are black.
+fn vox_palette(palette: &[Color]) -> Vec<PaletteColor> {
+    palette
+        .iter()
+        .map(|color| color.to_f32_rgba())
+        .chain(std::iter::repeat([0., 0., 0., 1.]))
+        .take(VOX_PALETTE_LEN)
+        .collect()
+}
+
+fn upload_palette(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    palette: Vec<PaletteColor>,
+) -> Arc<CpuAccessibleBuffer<[PaletteColor]>> {
+    CpuAccessibleBuffer::from_iter(
+        memory_allocator,
+        BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::empty()
+        },
+        false,
+        palette.into_iter(),
+    )
+    .unwrap()
+}
+
 #[repr(C)]
 #[derive(Default, Clone, Copy, Zeroable, Pod)]
 struct MagicaVertex {
     position: [f32; 3],
-    color: [u32; 3],
+    /// The vertex's color, as an index into its model's palette.
+    color_index: u32,
+    normal: [f32; 3],
+    /// The fraction of ambient light reaching the vertex, from 0 to 1; see [`corner_ao`].
+    ao: f32,
 }
 
-vulkano::impl_vertex!(MagicaVertex, position, color);
+vulkano::impl_vertex!(MagicaVertex, position, color_index, normal, ao);
 
 mod vs {
     vulkano_shaders::shader! {
@@ -224,16 +797,29 @@ layout(binding = 0) uniform UniformBufferObject {
     mat4 model;
     mat4 view;
     mat4 proj;
+    float t;
+    float ambient;
+    vec3 light_dir;
 } ubo;
 
+layout(set = 1, binding = 0) readonly buffer Palette {
+    vec4 colors[];
+} palette;
+
 layout(location = 0) in vec3 position;
-layout(location = 1) in uvec3 color;
+layout(location = 1) in uint color_index;
+layout(location = 2) in vec3 normal;
+layout(location = 3) in float ao;
 
 layout(location = 0) out vec3 color_out;
+layout(location = 1) out vec3 normal_out;
+layout(location = 2) out float ao_out;
 
 void main() {
     gl_Position = ubo.proj * ubo.view * vec4(position.x, position.y, position.z, 1.0);
-    color_out = vec3(color.r / 255.0, color.g / 255.0, color.b / 255.0);
+    color_out = palette.colors[color_index].rgb;
+    normal_out = normal;
+    ao_out = ao;
 }"
     }
 }
@@ -244,12 +830,359 @@ mod fs {
         src: "\
 #version 450
 
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    float t;
+    float ambient;
+    vec3 light_dir;
+} ubo;
+
 layout(location = 0) in vec3 in_color;
+layout(location = 1) in vec3 in_normal;
+layout(location = 2) in float in_ao;
 
 layout(location = 0) out vec3 out_color;
 
 void main() {
-    out_color = in_color;
+    float light = max(dot(normalize(in_normal), -ubo.light_dir), 0.0) + ubo.ambient;
+    // Even a fully occluded corner keeps some of its color.
+    float occlusion = mix(0.4, 1.0, in_ao);
+    out_color = in_color * light * occlusion;
 }"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    use voxel_map::ChunkRelativeCoord;
+    use voxel_mod::{Module, ModuleBlockDefinition};
+    use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, TypedBufferAccess};
+    use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+    use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+    use vulkano::memory::allocator::StandardMemoryAllocator;
+    use vulkano::pipeline::graphics::multisample::MultisampleState;
+    use vulkano::pipeline::graphics::rasterization::PolygonMode;
+    use vulkano::pipeline::Pipeline;
+
+    /// A module with a single block, "stone", which only holds a weak reference to its module; keep
+    /// the module around while the block is in use.
+    fn stone() -> (Arc<Module>, Arc<ModuleBlockDefinition>) {
+        static BLOCK_DEFS: &str = "stone:
+  texture: stone.png
+  color:
+    r: 120
+    g: 120
+    b: 128
+  homogeneous: true
+";
+        let module = Module::for_test(BLOCK_DEFS);
+        let stone = module.block_by_id("stone").unwrap();
+        (module, stone)
+    }
+
+    /// A chunk with blocks at (1, 2, 3) and (40, 5, 60).
+    fn small_chunk(stone: &Arc<ModuleBlockDefinition>) -> voxel_map::Chunk {
+        let mut chunk = voxel_map::Chunk::new();
+        chunk.set_block(ChunkRelativeCoord::new(1, 2, 3), Some(stone.clone()));
+        chunk.set_block(ChunkRelativeCoord::new(40, 5, 60), Some(stone.clone()));
+        chunk
+    }
+
+    #[test]
+    fn test_mesh_chunk() {
+        let (_module, stone) = stone();
+        let model_builder = super::mesh_chunk(&small_chunk(&stone));
+        let vertexes = model_builder.vertexes();
+        // Each block is a cube of 4 vertexes on each of 6 sides.
+        assert_eq!(vertexes.len(), 2 * 6 * 4);
+        let stone_color = [120, 120, 128];
+        assert!(vertexes.iter().all(|(vertex, _)| vertex.3 == stone_color));
+        let mut corners = vertexes
+            .iter()
+            .map(|&((x, y, z, _, _), _)| {
+                let steps = super::BLOCK_STEPS;
+                (x / steps, y / steps, z / steps)
+            })
+            .collect::<Vec<_>>();
+        corners.sort_unstable();
+        corners.dedup();
+        assert_eq!(corners.len(), 16);
+        assert_eq!(corners.first(), Some(&(1, 2, 3)));
+        assert!(corners.contains(&(2, 3, 4)));
+        assert!(corners.contains(&(40, 5, 60)));
+        assert_eq!(corners.last(), Some(&(41, 6, 61)));
+
+        let empty = super::mesh_chunk(&voxel_map::Chunk::new());
+        assert!(empty.vertexes().is_empty());
+    }
+
+    #[test]
+    fn test_mesh_slab() {
+        static BLOCK_DEFS: &str = "slab:
+  texture: stone.png
+  color:
+    r: 120
+    g: 120
+    b: 128
+  homogeneous: true
+  shape: slab
+";
+        let module = Module::for_test(BLOCK_DEFS);
+        let slab = module.block_by_id("slab").unwrap();
+
+        // A 2×2×2 node's worth of slabs, which would merge into one node if they were cubes.
+        let mut chunk = voxel_map::Chunk::new();
+        for x in 0..2 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    chunk.set_block(ChunkRelativeCoord::new(x, y, z), Some(slab.clone()));
+                }
+            }
+        }
+        let slab_nodes = chunk.blocks().filter(|(_, block)| block.is_some()).count();
+        assert_eq!(slab_nodes, 8);
+
+        let model_builder = super::mesh_chunk(&chunk);
+        // Each slab is a box of its own: 6 sides of 2 triangles.
+        assert_eq!(model_builder.index_count(), 8 * 6 * 6);
+        let vertexes = model_builder.vertexes();
+        // Each slab is a box as wide & deep as a block, & half as high.
+        let steps = super::BLOCK_STEPS;
+        let slab_vertexes = |corner: (u16, u16, u16)| {
+            vertexes
+                .iter()
+                .map(|&((x, y, z, _, _), _)| (x, y, z))
+                .filter(|&(x, y, z)| {
+                    (corner.0 * steps..=(corner.0 + 1) * steps).contains(&x)
+                        && (corner.1 * steps..=(corner.1 + 1) * steps).contains(&y)
+                        && (corner.2 * steps..=(corner.2 + 1) * steps).contains(&z)
+                })
+                .collect::<Vec<_>>()
+        };
+        let top_slab = slab_vertexes((1, 1, 1));
+        let min = top_slab.iter().min().unwrap();
+        let max = top_slab.iter().max().unwrap();
+        assert_eq!(*min, (steps, steps, steps));
+        assert_eq!(*max, (2 * steps, steps + steps / 2, 2 * steps));
+    }
+
+    #[test]
+    fn test_magica_mesh() {
+        // Meshing needs no GPU.
+        let vox_file = include_bytes!("../vox/logo.vox");
+        let top_chunk = super::io::from_reader(std::io::Cursor::new(&vox_file[..])).unwrap();
+        let mesh = super::MagicaMesh::new(&top_chunk).unwrap();
+        assert!(mesh.vertex_count() != 0);
+        // Two triangles per face.
+        assert!(mesh.index_count() % 6 == 0);
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let vox_file = include_bytes!("../vox/logo.vox");
+        let top_chunk = super::io::from_reader(std::io::Cursor::new(&vox_file[..])).unwrap();
+        // The logo is a flat 22×5 sign, one voxel thick.
+        assert_eq!(
+            super::bounding_box(&top_chunk).unwrap(),
+            Some(((0, 0, 0), (21, 0, 4)))
+        );
+
+        assert_eq!(super::voxel_bounds(&[]), None);
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        // An L: two voxels side by side on the floor, & one on top of the second.
+        let voxel = |x, y, z, color_index| super::io::Voxel {
+            x,
+            y,
+            z,
+            color_index,
+        };
+        let voxels = [voxel(0, 0, 0, 1), voxel(1, 0, 0, 2), voxel(1, 1, 0, 3)];
+        let model_builder = super::mesh_voxels(&voxels);
+        // The AO of the corner at `position` of the top face of the first voxel.
+        let top_ao = |position: (u16, u16, u16)| {
+            let aos = model_builder
+                .vertexes()
+                .iter()
+                .filter(|((x, y, z, color_index, _), normal)| {
+                    (*x, *y, *z) == position && *color_index == 1 && *normal == [0, 1, 0]
+                })
+                .map(|((_, _, _, _, ao), _)| *ao)
+                .collect::<Vec<_>>();
+            assert_eq!(aos.len(), 1);
+            aos[0]
+        };
+        // Its corners against the upper voxel, in the inside of the L, are darker…
+        let inner = top_ao((1, 1, 0));
+        assert_eq!(inner, top_ao((1, 1, 1)));
+        // … than its open corners.
+        let open = top_ao((0, 1, 0));
+        assert_eq!(open, top_ao((0, 1, 1)));
+        assert_eq!(open, 3);
+        assert!(inner < open);
+
+        assert_eq!(super::corner_ao(true, true, false), 0);
+        assert_eq!(super::corner_ao(false, false, true), 2);
+        assert_eq!(super::corner_ao(false, false, false), 3);
+    }
+
+    #[test]
+    fn test_mesh_chunk_lod() {
+        // A checkerboard of stone filling an 8×8×8 corner of the chunk: nothing in it merges.
+        let (_module, stone) = stone();
+        let mut chunk = voxel_map::Chunk::new();
+        for x in 0..8 {
+            for y in 0..8 {
+                for z in 0..8 {
+                    if (x + y + z) % 2 == 0 {
+                        chunk.set_block(ChunkRelativeCoord::new(x, y, z), Some(stone.clone()));
+                    }
+                }
+            }
+        }
+        let origin = [0., 0., 0.];
+
+        let near = super::mesh_chunk_lod(&chunk, origin, [32., 32., 32.]);
+        let near = near.vertexes();
+        assert_eq!(near.len(), super::mesh_chunk(&chunk).vertexes().len());
+        let far = super::mesh_chunk_lod(&chunk, origin, [2000., 32., 32.]);
+        let far = far.vertexes();
+        assert!(far.len() * 10 < near.len());
+        assert!(!far.is_empty());
+        // The averaged blocks are all stone, so the same color.
+        let stone_color = [120, 120, 128];
+        assert!(far.iter().all(|(vertex, _)| vertex.3 == stone_color));
+
+        assert!(super::lod_depth(0.) == 6);
+        assert!(super::lod_depth(100.) == 6);
+        assert!(super::lod_depth(1000.) < 6);
+        assert!(super::lod_depth(1e9) == 0);
+    }
+
+    #[test]
+    fn test_chunk_palette() {
+        let vertex = |x, color| ((x, 0, 0, color, 3), [0, 1, 0]);
+        let (red, green) = ([255, 0, 0], [0, 255, 0]);
+        let vertexes = [
+            vertex(0, red),
+            vertex(1, green),
+            vertex(2, red),
+            vertex(3, red),
+        ];
+        let (palette, indexes) = super::chunk_palette(&vertexes);
+        assert!(palette == [red, green]);
+        for ((_, _, _, color, _), _) in vertexes.iter() {
+            assert!(palette[indexes[color] as usize] == *color);
+        }
+
+        // Every vertex of a chunk's mesh has a color in its palette.
+        let (_module, stone) = stone();
+        let model_builder = super::mesh_chunk(&small_chunk(&stone));
+        let (palette, indexes) = super::chunk_palette(model_builder.vertexes());
+        assert!(palette == [[120, 120, 128]]);
+        assert!(indexes.len() == 1);
+    }
+
+    #[test]
+    fn test_chunk_model_through_allocator() {
+        let (device, _) = crate::init::test_device!();
+        let memory_allocator = StandardMemoryAllocator::new_default(device);
+        let (_module, stone) = stone();
+        let model =
+            super::MagicaModel::from_chunk(&memory_allocator, &small_chunk(&stone), [0.0; 3])
+                .unwrap();
+        assert_eq!(model.vertex_buffer.len(), 2 * 6 * 4);
+        assert_eq!(model.index_buffer.len(), 2 * 6 * 6);
+        assert_eq!(model.palette.len(), 1);
+        let stone = super::Color::from_rgb([120, 120, 128]).to_f32_rgba();
+        assert!(model.palette.read().unwrap()[0] == stone);
+        let vertexes = model.vertex_buffer.read().unwrap();
+        assert!(vertexes.iter().all(|v| v.color_index == 0));
+        let empty = voxel_map::Chunk::new();
+        assert!(super::MagicaModel::from_chunk(&memory_allocator, &empty, [0.0; 3]).is_none());
+    }
+
+    #[test]
+    fn test_light_direction() {
+        for t in [0.0, 1.0, 2.5, 100.0] {
+            let [x, y, z] = super::light_direction(t);
+            assert!((x * x + y * y + z * z - 1.0).abs() < 1e-5);
+            assert!(y < 0.0);
+        }
+        assert!(super::light_direction(0.0) != super::light_direction(1.0));
+    }
+
+    #[test]
+    fn test_pipeline_with_lighting_uniform() {
+        let (device, _) = crate::init::test_device!();
+        let pipeline = super::build_pipeline(
+            device.clone(),
+            crate::init::test_render_pass(device.clone()),
+            MultisampleState::default(),
+            PolygonMode::Fill,
+            &super::MagicaShaders::load(device.clone()),
+        );
+
+        let memory_allocator = StandardMemoryAllocator::new_default(device.clone());
+        let ubo = crate::UniformBufferObject {
+            t: 1.0,
+            ambient: super::AMBIENT_LIGHT,
+            light_dir: super::light_direction(1.0),
+            ..bytemuck::Zeroable::zeroed()
+        };
+        let uniform_buffer = CpuAccessibleBuffer::from_data(
+            &memory_allocator,
+            BufferUsage {
+                uniform_buffer: true,
+                ..BufferUsage::empty()
+            },
+            false,
+            ubo,
+        )
+        .unwrap();
+        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device);
+        PersistentDescriptorSet::new(
+            &descriptor_set_allocator,
+            pipeline.layout().set_layouts()[0].clone(),
+            [WriteDescriptorSet::buffer(0, uniform_buffer)],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_magica_model_through_allocator() {
+        let (device, _) = crate::init::test_device!();
+        let memory_allocator = StandardMemoryAllocator::new_default(device);
+        let vox_file = include_bytes!("../vox/logo.vox");
+        let top_chunk = super::io::from_reader(std::io::Cursor::new(&vox_file[..])).unwrap();
+        let model = super::MagicaModel::new(&memory_allocator, &top_chunk).unwrap();
+        assert!(model.index_buffer.len() != 0);
+
+        // The palette is uploaded once, & the vertexes only refer to it.
+        assert_eq!(model.palette.len(), super::VOX_PALETTE_LEN as u64);
+        let palette_len = u32::try_from(super::VOX_PALETTE_LEN).unwrap();
+        let vertexes = model.vertex_buffer.read().unwrap();
+        assert!(vertexes.iter().all(|v| v.color_index < palette_len));
+        let file_palette = super::find_rgba_data(&top_chunk).unwrap();
+        let palette = model.palette.read().unwrap();
+        for (color, uploaded) in file_palette.iter().zip(palette.iter()) {
+            assert!(color.to_f32_rgba() == *uploaded);
+        }
+    }
+
+    #[test]
+    fn test_vox_palette() {
+        let short = [super::Color::from_rgb([10, 20, 30])];
+        let palette = super::vox_palette(&short);
+        assert!(palette.len() == super::VOX_PALETTE_LEN);
+        assert!(palette[0] == short[0].to_f32_rgba());
+        assert!(palette[1..].iter().all(|&color| color == [0., 0., 0., 1.]));
+    }
+}
@@ -0,0 +1,84 @@
+//! Export meshed geometry as Wavefront OBJ, for use in other tools.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Write the triangles given by `indices` into `vertices` as an OBJ file. Vertices at the same
+/// position (e.g., the corners shared by a cube's faces, which are only separate vertices for
+/// their normals) are written once.
+pub fn export_obj<W: Write>(mut w: W, vertices: &[[f32; 3]], indices: &[usize]) -> io::Result<()> {
+    let triangles = indices.chunks_exact(3);
+    assert!(
+        triangles.remainder().is_empty(),
+        "indices should be whole triangles, but there are {}",
+        indices.len()
+    );
+    // Each vertex's index among the unique positions. (Compared by their bits, as floats aren't
+    // `Eq`.)
+    let mut position_indices = HashMap::new();
+    let mut obj_indices = Vec::with_capacity(vertices.len());
+    for position in vertices {
+        let next_index = position_indices.len();
+        let index = *position_indices
+            .entry(position.map(f32::to_bits))
+            .or_insert_with(|| next_index);
+        if index == next_index {
+            writeln!(w, "v {} {} {}", position[0], position[1], position[2])?;
+        }
+        obj_indices.push(index);
+    }
+    for triangle in triangles {
+        // OBJ's indices start from 1.
+        let [a, b, c] = [0, 1, 2].map(|corner| obj_indices[triangle[corner]] + 1);
+        writeln!(w, "f {} {} {}", a, b, c)?;
+    }
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_export_cube() {
+        // A cube as a mesher would produce it: 4 vertices per face, each face with its own.
+        let faces: [[[f32; 3]; 4]; 6] = [
+            [[0., 0., 0.], [1., 0., 0.], [1., 0., 1.], [0., 0., 1.]],
+            [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]],
+            [[0., 0., 1.], [0., 1., 1.], [1., 1., 1.], [1., 0., 1.]],
+            [[1., 0., 0.], [1., 0., 1.], [1., 1., 1.], [1., 1., 0.]],
+            [[0., 0., 0.], [0., 1., 0.], [0., 1., 1.], [0., 0., 1.]],
+            [[0., 1., 0.], [0., 1., 1.], [1., 1., 1.], [1., 1., 0.]],
+        ];
+        let vertices = faces.iter().flatten().copied().collect::<Vec<_>>();
+        let indices = (0..6)
+            .flat_map(|face| [0, 1, 2, 0, 2, 3].map(|corner| face * 4 + corner))
+            .collect::<Vec<_>>();
+
+        let mut obj = Vec::new();
+        super::export_obj(&mut obj, &vertices, &indices).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+
+        let positions = obj
+            .lines()
+            .filter(|line| line.starts_with("v "))
+            .collect::<Vec<_>>();
+        assert_eq!(positions.len(), 8);
+        assert_eq!(positions.iter().collect::<HashSet<_>>().len(), 8);
+        assert!(positions.contains(&"v 1 1 1"));
+
+        let faces = obj
+            .lines()
+            .filter(|line| line.starts_with("f "))
+            .collect::<Vec<_>>();
+        assert_eq!(faces.len(), 12);
+        for face in faces {
+            let corners = face[2..]
+                .split(' ')
+                .map(|index| index.parse::<usize>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(corners.len(), 3);
+            assert!(corners.iter().all(|index| (1..=8).contains(index)));
+        }
+    }
+}
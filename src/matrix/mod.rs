@@ -27,6 +27,27 @@ impl Matrix {
         ])
     }
 
+    /// The matrix for transforming normals by this (affine) transform: the inverse-transpose of its
+    /// upper-left 3x3, in column-major order. Unlike this matrix, it keeps normals perpendicular to
+    /// their surfaces under non-uniform scaling. The normals it produces need re-normalizing.
+    pub fn normal_matrix(&self) -> [[f32; 3]; 3] {
+        let mut upper = [[0.; 3]; 3];
+        for (c, column) in upper.iter_mut().enumerate() {
+            column.copy_from_slice(&self.data[c][..3]);
+        }
+        transpose_3x3(inverse_3x3(upper))
+    }
+
+    /// Row `r` of the matrix.
+    pub fn row(&self, r: usize) -> [f32; 4] {
+        [
+            self.data[0][r],
+            self.data[1][r],
+            self.data[2][r],
+            self.data[3][r],
+        ]
+    }
+
     /*
     fn transpose(mut self) -> Matrix {
         std::mem::swap(&mut self.data[1][0], &mut self.data[0][1]);
@@ -43,6 +64,36 @@ impl Matrix {
     */
 }
 
+/// Invert a 3x3 matrix. A singular matrix gives its adjugate, which is all a normal matrix needs:
+/// normals are re-normalized anyways.
+fn inverse_3x3(m: [[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let cofactor = |r: usize, c: usize| {
+        let (r1, r2) = ((r + 1) % 3, (r + 2) % 3);
+        let (c1, c2) = ((c + 1) % 3, (c + 2) % 3);
+        m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+    };
+    let det = m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+    let scale = if det == 0. { 1. } else { 1. / det };
+    let mut inverse = [[0.; 3]; 3];
+    for (r, row) in inverse.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            // The adjugate is the transpose of the cofactor matrix.
+            *value = cofactor(c, r) * scale;
+        }
+    }
+    inverse
+}
+
+fn transpose_3x3(m: [[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let mut transposed = m;
+    for (r, row) in transposed.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = m[c][r];
+        }
+    }
+    transposed
+}
+
 impl From<[[f32; 4]; 4]> for Matrix {
     fn from(matrix: [[f32; 4]; 4]) -> Matrix {
         Matrix {
@@ -198,7 +249,8 @@ impl std::ops::Mul<Vertex4d> for Matrix {
 
 #[cfg(test)]
 mod tests {
-    use super::Matrix;
+    use super::transformations::{rotate_x, rotate_y, rotate_z, scale, translate};
+    use super::{Matrix, Vertex3d};
 
     #[test]
     fn test_matrix_debug() {
@@ -235,4 +287,31 @@ mod tests {
         ]);
         assert!(c == expected);
     }
+
+    #[test]
+    fn test_normal_matrix() {
+        let close = |a: [[f32; 3]; 3], b: [[f32; 3]; 3]| {
+            (0..3).all(|c| (0..3).all(|r| (a[c][r] - b[c][r]).abs() < 1e-6))
+        };
+
+        let rotation = rotate_y(0.7) * rotate_x(-1.1) * rotate_z(2.3);
+        let upper = [0, 1, 2].map(|c| [0, 1, 2].map(|r| rotation.data[c][r]));
+        assert!(close(rotation.normal_matrix(), upper));
+        // Translation doesn't affect normals:
+        let moved = translate(4., 5., 6.) * rotation;
+        assert!(close(moved.normal_matrix(), upper));
+
+        // The normal of the plane x = y must, after stretching Y, stay perpendicular to it:
+        let stretch = scale(1., 2., 1.);
+        let normal_matrix = stretch.normal_matrix();
+        let stretch_upper = [[1., 0., 0.], [0., 2., 0.], [0., 0., 1.]];
+        assert!(!close(normal_matrix, stretch_upper));
+        let normal = [1., -1., 0.];
+        let normal: Vec<f32> = (0..3)
+            .map(|r| (0..3).map(|c| normal_matrix[c][r] * normal[c]).sum())
+            .collect();
+        let along_plane = stretch * Vertex3d::new(1., 1., 0.);
+        let dot = normal[0] * along_plane.x + normal[1] * along_plane.y + normal[2] * along_plane.z;
+        assert!(dot.abs() < 1e-6);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::matrix::Matrix;
+use crate::matrix::{Matrix, Vertex3d};
 
 // Useful references:
 //   https://www.scratchapixel.com/lessons/3d-basic-rendering/perspective-and-orthographic-projection-matrix/building-basic-perspective-projection-matrix
@@ -6,8 +6,20 @@ use crate::matrix::Matrix;
 //   http://www.alexisbreust.fr/2018-game-engine-frustum-culling.html
 //   https://ksimek.github.io/2013/06/03/calibrated_cameras_in_opengl/
 
-/// Builds a perspective projection matrix.
+/// Panics unless `0 < near < far`, both finite; otherwise, the projection would be full of
+/// infinities & NaNs, which only show up once they reach the GPU (as nothing being drawn).
+fn check_clip_planes(near: f32, far: f32) {
+    assert!(
+        0. < near && near < far && far.is_finite(),
+        "the clip planes must be 0 < near < far, but near = {} & far = {}",
+        near,
+        far,
+    );
+}
+
+/// Builds a perspective projection matrix. The clip planes must be `0 < near < far`.
 pub fn perspective(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Matrix {
+    check_clip_planes(near, far);
     let a = (right + left) / (right - left);
     let b = (top + bottom) / (top - bottom);
     let c = -(far + near) / (far - near);
@@ -27,6 +39,8 @@ pub fn perspective(left: f32, right: f32, bottom: f32, top: f32, near: f32, far:
     proj * correction
 }
 
+/// Builds a perspective transformation matrix given horizontal & vertical fields of view. The clip
+/// planes must be `0 < near < far`.
 pub fn perspective_fov_both(fov_horizontal: f32, fov_vertical: f32, near: f32, far: f32) -> Matrix {
     // Notes:
     //
@@ -65,9 +79,142 @@ pub fn perspective_fov_both(fov_horizontal: f32, fov_vertical: f32, near: f32, f
 }
 
 /// Builds a perspective transformation matrix given a vertical field of view and an aspect ratio
-/// (_width_ / _height_).
+/// (_width_ / _height_). The clip planes must be `0 < near < far`.
 pub fn perspective_fov(fov_vertical: f32, aspect_ratio: f32, near: f32, far: f32) -> Matrix {
     let top = (fov_vertical / 2.).tan() * near;
     let right = top * aspect_ratio;
     perspective(-right, right, -top, top, near, far)
 }
+
+/// Builds a perspective transformation matrix, like [`perspective_fov`], but with "reversed Z":
+/// points on the near plane get a depth of 1, and depth falls towards 0 as points get further
+/// away, with no far plane. Floats are far more precise near 0, so this spreads depth precision
+/// much more evenly with distance, avoiding z-fighting in the distance.
+///
+/// Rendering with this needs the depth test flipped: compare with `CompareOp::Greater` (nearer is
+/// *greater*), and clear the depth buffer to 0, not 1.
+///
+/// `near` must be positive.
+pub fn perspective_fov_reverse_z(fov_vertical: f32, aspect_ratio: f32, near: f32) -> Matrix {
+    check_clip_planes(near, f32::MAX);
+    let f = 1. / (fov_vertical / 2.).tan();
+    // The depth (z / w) is near / -z, with w = -z as the camera looks down -Z.
+    Matrix::from([
+        [f / aspect_ratio, 0., 0., 0.],
+        [0., -f, 0., 0.],
+        [0., 0., 0., near],
+        [0., 0., -1., 0.],
+    ])
+}
+
+/// Extract the six planes of the view frustum from a combined projection & view matrix (Gribb &
+/// Hartmann's method), as `[a, b, c, d]`: a point is on the inside of a plane when
+/// `a * x + b * y + c * z + d >= 0`. The planes aren't normalized.
+///
+/// Clip space is Vulkan's: X & Y run from -W to W, and Z from 0 to W.
+pub fn frustum_planes(view_proj: &Matrix) -> [[f32; 4]; 6] {
+    let [x, y, z, w] = [0, 1, 2, 3].map(|r| view_proj.row(r));
+    let add = |a: [f32; 4], b: [f32; 4]| [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]];
+    let sub = |a: [f32; 4], b: [f32; 4]| [a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3]];
+    [
+        add(w, x), // left
+        sub(w, x), // right
+        add(w, y), // bottom
+        sub(w, y), // top
+        z,         // near
+        sub(w, z), // far
+    ]
+}
+
+/// Whether any of the axis-aligned box from `min` to `max` might be inside the frustum with the
+/// given `planes` (see [`frustum_planes`]). Boxes that are outside may still be reported as
+/// inside, near the frustum's corners, but never the other way around.
+pub fn aabb_in_frustum(planes: &[[f32; 4]; 6], min: Vertex3d, max: Vertex3d) -> bool {
+    planes.iter().all(|[a, b, c, d]| {
+        // The corner of the box furthest along the plane's normal: if even that is outside, all of
+        // the box is.
+        let x = if *a >= 0. { max.x } else { min.x };
+        let y = if *b >= 0. { max.y } else { min.y };
+        let z = if *c >= 0. { max.z } else { min.z };
+        a * x + b * y + c * z + d >= 0.
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::matrix::{Vertex3d, Vertex4d};
+
+    #[test]
+    fn test_perspective_fov_reverse_z() {
+        let near = 0.1;
+        let proj = super::perspective_fov_reverse_z(90f32.to_radians(), 16. / 9., near);
+        let depth = |z: f32| {
+            let clip = proj * Vertex4d::new(0.5, 0.5, z, 1.);
+            clip.z / clip.w
+        };
+        assert!((depth(-near) - 1.).abs() < 1e-6);
+        assert!((depth(-1.) - near).abs() < 1e-6);
+        assert!(depth(-1e6) < 1e-6);
+        assert!(0. < depth(-1e6));
+        // Further points are always shallower:
+        let mut last = depth(-near);
+        for z in [-0.5, -10., -1000., -1e5] {
+            assert!(depth(z) < last);
+            last = depth(z);
+        }
+
+        // It's a (vertically-flipped, for Vulkan) perspective projection, with a 90° FoV:
+        let clip = proj * Vertex4d::new(0., 1., -1., 1.);
+        assert!((clip.y / clip.w + 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_perspective_fov() {
+        let proj = super::perspective_fov(90f32.to_radians(), 16. / 9., 0.1, 80.);
+        for row in 0..4 {
+            assert!(proj.row(row).iter().all(|c| c.is_finite()));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "0 < near < far")]
+    fn test_perspective_fov_near_equals_far() {
+        super::perspective_fov(90f32.to_radians(), 16. / 9., 10., 10.);
+    }
+
+    #[test]
+    #[should_panic(expected = "0 < near < far")]
+    fn test_perspective_fov_zero_near() {
+        super::perspective_fov(90f32.to_radians(), 16. / 9., 0., 80.);
+    }
+
+    #[test]
+    #[should_panic(expected = "0 < near < far")]
+    fn test_perspective_near_beyond_far() {
+        super::perspective(-1., 1., -1., 1., 80., 0.1);
+    }
+
+    #[test]
+    fn test_aabb_in_frustum() {
+        let proj = super::perspective_fov(90f32.to_radians(), 1., 0.1, 80.);
+        let planes = super::frustum_planes(&proj);
+        let visible = |min: (f32, f32, f32), max: (f32, f32, f32)| {
+            super::aabb_in_frustum(
+                &planes,
+                Vertex3d::new(min.0, min.1, min.2),
+                Vertex3d::new(max.0, max.1, max.2),
+            )
+        };
+
+        // A box around the camera:
+        assert!(visible((-10., -10., -10.), (10., 10., 10.)));
+        // A box in front of the camera (which looks down -Z):
+        assert!(visible((-1., -1., -20.), (1., 1., -10.)));
+        // A box behind the camera:
+        assert!(!visible((-10., -10., 5.), (10., 10., 20.)));
+        // Boxes far off to the side, above, and beyond the far plane:
+        assert!(!visible((1000., -1., -20.), (1010., 1., -10.)));
+        assert!(!visible((-1., 1000., -20.), (1., 1010., -10.)));
+        assert!(!visible((-1., -1., -1010.), (1., 1., -1000.)));
+    }
+}
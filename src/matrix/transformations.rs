@@ -1,4 +1,4 @@
-use crate::matrix::Matrix;
+use crate::matrix::{Matrix, Vertex3d};
 
 // https://www.cs.cornell.edu/courses/cs4620/2010fa/lectures/03transforms3d.pdf
 
@@ -11,6 +11,15 @@ pub fn translate(x: f32, y: f32, z: f32) -> Matrix {
     ])
 }
 
+pub fn scale(x: f32, y: f32, z: f32) -> Matrix {
+    Matrix::from([
+        [x, 0., 0., 0.],
+        [0., y, 0., 0.],
+        [0., 0., z, 0.],
+        [0., 0., 0., 1.],
+    ])
+}
+
 pub fn rotate_x(by: f32) -> Matrix {
     Matrix::from([
         [1., 0., 0., 0.],
@@ -37,3 +46,42 @@ pub fn rotate_z(by: f32) -> Matrix {
         [0., 0., 0., 1.],
     ])
 }
+
+/// A transform that scales by `scale`, then rotates by `rotation`, then translates by
+/// `translation`; i.e., places a model in the world.
+pub fn trs(translation: Vertex3d, rotation: &Matrix, scale: Vertex3d) -> Matrix {
+    self::translate(translation.x, translation.y, translation.z)
+        * *rotation
+        * self::scale(scale.x, scale.y, scale.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::matrix::{Matrix, Vertex3d};
+
+    #[test]
+    fn test_trs() {
+        let rotation = super::rotate_y(1.2) * super::rotate_x(0.3);
+        let trs = super::trs(
+            Vertex3d::new(1., 2., 3.),
+            &rotation,
+            Vertex3d::new(2., 3., 4.),
+        );
+        let origin = trs * Vertex3d::new(0., 0., 0.);
+        assert_eq!((origin.x, origin.y, origin.z), (1., 2., 3.));
+
+        // Scaling happens first, then rotation, then translation:
+        let point = trs * Vertex3d::new(1., 0., 0.);
+        let expected = rotation * Vertex3d::new(2., 0., 0.);
+        assert!((point.x - (expected.x + 1.)).abs() < 1e-6);
+        assert!((point.y - (expected.y + 2.)).abs() < 1e-6);
+        assert!((point.z - (expected.z + 3.)).abs() < 1e-6);
+
+        let pure_scale = super::trs(
+            Vertex3d::new(0., 0., 0.),
+            &Matrix::identity(),
+            Vertex3d::new(2., 3., 4.),
+        );
+        assert_eq!(pure_scale, super::scale(2., 3., 4.));
+    }
+}
@@ -1,4 +1,11 @@
 //! Utility code for building in-GPU vertex buffers/index buffers for models.
+//!
+//! [`ModelBuilder`] merges vertexes that compare equal, so anything that must keep vertexes apart
+//! has to be part of the vertex. For lit models, that includes the normal: a cube's corner is
+//! shared by three faces, each needing its own normal. Build those models from `(vertex, normal)`
+//! pairs, with [`ModelBuilder::push_triangle_with_normal`]; as floats aren't `Eq`, store the
+//! normal in some exact form (e.g., `[i8; 3]` for axis-aligned faces), and convert it to floats
+//! when mapping the vertexes in [`ModelBuilder::into_gpu`].
 
 use std::collections::{hash_map, HashMap};
 use std::convert::TryFrom;
@@ -6,14 +13,21 @@ use std::hash::Hash;
 use std::sync::Arc;
 
 use bytemuck::Pod;
-use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, TypedBufferAccess};
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer, TypedBufferAccess};
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CommandBufferUsage, PrimaryCommandBufferAbstract,
+};
+use vulkano::device::Queue;
 use vulkano::memory::allocator::MemoryAllocator;
+use vulkano::sync::GpuFuture;
 
 pub struct ModelBuilder<V> {
     vertex_to_index: HashMap<V, usize>,
     vertexes: Vec<V>,
     index_map: Vec<usize>,
+    /// The minimum & maximum corners of the positions given to `push_vertex_with_pos`.
+    bounds: Option<([f32; 3], [f32; 3])>,
 }
 
 impl<V: Clone + Eq + Hash> ModelBuilder<V> {
@@ -22,6 +36,7 @@ impl<V: Clone + Eq + Hash> ModelBuilder<V> {
             vertex_to_index: HashMap::new(),
             vertexes: Vec::new(),
             index_map: Vec::new(),
+            bounds: None,
         }
     }
 
@@ -38,6 +53,36 @@ impl<V: Clone + Eq + Hash> ModelBuilder<V> {
         self.index_map.push(index);
     }
 
+    /// Push a vertex, which is at `pos`, counting it towards the model's bounding box.
+    pub fn push_vertex_with_pos(&mut self, vertex: V, pos: (f32, f32, f32)) {
+        let (x, y, z) = pos;
+        let (min, max) = self.bounds.get_or_insert(([x, y, z], [x, y, z]));
+        *min = [min[0].min(x), min[1].min(y), min[2].min(z)];
+        *max = [max[0].max(x), max[1].max(y), max[2].max(z)];
+        self.push_vertex(vertex);
+    }
+
+    /// The minimum & maximum corners of the box containing every vertex pushed with
+    /// [`ModelBuilder::push_vertex_with_pos`]; `None` if there aren't any.
+    pub fn bounding_box(&self) -> Option<([f32; 3], [f32; 3])> {
+        self.bounds
+    }
+
+    /// The distinct vertexes pushed so far, in index order.
+    pub fn vertexes(&self) -> &[V] {
+        &self.vertexes
+    }
+
+    /// The number of vertexes pushed so far, i.e., the length of the index buffer.
+    pub fn index_count(&self) -> usize {
+        self.index_map.len()
+    }
+
+    /// The index, into [`vertexes`](Self::vertexes), of each vertex pushed so far.
+    pub fn indexes(&self) -> &[usize] {
+        &self.index_map
+    }
+
     pub fn into_gpu<F, U: Pod + Send + Sync + 'static>(
         self,
         memory_allocator: &(impl MemoryAllocator + ?Sized),
@@ -47,8 +92,7 @@ impl<V: Clone + Eq + Hash> ModelBuilder<V> {
     where
         F: Fn(V) -> U,
     {
-        // TODO: use DeviceLocalBuffer, maybe ImmutableBuffer.
-        // (This TODO was from an old version of Vulkano, 0.30.0 or earlier. Does it still apply?)
+        // For static models, see `into_gpu_device_local`.
         let vertex_buffer = CpuAccessibleBuffer::from_iter(
             memory_allocator,
             BufferUsage {
@@ -63,12 +107,97 @@ impl<V: Clone + Eq + Hash> ModelBuilder<V> {
         let index_buffer = IndexBuffer::new(memory_allocator, u8_ext, &self.index_map);
         (vertex_buffer, index_buffer)
     }
+
+    /// Like [`ModelBuilder::into_gpu`], but into device-local buffers, which are faster for the
+    /// GPU to read, for models that won't change.
+    ///
+    /// The data is copied in from staging buffers by a command buffer submitted to `queue`; join
+    /// the returned future before using the buffers.
+    pub fn into_gpu_device_local<F, U: Pod + Send + Sync + 'static>(
+        self,
+        memory_allocator: &(impl MemoryAllocator + ?Sized),
+        command_buffer_allocator: &StandardCommandBufferAllocator,
+        queue: &Arc<Queue>,
+        vertex_map: F,
+        u8_ext: bool,
+    ) -> (Arc<DeviceLocalBuffer<[U]>>, IndexBuffer, Box<dyn GpuFuture>)
+    where
+        F: Fn(V) -> U,
+    {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        let vertex_buffer = DeviceLocalBuffer::from_iter(
+            memory_allocator,
+            self.vertexes.into_iter().map(vertex_map),
+            BufferUsage {
+                vertex_buffer: true,
+                ..BufferUsage::empty()
+            },
+            &mut builder,
+        )
+        .unwrap();
+        let index_buffer =
+            IndexBuffer::new_device_local(memory_allocator, u8_ext, &self.index_map, &mut builder);
+
+        let upload = builder.build().unwrap().execute(queue.clone()).unwrap();
+        (vertex_buffer, index_buffer, Box::new(upload))
+    }
+}
+
+impl<V: Clone + Eq + Hash, N: Clone + Eq + Hash> ModelBuilder<(V, N)> {
+    /// Push a triangle of a flat face, whose vertexes all get the face's `normal`.
+    pub fn push_triangle_with_normal(&mut self, triangle: [V; 3], normal: N) {
+        for vertex in triangle {
+            self.push_vertex((vertex, normal.clone()));
+        }
+    }
 }
 
 enum IndexBufferRepr {
     U8(Arc<CpuAccessibleBuffer<[u8]>>),
     U16(Arc<CpuAccessibleBuffer<[u16]>>),
     U32(Arc<CpuAccessibleBuffer<[u32]>>),
+    DeviceLocalU8(Arc<DeviceLocalBuffer<[u8]>>),
+    DeviceLocalU16(Arc<DeviceLocalBuffer<[u16]>>),
+    DeviceLocalU32(Arc<DeviceLocalBuffer<[u32]>>),
+}
+
+/// The type of the indexes in an index buffer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum IndexType {
+    U8,
+    U16,
+    U32,
+}
+
+impl IndexType {
+    /// The smallest index type that can hold every one of `indexes`. (`u8` indexes need a device
+    /// extension, so are only used if `u8_ext`.)
+    fn for_indexes(indexes: &[usize], u8_ext: bool) -> IndexType {
+        let max_index = indexes.iter().max().expect("expected at least one index");
+        match (max_index, u8_ext) {
+            (0..=0xff, true) => IndexType::U8,
+            (0..=0xffff, _) => IndexType::U16,
+            (0x10000..=0xffff_ffff, _) => IndexType::U32,
+            _ => panic!(
+                "max index of {} exceeds GPU limits of 32-bit indexes",
+                max_index
+            ),
+        }
+    }
+}
+
+/// Convert `indexes` to the index type `I`, which they must all fit in.
+fn narrow<I: TryFrom<usize>>(indexes: &[usize]) -> impl ExactSizeIterator<Item = I> + '_ {
+    indexes.iter().map(|v| {
+        I::try_from(*v)
+            .ok()
+            .expect("all indexes should have fit in the index type")
+    })
 }
 
 pub struct IndexBuffer(IndexBufferRepr);
@@ -79,55 +208,50 @@ impl IndexBuffer {
         u8_ext: bool,
         indexes: &[usize],
     ) -> IndexBuffer {
-        let max_index = indexes.iter().max().expect("expected at least one index");
-        let buffer_usage = BufferUsage {
+        let usage = BufferUsage {
             index_buffer: true,
             ..BufferUsage::empty()
         };
-        let repr = match (max_index, u8_ext) {
-            (0..=0xff, true) => CpuAccessibleBuffer::from_iter(
-                memory_allocator,
-                buffer_usage,
-                false,
-                indexes
-                    .iter()
-                    .map(|v| u8::try_from(*v).expect("all indexes should have fit in a u8")),
-            )
-            .map(IndexBufferRepr::U8)
-            .unwrap(),
-            (0..=0xff, false) => CpuAccessibleBuffer::from_iter(
-                memory_allocator,
-                buffer_usage,
-                false,
-                indexes.iter().map(|v| {
-                    u16::try_from(*v).expect("all indexes should have fit in a u8, let alone a u16")
-                }),
-            )
-            .map(IndexBufferRepr::U16)
-            .unwrap(),
-            (0x100..=0xffff, _) => CpuAccessibleBuffer::from_iter(
-                memory_allocator,
-                buffer_usage,
-                false,
-                indexes
-                    .iter()
-                    .map(|v| u16::try_from(*v).expect("all indexes should have fit in a u16")),
-            )
-            .map(IndexBufferRepr::U16)
-            .unwrap(),
-            (0x10000..=0xffff_ffff, _) => CpuAccessibleBuffer::from_iter(
-                memory_allocator,
-                buffer_usage,
-                false,
-                indexes
-                    .iter()
-                    .map(|v| u32::try_from(*v).expect("all indexes should have fit in a u32")),
-            )
-            .map(IndexBufferRepr::U32)
-            .unwrap(),
-            _ => panic!(
-                "max index of {} exceeds GPU limits of 32-bit indexes",
-                max_index
+        let repr = match IndexType::for_indexes(indexes, u8_ext) {
+            IndexType::U8 => IndexBufferRepr::U8(
+                CpuAccessibleBuffer::from_iter(memory_allocator, usage, false, narrow(indexes))
+                    .unwrap(),
+            ),
+            IndexType::U16 => IndexBufferRepr::U16(
+                CpuAccessibleBuffer::from_iter(memory_allocator, usage, false, narrow(indexes))
+                    .unwrap(),
+            ),
+            IndexType::U32 => IndexBufferRepr::U32(
+                CpuAccessibleBuffer::from_iter(memory_allocator, usage, false, narrow(indexes))
+                    .unwrap(),
+            ),
+        };
+        IndexBuffer(repr)
+    }
+
+    /// Create a device-local index buffer, recording the copy of `indexes` into it in `builder`.
+    fn new_device_local<L>(
+        memory_allocator: &(impl MemoryAllocator + ?Sized),
+        u8_ext: bool,
+        indexes: &[usize],
+        builder: &mut AutoCommandBufferBuilder<L>,
+    ) -> IndexBuffer {
+        let usage = BufferUsage {
+            index_buffer: true,
+            ..BufferUsage::empty()
+        };
+        let repr = match IndexType::for_indexes(indexes, u8_ext) {
+            IndexType::U8 => IndexBufferRepr::DeviceLocalU8(
+                DeviceLocalBuffer::from_iter(memory_allocator, narrow(indexes), usage, builder)
+                    .unwrap(),
+            ),
+            IndexType::U16 => IndexBufferRepr::DeviceLocalU16(
+                DeviceLocalBuffer::from_iter(memory_allocator, narrow(indexes), usage, builder)
+                    .unwrap(),
+            ),
+            IndexType::U32 => IndexBufferRepr::DeviceLocalU32(
+                DeviceLocalBuffer::from_iter(memory_allocator, narrow(indexes), usage, builder)
+                    .unwrap(),
             ),
         };
         IndexBuffer(repr)
@@ -138,6 +262,9 @@ impl IndexBuffer {
             IndexBufferRepr::U8(b) => b.len(),
             IndexBufferRepr::U16(b) => b.len(),
             IndexBufferRepr::U32(b) => b.len(),
+            IndexBufferRepr::DeviceLocalU8(b) => b.len(),
+            IndexBufferRepr::DeviceLocalU16(b) => b.len(),
+            IndexBufferRepr::DeviceLocalU32(b) => b.len(),
         }
     }
 
@@ -146,6 +273,226 @@ impl IndexBuffer {
             IndexBufferRepr::U8(buf) => cb.bind_index_buffer(buf.clone()),
             IndexBufferRepr::U16(buf) => cb.bind_index_buffer(buf.clone()),
             IndexBufferRepr::U32(buf) => cb.bind_index_buffer(buf.clone()),
+            IndexBufferRepr::DeviceLocalU8(buf) => cb.bind_index_buffer(buf.clone()),
+            IndexBufferRepr::DeviceLocalU16(buf) => cb.bind_index_buffer(buf.clone()),
+            IndexBufferRepr::DeviceLocalU32(buf) => cb.bind_index_buffer(buf.clone()),
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::{Pod, Zeroable};
+    use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+    use vulkano::command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, RenderPassBeginInfo, SubpassContents,
+    };
+    use vulkano::format::Format;
+    use vulkano::image::view::ImageView;
+    use vulkano::image::{AttachmentImage, ImageUsage};
+    use vulkano::memory::allocator::StandardMemoryAllocator;
+    use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+    use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
+    use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+    use vulkano::pipeline::GraphicsPipeline;
+    use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, Subpass};
+    use vulkano::sync::GpuFuture;
+
+    use super::ModelBuilder;
+    use crate::screenshot::Screenshot;
+    use crate::sw_image::Pixel;
+
+    #[repr(C)]
+    #[derive(Default, Clone, Copy, Zeroable, Pod)]
+    struct TestVertex {
+        position: [f32; 2],
+    }
+
+    vulkano::impl_vertex!(TestVertex, position);
+
+    mod vs {
+        vulkano_shaders::shader! {
+            ty: "vertex",
+            src: "
+#version 450
+
+layout(location = 0) in vec2 position;
+
+void main() {
+    gl_Position = vec4(position, 0.5, 1.0);
+}"
+        }
+    }
+
+    mod fs {
+        vulkano_shaders::shader! {
+            ty: "fragment",
+            src: "
+#version 450
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = vec4(1.0, 1.0, 1.0, 1.0);
+}"
+        }
+    }
+
+    #[test]
+    fn test_into_gpu_device_local_draws() {
+        let (device, queue) = crate::init::test_device!();
+        let memory_allocator = StandardMemoryAllocator::new_default(device.clone());
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(device.clone(), Default::default());
+
+        // A square covering the whole image.
+        let mut model_builder = ModelBuilder::new();
+        for vertex in [(-1, -1), (1, -1), (1, 1), (-1, -1), (1, 1), (-1, 1)] {
+            model_builder.push_vertex(vertex);
+        }
+        let (vertex_buffer, index_buffer, upload) = model_builder.into_gpu_device_local(
+            &memory_allocator,
+            &command_buffer_allocator,
+            &queue,
+            |(x, y): (i8, i8)| TestVertex {
+                position: [f32::from(x), f32::from(y)],
+            },
+            false,
+        );
+        assert_eq!(index_buffer.len(), 6);
+
+        let render_pass = crate::init::test_render_pass(device.clone());
+        let color = AttachmentImage::with_usage(
+            &memory_allocator,
+            [8, 8],
+            Format::R8G8B8A8_UNORM,
+            ImageUsage {
+                color_attachment: true,
+                transfer_src: true,
+                ..ImageUsage::empty()
+            },
+        )
+        .unwrap();
+        let depth =
+            AttachmentImage::transient(&memory_allocator, [8, 8], Format::D16_UNORM).unwrap();
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![
+                    ImageView::new_default(color.clone()).unwrap(),
+                    ImageView::new_default(depth).unwrap(),
+                ],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let vs = vs::load(device.clone()).unwrap();
+        let fs = fs::load(device.clone()).unwrap();
+        let pipeline = GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new().vertex::<TestVertex>())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .viewport_state(ViewportState::viewport_fixed_scissor_irrelevant([
+                Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [8.0, 8.0],
+                    depth_range: 0.0..1.0,
+                },
+            ]))
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .depth_stencil_state(DepthStencilState::simple_depth_test())
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build(device)
+            .unwrap();
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some([0.0, 0.0, 0.0, 1.0].into()), Some(1f32.into())],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                SubpassContents::Inline,
+            )
+            .unwrap()
+            .bind_pipeline_graphics(pipeline)
+            .bind_vertex_buffers(0, vertex_buffer);
+        index_buffer.bind(&mut builder);
+        builder
+            .draw_indexed(6, 1, 0, 0, 0)
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+        let screenshot = Screenshot::record(&mut builder, &memory_allocator, color).unwrap();
+        upload
+            .then_execute(queue, builder.build().unwrap())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        let white = Pixel {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        assert!(screenshot
+            .into_image()
+            .unwrap()
+            .pixels()
+            .all(|p| p == white));
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let mut model_builder = ModelBuilder::new();
+        assert!(model_builder.bounding_box().is_none());
+
+        // A 2×2×2 cube, with its corner nearest the origin at (-1, 2, 0.5).
+        for corner in 0..8u8 {
+            let (x, y, z) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let pos = (
+                -1. + 2. * f32::from(x),
+                2. + 2. * f32::from(y),
+                0.5 + 2. * f32::from(z),
+            );
+            model_builder.push_vertex_with_pos((x, y, z), pos);
+        }
+        assert_eq!(
+            model_builder.bounding_box(),
+            Some(([-1., 2., 0.5], [1., 4., 2.5])),
+        );
+    }
+
+    #[test]
+    fn test_push_triangle_with_normal() {
+        let mut model_builder = ModelBuilder::new();
+        // A square in the Z = 0 plane, and one in the X = 0 plane, sharing the edge along Y.
+        let z_face = [(0, 0, 0), (1, 0, 0), (1, 1, 0), (0, 1, 0)];
+        let x_face = [(0, 0, 0), (0, 1, 0), (0, 1, 1), (0, 0, 1)];
+        for (face, normal) in [(z_face, [0i8, 0, -1]), (x_face, [-1, 0, 0])] {
+            model_builder.push_triangle_with_normal([face[0], face[1], face[2]], normal);
+            model_builder.push_triangle_with_normal([face[0], face[2], face[3]], normal);
+        }
+
+        // Within a face, vertexes are shared; across faces, the shared edge's vertexes aren't.
+        assert_eq!(model_builder.vertexes().len(), 8);
+        assert_eq!(model_builder.index_map.len(), 12);
+        for corner in [(0, 0, 0), (0, 1, 0)] {
+            let mut normals = model_builder
+                .vertexes()
+                .iter()
+                .filter(|(vertex, _)| *vertex == corner)
+                .map(|(_, normal)| *normal)
+                .collect::<Vec<_>>();
+            normals.sort_unstable();
+            assert_eq!(normals, vec![[-1, 0, 0], [0, 0, -1]]);
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use std::io::Write;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::sw_image::SwImage;
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Pixel {
@@ -18,6 +21,17 @@ impl ToPixel for Pixel {
     }
 }
 
+impl ToPixel for crate::sw_image::Pixel {
+    fn to_pixel(&self) -> Pixel {
+        Pixel {
+            r: self.r,
+            g: self.g,
+            b: self.b,
+            a: self.a,
+        }
+    }
+}
+
 pub fn write_png<W: Write, I: IntoIterator<Item = P>, P: ToPixel>(
     write: W,
     width: u32,
@@ -35,11 +49,183 @@ pub fn write_png<W: Write, I: IntoIterator<Item = P>, P: ToPixel>(
         }
         pixel_data
     };
+    encode(write, width, height, png::ColorType::Rgba, &pixel_data)
+}
+
+/// Write an RGB PNG, for opaque images; the alpha of `pixels` is ignored.
+pub fn write_png_rgb<W: Write, I: IntoIterator<Item = P>, P: ToPixel>(
+    write: W,
+    width: u32,
+    height: u32,
+    pixels: I,
+) -> Result<(), png::EncodingError> {
+    let pixel_data = {
+        let mut pixel_data = Vec::new();
+        for pixel in pixels {
+            let pixel = pixel.to_pixel();
+            pixel_data.push(pixel.r);
+            pixel_data.push(pixel.g);
+            pixel_data.push(pixel.b);
+        }
+        pixel_data
+    };
+    encode(write, width, height, png::ColorType::Rgb, &pixel_data)
+}
 
+/// Write a grayscale PNG, storing the luma of `pixels`; their alpha is ignored.
+pub fn write_png_gray<W: Write, I: IntoIterator<Item = P>, P: ToPixel>(
+    write: W,
+    width: u32,
+    height: u32,
+    pixels: I,
+) -> Result<(), png::EncodingError> {
+    let pixel_data = pixels
+        .into_iter()
+        .map(|p| luma(p.to_pixel()))
+        .collect::<Vec<_>>();
+    encode(write, width, height, png::ColorType::Grayscale, &pixel_data)
+}
+
+/// The (Rec. 601) luma of a pixel; gray pixels map to their value exactly.
+fn luma(pixel: Pixel) -> u8 {
+    let weighted =
+        299 * u32::from(pixel.r) + 587 * u32::from(pixel.g) + 114 * u32::from(pixel.b) + 500;
+    u8::try_from(weighted / 1000).unwrap()
+}
+
+fn encode<W: Write>(
+    write: W,
+    width: u32,
+    height: u32,
+    color_type: png::ColorType,
+    pixel_data: &[u8],
+) -> Result<(), png::EncodingError> {
     let mut encoder = png::Encoder::new(write, width, height);
-    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_color(color_type);
     encoder.set_depth(png::BitDepth::Eight);
     let mut writer = encoder.write_header()?;
-    writer.write_image_data(&pixel_data)?;
+    writer.write_image_data(pixel_data)?;
     writer.finish()
 }
+
+/// Decode a PNG into an image.
+///
+/// Grayscale, RGB, & indexed PNGs (and those with 16 bits per channel) are converted to 8-bit
+/// RGBA.
+pub fn read_png<R: Read>(read: R) -> Result<SwImage, png::DecodingError> {
+    let mut decoder = png::Decoder::new(read);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let data = &buffer[..info.buffer_size()];
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => data.to_vec(),
+        png::ColorType::Rgb => expand_to_rgba(data, 3, |p| [p[0], p[1], p[2], 255]),
+        png::ColorType::GrayscaleAlpha => expand_to_rgba(data, 2, |p| [p[0], p[0], p[0], p[1]]),
+        png::ColorType::Grayscale => expand_to_rgba(data, 1, |p| [p[0], p[0], p[0], 255]),
+        png::ColorType::Indexed => {
+            // The EXPAND transformation should have converted this to RGB(A).
+            return Err(invalid_png("indexed PNG was not expanded to RGB"));
+        }
+    };
+    SwImage::from_raw(info.width, info.height, rgba).map_err(invalid_png)
+}
+
+fn expand_to_rgba(data: &[u8], channels: usize, to_rgba: impl Fn(&[u8]) -> [u8; 4]) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(data.len() / channels * 4);
+    for p in data.chunks_exact(channels) {
+        rgba.extend_from_slice(&to_rgba(p));
+    }
+    rgba
+}
+
+fn invalid_png<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> png::DecodingError {
+    png::DecodingError::IoError(io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::Pixel;
+    use crate::sw_image::{self, SwImage};
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut image = SwImage::new(5, 3);
+        for y in 0..3 {
+            for x in 0..5 {
+                let v = u8::try_from(y * 5 + x).unwrap();
+                let pixel = sw_image::Pixel {
+                    r: v * 10,
+                    g: 255 - v,
+                    b: v,
+                    a: v * 16,
+                };
+                image.set_pixel(x, y, pixel);
+            }
+        }
+
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+        let decoded = super::read_png(png.as_slice()).unwrap();
+        assert!(decoded.width() == image.width());
+        assert!(decoded.height() == image.height());
+        assert!(decoded.pixels().eq(image.pixels()));
+    }
+
+    /// Decode a PNG without any transformations, returning its color type & raw pixel data.
+    fn decode_raw(png: &[u8]) -> (png::ColorType, Vec<u8>) {
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        buffer.truncate(info.buffer_size());
+        (info.color_type, buffer)
+    }
+
+    fn gray(v: u8) -> Pixel {
+        Pixel {
+            r: v,
+            g: v,
+            b: v,
+            a: 255,
+        }
+    }
+
+    #[test]
+    fn test_write_png_gray() {
+        let pixels = [gray(0), gray(64), gray(200), gray(255)];
+        let mut png = Vec::new();
+        super::write_png_gray(&mut png, 2, 2, pixels.iter().copied()).unwrap();
+        let (color_type, data) = decode_raw(&png);
+        assert!(color_type == png::ColorType::Grayscale);
+        assert!(color_type.samples() == 1);
+        assert!(data == vec![0, 64, 200, 255]);
+    }
+
+    #[test]
+    fn test_write_png_rgb() {
+        let pixels = [
+            Pixel {
+                r: 1,
+                g: 2,
+                b: 3,
+                a: 255,
+            },
+            Pixel {
+                r: 4,
+                g: 5,
+                b: 6,
+                a: 0,
+            },
+        ];
+        let mut png = Vec::new();
+        super::write_png_rgb(&mut png, 2, 1, pixels.iter().copied()).unwrap();
+        let (color_type, data) = decode_raw(&png);
+        assert!(color_type == png::ColorType::Rgb);
+        assert!(color_type.samples() == 3);
+        assert!(data == vec![1, 2, 3, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,118 @@
+//! A map chunk together with the model drawn for it, re-meshed only after the chunk is edited.
+
+use std::sync::Arc;
+
+use voxel_map::{Chunk, ChunkRelativeCoord};
+use voxel_mod::ModuleBlockDefinition;
+
+/// A chunk, its position in the world, and its model, of type `M` (e.g., a
+/// [`MagicaModel`](crate::magica::MagicaModel)).
+pub struct RenderedChunk<M> {
+    chunk: Chunk,
+    origin: [f32; 3],
+    model: Option<M>,
+    /// Set when the chunk has been edited since `model` was meshed from it.
+    dirty: bool,
+}
+
+impl<M> RenderedChunk<M> {
+    /// Wrap `chunk`, with its corner at `origin`. It has no model until the first
+    /// [`remesh_if_dirty`](Self::remesh_if_dirty).
+    pub fn new(chunk: Chunk, origin: [f32; 3]) -> RenderedChunk<M> {
+        RenderedChunk {
+            chunk,
+            origin,
+            model: None,
+            dirty: true,
+        }
+    }
+
+    pub fn chunk(&self) -> &Chunk {
+        &self.chunk
+    }
+
+    /// The chunk's model, as of the last re-mesh. `None` if the chunk was empty (or hasn't been
+    /// meshed yet).
+    pub fn model(&self) -> Option<&M> {
+        self.model.as_ref()
+    }
+
+    /// Whether the chunk has been edited since it was last meshed.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn set_block(&mut self, coord: ChunkRelativeCoord, block: Arc<ModuleBlockDefinition>) {
+        self.chunk.set_block(coord, Some(block));
+        self.dirty = true;
+    }
+
+    pub fn remove_block(&mut self, coord: ChunkRelativeCoord) {
+        self.chunk.set_block(coord, None);
+        self.dirty = true;
+    }
+
+    /// If the chunk has been edited since it was last meshed, rebuild its model with `mesh`,
+    /// which is given the chunk & its origin. Returns whether it re-meshed.
+    pub fn remesh_if_dirty<F>(&mut self, mesh: F) -> bool
+    where
+        F: FnOnce(&Chunk, [f32; 3]) -> Option<M>,
+    {
+        if !self.dirty {
+            return false;
+        }
+        self.model = mesh(&self.chunk, self.origin);
+        self.dirty = false;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use voxel_map::ChunkRelativeCoord;
+
+    use super::RenderedChunk;
+
+    #[test]
+    fn test_remesh_if_dirty() {
+        let module = voxel_mod::Module::for_test(
+            "stone:\n  texture: stone.png\n  color: \"#787880\"\n  homogeneous: true\n",
+        );
+        let stone = module.block_by_id("stone").unwrap();
+
+        // The "model" is just the number of non-empty blocks meshed.
+        let meshes = Cell::new(0);
+        let mesh = |chunk: &voxel_map::Chunk, origin: [f32; 3]| {
+            assert!(origin == [0., 0., 0.]);
+            meshes.set(meshes.get() + 1);
+            Some(chunk.count_non_empty())
+        };
+
+        let mut rendered = RenderedChunk::new(voxel_map::Chunk::new(), [0., 0., 0.]);
+        assert!(rendered.is_dirty());
+        assert!(rendered.remesh_if_dirty(mesh));
+        assert!(!rendered.is_dirty());
+        assert!(rendered.model() == Some(&0));
+
+        // Nothing changed, so there's nothing to do:
+        assert!(!rendered.remesh_if_dirty(mesh));
+        assert!(meshes.get() == 1);
+
+        let coord = ChunkRelativeCoord::new(1, 2, 3);
+        rendered.set_block(coord, stone);
+        assert!(rendered.is_dirty());
+        // The model is stale until it's re-meshed.
+        assert!(rendered.model() == Some(&0));
+        assert!(rendered.remesh_if_dirty(mesh));
+        assert!(!rendered.is_dirty());
+        assert!(rendered.model() == Some(&1));
+
+        rendered.remove_block(coord);
+        assert!(rendered.is_dirty());
+        assert!(rendered.remesh_if_dirty(mesh));
+        assert!(rendered.model() == Some(&0));
+        assert!(meshes.get() == 3);
+    }
+}
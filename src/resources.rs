@@ -1,6 +1,8 @@
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
+use anyhow::Context;
+
 use crate::text_rendering::cache::GlyphCache;
 use crate::text_rendering::freetype::{FtFace, FtLibrary};
 
@@ -11,27 +13,27 @@ pub struct Fonts {
 }
 
 impl Fonts {
+    /// Load the fonts from the resources directory found by [`resources_path`].
     pub fn init(in_bench: bool) -> anyhow::Result<Fonts> {
+        Fonts::init_from(&resources_path(in_bench)?)
+    }
+
+    /// Load the fonts from `resources`, the directory containing `third-party`.
+    pub fn init_from(resources: &Path) -> anyhow::Result<Fonts> {
+        let third_party = resources.join("third-party");
+        let press_start_2p = read_font(&third_party, "press-start-2p/PressStart2P.ttf")?;
+        let deja_vu = read_font(
+            &third_party,
+            "deja-vu/dejavu-fonts-ttf-2.37/ttf/DejaVuSansMono.ttf",
+        )?;
+        Fonts::from_bytes(&deja_vu, &press_start_2p)
+    }
+
+    /// Load the fonts from font files' contents, e.g., from `include_bytes!`.
+    pub fn from_bytes(deja_vu: &[u8], press_start: &[u8]) -> anyhow::Result<Fonts> {
         let freetype_lib = Arc::new(Mutex::new(FtLibrary::new()?));
-        let third_party = {
-            let mut resources_path = determine_resources_path(in_bench)?;
-            resources_path.push("third-party");
-            resources_path
-        };
-        let press_start_2p = {
-            let mut p = third_party.to_owned();
-            p.push("press-start-2p");
-            p.push("PressStart2P.ttf");
-            load_font(freetype_lib.clone(), &p)?
-        };
-        let mut deja_vu = {
-            let mut p = third_party.to_owned();
-            p.push("deja-vu");
-            p.push("dejavu-fonts-ttf-2.37");
-            p.push("ttf");
-            p.push("DejaVuSansMono.ttf");
-            load_font(freetype_lib.clone(), &p)?
-        };
+        let press_start_2p = font_from_bytes(freetype_lib.clone(), press_start)?;
+        let mut deja_vu = font_from_bytes(freetype_lib, deja_vu)?;
 
         let deja_vu_cache = GlyphCache::new(&mut deja_vu, 14 << 6)?;
 
@@ -43,6 +45,24 @@ impl Fonts {
     }
 }
 
+/// The environment variable that, if set, gives the resources directory, overriding the one
+/// guessed from the executable's path.
+pub const RESOURCES_PATH_VAR: &str = "VOXEL_RESOURCES";
+
+/// The directory containing `third-party`: [`RESOURCES_PATH_VAR`], if it's set, or else the root
+/// of the source tree the executable was built in.
+pub fn resources_path(in_bench: bool) -> anyhow::Result<PathBuf> {
+    match std::env::var_os(RESOURCES_PATH_VAR) {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => determine_resources_path(in_bench).with_context(|| {
+            format!(
+                "couldn't find the resources directory; set {} to it",
+                RESOURCES_PATH_VAR
+            )
+        }),
+    }
+}
+
 // FIXME: this is a giant hack.
 fn determine_resources_path(in_bench: bool) -> anyhow::Result<PathBuf> {
     let mut path = std::env::current_exe()?;
@@ -55,7 +75,77 @@ fn determine_resources_path(in_bench: bool) -> anyhow::Result<PathBuf> {
     Ok(path)
 }
 
-fn load_font<P: AsRef<Path>>(ft_lib: Arc<Mutex<FtLibrary>>, path: P) -> anyhow::Result<FtFace> {
-    let data = std::fs::read(path)?;
-    Ok(FtFace::new_from_buffer(ft_lib, data.into_boxed_slice())?)
+/// Read the font at `path`, within `third_party`.
+fn read_font(third_party: &Path, path: &str) -> anyhow::Result<Vec<u8>> {
+    let full_path = third_party.join(path);
+    std::fs::read(&full_path).with_context(|| {
+        format!(
+            "couldn't read the font {} (looked for it at {}; set {} to the directory containing \
+             third-party if that's the wrong place)",
+            path,
+            full_path.display(),
+            RESOURCES_PATH_VAR,
+        )
+    })
+}
+
+/// Load a font that isn't otherwise part of [`Fonts`], given its path within `third-party`.
+#[cfg(test)]
+pub(crate) fn load_third_party_font(in_bench: bool, path: &str) -> anyhow::Result<FtFace> {
+    let freetype_lib = Arc::new(Mutex::new(FtLibrary::new()?));
+    let third_party = resources_path(in_bench)?.join("third-party");
+    font_from_bytes(freetype_lib, &read_font(&third_party, path)?)
+}
+
+fn font_from_bytes(ft_lib: Arc<Mutex<FtLibrary>>, data: &[u8]) -> anyhow::Result<FtFace> {
+    Ok(FtFace::new_from_buffer(ft_lib, data.into())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sw_image::Pixel;
+    use crate::text_rendering::{render_text, FormattedText, TextOptions};
+
+    #[test]
+    fn test_fonts_from_bytes() {
+        let third_party = super::resources_path(true).unwrap().join("third-party");
+        let press_start =
+            std::fs::read(third_party.join("press-start-2p/PressStart2P.ttf")).unwrap();
+        let deja_vu =
+            std::fs::read(third_party.join("deja-vu/dejavu-fonts-ttf-2.37/ttf/DejaVuSansMono.ttf"))
+                .unwrap();
+        let mut fonts = super::Fonts::from_bytes(&deja_vu, &press_start).unwrap();
+
+        let mut text = FormattedText::new();
+        let white = Pixel {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        text.add_str("Hi", white);
+        let image = render_text(
+            &text,
+            &mut fonts.press_start_2p,
+            8 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        assert!(0 < image.width() && 0 < image.height());
+
+        assert!(super::Fonts::from_bytes(b"not a font", &press_start).is_err());
+    }
+
+    #[test]
+    fn test_init_from_missing_path() {
+        let bogus = std::path::Path::new("/nonexistent/voxel-resources");
+        let err = match super::Fonts::init_from(bogus) {
+            Ok(_) => panic!("loaded fonts from {}", bogus.display()),
+            Err(err) => format!("{:#}", err),
+        };
+        assert!(err.contains("PressStart2P.ttf"), "{}", err);
+        let third_party = bogus.join("third-party");
+        assert!(err.contains(&third_party.display().to_string()), "{}", err);
+    }
 }
@@ -0,0 +1,216 @@
+//! Capturing rendered frames to PNG files.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CopyImageToBufferInfo};
+use vulkano::format::Format;
+use vulkano::image::ImageAccess;
+use vulkano::memory::allocator::MemoryAllocator;
+
+use crate::sw_image::{SwImage, SwImageError};
+
+/// A copy of an image, recorded into a command buffer; once that command buffer has finished
+/// executing, the copy can be read back with [`Screenshot::into_image`].
+pub struct Screenshot {
+    buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    format: Format,
+    dimensions: [u32; 2],
+}
+
+impl Screenshot {
+    /// Record a copy of `image` (which must have been created with `transfer_src` usage) into a
+    /// new CPU-accessible buffer.
+    pub fn record<L>(
+        builder: &mut AutoCommandBufferBuilder<L>,
+        memory_allocator: &(impl MemoryAllocator + ?Sized),
+        image: Arc<dyn ImageAccess>,
+    ) -> Result<Screenshot, ScreenshotError> {
+        let format = image.format();
+        if !is_supported_format(format) {
+            return Err(ScreenshotError::UnsupportedFormat(format));
+        }
+        let dimensions = image.dimensions().width_height();
+        let len = u64::from(dimensions[0]) * u64::from(dimensions[1]) * 4;
+        let buffer = CpuAccessibleBuffer::from_iter(
+            memory_allocator,
+            BufferUsage {
+                transfer_dst: true,
+                ..BufferUsage::empty()
+            },
+            false, // host_cached
+            (0..len).map(|_| 0u8),
+        )
+        .map_err(|err| ScreenshotError::FailedToCreateBuffer(err.to_string()))?;
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image, buffer.clone()))
+            .map_err(|err| ScreenshotError::FailedToCopy(err.to_string()))?;
+        Ok(Screenshot {
+            buffer,
+            format,
+            dimensions,
+        })
+    }
+
+    /// Read the copied image back. The command buffer the copy was recorded into must have
+    /// finished executing.
+    pub fn into_image(self) -> Result<SwImage, ScreenshotError> {
+        let data = self
+            .buffer
+            .read()
+            .map_err(|err| ScreenshotError::FailedToRead(err.to_string()))?;
+        let [width, height] = self.dimensions;
+        Ok(SwImage::from_raw(
+            width,
+            height,
+            to_rgba(self.format, &data)?,
+        )?)
+    }
+}
+
+/// Can images of `format` be converted by [`Screenshot`]?
+fn is_supported_format(format: Format) -> bool {
+    matches!(
+        format,
+        Format::R8G8B8A8_UNORM
+            | Format::R8G8B8A8_SRGB
+            | Format::B8G8R8A8_UNORM
+            | Format::B8G8R8A8_SRGB
+    )
+}
+
+/// Convert pixel data in `format` to RGBA order.
+fn to_rgba(format: Format, data: &[u8]) -> Result<Vec<u8>, ScreenshotError> {
+    match format {
+        Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => Ok(data.to_vec()),
+        Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => Ok(data
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect()),
+        _ => Err(ScreenshotError::UnsupportedFormat(format)),
+    }
+}
+
+/// A file name for a screenshot taken at `time`, e.g., `screenshot-1665939600123.png`.
+pub fn file_name(time: SystemTime) -> PathBuf {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis());
+    PathBuf::from(format!("screenshot-{}.png", millis))
+}
+
+/// Write `image` to a timestamped PNG file in the current directory, returning its path.
+pub fn save(image: &SwImage) -> anyhow::Result<PathBuf> {
+    let path = file_name(SystemTime::now());
+    let file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+    crate::png::write_png(file, image.width(), image.height(), image.pixels())?;
+    Ok(path)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScreenshotError {
+    #[error("screenshots of {0:?} images aren't supported")]
+    UnsupportedFormat(Format),
+    #[error("failed to create the screenshot buffer: {0}")]
+    FailedToCreateBuffer(String),
+    #[error("failed to record the screenshot copy: {0}")]
+    FailedToCopy(String),
+    #[error("failed to read the screenshot buffer: {0}")]
+    FailedToRead(String),
+    #[error("screenshot data didn't fit the image: {0}")]
+    BadImage(#[from] SwImageError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+    use vulkano::command_buffer::{
+        AutoCommandBufferBuilder, ClearColorImageInfo, CommandBufferUsage,
+        PrimaryCommandBufferAbstract,
+    };
+    use vulkano::format::{ClearColorValue, Format};
+    use vulkano::image::{AttachmentImage, ImageUsage};
+    use vulkano::memory::allocator::StandardMemoryAllocator;
+    use vulkano::sync::GpuFuture;
+
+    use crate::sw_image::Pixel;
+
+    #[test]
+    fn test_to_rgba() {
+        let bgra = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(
+            super::to_rgba(Format::B8G8R8A8_SRGB, &bgra).unwrap(),
+            vec![3, 2, 1, 4, 7, 6, 5, 8],
+        );
+        assert_eq!(
+            super::to_rgba(Format::R8G8B8A8_UNORM, &bgra).unwrap(),
+            bgra.to_vec(),
+        );
+        assert!(super::to_rgba(Format::R16G16B16A16_SFLOAT, &bgra).is_err());
+    }
+
+    #[test]
+    fn test_file_name() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_665_939_600_123);
+        assert_eq!(
+            super::file_name(time).to_str(),
+            Some("screenshot-1665939600123.png"),
+        );
+    }
+
+    #[test]
+    fn test_screenshot_of_cleared_image() {
+        let (device, queue) = crate::init::test_device!();
+        let memory_allocator = StandardMemoryAllocator::new_default(device.clone());
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(device.clone(), Default::default());
+        let image = AttachmentImage::with_usage(
+            &memory_allocator,
+            [7, 5],
+            Format::B8G8R8A8_UNORM,
+            ImageUsage {
+                transfer_src: true,
+                transfer_dst: true,
+                ..ImageUsage::empty()
+            },
+        )
+        .unwrap();
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .clear_color_image(ClearColorImageInfo {
+                clear_value: ClearColorValue::Float([1.0, 0.0, 0.0, 1.0]),
+                ..ClearColorImageInfo::image(image.clone())
+            })
+            .unwrap();
+        let screenshot = super::Screenshot::record(&mut builder, &memory_allocator, image).unwrap();
+        builder
+            .build()
+            .unwrap()
+            .execute(queue)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        let image = screenshot.into_image().unwrap();
+        assert_eq!((image.width(), image.height()), (7, 5));
+        let red = Pixel {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        assert!(image.pixels().all(|p| p == red));
+    }
+}
@@ -0,0 +1,179 @@
+//! Recording draws into secondary command buffers. Unlike a primary command buffer, several
+//! secondaries can be recorded at once, on different threads; the primary then executes them, in
+//! order, within a render pass begun with `SubpassContents::SecondaryCommandBuffers`.
+
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CommandBufferInheritanceInfo, CommandBufferInheritanceRenderPassInfo,
+    CommandBufferInheritanceRenderPassType, CommandBufferUsage, SecondaryAutoCommandBuffer,
+};
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::render_pass::Subpass;
+
+/// Start recording a secondary command buffer, to be executed within `subpass`. Dynamic state
+/// isn't inherited from the primary, so the viewport is set to `viewport`.
+pub fn builder(
+    command_buffer_allocator: &StandardCommandBufferAllocator,
+    queue_family_index: u32,
+    subpass: Subpass,
+    viewport: Viewport,
+) -> AutoCommandBufferBuilder<SecondaryAutoCommandBuffer> {
+    let mut builder = AutoCommandBufferBuilder::secondary(
+        command_buffer_allocator,
+        queue_family_index,
+        CommandBufferUsage::OneTimeSubmit,
+        CommandBufferInheritanceInfo {
+            render_pass: Some(CommandBufferInheritanceRenderPassType::BeginRenderPass(
+                CommandBufferInheritanceRenderPassInfo {
+                    subpass,
+                    framebuffer: None,
+                },
+            )),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    builder.set_viewport(0, [viewport]);
+    builder
+}
+
+/// Split `items` into (up to) `threads` runs, and record each into a secondary command buffer
+/// with `record`, on its own thread. The secondaries are returned in the same order as `items`.
+pub fn record_in_parallel<T, F>(
+    items: &[T],
+    threads: usize,
+    record: F,
+) -> Vec<SecondaryAutoCommandBuffer>
+where
+    T: Sync,
+    F: Fn(&[T]) -> SecondaryAutoCommandBuffer + Sync,
+{
+    if items.is_empty() {
+        return Vec::new();
+    }
+    let per_thread = (items.len() + threads.max(1) - 1) / threads.max(1);
+    let record = &record;
+    std::thread::scope(|scope| {
+        let recordings = items
+            .chunks(per_thread)
+            .map(|run| scope.spawn(move || record(run)))
+            .collect::<Vec<_>>();
+        recordings
+            .into_iter()
+            .map(|recording| {
+                recording
+                    .join()
+                    .expect("recording a command buffer panicked")
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+    use vulkano::command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, PrimaryCommandBufferAbstract,
+        RenderPassBeginInfo, SubpassContents,
+    };
+    use vulkano::format::Format;
+    use vulkano::image::view::ImageView;
+    use vulkano::image::{AttachmentImage, ImageUsage};
+    use vulkano::memory::allocator::StandardMemoryAllocator;
+    use vulkano::pipeline::graphics::multisample::MultisampleState;
+    use vulkano::pipeline::graphics::rasterization::PolygonMode;
+    use vulkano::pipeline::graphics::viewport::Viewport;
+    use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, Subpass};
+    use vulkano::sync::GpuFuture;
+
+    #[test]
+    fn test_record_in_parallel() {
+        let (device, queue) = crate::init::test_device!();
+        let memory_allocator = StandardMemoryAllocator::new_default(device.clone());
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(device.clone(), Default::default());
+        let render_pass = crate::init::test_render_pass(device.clone());
+        let color = AttachmentImage::with_usage(
+            &memory_allocator,
+            [8, 8],
+            Format::R8G8B8A8_UNORM,
+            ImageUsage {
+                color_attachment: true,
+                ..ImageUsage::empty()
+            },
+        )
+        .unwrap();
+        let depth =
+            AttachmentImage::transient(&memory_allocator, [8, 8], Format::D16_UNORM).unwrap();
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![
+                    ImageView::new_default(color).unwrap(),
+                    ImageView::new_default(depth).unwrap(),
+                ],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let viewport = Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [8.0, 8.0],
+            depth_range: 0.0..1.0,
+        };
+        let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+        let pipeline = crate::magica::build_pipeline(
+            device.clone(),
+            render_pass,
+            MultisampleState::default(),
+            PolygonMode::Fill,
+            &crate::magica::MagicaShaders::load(device.clone()),
+        );
+
+        // Each "draw" just binds the pipeline; what matters is that the secondaries are valid
+        // within the render pass.
+        let draws = [1, 2];
+        let secondaries = super::record_in_parallel(&draws, 2, |run| {
+            let mut builder = super::builder(
+                &command_buffer_allocator,
+                queue.queue_family_index(),
+                subpass.clone(),
+                viewport.clone(),
+            );
+            for _ in run {
+                builder.bind_pipeline_graphics(pipeline.clone());
+            }
+            builder.build().unwrap()
+        });
+        assert_eq!(secondaries.len(), 2);
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some([0.0, 0.0, 0.0, 1.0].into()), Some(1f32.into())],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                SubpassContents::SecondaryCommandBuffers,
+            )
+            .unwrap()
+            .execute_commands_from_vec(secondaries)
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+        builder
+            .build()
+            .unwrap()
+            .execute(queue)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+    }
+}
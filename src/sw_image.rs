@@ -1,4 +1,13 @@
 use std::convert::TryFrom;
+use std::io::Write;
+use std::sync::Arc;
+
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageDimensions, ImmutableImage, MipmapsCount};
+use vulkano::memory::allocator::StandardMemoryAllocator;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Pixel {
@@ -8,6 +17,19 @@ pub struct Pixel {
     pub a: u8,
 }
 
+/// How [`SwImage::blend_pixel_mode`] combines a pixel with the one already in the image.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BlendMode {
+    /// The new pixel is drawn over the old; see [`SwImage::blend_pixel`].
+    Over,
+    /// The new pixel's color, weighted by its alpha, is added to the old, saturating at white.
+    /// (As are their alphas.)
+    Add,
+    /// The old pixel's color is multiplied by the new's, which is weighted by its alpha; the old
+    /// pixel's alpha is kept.
+    Multiply,
+}
+
 /// A 32-bit RGBA image held in RAM, manipulated on the CPU. (I.e., not an image on the GPU.)
 #[derive(Clone)]
 pub struct SwImage {
@@ -39,10 +61,57 @@ impl SwImage {
         }
     }
 
+    /// Create an image from tightly packed RGBA data, 4 bytes per pixel, row by row.
+    pub fn from_raw(width: u32, height: u32, data: Vec<u8>) -> Result<SwImage, SwImageError> {
+        let expected_len = usize::try_from(width)
+            .ok()
+            .zip(usize::try_from(height).ok())
+            .and_then(|(w, h)| w.checked_mul(h))
+            .and_then(|v| v.checked_mul(4))
+            .ok_or(SwImageError::TooLarge(width, height))?;
+        if data.len() != expected_len {
+            return Err(SwImageError::WrongDataLength {
+                expected: expected_len,
+                actual: data.len(),
+            });
+        }
+        let pixels = data
+            .chunks_exact(4)
+            .map(|p| Pixel {
+                r: p[0],
+                g: p[1],
+                b: p[2],
+                a: p[3],
+            })
+            .collect();
+        Ok(SwImage {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Convert the image into tightly packed RGBA data, as taken by [`SwImage::from_raw`].
+    pub fn into_raw(self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 4);
+        for p in self.pixels {
+            data.extend_from_slice(&[p.r, p.g, p.b, p.a]);
+        }
+        data
+    }
+
     pub fn pixels(&self) -> impl Iterator<Item = Pixel> + ExactSizeIterator + '_ {
         self.pixels.iter().copied()
     }
 
+    /// The image's rows of pixels, from the top; each is `width` pixels long.
+    pub fn rows(&self) -> impl ExactSizeIterator<Item = &[Pixel]> + '_ {
+        let width = usize::try_from(self.width).expect("width exceeded usize limits");
+        let height = usize::try_from(self.height).expect("height exceeded usize limits");
+        // (Not `chunks`, which can't split a 0-wide image into its (empty) rows.)
+        (0..height).map(move |y| &self.pixels[y * width..(y + 1) * width])
+    }
+
     fn index_for(&self, x: u32, y: u32) -> usize {
         if self.width <= x || self.height <= y {
             panic!("pixel at ({}, {}) lies outside image bounds", x, y);
@@ -53,12 +122,299 @@ impl SwImage {
         y.checked_mul(width).and_then(|v| v.checked_add(x)).expect("index overflowed usize")
     }
 
+    /// Get the pixel at `(x, y)`; panics if that lies outside the image.
+    pub fn get_pixel(&self, x: u32, y: u32) -> Pixel {
+        self.pixels[self.index_for(x, y)]
+    }
+
+    /// Get the pixel at `(x, y)`, or `None` if that lies outside the image.
+    pub fn try_get_pixel(&self, x: u32, y: u32) -> Option<Pixel> {
+        if self.width <= x || self.height <= y {
+            None
+        } else {
+            Some(self.get_pixel(x, y))
+        }
+    }
+
+    /// Overwrite the pixel at `(x, y)` with `value`, without blending.
+    pub fn set_pixel(&mut self, x: u32, y: u32, value: Pixel) {
+        let index = self.index_for(x, y);
+        self.pixels[index] = value;
+    }
+
+    /// Overwrite every pixel in the image with `color`.
+    pub fn clear(&mut self, color: Pixel) {
+        for p in self.pixels.iter_mut() {
+            *p = color;
+        }
+    }
+
     pub fn blend_pixel(&mut self, x: u32, y: u32, value: Pixel) {
         let index = self.index_for(x, y);
         let old_value = self.pixels[index];
         self.pixels[index] = blend(value, old_value);
     }
 
+    /// Combine `value` with the pixel at `(x, y)`, according to `mode`.
+    pub fn blend_pixel_mode(&mut self, x: u32, y: u32, value: Pixel, mode: BlendMode) {
+        let index = self.index_for(x, y);
+        let old_value = self.pixels[index];
+        self.pixels[index] = match mode {
+            BlendMode::Over => blend(value, old_value),
+            BlendMode::Add => blend_add(value, old_value),
+            BlendMode::Multiply => blend_multiply(value, old_value),
+        };
+    }
+
+    /// Blend `src` over this image, with `src`'s top-left corner at `(dest_x, dest_y)`. Any part
+    /// of `src` falling outside this image is clipped.
+    pub fn blit(&mut self, src: &SwImage, dest_x: u32, dest_y: u32) {
+        let width = std::cmp::min(src.width, self.width.saturating_sub(dest_x));
+        let height = std::cmp::min(src.height, self.height.saturating_sub(dest_y));
+        for y in 0..height {
+            for x in 0..width {
+                self.blend_pixel(dest_x + x, dest_y + y, src.get_pixel(x, y));
+            }
+        }
+    }
+
+    /// Blend `color` over the `w`×`h` rectangle with its top-left corner at `(x, y)`, clipped to
+    /// the bounds of the image.
+    pub fn fill_rect(&mut self, x: u32, y: u32, w: u32, h: u32, color: Pixel) {
+        let x_end = std::cmp::min(x.saturating_add(w), self.width);
+        let y_end = std::cmp::min(y.saturating_add(h), self.height);
+        for row in y..y_end {
+            for col in x..x_end {
+                self.blend_pixel(col, row, color);
+            }
+        }
+    }
+
+    /// Copy out the `w`×`h` rectangle with its top-left corner at `(x, y)`. The rectangle is
+    /// clamped to the bounds of this image, so the result may be smaller than requested.
+    pub fn crop(&self, x: u32, y: u32, w: u32, h: u32) -> SwImage {
+        let x = std::cmp::min(x, self.width);
+        let y = std::cmp::min(y, self.height);
+        let w = std::cmp::min(w, self.width - x);
+        let h = std::cmp::min(h, self.height - y);
+        let mut cropped = SwImage::new(w, h);
+        for row in 0..h {
+            for col in 0..w {
+                cropped.set_pixel(col, row, self.get_pixel(x + col, y + row));
+            }
+        }
+        cropped
+    }
+
+    /// Scale this image to `new_width`×`new_height`, using bilinear interpolation.
+    ///
+    /// The corner pixels of the image map onto the corner pixels of the result. Colors are
+    /// interpolated weighted by their alpha, so transparent pixels don't bleed their color into
+    /// their neighbours.
+    pub fn resize(&self, new_width: u32, new_height: u32) -> SwImage {
+        let mut resized = SwImage::new(new_width, new_height);
+        if self.width == 0 || self.height == 0 {
+            return resized;
+        }
+        // Maps a coordinate in the result to a (fractional) coordinate in this image.
+        let to_src = |dest: u32, dest_len: u32, src_len: u32| -> f32 {
+            match dest_len {
+                0 | 1 => 0.,
+                _ => (dest as f32) * ((src_len - 1) as f32) / ((dest_len - 1) as f32),
+            }
+        };
+        for y in 0..new_height {
+            let src_y = to_src(y, new_height, self.height);
+            let y0 = src_y.floor() as u32;
+            let y1 = std::cmp::min(y0 + 1, self.height - 1);
+            let frac_y = src_y - (y0 as f32);
+            for x in 0..new_width {
+                let src_x = to_src(x, new_width, self.width);
+                let x0 = src_x.floor() as u32;
+                let x1 = std::cmp::min(x0 + 1, self.width - 1);
+                let frac_x = src_x - (x0 as f32);
+
+                let samples = [
+                    (x0, y0, (1. - frac_x) * (1. - frac_y)),
+                    (x1, y0, frac_x * (1. - frac_y)),
+                    (x0, y1, (1. - frac_x) * frac_y),
+                    (x1, y1, frac_x * frac_y),
+                ];
+                let (mut r, mut g, mut b, mut a) = (0., 0., 0., 0.);
+                for (sample_x, sample_y, weight) in samples.iter() {
+                    let p = self.get_pixel(*sample_x, *sample_y);
+                    let alpha_weight = (p.a as f32) / 255. * weight;
+                    r += (p.r as f32) * alpha_weight;
+                    g += (p.g as f32) * alpha_weight;
+                    b += (p.b as f32) * alpha_weight;
+                    a += alpha_weight;
+                }
+                let pixel = match a > 0. {
+                    true => Pixel {
+                        r: (r / a).round() as u8,
+                        g: (g / a).round() as u8,
+                        b: (b / a).round() as u8,
+                        a: (a * 255.).round() as u8,
+                    },
+                    false => Pixel {
+                        r: 0,
+                        g: 0,
+                        b: 0,
+                        a: 0,
+                    },
+                };
+                resized.set_pixel(x, y, pixel);
+            }
+        }
+        resized
+    }
+
+    /// Return a copy of this image, upside-down.
+    pub fn flip_vertical(&self) -> SwImage {
+        let width = usize::try_from(self.width).expect("width exceeded usize limits");
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        if width != 0 {
+            for row in self.pixels.chunks(width).rev() {
+                pixels.extend_from_slice(row);
+            }
+        }
+        SwImage {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    /// Return a copy of this image, mirrored left-to-right.
+    pub fn flip_horizontal(&self) -> SwImage {
+        let width = usize::try_from(self.width).expect("width exceeded usize limits");
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        if width != 0 {
+            for row in self.pixels.chunks(width) {
+                pixels.extend(row.iter().rev());
+            }
+        }
+        SwImage {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    /// Return a copy of this image, rotated a quarter turn clockwise. (So its width & height are
+    /// swapped.)
+    pub fn rotate90(&self) -> SwImage {
+        let mut rotated = SwImage::new(self.height, self.width);
+        for y in 0..self.width {
+            for x in 0..self.height {
+                rotated.set_pixel(x, y, self.get_pixel(y, self.height - 1 - x));
+            }
+        }
+        rotated
+    }
+
+    /// Return a copy of this image, rotated a half turn.
+    pub fn rotate180(&self) -> SwImage {
+        SwImage {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().rev().copied().collect(),
+        }
+    }
+
+    /// Return a copy of this image, rotated a quarter turn counter-clockwise. (So its width &
+    /// height are swapped.)
+    pub fn rotate270(&self) -> SwImage {
+        let mut rotated = SwImage::new(self.height, self.width);
+        for y in 0..self.width {
+            for x in 0..self.height {
+                rotated.set_pixel(x, y, self.get_pixel(self.width - 1 - y, x));
+            }
+        }
+        rotated
+    }
+
+    /// Return a copy of this image in shades of gray: each pixel's color is replaced by its
+    /// luminance, keeping its alpha.
+    pub fn to_grayscale(&self) -> SwImage {
+        let pixels = self
+            .pixels
+            .iter()
+            .map(|p| {
+                let luminance = 0.299 * (p.r as f32) + 0.587 * (p.g as f32) + 0.114 * (p.b as f32);
+                let gray = luminance.round() as u8;
+                Pixel {
+                    r: gray,
+                    g: gray,
+                    b: gray,
+                    a: p.a,
+                }
+            })
+            .collect();
+        SwImage {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    /// Return a copy of this image, blurred by a box blur of `radius` pixels (each pixel becomes
+    /// the average of the `2 × radius + 1` square around it). Pixels beyond the edges of the image
+    /// count as transparent.
+    ///
+    /// Colors are weighted by their alpha, so transparent pixels don't darken their neighbours'
+    /// edges.
+    pub fn blur(&self, radius: u32) -> SwImage {
+        if radius == 0 {
+            return self.clone();
+        }
+        let width = usize::try_from(self.width).expect("width exceeded usize limits");
+        let height = usize::try_from(self.height).expect("height exceeded usize limits");
+        let radius = usize::try_from(radius).expect("radius exceeded usize limits");
+        let premultiplied = self
+            .pixels
+            .iter()
+            .map(|p| {
+                let alpha = (p.a as f32) / 255.;
+                [
+                    (p.r as f32) * alpha,
+                    (p.g as f32) * alpha,
+                    (p.b as f32) * alpha,
+                    p.a as f32,
+                ]
+            })
+            .collect::<Vec<_>>();
+        // A box blur is separable: blurring the rows, then the columns, is the same as blurring
+        // the square.
+        let horizontal = box_blur_pass(&premultiplied, width, height, radius, |x, y| y * width + x);
+        let blurred = box_blur_pass(&horizontal, height, width, radius, |y, x| y * width + x);
+        let pixels = blurred
+            .into_iter()
+            .map(|[r, g, b, a]| match a > 0. {
+                true => {
+                    let alpha = a / 255.;
+                    Pixel {
+                        r: (r / alpha).round().min(255.) as u8,
+                        g: (g / alpha).round().min(255.) as u8,
+                        b: (b / alpha).round().min(255.) as u8,
+                        a: a.round() as u8,
+                    }
+                }
+                false => Pixel {
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                    a: 0,
+                },
+            })
+            .collect();
+        SwImage {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
     #[inline]
     pub fn width(&self) -> u32 {
         self.width
@@ -68,6 +424,93 @@ impl SwImage {
     pub fn height(&self) -> u32 {
         self.height
     }
+
+    /// Write the image out as an RGBA PNG.
+    pub fn write_png<W: Write>(&self, w: W) -> Result<(), png::EncodingError> {
+        crate::png::write_png(w, self.width, self.height, self.pixels())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SwImageError {
+    #[error("a {0}×{1} image is too large to address")]
+    TooLarge(u32, u32),
+    #[error("expected {expected} bytes of RGBA data for the image, but got {actual}")]
+    WrongDataLength { expected: usize, actual: usize },
+}
+
+/// Upload `image` to the GPU (as part of the commands in `builder`), as an `R8G8B8A8_UNORM` image
+/// with a full chain of mipmaps generated from it.
+pub fn upload_to_gpu<L>(
+    image: &SwImage,
+    memory_allocator: &StandardMemoryAllocator,
+    builder: &mut AutoCommandBufferBuilder<L>,
+) -> Arc<ImageView<ImmutableImage>> {
+    let rgba_pixel_data = CpuAccessibleBuffer::from_iter(
+        memory_allocator,
+        BufferUsage {
+            transfer_src: true,
+            ..BufferUsage::empty()
+        },
+        false, // host_cached
+        image.pixels().map(|p| (p.r, p.g, p.b, p.a)),
+    )
+    .unwrap();
+    let dimensions = ImageDimensions::Dim2d {
+        width: image.width(),
+        height: image.height(),
+        array_layers: 1,
+    };
+    let image = ImmutableImage::from_buffer(
+        memory_allocator,
+        rgba_pixel_data,
+        dimensions,
+        MipmapsCount::Log2,
+        Format::R8G8B8A8_UNORM,
+        builder,
+    )
+    .unwrap();
+    ImageView::new_default(image).unwrap()
+}
+
+/// One pass of [`SwImage::blur`]: average each of `lines` lines of `len` pixels along its length,
+/// over a window of `radius` to either side. `index(i, line)` is the index of the `i`th pixel of
+/// `line` in `pixels`.
+fn box_blur_pass(
+    pixels: &[[f32; 4]],
+    len: usize,
+    lines: usize,
+    radius: usize,
+    index: impl Fn(usize, usize) -> usize,
+) -> Vec<[f32; 4]> {
+    let window = (2 * radius + 1) as f32;
+    let mut blurred = vec![[0.; 4]; pixels.len()];
+    for line in 0..lines {
+        // A running sum over the window, which slides along the line.
+        let mut sum = [0f32; 4];
+        for i in 0..std::cmp::min(radius, len) {
+            let p = pixels[index(i, line)];
+            for c in 0..4 {
+                sum[c] += p[c];
+            }
+        }
+        for i in 0..len {
+            if i + radius < len {
+                let p = pixels[index(i + radius, line)];
+                for c in 0..4 {
+                    sum[c] += p[c];
+                }
+            }
+            if i > radius {
+                let p = pixels[index(i - radius - 1, line)];
+                for c in 0..4 {
+                    sum[c] -= p[c];
+                }
+            }
+            blurred[index(i, line)] = sum.map(|c| c / window);
+        }
+    }
+    blurred
 }
 
 /// Blend `a` over `b`.
@@ -76,6 +519,16 @@ fn blend(a: Pixel, b: Pixel) -> Pixel {
     let alpha_a = (a.a as f32) / 255.;
     let alpha_b_w = (b.a as f32) / 255. * (1. - alpha_a);
     let alpha_o = alpha_a + alpha_b_w;
+    if alpha_o == 0. {
+        // Both are fully transparent; the color is meaningless (and computing it would divide
+        // by zero).
+        return Pixel {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        };
+    }
     let o_r = ((a.r as f32) * alpha_a + (b.r as f32) * alpha_b_w) / alpha_o;
     let o_g = ((a.g as f32) * alpha_a + (b.g as f32) * alpha_b_w) / alpha_o;
     let o_b = ((a.b as f32) * alpha_a + (b.b as f32) * alpha_b_w) / alpha_o;
@@ -86,3 +539,496 @@ fn blend(a: Pixel, b: Pixel) -> Pixel {
         a: (alpha_o * 255.) as u8,
     }
 }
+
+/// Add `a`'s color, weighted by its alpha, to `b`.
+fn blend_add(a: Pixel, b: Pixel) -> Pixel {
+    let alpha_a = (a.a as f32) / 255.;
+    let add = |a: u8, b: u8| ((b as f32) + (a as f32) * alpha_a).round().min(255.) as u8;
+    Pixel {
+        r: add(a.r, b.r),
+        g: add(a.g, b.g),
+        b: add(a.b, b.b),
+        a: b.a.saturating_add(a.a),
+    }
+}
+
+/// Multiply `b`'s color by `a`'s, weighted by `a`'s alpha: a transparent `a` leaves `b` as it is.
+fn blend_multiply(a: Pixel, b: Pixel) -> Pixel {
+    let alpha_a = (a.a as f32) / 255.;
+    let multiply = |a: u8, b: u8| {
+        let factor = 1. - alpha_a + alpha_a * (a as f32) / 255.;
+        ((b as f32) * factor).round() as u8
+    };
+    Pixel {
+        r: multiply(a.r, b.r),
+        g: multiply(a.g, b.g),
+        b: multiply(a.b, b.b),
+        a: b.a,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::{BlendMode, Pixel, SwImage, SwImageError};
+
+    const TRANSPARENT: Pixel = Pixel {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 0,
+    };
+    const WHITE: Pixel = Pixel {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+    };
+
+    #[test]
+    fn test_get_pixel() {
+        let mut image = SwImage::new(4, 3);
+        let orange = Pixel {
+            r: 255,
+            g: 128,
+            b: 0,
+            a: 255,
+        };
+        image.blend_pixel(2, 1, orange);
+        assert!(image.get_pixel(2, 1) == orange);
+        assert!(image.get_pixel(1, 2) == TRANSPARENT);
+        assert!(image.try_get_pixel(2, 1) == Some(orange));
+        assert!(image.try_get_pixel(4, 0).is_none());
+        assert!(image.try_get_pixel(0, 3).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_get_pixel_out_of_bounds() {
+        SwImage::new(4, 3).get_pixel(4, 0);
+    }
+
+    #[test]
+    fn test_blit() {
+        let mut square = SwImage::new(2, 2);
+        for y in 0..2 {
+            for x in 0..2 {
+                square.blend_pixel(x, y, WHITE);
+            }
+        }
+
+        let mut image = SwImage::new(6, 6);
+        image.blit(&square, 2, 2);
+        for y in 0..6 {
+            for x in 0..6 {
+                let expected = match (2..4).contains(&x) && (2..4).contains(&y) {
+                    true => WHITE,
+                    false => TRANSPARENT,
+                };
+                assert!(image.get_pixel(x, y) == expected);
+            }
+        }
+
+        // Blitting off the edge clips:
+        let mut image = SwImage::new(6, 6);
+        image.blit(&square, 5, 5);
+        image.blit(&square, 6, 0);
+        assert!(image.get_pixel(5, 5) == WHITE);
+        assert!(image.pixels().filter(|p| *p == WHITE).count() == 1);
+    }
+
+    /// A `width`×`height` image where every pixel is distinct.
+    fn numbered_image(width: u32, height: u32) -> SwImage {
+        let mut image = SwImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = Pixel {
+                    r: u8::try_from(x).unwrap(),
+                    g: u8::try_from(y).unwrap(),
+                    b: 0,
+                    a: 255,
+                };
+                image.blend_pixel(x, y, pixel);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_rows() {
+        let image = numbered_image(4, 3);
+        assert!(image.rows().len() == 3);
+        for (y, row) in image.rows().enumerate() {
+            assert!(row.len() == 4);
+            let y = u32::try_from(y).unwrap();
+            let expected = (0..4).map(|x| image.get_pixel(x, y));
+            assert!(row.iter().copied().eq(expected));
+        }
+
+        let empty = SwImage::new(0, 2);
+        assert!(empty.rows().len() == 2);
+        assert!(empty.rows().all(|row| row.is_empty()));
+    }
+
+    #[test]
+    fn test_crop() {
+        let image = numbered_image(4, 4);
+        let cropped = image.crop(1, 2, 2, 2);
+        assert!(cropped.width() == 2 && cropped.height() == 2);
+        for y in 0..2 {
+            for x in 0..2 {
+                assert!(cropped.get_pixel(x, y) == image.get_pixel(x + 1, y + 2));
+            }
+        }
+
+        // Out of bounds is clamped:
+        let cropped = image.crop(3, 1, 5, 5);
+        assert!(cropped.width() == 1 && cropped.height() == 3);
+        assert!(cropped.get_pixel(0, 2) == image.get_pixel(3, 3));
+        let cropped = image.crop(10, 10, 5, 5);
+        assert!(cropped.width() == 0 && cropped.height() == 0);
+    }
+
+    #[test]
+    fn test_resize_up() {
+        let black = Pixel {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let mut checkerboard = SwImage::new(2, 2);
+        checkerboard.blend_pixel(0, 0, WHITE);
+        checkerboard.blend_pixel(1, 0, black);
+        checkerboard.blend_pixel(0, 1, black);
+        checkerboard.blend_pixel(1, 1, WHITE);
+
+        let resized = checkerboard.resize(4, 4);
+        assert!(resized.width() == 4 && resized.height() == 4);
+        assert!(resized.get_pixel(0, 0) == WHITE);
+        assert!(resized.get_pixel(3, 0) == black);
+        assert!(resized.get_pixel(0, 3) == black);
+        assert!(resized.get_pixel(3, 3) == WHITE);
+        // Between the corners, we get shades of gray:
+        let between = resized.get_pixel(1, 0);
+        assert!(0 < between.r && between.r < 255);
+        assert!(between.r == between.g && between.g == between.b);
+        assert!(between.a == 255);
+    }
+
+    #[test]
+    fn test_resize_down() {
+        let image = numbered_image(5, 5);
+        let resized = image.resize(3, 3);
+        assert!(resized.get_pixel(0, 0) == image.get_pixel(0, 0));
+        assert!(resized.get_pixel(1, 1) == image.get_pixel(2, 2));
+        assert!(resized.get_pixel(2, 2) == image.get_pixel(4, 4));
+    }
+
+    #[test]
+    fn test_resize_degenerate() {
+        let mut dot = SwImage::new(1, 1);
+        dot.blend_pixel(0, 0, WHITE);
+        let resized = dot.resize(3, 2);
+        assert!(resized.pixels().all(|p| p == WHITE));
+
+        let empty = SwImage::new(0, 0).resize(2, 2);
+        assert!(empty.pixels().all(|p| p == TRANSPARENT));
+        assert!(dot.resize(0, 0).pixels().len() == 0);
+    }
+
+    #[test]
+    fn test_flip_vertical() {
+        let image = numbered_image(3, 2);
+        let flipped = image.flip_vertical();
+        assert!(flipped.width() == 3 && flipped.height() == 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                assert!(flipped.get_pixel(x, y) == image.get_pixel(x, 1 - y));
+            }
+        }
+        assert!(flipped.flip_vertical().pixels().eq(image.pixels()));
+    }
+
+    #[test]
+    fn test_flip_horizontal() {
+        let image = numbered_image(3, 2);
+        let flipped = image.flip_horizontal();
+        assert!(flipped.width() == 3 && flipped.height() == 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                assert!(flipped.get_pixel(x, y) == image.get_pixel(2 - x, y));
+            }
+        }
+        assert!(flipped.flip_horizontal().pixels().eq(image.pixels()));
+    }
+
+    #[test]
+    fn test_to_grayscale() {
+        let mut image = SwImage::new(2, 1);
+        let red = Pixel {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 128,
+        };
+        image.set_pixel(0, 0, red);
+        image.set_pixel(1, 0, WHITE);
+        let gray = image.to_grayscale();
+        assert!(gray.width() == 2 && gray.height() == 1);
+        // 0.299 × 255 = 76.2
+        assert!(
+            gray.get_pixel(0, 0)
+                == Pixel {
+                    r: 76,
+                    g: 76,
+                    b: 76,
+                    a: 128,
+                }
+        );
+        assert!(gray.get_pixel(1, 0) == WHITE);
+    }
+
+    #[test]
+    fn test_blur() {
+        let mut image = SwImage::new(9, 9);
+        image.set_pixel(4, 4, WHITE);
+        let blurred = image.blur(1);
+        assert!(blurred.width() == 9 && blurred.height() == 9);
+
+        // The pixel is spread evenly over the 3×3 square around it, & no further.
+        for y in 0..9 {
+            for x in 0..9 {
+                let p = blurred.get_pixel(x, y);
+                if (3..6).contains(&x) && (3..6).contains(&y) {
+                    // (255 / 9 = 28.3)
+                    assert!(p.a == 28, "({}, {}): {:?}", x, y, p);
+                    // The transparent pixels around it didn't darken it:
+                    assert!(p.r == 255 && p.g == 255 && p.b == 255);
+                } else {
+                    assert!(p == TRANSPARENT, "({}, {}): {:?}", x, y, p);
+                }
+            }
+        }
+        // Its brightness is (roughly) conserved.
+        let total: u32 = blurred.pixels().map(|p| u32::from(p.a)).sum();
+        assert!((250..=255).contains(&total), "{}", total);
+
+        assert!(blurred.blur(0).pixels().eq(blurred.pixels()));
+    }
+
+    #[test]
+    fn test_rotate() {
+        let image = numbered_image(3, 2);
+
+        let rotated = image.rotate90();
+        assert!(rotated.width() == 2 && rotated.height() == 3);
+        // The bottom-left corner moves to the top-left, & the top-left to the top-right:
+        assert!(rotated.get_pixel(0, 0) == image.get_pixel(0, 1));
+        assert!(rotated.get_pixel(1, 0) == image.get_pixel(0, 0));
+        assert!(rotated.get_pixel(1, 2) == image.get_pixel(2, 0));
+        let rotated_4 = rotated.rotate90().rotate90().rotate90();
+        assert!(rotated_4.width() == 3 && rotated_4.height() == 2);
+        assert!(rotated_4.pixels().eq(image.pixels()));
+
+        let rotated = image.rotate180();
+        assert!(rotated.width() == 3 && rotated.height() == 2);
+        assert!(rotated.get_pixel(0, 0) == image.get_pixel(2, 1));
+        assert!(rotated.pixels().eq(image.rotate90().rotate90().pixels()));
+
+        let rotated = image.rotate270();
+        assert!(rotated.width() == 2 && rotated.height() == 3);
+        assert!(rotated.get_pixel(0, 0) == image.get_pixel(2, 0));
+        assert!(rotated.rotate90().pixels().eq(image.pixels()));
+    }
+
+    #[test]
+    fn test_fill_rect() {
+        let mut image = SwImage::new(5, 4);
+        image.fill_rect(1, 1, 3, 2, WHITE);
+        for y in 0..4 {
+            for x in 0..5 {
+                let expected = match (1..4).contains(&x) && (1..3).contains(&y) {
+                    true => WHITE,
+                    false => TRANSPARENT,
+                };
+                assert!(image.get_pixel(x, y) == expected);
+            }
+        }
+
+        // Rectangles are clipped to the image:
+        let mut image = SwImage::new(5, 4);
+        image.fill_rect(3, 2, 10, 10, WHITE);
+        image.fill_rect(5, 0, 1, 1, WHITE);
+        assert!(image.pixels().filter(|p| *p == WHITE).count() == 4);
+    }
+
+    #[test]
+    fn test_raw_round_trip() {
+        let data = (0..24).collect::<Vec<u8>>();
+        let image = SwImage::from_raw(3, 2, data.clone()).unwrap();
+        assert!(
+            image.get_pixel(1, 1)
+                == Pixel {
+                    r: 16,
+                    g: 17,
+                    b: 18,
+                    a: 19,
+                }
+        );
+        assert!(image.into_raw() == data);
+    }
+
+    #[test]
+    fn test_from_raw_wrong_length() {
+        match SwImage::from_raw(3, 2, vec![0; 23]) {
+            Err(SwImageError::WrongDataLength {
+                expected: 24,
+                actual: 23,
+            }) => (),
+            _ => panic!("expected a WrongDataLength error"),
+        }
+        assert!(SwImage::from_raw(u32::MAX, u32::MAX, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_set_pixel() {
+        let mut image = SwImage::new(2, 2);
+        image.set_pixel(1, 0, WHITE);
+        assert!(image.get_pixel(1, 0) == WHITE);
+
+        // Unlike blending, a translucent (or transparent) pixel replaces what was there.
+        let translucent = Pixel {
+            r: 10,
+            g: 20,
+            b: 30,
+            a: 40,
+        };
+        image.set_pixel(1, 0, translucent);
+        assert!(image.get_pixel(1, 0) == translucent);
+        image.set_pixel(1, 0, TRANSPARENT);
+        assert!(image.get_pixel(1, 0) == TRANSPARENT);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut image = numbered_image(3, 3);
+        image.clear(WHITE);
+        assert!(image.pixels().all(|p| p == WHITE));
+        image.clear(TRANSPARENT);
+        assert!(image.pixels().all(|p| p == TRANSPARENT));
+    }
+
+    #[test]
+    fn test_blend_pixel_mode() {
+        let background = Pixel {
+            r: 100,
+            g: 150,
+            b: 200,
+            a: 255,
+        };
+        let foreground = Pixel {
+            r: 100,
+            g: 200,
+            b: 50,
+            a: 255,
+        };
+        let blended = |value, mode| {
+            let mut image = SwImage::new(1, 1);
+            image.set_pixel(0, 0, background);
+            image.blend_pixel_mode(0, 0, value, mode);
+            image.get_pixel(0, 0)
+        };
+
+        assert!(blended(foreground, BlendMode::Over) == foreground);
+        assert!(
+            blended(foreground, BlendMode::Add)
+                == Pixel {
+                    r: 200,
+                    g: 255,
+                    b: 250,
+                    a: 255,
+                }
+        );
+        // E.g., 150 × 200 / 255 = 117.6
+        assert!(
+            blended(foreground, BlendMode::Multiply)
+                == Pixel {
+                    r: 39,
+                    g: 118,
+                    b: 39,
+                    a: 255,
+                }
+        );
+
+        // A half-transparent pixel has half the effect:
+        let translucent = Pixel {
+            a: 128,
+            ..foreground
+        };
+        assert!(
+            blended(translucent, BlendMode::Add)
+                == Pixel {
+                    r: 150,
+                    g: 250,
+                    b: 225,
+                    a: 255,
+                }
+        );
+        assert!(
+            blended(translucent, BlendMode::Multiply)
+                == Pixel {
+                    r: 69,
+                    g: 134,
+                    b: 119,
+                    a: 255,
+                }
+        );
+        // & a transparent one has none.
+        for mode in [BlendMode::Over, BlendMode::Add, BlendMode::Multiply] {
+            assert!(blended(TRANSPARENT, mode) == background, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_blend_transparent_over_transparent() {
+        let transparent_red = Pixel {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 0,
+        };
+        assert!(super::blend(TRANSPARENT, TRANSPARENT) == TRANSPARENT);
+        assert!(super::blend(transparent_red, transparent_red) == TRANSPARENT);
+    }
+
+    #[test]
+    fn test_upload_to_gpu() {
+        use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+        use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage};
+        use vulkano::image::ImageAccess;
+        use vulkano::memory::allocator::StandardMemoryAllocator;
+
+        let (device, queue) = crate::init::test_device!();
+        let memory_allocator = StandardMemoryAllocator::new_default(device.clone());
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(device, Default::default());
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        let mut image = SwImage::new(64, 32);
+        image.fill_rect(8, 8, 16, 8, WHITE);
+        let view = super::upload_to_gpu(&image, &memory_allocator, &mut builder);
+        assert_eq!(view.image().dimensions().width_height(), [64, 32]);
+        assert_eq!(
+            view.image().format(),
+            vulkano::format::Format::R8G8B8A8_UNORM
+        );
+        // 64×32, 32×16, … 1×1:
+        assert_eq!(view.image().mip_levels(), 7);
+    }
+}
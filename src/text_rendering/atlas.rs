@@ -0,0 +1,447 @@
+//! A glyph atlas: each glyph is rasterized once, into a shared image, and text is then drawn as
+//! one quad per glyph copied out of that image. (E.g., on the GPU, where the atlas only needs
+//! uploading when new glyphs are added to it, rather than uploading all of the text's pixels
+//! whenever it changes.)
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::os::raw::{c_int, c_uint};
+
+use ::freetype::freetype as ft_lib;
+use ft_lib::FT_F26Dot6;
+
+use super::cache::{GlyphCache, GlyphCaches};
+use super::sdf::{self, SdfGlyphCache};
+use super::{
+    freetype, glyph_rendering, FormattedText, GlyphMeasuresBuilder, RenderError, TextOptions,
+};
+use crate::sw_image::{Pixel, SwImage};
+
+/// Empty space left between glyphs in the atlas, so that sampling one glyph with a linear filter
+/// never picks up its neighbors.
+const PADDING: u32 = 1;
+
+/// Where a glyph's image is in the atlas.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AtlasRegion {
+    /// The top-left corner of the glyph within the atlas image.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// The offset of the glyph's top-left corner from its origin (the pen position, on the
+    /// baseline), in pixels, with Y pointing down.
+    pub left: i32,
+    pub top: i32,
+}
+
+/// One glyph of laid out text, to be drawn by copying its region of the atlas.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GlyphQuad {
+    /// Where to draw the glyph's top-left corner, relative to the top-left corner of the image
+    /// [`render_text`](super::render_text) would have produced for the same text.
+    pub x: i32,
+    pub y: i32,
+    pub region: AtlasRegion,
+    /// The atlas holds glyphs in white; they should be tinted this color.
+    pub color: Pixel,
+}
+
+/// One glyph of laid out text, to be drawn by scaling its distance field (see [`super::sdf`]) out
+/// of the atlas, & thresholding it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SdfGlyphQuad {
+    /// Where to draw the top-left corner of the glyph's field, as for a [`GlyphQuad`].
+    pub x: i32,
+    pub y: i32,
+    /// The size to draw the field at; its region of the atlas is scaled to fill this.
+    pub width: u32,
+    pub height: u32,
+    pub region: AtlasRegion,
+    pub color: Pixel,
+}
+
+pub struct GlyphAtlas {
+    image: SwImage,
+    /// The glyphs in the atlas, by the height they were rendered at & their index. Glyphs with
+    /// nothing to draw (e.g., spaces) map to `None`.
+    regions: HashMap<(FT_F26Dot6, c_uint), Option<AtlasRegion>>,
+    /// The glyphs' distance fields in the atlas, by glyph index; they serve every height.
+    sdf_regions: HashMap<c_uint, Option<AtlasRegion>>,
+    /// Glyphs are packed into rows ("shelves"); the current one starts at `shelf_y`, is as tall as
+    /// the tallest glyph in it, and has been filled as far as `shelf_x`.
+    shelf_x: u32,
+    shelf_y: u32,
+    shelf_height: u32,
+    /// Incremented whenever `image` changes.
+    generation: u64,
+}
+
+impl GlyphAtlas {
+    /// Create an empty atlas, with an image of the given size.
+    pub fn new(width: u32, height: u32) -> GlyphAtlas {
+        GlyphAtlas {
+            image: SwImage::new(width, height),
+            regions: HashMap::new(),
+            sdf_regions: HashMap::new(),
+            shelf_x: 0,
+            shelf_y: 0,
+            shelf_height: 0,
+            generation: 0,
+        }
+    }
+
+    /// The atlas image. Glyphs are white, with their coverage as alpha.
+    pub fn image(&self) -> &SwImage {
+        &self.image
+    }
+
+    /// A number that changes whenever glyphs are added to the image; i.e., whenever a copy of it
+    /// (e.g., on the GPU) needs updating.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The number of glyphs (& distance fields) in the atlas, including those with nothing to
+    /// draw.
+    pub fn glyph_count(&self) -> usize {
+        self.regions.len() + self.sdf_regions.len()
+    }
+
+    /// Lay out `text`, as [`render_text`](super::render_text) would, adding any glyphs missing
+    /// from the atlas to it, and return a quad for each glyph that draws something.
+    ///
+    /// Unlike `render_text`, underlines & `options.background` aren't drawn.
+    pub fn layout_text(
+        &mut self,
+        text: &FormattedText,
+        face: &mut freetype::FtFace,
+        height: FT_F26Dot6,
+        options: &TextOptions,
+        cache: &mut impl GlyphCaches,
+    ) -> Result<Vec<GlyphQuad>, AtlasError> {
+        let mut cache = cache.cache_for_height(height);
+        face.set_char_size(height).map_err(RenderError::from)?;
+        let layout = super::layout_text(text, &mut [&mut *face], options, cache.as_deref_mut())?;
+        let mut quads = Vec::new();
+        for ((line_idx, line), offset) in layout.lines.iter().enumerate().zip(&layout.line_offsets)
+        {
+            let mut pen_x = layout.origin_x.checked_add(*offset).unwrap();
+            let line_y = super::line_baseline(line_idx, layout.line_height);
+            for glyph in line {
+                let region = self.glyph(face, height, cache.as_deref_mut(), glyph.codepoint)?;
+                if let Some(region) = region {
+                    // FreeType's Y axis points up; the image's, down.
+                    let baseline = layout.base_y - line_y.checked_add(glyph.y_offset).unwrap();
+                    quads.push(GlyphQuad {
+                        x: pen_x + glyph.x_offset + region.left,
+                        y: baseline + region.top,
+                        region,
+                        color: text.color_for_index(glyph.cluster),
+                    });
+                }
+                pen_x = pen_x.checked_add(glyph.x_advance).unwrap();
+            }
+        }
+        Ok(quads)
+    }
+
+    /// Lay out `text` as [`layout_text`](GlyphAtlas::layout_text) does, but as quads of the
+    /// glyphs' distance fields scaled to `height`, which stay crisp at any size. Fields missing
+    /// from the atlas are generated (with `sdf_cache`) & added to it.
+    ///
+    /// A field is stored as its glyph's alpha, so its quads must be drawn with a shader that
+    /// thresholds it, rather than blended as they are.
+    pub fn layout_text_sdf(
+        &mut self,
+        text: &FormattedText,
+        face: &mut freetype::FtFace,
+        height: FT_F26Dot6,
+        options: &TextOptions,
+        sdf_cache: &mut SdfGlyphCache,
+    ) -> Result<Vec<SdfGlyphQuad>, AtlasError> {
+        face.set_char_size(height).map_err(RenderError::from)?;
+        let layout = super::layout_text(text, &mut [&mut *face], options, None)?;
+        // The fields are generated at the reference height, & scaled from there.
+        face.set_char_size(sdf::REFERENCE_HEIGHT)
+            .map_err(RenderError::from)?;
+        let scale = sdf::scale_for_height(height);
+        let scaled = |n: i32| (n as f32 * scale).round() as i32;
+        let mut quads = Vec::new();
+        for ((line_idx, line), offset) in layout.lines.iter().enumerate().zip(&layout.line_offsets)
+        {
+            let mut pen_x = layout.origin_x.checked_add(*offset).unwrap();
+            let line_y = super::line_baseline(line_idx, layout.line_height);
+            for glyph in line {
+                let region = self.sdf_glyph(face, sdf_cache, glyph.codepoint)?;
+                if let Some(region) = region {
+                    let baseline = layout.base_y - line_y.checked_add(glyph.y_offset).unwrap();
+                    let size = |n: u32| u32::try_from(scaled(i32::try_from(n).unwrap())).unwrap();
+                    quads.push(SdfGlyphQuad {
+                        x: pen_x + glyph.x_offset + scaled(region.left),
+                        y: baseline + scaled(region.top),
+                        width: size(region.width),
+                        height: size(region.height),
+                        region,
+                        color: text.color_for_index(glyph.cluster),
+                    });
+                }
+                pen_x = pen_x.checked_add(glyph.x_advance).unwrap();
+            }
+        }
+        Ok(quads)
+    }
+
+    /// Find a glyph's distance field in the atlas, generating it into the atlas if it isn't there
+    /// yet. The face's character size must already be set to [`sdf::REFERENCE_HEIGHT`].
+    fn sdf_glyph(
+        &mut self,
+        face: &mut freetype::FtFace,
+        sdf_cache: &mut SdfGlyphCache,
+        glyph: c_uint,
+    ) -> Result<Option<AtlasRegion>, AtlasError> {
+        if let Some(region) = self.sdf_regions.get(&glyph) {
+            return Ok(*region);
+        }
+        let sdf = sdf_cache
+            .get_or_generate(face, glyph)
+            .map_err(RenderError::RenderError)?;
+        let region = match sdf {
+            None => None,
+            Some(sdf) => {
+                let field = sdf.field.to_image();
+                let (x, y) = self.allocate(field.width(), field.height())?;
+                for row in 0..field.height() {
+                    for column in 0..field.width() {
+                        self.image
+                            .set_pixel(x + column, y + row, field.get_pixel(column, row));
+                    }
+                }
+                self.generation += 1;
+                Some(AtlasRegion {
+                    x,
+                    y,
+                    width: field.width(),
+                    height: field.height(),
+                    left: sdf.left,
+                    top: sdf.top,
+                })
+            }
+        };
+        self.sdf_regions.insert(glyph, region);
+        Ok(region)
+    }
+
+    /// Find a glyph in the atlas, rasterizing it into the atlas if it isn't there yet. The face's
+    /// character size must already be set to `height`.
+    fn glyph(
+        &mut self,
+        face: &mut freetype::FtFace,
+        height: FT_F26Dot6,
+        cache: Option<&mut GlyphCache>,
+        glyph: c_uint,
+    ) -> Result<Option<AtlasRegion>, AtlasError> {
+        if let Some(region) = self.regions.get(&(height, glyph)) {
+            return Ok(*region);
+        }
+        let spans: Vec<(c_int, ft_lib::FT_Span)> = match cache {
+            Some(cache) => cache
+                .get_or_render(face, glyph)
+                .map_err(RenderError::RenderError)?
+                .spans()
+                .collect(),
+            None => {
+                let raw_face = face.as_mut_raw();
+                let mut ft_library_lock = face.library().lock().unwrap();
+                let ft_library = ft_library_lock.as_mut_raw();
+                glyph_rendering::render_glyph(ft_library, raw_face, glyph)
+                    .map_err(RenderError::RenderError)?
+                    .spans()
+                    .collect()
+            }
+        };
+        let region = match GlyphMeasuresBuilder::from_spans(spans.iter().copied()) {
+            None => None,
+            Some(measures) => {
+                let min_x = i32::from(measures.min_x);
+                let width = u32::try_from(i32::from(measures.max_x) - min_x).unwrap();
+                let rows = u32::try_from(measures.max_y - measures.min_y + 1).unwrap();
+                let (x, y) = self.allocate(width, rows)?;
+                for (span_y, span) in spans {
+                    let row = y + u32::try_from(measures.max_y - span_y).unwrap();
+                    let start = x + u32::try_from(i32::from(span.x) - min_x).unwrap();
+                    let white = Pixel {
+                        r: 255,
+                        g: 255,
+                        b: 255,
+                        a: span.coverage,
+                    };
+                    for column in start..start + u32::from(span.len) {
+                        self.image.set_pixel(column, row, white);
+                    }
+                }
+                self.generation += 1;
+                Some(AtlasRegion {
+                    x,
+                    y,
+                    width,
+                    height: rows,
+                    left: min_x,
+                    top: -measures.max_y,
+                })
+            }
+        };
+        self.regions.insert((height, glyph), region);
+        Ok(region)
+    }
+
+    /// Find space for a `width` × `height` glyph, returning its top-left corner.
+    fn allocate(&mut self, width: u32, height: u32) -> Result<(u32, u32), AtlasError> {
+        let (padded_width, padded_height) = (width + PADDING, height + PADDING);
+        if self.image.width() < self.shelf_x + padded_width {
+            // Start a new shelf.
+            self.shelf_y += self.shelf_height;
+            self.shelf_x = 0;
+            self.shelf_height = 0;
+        }
+        if self.image.width() < padded_width || self.image.height() < self.shelf_y + padded_height {
+            return Err(AtlasError::Full(self.image.width(), self.image.height()));
+        }
+        let corner = (self.shelf_x, self.shelf_y);
+        self.shelf_x += padded_width;
+        self.shelf_height = std::cmp::max(self.shelf_height, padded_height);
+        Ok(corner)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AtlasError {
+    #[error(transparent)]
+    Render(#[from] RenderError),
+    #[error("the {0}x{1} glyph atlas is full")]
+    Full(u32, u32),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::super::sdf::SdfGlyphCache;
+    use super::super::{FormattedText, TextOptions};
+    use super::GlyphAtlas;
+    use crate::sw_image::{Pixel, SwImage};
+
+    #[test]
+    fn test_atlas_reuses_glyphs() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut text = FormattedText::new();
+        let green = Pixel {
+            r: 0,
+            g: 255,
+            b: 0,
+            a: 255,
+        };
+        text.add_str("aa b", green);
+
+        let mut atlas = GlyphAtlas::new(256, 256);
+        let mut layout = |atlas: &mut GlyphAtlas| {
+            atlas
+                .layout_text(
+                    &text,
+                    &mut fonts.deja_vu,
+                    14 << 6,
+                    &TextOptions::default(),
+                    &mut fonts.deja_vu_cache,
+                )
+                .unwrap()
+        };
+        let quads = layout(&mut atlas);
+        // The space draws nothing.
+        assert!(quads.len() == 3);
+        assert!(quads[0].region == quads[1].region);
+        assert!(quads[0].x < quads[1].x);
+        assert!(quads[0].region != quads[2].region);
+        assert!(atlas.glyph_count() == 3);
+
+        // Laying the text out again doesn't touch the atlas.
+        let generation = atlas.generation();
+        assert!(layout(&mut atlas) == quads);
+        assert!(atlas.generation() == generation);
+
+        // Drawing the quads reproduces what render_text draws.
+        let expected = super::super::render_text(
+            &text,
+            &mut fonts.deja_vu,
+            14 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        let mut drawn = SwImage::new(expected.width(), expected.height());
+        for quad in quads.iter() {
+            for y in 0..quad.region.height {
+                for x in 0..quad.region.width {
+                    let mut color = quad.color;
+                    let coverage = atlas
+                        .image()
+                        .get_pixel(quad.region.x + x, quad.region.y + y)
+                        .a;
+                    color.a = u8::try_from(u16::from(coverage) * u16::from(color.a) / 255).unwrap();
+                    let dest_x = u32::try_from(quad.x).unwrap() + x;
+                    let dest_y = u32::try_from(quad.y).unwrap() + y;
+                    drawn.blend_pixel(dest_x, dest_y, color);
+                }
+            }
+        }
+        assert!(drawn.pixels().eq(expected.pixels()));
+    }
+
+    #[test]
+    fn test_atlas_sdf_glyphs() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut text = FormattedText::new();
+        let white = Pixel {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        text.add_str("aa b", white);
+
+        let mut atlas = GlyphAtlas::new(512, 512);
+        let mut sdf_cache = SdfGlyphCache::new();
+        let mut layout = |atlas: &mut GlyphAtlas, height| {
+            atlas
+                .layout_text_sdf(
+                    &text,
+                    &mut fonts.deja_vu,
+                    height,
+                    &TextOptions::default(),
+                    &mut sdf_cache,
+                )
+                .unwrap()
+        };
+        let small = layout(&mut atlas, 14 << 6);
+        // The space draws nothing.
+        assert!(small.len() == 3);
+        assert!(small[0].region == small[1].region);
+        assert!(small[0].x < small[1].x);
+        assert!(small[0].region != small[2].region);
+        assert!(atlas.glyph_count() == 3);
+        // The fields are drawn scaled down from the reference height.
+        assert!(small[0].width < small[0].region.width);
+
+        // Text of another height reuses the same fields, scaled differently.
+        let generation = atlas.generation();
+        let large = layout(&mut atlas, 28 << 6);
+        assert!(atlas.generation() == generation);
+        assert!(atlas.glyph_count() == 3);
+        assert!(large.len() == 3);
+        for (small, large) in small.iter().zip(large.iter()) {
+            assert!(small.region == large.region);
+            assert!((i64::from(large.width) - 2 * i64::from(small.width)).abs() <= 1);
+            assert!((i64::from(large.height) - 2 * i64::from(small.height)).abs() <= 1);
+        }
+        assert!(sdf_cache.generated_count() == 3);
+    }
+}
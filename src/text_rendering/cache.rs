@@ -3,11 +3,35 @@ use std::collections::HashMap;
 use ::freetype::freetype as ft_lib;
 use ft_lib::FT_F26Dot6;
 
+use super::glyph_rendering::RenderGlyphError;
 use super::{freetype, GlyphMeasures};
 
+/// The default budget for a glyph cache's memory use, in B.
+pub const DEFAULT_MAX_BYTES: usize = 1 << 20;
+
+/// Somewhere to cache glyphs rendered at some height: a [`GlyphCache`], for a single height, or a
+/// [`MultiSizeGlyphCache`], for any.
+pub trait GlyphCaches {
+    /// The cache for glyphs rendered at `height`, if there is one.
+    fn cache_for_height(&mut self, height: FT_F26Dot6) -> Option<&mut GlyphCache>;
+}
+
 pub struct GlyphCache {
     pub(super) for_height: FT_F26Dot6,
-    cache: HashMap<std::os::raw::c_uint, CachedGlyph>,
+    cache: HashMap<std::os::raw::c_uint, CacheEntry>,
+    /// The number of glyphs that have been rendered into the cache.
+    rendered: usize,
+    /// The (estimated) size of everything in the cache, in B.
+    total_bytes: usize,
+    /// Once `total_bytes` would exceed this, least-recently-used glyphs are evicted.
+    max_bytes: usize,
+    /// Incremented on every lookup; used to find the least-recently-used glyph.
+    clock: u64,
+}
+
+struct CacheEntry {
+    glyph: CachedGlyph,
+    last_used: u64,
 }
 
 pub(super) struct CachedGlyph {
@@ -16,6 +40,21 @@ pub(super) struct CachedGlyph {
 }
 
 impl CachedGlyph {
+    fn render(
+        face: &mut freetype::FtFace,
+        glyph: std::os::raw::c_uint,
+    ) -> Result<CachedGlyph, RenderGlyphError> {
+        let raw_face = face.as_mut_raw();
+        let mut ft_library_lock = face.library().lock().unwrap();
+        let ft_library = ft_library_lock.as_mut_raw();
+        let rendered_glyph = super::glyph_rendering::render_glyph(ft_library, raw_face, glyph)?;
+        let measures = super::GlyphMeasuresBuilder::from_spans(rendered_glyph.spans());
+        Ok(CachedGlyph {
+            render: rendered_glyph,
+            measures,
+        })
+    }
+
     pub(super) fn spans(
         &self,
     ) -> impl Iterator<Item = (std::os::raw::c_int, ft_lib::FT_Span)> + '_ {
@@ -25,18 +64,35 @@ impl CachedGlyph {
     pub(super) fn measures(&self) -> Option<&GlyphMeasures> {
         self.measures.as_ref()
     }
+
+    /// A rough estimate, in B, of the memory used by this glyph in the cache.
+    fn size_in_cache(&self) -> usize {
+        self.render.size_indirect() + std::mem::size_of::<(std::os::raw::c_uint, CacheEntry)>()
+    }
 }
 
 impl GlyphCache {
     pub fn empty(for_height: FT_F26Dot6) -> GlyphCache {
+        GlyphCache::with_capacity(for_height, DEFAULT_MAX_BYTES)
+    }
+
+    /// Create an empty cache that will hold at most (roughly) `max_bytes` of glyphs.
+    ///
+    /// A single glyph larger than `max_bytes` is still cached, but only until the next glyph is
+    /// added.
+    pub fn with_capacity(for_height: FT_F26Dot6, max_bytes: usize) -> GlyphCache {
         GlyphCache {
             for_height,
             cache: HashMap::new(),
+            rendered: 0,
+            total_bytes: 0,
+            max_bytes,
+            clock: 0,
         }
     }
 
     pub fn new(face: &mut freetype::FtFace, height: FT_F26Dot6) -> Result<GlyphCache, CacheError> {
-        let mut cache = HashMap::new();
+        let mut cache = GlyphCache::with_capacity(height, DEFAULT_MAX_BYTES);
 
         let raw_face = face.as_mut_raw();
 
@@ -54,32 +110,124 @@ impl GlyphCache {
                 // This character lacks a glyph in the given font, & thus cannot be cached.
                 continue;
             }
-            let cached_glyph = {
-                let mut ft_library_lock = face.library().lock().unwrap();
-                let ft_library = ft_library_lock.as_mut_raw();
-                let rendered_glyph = super::glyph_rendering::render_glyph(ft_library, raw_face, ch_as_glyph)
-                    .map_err(|err| CacheError::RenderGlyph(ch, err))?;
-                let measures = super::GlyphMeasuresBuilder::from_spans(rendered_glyph.spans());
-                CachedGlyph {
-                    render: rendered_glyph,
-                    measures,
-                }
-            };
-            cache.insert(ch_as_glyph, cached_glyph);
+            cache
+                .get_or_render(face, ch_as_glyph)
+                .map_err(|err| CacheError::RenderGlyph(ch, err))?;
         }
 
-        let mut total_size: usize = cache.values().map(|v| v.render.size_indirect()).sum();
-        total_size += cache.capacity() * std::mem::size_of::<(std::os::raw::c_uint, CachedGlyph)>();
-        log::debug!("Cached {} font glyphs, {}B.", cache.len(), total_size);
+        log::debug!(
+            "Cached {} font glyphs, {}B.",
+            cache.cache.len(),
+            cache.total_bytes
+        );
 
-        Ok(GlyphCache {
-            for_height: height,
-            cache,
-        })
+        Ok(cache)
     }
 
-    pub(super) fn get_glyph(&self, glyph: std::os::raw::c_uint) -> Option<&CachedGlyph> {
-        self.cache.get(&glyph)
+    /// Look up a glyph, rendering it & adding it to the cache if it isn't already present.
+    ///
+    /// The face's character size must already be set to the height this cache is for.
+    pub(super) fn get_or_render(
+        &mut self,
+        face: &mut freetype::FtFace,
+        glyph: std::os::raw::c_uint,
+    ) -> Result<&CachedGlyph, RenderGlyphError> {
+        self.clock += 1;
+        if !self.cache.contains_key(&glyph) {
+            log::debug!("Caching glyph {}", glyph);
+            let cached_glyph = CachedGlyph::render(face, glyph)?;
+            self.rendered += 1;
+            let size = cached_glyph.size_in_cache();
+            while self.max_bytes < self.total_bytes + size && self.evict_least_recently_used() {}
+            self.total_bytes += size;
+            self.cache.insert(
+                glyph,
+                CacheEntry {
+                    glyph: cached_glyph,
+                    last_used: self.clock,
+                },
+            );
+        }
+        let entry = self.cache.get_mut(&glyph).unwrap();
+        entry.last_used = self.clock;
+        Ok(&entry.glyph)
+    }
+
+    /// Returns `false` if the cache was already empty.
+    fn evict_least_recently_used(&mut self) -> bool {
+        let lru = self
+            .cache
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(glyph, _)| *glyph);
+        match lru.and_then(|glyph| self.cache.remove(&glyph)) {
+            Some(entry) => {
+                self.total_bytes -= entry.glyph.size_in_cache();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The number of glyphs rendered into the cache over its lifetime, including those rendered
+    /// when it was created.
+    pub fn rendered_count(&self) -> usize {
+        self.rendered
+    }
+}
+
+impl GlyphCaches for GlyphCache {
+    /// This cache, if it's for `height`.
+    fn cache_for_height(&mut self, height: FT_F26Dot6) -> Option<&mut GlyphCache> {
+        match self.for_height == height {
+            true => Some(self),
+            false => None,
+        }
+    }
+}
+
+/// Glyph caches for a face at several heights (e.g., for body text & headings), each created the
+/// first time text is rendered at its height.
+pub struct MultiSizeGlyphCache {
+    caches: HashMap<FT_F26Dot6, GlyphCache>,
+    /// The budget for each height's cache; see [`GlyphCache::with_capacity`].
+    max_bytes_per_height: usize,
+}
+
+impl MultiSizeGlyphCache {
+    pub fn new() -> MultiSizeGlyphCache {
+        MultiSizeGlyphCache::with_capacity_per_height(DEFAULT_MAX_BYTES)
+    }
+
+    /// Create an empty cache, whose cache for each height will hold at most (roughly) `max_bytes`
+    /// of glyphs.
+    pub fn with_capacity_per_height(max_bytes: usize) -> MultiSizeGlyphCache {
+        MultiSizeGlyphCache {
+            caches: HashMap::new(),
+            max_bytes_per_height: max_bytes,
+        }
+    }
+
+    /// The heights there are caches for, in no particular order.
+    pub fn heights(&self) -> impl Iterator<Item = FT_F26Dot6> + '_ {
+        self.caches.keys().copied()
+    }
+}
+
+impl Default for MultiSizeGlyphCache {
+    fn default() -> Self {
+        MultiSizeGlyphCache::new()
+    }
+}
+
+impl GlyphCaches for MultiSizeGlyphCache {
+    fn cache_for_height(&mut self, height: FT_F26Dot6) -> Option<&mut GlyphCache> {
+        let max_bytes = self.max_bytes_per_height;
+        Some(
+            self.caches
+                .entry(height)
+                .or_insert_with(|| GlyphCache::with_capacity(height, max_bytes)),
+        )
     }
 }
 
@@ -92,9 +240,88 @@ pub enum CacheError {
     #[error("failed to load glyph for {0:?}: {1}")]
     LoadGlyph(char, freetype::FtError),
     #[error("failed to render glyph for {0:?}: {1}")]
-    RenderGlyph(char, super::glyph_rendering::RenderGlyphError),
+    RenderGlyph(char, RenderGlyphError),
     #[error("overflow while counting spans/rows for {0:?}")]
     SpanCountOverflow(char),
 }
 
 const ALWAYS_CACHE: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789`~!@#$%^&*()-_=+[]{}\\|;:'\",.<>/?";
+
+#[cfg(test)]
+mod tests {
+    use ::freetype::freetype as ft_lib;
+
+    use super::{GlyphCache, GlyphCaches, MultiSizeGlyphCache};
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let face = &mut fonts.deja_vu;
+        face.set_char_size(14 << 6).unwrap();
+
+        let max_bytes = 4096;
+        let mut cache = GlyphCache::with_capacity(14 << 6, max_bytes);
+        // Glyph indexes, rather than characters; any glyph in the font will do.
+        let glyphs = 36..136;
+        for glyph in glyphs.clone() {
+            cache.get_or_render(face, glyph).unwrap();
+            assert!(cache.total_bytes <= max_bytes);
+        }
+        assert!(cache.rendered_count() == glyphs.len());
+        assert!(cache.cache.len() < glyphs.len());
+        // The oldest glyphs are the ones that got evicted:
+        assert!(!cache.cache.contains_key(&glyphs.start));
+        assert!(cache.cache.contains_key(&(glyphs.end - 1)));
+
+        // Touching the oldest remaining glyph keeps it around when the next is added.
+        let oldest = *cache
+            .cache
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .unwrap()
+            .0;
+        cache.get_or_render(face, oldest).unwrap();
+        cache.get_or_render(face, glyphs.end).unwrap();
+        assert!(cache.cache.contains_key(&oldest));
+        assert!(cache.total_bytes <= max_bytes);
+    }
+
+    #[test]
+    fn test_multi_size_cache() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let face = &mut fonts.deja_vu;
+        let glyph =
+            unsafe { ft_lib::FT_Get_Char_Index(face.as_mut_raw(), ft_lib::FT_ULong::from('A')) };
+        assert!(glyph != 0);
+
+        let mut cache = MultiSizeGlyphCache::new();
+        let mut spans_at = |height| {
+            face.set_char_size(height).unwrap();
+            let sized_cache = cache.cache_for_height(height).unwrap();
+            assert!(sized_cache.for_height == height);
+            let cached = sized_cache.get_or_render(face, glyph).unwrap();
+            cached
+                .spans()
+                .map(|(y, span)| (y, span.x, span.len, span.coverage))
+                .collect::<Vec<_>>()
+        };
+        let small = spans_at(14 << 6);
+        let large = spans_at(28 << 6);
+        assert!(!small.is_empty());
+        assert!(small != large);
+        // Each height's glyph comes back from its own cache, rather than being rendered again.
+        assert!(spans_at(14 << 6) == small);
+        assert!(spans_at(28 << 6) == large);
+        let mut heights = cache.heights().collect::<Vec<_>>();
+        heights.sort_unstable();
+        assert!(heights == [14 << 6, 28 << 6]);
+        for height in heights {
+            assert!(cache.cache_for_height(height).unwrap().rendered_count() == 1);
+        }
+
+        // A single-height cache has nothing for other heights.
+        let mut single = GlyphCache::empty(14 << 6);
+        assert!(single.cache_for_height(14 << 6).is_some());
+        assert!(single.cache_for_height(28 << 6).is_none());
+    }
+}
@@ -1,6 +1,10 @@
 use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
+/// The [`FtFace::id`] of the next face opened.
+static NEXT_FACE_ID: AtomicU64 = AtomicU64::new(0);
+
 pub struct FtLibrary {
     inner: freetype::freetype::FT_Library,
 }
@@ -28,6 +32,7 @@ pub struct FtFace {
     library: Arc<Mutex<FtLibrary>>,
     _buffer: Option<Box<[u8]>>,
     face: freetype::freetype::FT_Face,
+    id: u64,
 }
 
 impl FtFace {
@@ -58,6 +63,7 @@ impl FtFace {
             library,
             _buffer: Some(buffer),
             face,
+            id: NEXT_FACE_ID.fetch_add(1, Ordering::Relaxed),
         })
     }
 
@@ -65,6 +71,12 @@ impl FtFace {
         &self.library
     }
 
+    /// A number identifying this face, unique among the faces opened by this process. (Unlike the
+    /// face's address, it isn't reused once the face is dropped.)
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
     pub fn set_char_size(
         &mut self,
         char_height: freetype::freetype::FT_F26Dot6,
@@ -73,6 +85,34 @@ impl FtFace {
         FtError::from_ft(err)
     }
 
+    /// The distance between consecutive baselines, in pixels, at the current character size.
+    pub fn line_height(&self) -> i32 {
+        // FreeType always gives an open face an active size; its metrics are in 26.6 fixed point.
+        let height = unsafe { (*(*self.face).size).metrics.height };
+        i32::try_from(height >> 6).unwrap()
+    }
+
+    /// The position of the center of an underline (relative to the baseline, with positive Y
+    /// being up) and its thickness, in pixels, at the current character size.
+    pub fn underline_metrics(&self) -> (i32, i32) {
+        let (position, thickness, y_scale) = unsafe {
+            let face = &*self.face;
+            (
+                face.underline_position,
+                face.underline_thickness,
+                (*face.size).metrics.y_scale,
+            )
+        };
+        // Font units → 26.6 → pixels, rounding to the nearest pixel.
+        let to_pixels = |v: freetype::freetype::FT_Short| {
+            let scaled = unsafe {
+                freetype::freetype::FT_MulFix(freetype::freetype::FT_Long::from(v), y_scale)
+            };
+            i32::try_from((scaled + 32) >> 6).unwrap()
+        };
+        (to_pixels(position), std::cmp::max(1, to_pixels(thickness)))
+    }
+
     pub(super) fn as_mut_raw(&mut self) -> freetype::freetype::FT_Face {
         self.face
     }
@@ -0,0 +1,110 @@
+//! Dumping a single glyph's coverage to a grayscale PNG, for debugging glyph rendering.
+
+use std::convert::TryFrom;
+use std::io::Write;
+use std::path::Path;
+
+use ::freetype::freetype as ft_lib;
+
+use super::freetype::{FtError, FtFace};
+use super::glyph_rendering::{self, RenderGlyphError};
+use super::GlyphMeasuresBuilder;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DumpGlyphError {
+    #[error("failed to select the face's Unicode charmap: {0}")]
+    SelectCharmap(FtError),
+    #[error("the face has no glyph for {0:?}")]
+    NoGlyph(char),
+    #[error("failed to render the glyph for {0:?}: {1}")]
+    RenderGlyph(char, RenderGlyphError),
+    #[error("the glyph for {0:?} has no coverage")]
+    EmptyGlyph(char),
+    #[error("failed to write PNG: {0}")]
+    WritePng(#[from] png::EncodingError),
+}
+
+/// Rasterize `ch`, at the face's current character size, & write its coverage as an 8-bit
+/// grayscale PNG to `path`.
+pub fn dump_glyph_png<P: AsRef<Path>>(
+    face: &mut FtFace,
+    ch: char,
+    path: P,
+) -> Result<(), DumpGlyphError> {
+    let file = std::fs::File::create(path).map_err(png::EncodingError::from)?;
+    write_glyph_png(face, ch, std::io::BufWriter::new(file))
+}
+
+/// Like [`dump_glyph_png`], but writing the PNG to `write`.
+pub fn write_glyph_png<W: Write>(
+    face: &mut FtFace,
+    ch: char,
+    write: W,
+) -> Result<(), DumpGlyphError> {
+    let raw_face = face.as_mut_raw();
+    let err =
+        unsafe { ft_lib::FT_Select_Charmap(raw_face, ft_lib::FT_Encoding_::FT_ENCODING_UNICODE) };
+    FtError::from_ft(err).map_err(DumpGlyphError::SelectCharmap)?;
+    let glyph = unsafe { ft_lib::FT_Get_Char_Index(raw_face, ft_lib::FT_ULong::from(ch)) };
+    if glyph == 0 {
+        return Err(DumpGlyphError::NoGlyph(ch));
+    }
+
+    let rendered_glyph = {
+        let mut ft_library_lock = face.library().lock().unwrap();
+        let ft_library = ft_library_lock.as_mut_raw();
+        glyph_rendering::render_glyph(ft_library, raw_face, glyph)
+            .map_err(|err| DumpGlyphError::RenderGlyph(ch, err))?
+    };
+    let measures = GlyphMeasuresBuilder::from_spans(rendered_glyph.spans())
+        .ok_or(DumpGlyphError::EmptyGlyph(ch))?;
+
+    // FreeType's Y is up, so the highest row of spans is the first row of the image.
+    let min_x = i32::from(measures.min_x);
+    let width = usize::try_from(i32::from(measures.max_x) - min_x).unwrap();
+    let height = usize::try_from(measures.max_y - measures.min_y + 1).unwrap();
+    let mut coverage = vec![0u8; width * height];
+    for (y, span) in rendered_glyph.spans() {
+        let row = usize::try_from(measures.max_y - y).unwrap();
+        let start = row * width + usize::try_from(i32::from(span.x) - min_x).unwrap();
+        coverage[start..start + usize::from(span.len)].fill(span.coverage);
+    }
+
+    let pixels = coverage.into_iter().map(|c| crate::png::Pixel {
+        r: c,
+        g: c,
+        b: c,
+        a: 255,
+    });
+    crate::png::write_png_gray(
+        write,
+        u32::try_from(width).unwrap(),
+        u32::try_from(height).unwrap(),
+        pixels,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_write_glyph_png() {
+        let mut face = crate::resources::load_third_party_font(
+            true,
+            "deja-vu/dejavu-fonts-ttf-2.37/ttf/DejaVuSans.ttf",
+        )
+        .unwrap();
+        face.set_char_size(28 << 6).unwrap();
+
+        let mut png = Vec::new();
+        super::write_glyph_png(&mut face, 'A', &mut png).unwrap();
+        let image = crate::png::read_png(png.as_slice()).unwrap();
+        assert!(0 < image.width() && 0 < image.height());
+        assert!(image.pixels().any(|p| p.r != 0));
+        // Grayscale: every pixel is as red as it is green & blue.
+        assert!(image.pixels().all(|p| p.r == p.g && p.g == p.b));
+
+        let err = super::write_glyph_png(&mut face, ' ', &mut png).unwrap_err();
+        assert!(matches!(err, super::DumpGlyphError::EmptyGlyph(' ')));
+    }
+}
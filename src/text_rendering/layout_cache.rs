@@ -0,0 +1,351 @@
+//! Memoized text layout, for text (e.g., HUD labels) that's measured or laid out every frame
+//! without changing in between.
+
+use std::collections::HashMap;
+
+use ::freetype::freetype as ft_lib;
+use ft_lib::FT_F26Dot6;
+
+use super::cache::GlyphCaches;
+use super::{freetype, FormattedText, GlyphPlacement, RenderError, TextDirection, TextOptions};
+
+/// The default number of layouts a cache holds before it's emptied.
+pub const DEFAULT_MAX_ENTRIES: usize = 256;
+
+/// Caches the measured size & glyph placements of text, so that measuring or laying out the same
+/// text again, at the same height, doesn't shape it (with HarfBuzz) again.
+///
+/// Layouts are only valid for the face they were made with, so using the cache with a different
+/// face empties it. Once it holds its maximum number of layouts, it's emptied before the next one
+/// is added, rather than tracking which are least-recently-used: the text it's meant for is the
+/// same from frame to frame, & quickly cached again.
+pub struct TextLayoutCache {
+    /// The [`freetype::FtFace::id`] of the face the cached layouts were made with.
+    face_id: Option<u64>,
+    /// Layouts, by their text; texts differing in anything else (e.g., height) share an entry.
+    layouts: HashMap<String, Vec<CachedLayout>>,
+    len: usize,
+    max_entries: usize,
+    /// The number of times text has been shaped to fill the cache.
+    shaped: usize,
+}
+
+struct CachedLayout {
+    // Everything other than the text itself that affects its layout.
+    underline_spans: Vec<(usize, bool)>,
+    direction: TextDirection,
+    height: FT_F26Dot6,
+    options: TextOptions,
+    size: (u32, u32),
+    placements: Vec<GlyphPlacement>,
+}
+
+impl CachedLayout {
+    fn is_for(&self, text: &FormattedText, height: FT_F26Dot6, options: &TextOptions) -> bool {
+        self.underline_spans == text.underline_spans
+            && self.direction == text.direction
+            && self.height == height
+            && self.options == *options
+    }
+}
+
+impl TextLayoutCache {
+    pub fn new() -> TextLayoutCache {
+        TextLayoutCache::with_capacity(DEFAULT_MAX_ENTRIES)
+    }
+
+    /// Create an empty cache that will hold at most `max_entries` layouts.
+    pub fn with_capacity(max_entries: usize) -> TextLayoutCache {
+        TextLayoutCache {
+            face_id: None,
+            layouts: HashMap::new(),
+            len: 0,
+            max_entries,
+            shaped: 0,
+        }
+    }
+
+    /// The `(width, height)` [`super::measure_text`] would return for the same arguments.
+    pub fn measure(
+        &mut self,
+        text: &FormattedText,
+        face: &mut freetype::FtFace,
+        height: FT_F26Dot6,
+        options: &TextOptions,
+        cache: &mut impl GlyphCaches,
+    ) -> Result<(u32, u32), RenderError> {
+        Ok(self.get_or_layout(text, face, height, options, cache)?.size)
+    }
+
+    /// The glyph placements [`super::layout_glyphs`] would return for the same arguments.
+    pub fn layout_glyphs(
+        &mut self,
+        text: &FormattedText,
+        face: &mut freetype::FtFace,
+        height: FT_F26Dot6,
+        options: &TextOptions,
+        cache: &mut impl GlyphCaches,
+    ) -> Result<&[GlyphPlacement], RenderError> {
+        Ok(&self
+            .get_or_layout(text, face, height, options, cache)?
+            .placements)
+    }
+
+    /// Forget every cached layout.
+    pub fn clear(&mut self) {
+        self.face_id = None;
+        self.layouts.clear();
+        self.len = 0;
+    }
+
+    /// The number of layouts in the cache.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of times text has been shaped to fill the cache, over its lifetime; i.e., the
+    /// number of cache misses.
+    pub fn shaped_count(&self) -> usize {
+        self.shaped
+    }
+
+    fn get_or_layout(
+        &mut self,
+        text: &FormattedText,
+        face: &mut freetype::FtFace,
+        height: FT_F26Dot6,
+        options: &TextOptions,
+        cache: &mut impl GlyphCaches,
+    ) -> Result<&CachedLayout, RenderError> {
+        if self.face_id != Some(face.id()) {
+            self.clear();
+            self.face_id = Some(face.id());
+        }
+        let found = self.layouts.get(text.as_str()).and_then(|layouts| {
+            layouts
+                .iter()
+                .position(|layout| layout.is_for(text, height, options))
+        });
+        if let Some(idx) = found {
+            return Ok(&self.layouts[text.as_str()][idx]);
+        }
+
+        let cache = cache.cache_for_height(height);
+        face.set_char_size(height)?;
+        let layout = super::layout_text(text, &mut [face], options, cache)?;
+        self.shaped += 1;
+        if self.len >= self.max_entries {
+            self.layouts.clear();
+            self.len = 0;
+        }
+        let layouts = self.layouts.entry(text.as_str().to_owned()).or_default();
+        layouts.push(CachedLayout {
+            underline_spans: text.underline_spans.clone(),
+            direction: text.direction,
+            height,
+            options: *options,
+            size: (layout.width, layout.height),
+            placements: super::glyph_placements(&layout),
+        });
+        self.len += 1;
+        Ok(layouts.last().unwrap())
+    }
+}
+
+impl Default for TextLayoutCache {
+    fn default() -> Self {
+        TextLayoutCache::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TextLayoutCache;
+    use crate::sw_image::Pixel;
+    use crate::text_rendering::cache::MultiSizeGlyphCache;
+    use crate::text_rendering::{FormattedText, TextOptions};
+
+    const WHITE: Pixel = Pixel {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+    };
+
+    fn formatted(s: &str) -> FormattedText {
+        let mut text = FormattedText::new();
+        text.add_str(s, WHITE);
+        text
+    }
+
+    #[test]
+    fn test_layout_cache() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut glyph_cache = MultiSizeGlyphCache::new();
+        let mut layouts = TextLayoutCache::new();
+        let text = formatted("Chunks: 1234");
+        let options = TextOptions::default();
+
+        let size = layouts
+            .measure(
+                &text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &options,
+                &mut glyph_cache,
+            )
+            .unwrap();
+        assert!(layouts.shaped_count() == 1);
+        let expected_size = super::super::measure_text(
+            &text,
+            &mut fonts.deja_vu,
+            14 << 6,
+            &options,
+            &mut glyph_cache,
+        )
+        .unwrap();
+        assert!(size == expected_size);
+
+        // Measuring the same text again, or laying it out, doesn't shape it again.
+        let again = layouts
+            .measure(
+                &text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &options,
+                &mut glyph_cache,
+            )
+            .unwrap();
+        assert!(again == size);
+        let placements = layouts
+            .layout_glyphs(
+                &text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &options,
+                &mut glyph_cache,
+            )
+            .unwrap()
+            .to_vec();
+        assert!(layouts.shaped_count() == 1);
+        let expected_placements = super::super::layout_glyphs(
+            &text,
+            &mut fonts.deja_vu,
+            14 << 6,
+            &options,
+            &mut glyph_cache,
+        )
+        .unwrap();
+        assert!(placements == expected_placements);
+        // The text's color doesn't affect its layout.
+        let mut recolored = FormattedText::new();
+        recolored.add_str("Chunks: 1234", Pixel { r: 0, ..WHITE });
+        layouts
+            .measure(
+                &recolored,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &options,
+                &mut glyph_cache,
+            )
+            .unwrap();
+        assert!(layouts.shaped_count() == 1);
+
+        // A different height, underline, or options is a different layout.
+        let larger = layouts
+            .measure(
+                &text,
+                &mut fonts.deja_vu,
+                28 << 6,
+                &options,
+                &mut glyph_cache,
+            )
+            .unwrap();
+        assert!(larger.0 > size.0);
+        let mut underlined = FormattedText::new();
+        underlined.add_underlined_str("Chunks: 1234", WHITE);
+        layouts
+            .measure(
+                &underlined,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &options,
+                &mut glyph_cache,
+            )
+            .unwrap();
+        let spaced = TextOptions {
+            letter_spacing: 2,
+            ..TextOptions::default()
+        };
+        layouts
+            .measure(
+                &text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &spaced,
+                &mut glyph_cache,
+            )
+            .unwrap();
+        assert!(layouts.shaped_count() == 4);
+        assert!(layouts.len() == 4);
+
+        // Changing the font empties the cache.
+        let mut other_glyph_cache = MultiSizeGlyphCache::new();
+        let other_size = layouts
+            .measure(
+                &text,
+                &mut fonts.press_start_2p,
+                14 << 6,
+                &options,
+                &mut other_glyph_cache,
+            )
+            .unwrap();
+        assert!(other_size != size);
+        assert!(layouts.shaped_count() == 5);
+        assert!(layouts.len() == 1);
+        layouts
+            .measure(
+                &text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &options,
+                &mut glyph_cache,
+            )
+            .unwrap();
+        assert!(layouts.shaped_count() == 6);
+    }
+
+    #[test]
+    fn test_layout_cache_capacity() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut glyph_cache = MultiSizeGlyphCache::new();
+        let mut layouts = TextLayoutCache::with_capacity(2);
+        for s in ["a", "b", "c"].iter() {
+            layouts
+                .measure(
+                    &formatted(s),
+                    &mut fonts.deja_vu,
+                    14 << 6,
+                    &TextOptions::default(),
+                    &mut glyph_cache,
+                )
+                .unwrap();
+        }
+        // The cache filled up with "a" & "b", so was emptied to make room for "c".
+        assert!(layouts.len() == 1);
+        layouts
+            .measure(
+                &formatted("c"),
+                &mut fonts.deja_vu,
+                14 << 6,
+                &TextOptions::default(),
+                &mut glyph_cache,
+            )
+            .unwrap();
+        assert!(layouts.shaped_count() == 3);
+    }
+}
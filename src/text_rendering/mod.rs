@@ -1,15 +1,21 @@
 use std::convert::TryFrom;
 
 use ::freetype::freetype as ft_lib;
+use ft_lib::FT_F26Dot6;
 
 use crate::sw_image::{Pixel, SwImage};
 
+pub mod atlas;
 pub mod cache;
+pub mod glyph_dump;
 pub mod glyph_rendering;
 pub mod freetype;
 mod harfbuzz;
+pub mod layout_cache;
+pub mod sdf;
+pub mod wrap;
 
-use cache::GlyphCache;
+use cache::{GlyphCache, GlyphCaches};
 
 enum MaybeCachedGlyphMeasures<'a> {
     Cached(Option<&'a GlyphMeasures>),
@@ -31,6 +37,10 @@ pub struct FormattedText {
     // The vector is always sorted by .0, as we always append successively higher indexes.
     // TODO: SmallVec?
     color_spans: Vec<(usize, Pixel)>,
+    // Maps starting index → whether the text is underlined, as with `color_spans`. Text before the
+    // first entry isn't underlined.
+    underline_spans: Vec<(usize, bool)>,
+    direction: TextDirection,
 }
 
 impl FormattedText {
@@ -38,13 +48,43 @@ impl FormattedText {
         FormattedText {
             text: String::new(),
             color_spans: Vec::new(),
+            underline_spans: Vec::new(),
+            direction: TextDirection::Auto,
+        }
+    }
+
+    /// Set the direction the text runs in; it's detected from the text by default.
+    pub fn set_direction(&mut self, direction: TextDirection) {
+        self.direction = direction;
+    }
+
+    /// Whether the text runs right-to-left, after detecting its direction if need be.
+    fn is_rtl(&self) -> bool {
+        match self.direction {
+            TextDirection::LeftToRight => false,
+            TextDirection::RightToLeft => true,
+            TextDirection::Auto => matches!(
+                self.text.chars().find(|ch| ch.is_alphabetic()),
+                Some(ch) if is_rtl_char(ch)
+            ),
         }
     }
 
     pub fn add_str(&mut self, s: &str, color: Pixel) {
+        self.add_styled_str(s, color, false);
+    }
+
+    pub fn add_underlined_str(&mut self, s: &str, color: Pixel) {
+        self.add_styled_str(s, color, true);
+    }
+
+    fn add_styled_str(&mut self, s: &str, color: Pixel, underline: bool) {
         if self.color_spans.last().map(|(_, c)| *c) != Some(color) {
             self.color_spans.push((self.text.len(), color));
         }
+        if self.underline_spans.last().map_or(false, |(_, u)| *u) != underline {
+            self.underline_spans.push((self.text.len(), underline));
+        }
         self.text.push_str(s);
     }
 
@@ -61,67 +101,328 @@ impl FormattedText {
             Err(idx) => self.color_spans[idx - 1].1,
         }
     }
+
+    pub fn is_underlined(&self, index: usize) -> bool {
+        match self
+            .underline_spans
+            .binary_search_by_key(&index, |(i, _)| *i)
+        {
+            Ok(idx) => self.underline_spans[idx].1,
+            Err(0) => false,
+            Err(idx) => self.underline_spans[idx - 1].1,
+        }
+    }
+}
+
+/// Whether `ch` is from a right-to-left script (Hebrew, Arabic, Syriac, Thaana, N'Ko, etc.)
+fn is_rtl_char(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{0590}'..='\u{08ff}'
+            | '\u{fb1d}'..='\u{fdff}'
+            | '\u{fe70}'..='\u{feff}'
+            | '\u{10800}'..='\u{10fff}'
+            | '\u{1e800}'..='\u{1efff}'
+    )
+}
+
+/// The direction text runs in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TextDirection {
+    LeftToRight,
+    RightToLeft,
+    /// Right-to-left if the first letter of the text is from a right-to-left script (e.g., Hebrew
+    /// or Arabic), & left-to-right otherwise.
+    Auto,
+}
+
+/// How the lines of a multi-line text are aligned relative to each other.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TextAlign {
+    /// Aligned to where the text's lines start: the left for left-to-right text, & the right for
+    /// right-to-left text.
+    Start,
+    Left,
+    Center,
+    Right,
 }
 
+/// How far apart tab stops are.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TabWidth {
+    Pixels(u32),
+    /// A multiple of the advance of a space in the (first) face.
+    Spaces(u32),
+}
+
+/// Options controlling how text is laid out & drawn.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TextOptions {
+    pub align: TextAlign,
+    /// If set, the text's bounding box is filled with this color before the glyphs are drawn.
+    pub background: Option<Pixel>,
+    /// Extra space added to each glyph's advance, in pixels. Negative values tighten the text,
+    /// though glyphs are never moved back past the previous glyph's origin.
+    pub letter_spacing: i32,
+    /// Each `\t` advances the pen to the next tab stop, measured from the start of its line.
+    pub tab_width: TabWidth,
+    /// If set, each line wider than this many pixels has its trailing glyphs replaced with "…",
+    /// so that it fits. A line too narrow for even the ellipsis is left empty.
+    pub max_width: Option<u32>,
+}
+
+impl Default for TextOptions {
+    fn default() -> TextOptions {
+        TextOptions {
+            align: TextAlign::Start,
+            background: None,
+            letter_spacing: 0,
+            tab_width: TabWidth::Spaces(4),
+            max_width: None,
+        }
+    }
+}
+
+/// Render `text` into a new image, at the given `height` (in 26.6 fixed point).
+///
+/// Each `\n` in the text starts a new line; lines are stacked using the face's line height, and
+/// positioned horizontally according to `options.align`. The image is cropped tightly to the
+/// rendered glyphs.
+///
+/// `cache` is only used if it has a cache for the same `height` (a [`GlyphCache`] built for it, or
+/// any [`cache::MultiSizeGlyphCache`]); glyphs missing from it are rendered and added to it.
+/// Otherwise, all glyphs are rendered without it.
 pub fn render_text(
     text: &FormattedText,
     face: &mut freetype::FtFace,
-    cache: &GlyphCache,
+    height: FT_F26Dot6,
+    options: &TextOptions,
+    cache: &mut impl GlyphCaches,
 ) -> Result<SwImage, RenderError> {
-    // TODO: allow specifying the height
-    assert!(cache.for_height == 14 << 6);
-    face.set_char_size(14 << 6)?;
-    let raw_face = face.as_mut_raw();
-    let mut hb_font = harfbuzz::HarfbuzzFont::from_freetype_face(raw_face);
-    let mut buffer =
-        harfbuzz::HarfbuzzBuffer::new().ok_or_else(|| RenderError::HarfbuzzBufferAllocFailed)?;
-    buffer.set_direction(harfbuzz::hb_direction_t::HB_DIRECTION_LTR);
-    buffer.add_str(text.as_str());
-    harfbuzz::shape(&mut hb_font, &mut buffer);
-    let (glyphs, glyph_infos) = buffer.glyph_positions_and_infos();
-    assert!(glyphs.len() == glyph_infos.len());
-    let mut measure_info = MeasureInfo::NoneYet;
-    let mut base_x = 0;
-    // Measure:
-    for (glyph, glyph_info) in glyphs.iter().zip(glyph_infos.iter()) {
-        let measures = match cache.get_glyph(glyph_info.codepoint) {
-            Some(cached_glyph) => MaybeCachedGlyphMeasures::Cached(cached_glyph.measures()),
-            None => {
-                log::debug!("Manually measuring glyph {}", glyph_info.codepoint);
-                let mut captured_spans = glyph_rendering::CapturedSpans::new();
-                {
-                    let mut ft_library_lock = face.library().lock().unwrap();
-                    let ft_library = ft_library_lock.as_mut_raw();
-                    glyph_rendering::render_glyph_raw(
-                        ft_library,
-                        raw_face,
-                        glyph_info.codepoint,
-                        &mut captured_spans
-                    )
-                    .map_err(RenderError::RenderError)?;
-                }
-                let mut measure_builder = GlyphMeasuresBuilder::new();
-                for (y, _) in captured_spans.rows.iter() {
-                    measure_builder.measure_y(*y);
-                }
-                for span in captured_spans.spans.iter() {
-                    measure_builder.measure_span(*span);
-                }
-                MaybeCachedGlyphMeasures::Computed(measure_builder.finish())
+    render_text_with_fallback(text, &mut [face], height, options, cache)
+}
+
+/// Render `text` as [`render_text`] would, but with fallback fonts: characters `faces[0]` has no
+/// glyph for are shaped with `faces[1]` instead, and so on down the list. Line heights &
+/// underlines come from `faces[0]`.
+///
+/// `cache` is only used for glyphs from `faces[0]`.
+pub fn render_text_with_fallback(
+    text: &FormattedText,
+    faces: &mut [&mut freetype::FtFace],
+    height: FT_F26Dot6,
+    options: &TextOptions,
+    cache: &mut impl GlyphCaches,
+) -> Result<SwImage, RenderError> {
+    assert!(!faces.is_empty(), "at least one face is needed");
+    let mut cache = cache.cache_for_height(height);
+    for face in faces.iter_mut() {
+        face.set_char_size(height)?;
+    }
+    let layout = layout_text(text, faces, options, cache.as_deref_mut())?;
+    let mut image = SwImage::new(layout.width, layout.height);
+    draw_layout(&layout, text, faces, options, cache, &mut image, (0, 0))?;
+    Ok(image)
+}
+
+/// Render `text` as [`render_text`] would, but blend it into `dest`, with the top-left corner of
+/// what would have been `render_text`'s image placed at `origin`.
+///
+/// Anything falling outside of `dest` is clipped.
+pub fn render_text_into(
+    dest: &mut SwImage,
+    origin: (u32, u32),
+    text: &FormattedText,
+    face: &mut freetype::FtFace,
+    height: FT_F26Dot6,
+    options: &TextOptions,
+    cache: &mut impl GlyphCaches,
+) -> Result<(), RenderError> {
+    let mut cache = cache.cache_for_height(height);
+    face.set_char_size(height)?;
+    let layout = layout_text(text, &mut [&mut *face], options, cache.as_deref_mut())?;
+    draw_layout(&layout, text, &mut [face], options, cache, dest, origin)
+}
+
+/// Measure the `(width, height)` of the image [`render_text`] would produce for the same
+/// arguments, without rendering it.
+///
+/// `options` are needed as aligning the lines can change the overall width.
+pub fn measure_text(
+    text: &FormattedText,
+    face: &mut freetype::FtFace,
+    height: FT_F26Dot6,
+    options: &TextOptions,
+    cache: &mut impl GlyphCaches,
+) -> Result<(u32, u32), RenderError> {
+    let cache = cache.cache_for_height(height);
+    face.set_char_size(height)?;
+    let layout = layout_text(text, &mut [face], options, cache)?;
+    Ok((layout.width, layout.height))
+}
+
+/// Where a glyph lands in the image [`render_text`] would produce for the same arguments.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GlyphPlacement {
+    /// The byte index, into the text, of the cluster the glyph belongs to.
+    pub cluster: usize,
+    /// The pen position (the glyph's origin, before any offset of the glyph itself), in pixels
+    /// from the left edge of the image. This may be negative for glyphs that overhang it.
+    pub x: i32,
+    /// The row of the glyph's baseline, in pixels from the top of the image.
+    pub y: i32,
+    /// How far, in pixels, the pen moves after this glyph; the next glyph on the line starts at
+    /// `x + advance`.
+    pub advance: i32,
+}
+
+/// Lay out `text` as [`render_text`] would, and return where each of its glyphs lands, line by
+/// line, without rendering them. (E.g., for placing a cursor, or hit testing.)
+pub fn layout_glyphs(
+    text: &FormattedText,
+    face: &mut freetype::FtFace,
+    height: FT_F26Dot6,
+    options: &TextOptions,
+    cache: &mut impl GlyphCaches,
+) -> Result<Vec<GlyphPlacement>, RenderError> {
+    let cache = cache.cache_for_height(height);
+    face.set_char_size(height)?;
+    let layout = layout_text(text, &mut [face], options, cache)?;
+    Ok(glyph_placements(&layout))
+}
+
+/// Where each of the glyphs in `layout` lands, as [`layout_glyphs`] returns.
+fn glyph_placements(layout: &TextLayout) -> Vec<GlyphPlacement> {
+    let mut placements = Vec::new();
+    for ((line_idx, line), offset) in layout.lines.iter().enumerate().zip(&layout.line_offsets) {
+        let mut pen_x = layout.origin_x.checked_add(*offset).unwrap();
+        let y = layout
+            .base_y
+            .checked_sub(line_baseline(line_idx, layout.line_height))
+            .unwrap();
+        for glyph in line {
+            placements.push(GlyphPlacement {
+                cluster: glyph.cluster,
+                x: pen_x,
+                y,
+                advance: glyph.x_advance,
+            });
+            pen_x = pen_x.checked_add(glyph.x_advance).unwrap();
+        }
+    }
+    placements
+}
+
+/// Shaped & measured text, ready to be drawn.
+struct TextLayout {
+    lines: Vec<Vec<ShapedGlyph>>,
+    line_height: i32,
+    /// The X offset of each line, from aligning it.
+    line_offsets: Vec<i32>,
+    /// The Y coordinate, in FreeType's coordinates, of the top row of the text.
+    base_y: std::os::raw::c_int,
+    /// The X coordinate, relative to the left edge of the text, of the first line's origin.
+    origin_x: i32,
+    /// The lowest & highest rows of an underline, relative to the baseline.
+    underline_rows: (std::os::raw::c_int, std::os::raw::c_int),
+    width: u32,
+    height: u32,
+}
+
+/// Shape & measure `text`, falling back through `faces` as [`shape_lines_with_fallback`] does.
+/// The faces' character sizes must already be set.
+///
+/// `options.letter_spacing` & `options.tab_width` are applied to the glyphs' advances, and lines
+/// are truncated to `options.max_width`, here, so drawing the layout needs nothing further.
+fn layout_text(
+    text: &FormattedText,
+    faces: &mut [&mut freetype::FtFace],
+    options: &TextOptions,
+    mut cache: Option<&mut GlyphCache>,
+) -> Result<TextLayout, RenderError> {
+    let line_height = faces[0].line_height();
+    let underline_rows = {
+        let (position, thickness) = faces[0].underline_metrics();
+        let top = position + thickness / 2;
+        (top - thickness + 1, top)
+    };
+    let rtl = text.is_rtl();
+    let mut lines = shape_lines_with_fallback(text.as_str(), faces, rtl)?;
+    if options.letter_spacing != 0 {
+        for glyph in lines.iter_mut().flatten() {
+            // Zero-width glyphs (e.g., marks) stay attached to the glyph before them.
+            if glyph.x_advance != 0 {
+                glyph.x_advance = std::cmp::max(
+                    glyph.x_advance.checked_add(options.letter_spacing).unwrap(),
+                    0,
+                );
             }
+        }
+    }
+    if text.as_str().contains('\t') {
+        let space = shape_lines(" ", faces[0])?.remove(0).remove(0);
+        let tab_width = match options.tab_width {
+            TabWidth::Pixels(pixels) => i32::try_from(pixels).unwrap(),
+            TabWidth::Spaces(spaces) => i32::try_from(spaces)
+                .ok()
+                .and_then(|spaces| spaces.checked_mul(space.x_advance))
+                .unwrap(),
         };
-        if let Some(measures) = measures.as_ref() {
-            measure_info.merge(base_x, &measures);
+        expand_tabs(text.as_str(), &mut lines, &space, tab_width);
+    }
+    if let Some(max_width) = options.max_width {
+        let ellipsis = {
+            let ellipsis = shape_lines("…", faces[0])?.remove(0);
+            match ellipsis.iter().any(|glyph| glyph.codepoint == 0) {
+                true => shape_lines("...", faces[0])?.remove(0),
+                false => ellipsis,
+            }
+        };
+        let truncation = Truncation {
+            ellipsis,
+            max_width,
+            rtl,
+        };
+        for line in lines.iter_mut() {
+            truncate_line(
+                text,
+                line,
+                &truncation,
+                underline_rows,
+                faces,
+                cache.as_deref_mut(),
+            )?;
         }
-        base_x = base_x.checked_add(i32::from(glyph.x_advance >> 6)).unwrap();
     }
-    let (base_y, width, height) = match measure_info {
-        MeasureInfo::NoneYet => panic!("no measurements?"),
+    let mut line_measures = Vec::with_capacity(lines.len());
+    for (line_idx, line) in lines.iter().enumerate() {
+        line_measures.push(measure_line(
+            text,
+            line,
+            line_baseline(line_idx, line_height),
+            underline_rows,
+            faces,
+            cache.as_deref_mut(),
+        )?);
+    }
+    let align = match (options.align, rtl) {
+        (TextAlign::Start, false) => TextAlign::Left,
+        (TextAlign::Start, true) => TextAlign::Right,
+        (align, _) => align,
+    };
+    let line_offsets = align_lines(&line_measures, align);
+    let mut measure_info = MeasureInfo::NoneYet;
+    for (line, offset) in line_measures.iter().zip(line_offsets.iter()) {
+        measure_info.extend(*offset, line);
+    }
+    let (base_y, origin_x, height) = match measure_info {
+        // Nothing visible (e.g., empty or all whitespace) means an empty image.
+        MeasureInfo::NoneYet => (0, 0, 0),
         MeasureInfo::Measures {
             min_y,
             max_y,
             global_min_x,
-            global_max_x,
+            ..
         } => {
             let height = u32::try_from(
                 max_y
@@ -130,20 +431,49 @@ pub fn render_text(
                     .unwrap(),
             )
             .unwrap();
-            let width = u32::try_from(
-                global_max_x
-                    .checked_sub(global_min_x)
-                    .and_then(|v| v.checked_add(1))
-                    .unwrap(),
-            )
-            .unwrap();
-            (max_y, width, height)
+            (max_y, -global_min_x, height)
         }
     };
+    let width = measure_info.width();
+    Ok(TextLayout {
+        lines,
+        line_height,
+        line_offsets,
+        base_y,
+        origin_x,
+        underline_rows,
+        width,
+        height,
+    })
+}
+
+/// Draw laid out text into `image`, with the top-left corner of the text at `origin`.
+fn draw_layout(
+    layout: &TextLayout,
+    text: &FormattedText,
+    faces: &mut [&mut freetype::FtFace],
+    options: &TextOptions,
+    mut cache: Option<&mut GlyphCache>,
+    image: &mut SwImage,
+    origin: (u32, u32),
+) -> Result<(), RenderError> {
+    let origin_err = || RenderError::OriginOutOfRange(origin.0, origin.1);
+    let origin_x = i32::try_from(origin.0)
+        .ok()
+        .and_then(|x| x.checked_add(layout.origin_x))
+        .ok_or_else(origin_err)?;
+    let base_y = i32::try_from(origin.1)
+        .ok()
+        .and_then(|y| y.checked_add(layout.base_y))
+        .ok_or_else(origin_err)?;
+    if let Some(background) = options.background {
+        image.fill_rect(origin.0, origin.1, layout.width, layout.height, background);
+    }
     let mut render_info = RenderInfo {
         base_y,
         x: 0,
-        image: SwImage::new(width, height),
+        y: 0,
+        image,
         color: Pixel {
             r: 0,
             g: 0,
@@ -151,31 +481,363 @@ pub fn render_text(
             a: 0,
         },
     };
-    // Render:
-    for (glyph, glyph_info) in glyphs.iter().zip(glyph_infos.iter()) {
-        let glyph_index_in_str = usize::try_from(glyph_info.cluster).unwrap();
-        let color = text.color_for_index(glyph_index_in_str);
-        render_info.color = color;
-        match cache.get_glyph(glyph_info.codepoint) {
-            Some(cached_glyph) => {
-                render_cached_glyph(&mut render_info, cached_glyph)?;
+    for ((line_idx, line), offset) in layout.lines.iter().enumerate().zip(&layout.line_offsets) {
+        let mut pen_x = origin_x.checked_add(*offset).unwrap();
+        let line_y = line_baseline(line_idx, layout.line_height);
+        for glyph in line {
+            render_info.x = pen_x.checked_add(glyph.x_offset).unwrap();
+            render_info.y = line_y.checked_add(glyph.y_offset).unwrap();
+            render_info.color = text.color_for_index(glyph.cluster);
+            let face = &mut *faces[glyph.face];
+            match face_cache(glyph, cache.as_deref_mut()) {
+                Some(cache) => {
+                    let cached_glyph = cache
+                        .get_or_render(face, glyph.codepoint)
+                        .map_err(RenderError::RenderError)?;
+                    render_cached_glyph(&mut render_info, cached_glyph)?;
+                }
+                None => {
+                    log::debug!("Manually rendering glyph {}", glyph.codepoint);
+                    let rendered_glyph = {
+                        let raw_face = face.as_mut_raw();
+                        let mut ft_library_lock = face.library().lock().unwrap();
+                        let ft_library = ft_library_lock.as_mut_raw();
+                        glyph_rendering::render_glyph(ft_library, raw_face, glyph.codepoint)
+                            .map_err(RenderError::RenderError)?
+                    };
+                    for (y, span) in rendered_glyph.spans() {
+                        render_span(&mut render_info, y, span)?;
+                    }
+                }
             }
-            None => {
-                log::debug!("Manually rendering glyph {}", glyph_info.codepoint);
-                let rendered_glyph = {
-                    let mut ft_library_lock = face.library().lock().unwrap();
-                    let ft_library = ft_library_lock.as_mut_raw();
-                    glyph_rendering::render_glyph(ft_library, raw_face, glyph_info.codepoint)
-                        .map_err(RenderError::RenderError)?
+            if text.is_underlined(glyph.cluster) && 0 < glyph.x_advance {
+                render_info.x = pen_x;
+                render_info.y = line_y;
+                let span = ft_lib::FT_Span {
+                    x: 0,
+                    len: std::os::raw::c_ushort::try_from(glyph.x_advance).unwrap(),
+                    coverage: 255,
                 };
-                for (y, span) in rendered_glyph.spans() {
+                for y in layout.underline_rows.0..=layout.underline_rows.1 {
                     render_span(&mut render_info, y, span)?;
                 }
             }
+            pen_x = pen_x.checked_add(glyph.x_advance).unwrap();
+        }
+    }
+    Ok(())
+}
+
+/// Measure a shaped line, with its origin at X = 0 & its baseline at `base_y`.
+fn measure_line(
+    text: &FormattedText,
+    line: &[ShapedGlyph],
+    base_y: std::os::raw::c_int,
+    underline_rows: (std::os::raw::c_int, std::os::raw::c_int),
+    faces: &mut [&mut freetype::FtFace],
+    mut cache: Option<&mut GlyphCache>,
+) -> Result<MeasureInfo, RenderError> {
+    let mut base_x: i32 = 0;
+    let mut measure_info = MeasureInfo::NoneYet;
+    for glyph in line {
+        let measures = measure_glyph(
+            faces[glyph.face],
+            face_cache(glyph, cache.as_deref_mut()),
+            glyph.codepoint,
+        )?;
+        if let Some(measures) = measures.as_ref() {
+            measure_info.merge(
+                base_x.checked_add(glyph.x_offset).unwrap(),
+                base_y.checked_add(glyph.y_offset).unwrap(),
+                measures,
+            );
+        }
+        if text.is_underlined(glyph.cluster) && 0 < glyph.x_advance {
+            let underline = GlyphMeasures {
+                min_y: underline_rows.0,
+                max_y: underline_rows.1,
+                min_x: 0,
+                max_x: std::os::raw::c_short::try_from(glyph.x_advance).unwrap(),
+            };
+            measure_info.merge(base_x, base_y, &underline);
+        }
+        base_x = base_x.checked_add(glyph.x_advance).unwrap();
+    }
+    Ok(measure_info)
+}
+
+/// How lines too wide for `options.max_width` are truncated.
+struct Truncation {
+    ellipsis: Vec<ShapedGlyph>,
+    max_width: u32,
+    /// Right-to-left lines end on the left, so they're truncated from the left.
+    rtl: bool,
+}
+
+/// If `line` is wider than `truncation.max_width` pixels, drop glyphs from its end & append the
+/// ellipsis until it fits. The ellipsis takes the color (& underline) of the first glyph dropped.
+/// If even the ellipsis alone doesn't fit, the line is left empty.
+fn truncate_line(
+    text: &FormattedText,
+    line: &mut Vec<ShapedGlyph>,
+    truncation: &Truncation,
+    underline_rows: (std::os::raw::c_int, std::os::raw::c_int),
+    faces: &mut [&mut freetype::FtFace],
+    mut cache: Option<&mut GlyphCache>,
+) -> Result<(), RenderError> {
+    let measure = measure_line(text, line, 0, underline_rows, faces, cache.as_deref_mut())?;
+    if measure.width() <= truncation.max_width {
+        return Ok(());
+    }
+    while !line.is_empty() {
+        let (dropped, ellipsis_at) = match truncation.rtl {
+            false => (line.pop().unwrap(), line.len()),
+            true => (line.remove(0), 0),
+        };
+        let ellipsis = truncation.ellipsis.iter().map(|glyph| ShapedGlyph {
+            cluster: dropped.cluster,
+            ..*glyph
+        });
+        let ellipsis_range = ellipsis_at..ellipsis_at + ellipsis.len();
+        line.splice(ellipsis_at..ellipsis_at, ellipsis);
+        let measure = measure_line(text, line, 0, underline_rows, faces, cache.as_deref_mut())?;
+        if measure.width() <= truncation.max_width {
+            return Ok(());
+        }
+        line.drain(ellipsis_range);
+    }
+    Ok(())
+}
+
+/// Widen each tab so that the glyph after it starts at the next multiple of `tab_width` pixels
+/// from the start of its line. Tabs become `space` glyphs, so nothing is drawn for them.
+fn expand_tabs(text: &str, lines: &mut [Vec<ShapedGlyph>], space: &ShapedGlyph, tab_width: i32) {
+    let tab_width = std::cmp::max(tab_width, 1);
+    for line in lines {
+        let mut pen_x: i32 = 0;
+        for glyph in line.iter_mut() {
+            if text.as_bytes()[glyph.cluster] == b'\t' {
+                let next_stop = (pen_x.div_euclid(tab_width) + 1)
+                    .checked_mul(tab_width)
+                    .unwrap();
+                glyph.codepoint = space.codepoint;
+                glyph.face = space.face;
+                glyph.x_advance = next_stop - pen_x;
+                glyph.x_offset = 0;
+                glyph.y_offset = 0;
+            }
+            pen_x = pen_x.checked_add(glyph.x_advance).unwrap();
+        }
+    }
+}
+
+/// Compute the X offset of each line needed to align the lines as requested.
+///
+/// Left-aligned lines all start at the pen origin; otherwise, lines are shifted so that the right
+/// edges (or midpoints) of their rendered glyphs line up. Empty lines aren't shifted.
+fn align_lines(line_measures: &[MeasureInfo], align: TextAlign) -> Vec<i32> {
+    // For centering, we work with twice the midpoint, to stay in integers.
+    let anchor = |min_x: i32, max_x: i32| match align {
+        TextAlign::Start | TextAlign::Left => 0,
+        TextAlign::Center => min_x.checked_add(max_x).unwrap(),
+        TextAlign::Right => max_x,
+    };
+    let target = line_measures
+        .iter()
+        .filter_map(MeasureInfo::x_extent)
+        .map(|(min_x, max_x)| anchor(min_x, max_x))
+        .max()
+        .unwrap_or(0);
+    line_measures
+        .iter()
+        .map(|m| match m.x_extent() {
+            Some((min_x, max_x)) => {
+                let offset = target - anchor(min_x, max_x);
+                match align {
+                    TextAlign::Center => offset / 2,
+                    _ => offset,
+                }
+            }
+            None => 0,
+        })
+        .collect()
+}
+
+/// A glyph, as shaped & positioned by Harfbuzz.
+#[derive(Clone, Copy)]
+struct ShapedGlyph {
+    codepoint: std::os::raw::c_uint,
+    /// The byte index into the full text of the cluster this glyph belongs to.
+    cluster: usize,
+    /// In pixels.
+    x_advance: i32,
+    /// The offset of the glyph from the pen position, in pixels. (E.g., for placing marks.)
+    x_offset: i32,
+    y_offset: i32,
+    /// The index of the face (of those the text was shaped with) the glyph is from.
+    face: usize,
+}
+
+/// Split `text` on newlines, and shape each line separately, left-to-right.
+fn shape_lines(
+    text: &str,
+    face: &mut freetype::FtFace,
+) -> Result<Vec<Vec<ShapedGlyph>>, RenderError> {
+    shape_lines_in_direction(text, face, false)
+}
+
+/// Shape `text` as [`shape_lines`] does, but right-to-left if `rtl`. Glyphs are always in visual
+/// order, i.e., left to right, so `rtl` lines' clusters run backwards.
+fn shape_lines_in_direction(
+    text: &str,
+    face: &mut freetype::FtFace,
+    rtl: bool,
+) -> Result<Vec<Vec<ShapedGlyph>>, RenderError> {
+    let mut hb_font = harfbuzz::HarfbuzzFont::from_freetype_face(face.as_mut_raw());
+    let mut lines = Vec::new();
+    let mut line_start = 0;
+    for line in text.split('\n') {
+        lines.push(shape_str(&mut hb_font, line, line_start, 0, rtl)?);
+        // +1 for the '\n' we split on.
+        line_start += line.len() + 1;
+    }
+    Ok(lines)
+}
+
+/// Shape `text` as [`shape_lines_in_direction`] does with `faces[0]`, then re-shape each run of
+/// glyphs that face lacks (i.e., `.notdef`, glyph 0) with each of the following faces in turn,
+/// until one has glyphs for it.
+fn shape_lines_with_fallback(
+    text: &str,
+    faces: &mut [&mut freetype::FtFace],
+    rtl: bool,
+) -> Result<Vec<Vec<ShapedGlyph>>, RenderError> {
+    let mut lines = shape_lines_in_direction(text, faces[0], rtl)?;
+    for (face_idx, face) in faces.iter_mut().enumerate().skip(1) {
+        let mut hb_font = harfbuzz::HarfbuzzFont::from_freetype_face(face.as_mut_raw());
+        let mut line_start = 0;
+        for (line, glyphs) in text.split('\n').zip(lines.iter_mut()) {
+            let line_end = line_start + line.len();
+            let mut idx = 0;
+            while idx < glyphs.len() {
+                if glyphs[idx].codepoint != 0 {
+                    idx += 1;
+                    continue;
+                }
+                let run_end = glyphs[idx..]
+                    .iter()
+                    .position(|glyph| glyph.codepoint != 0)
+                    .map_or(glyphs.len(), |len| idx + len);
+                // The run's text ends where the next cluster after it in the text starts. (With
+                // right-to-left text, that's the glyph before the run, not after.)
+                let run = &glyphs[idx..run_end];
+                let start = run.iter().map(|glyph| glyph.cluster).min().unwrap();
+                let run_max = run.iter().map(|glyph| glyph.cluster).max().unwrap();
+                let end = glyphs
+                    .iter()
+                    .map(|glyph| glyph.cluster)
+                    .filter(|cluster| run_max < *cluster)
+                    .min()
+                    .unwrap_or(line_end);
+                let reshaped = shape_str(&mut hb_font, &text[start..end], start, face_idx, rtl)?;
+                if reshaped.iter().any(|glyph| glyph.codepoint != 0) {
+                    let reshaped_len = reshaped.len();
+                    glyphs.splice(idx..run_end, reshaped);
+                    idx += reshaped_len;
+                } else {
+                    idx = run_end;
+                }
+            }
+            // +1 for the '\n' we split on.
+            line_start = line_end + 1;
         }
-        render_info.x += u32::try_from(glyph.x_advance >> 6).unwrap();
     }
-    Ok(render_info.image)
+    Ok(lines)
+}
+
+/// Shape a single line of text, `cluster_offset` bytes into the full text, with the `face_idx`th
+/// face; right-to-left if `rtl`.
+fn shape_str(
+    hb_font: &mut harfbuzz::HarfbuzzFont,
+    text: &str,
+    cluster_offset: usize,
+    face_idx: usize,
+    rtl: bool,
+) -> Result<Vec<ShapedGlyph>, RenderError> {
+    let mut buffer =
+        harfbuzz::HarfbuzzBuffer::new().ok_or_else(|| RenderError::HarfbuzzBufferAllocFailed)?;
+    buffer.set_direction(match rtl {
+        true => harfbuzz::hb_direction_t::HB_DIRECTION_RTL,
+        false => harfbuzz::hb_direction_t::HB_DIRECTION_LTR,
+    });
+    buffer.add_str(text);
+    harfbuzz::shape(hb_font, &mut buffer);
+    let (glyphs, glyph_infos) = buffer.glyph_positions_and_infos();
+    assert!(glyphs.len() == glyph_infos.len());
+    Ok(glyphs
+        .iter()
+        .zip(glyph_infos.iter())
+        .map(|(glyph, glyph_info)| ShapedGlyph {
+            codepoint: glyph_info.codepoint,
+            cluster: cluster_offset + usize::try_from(glyph_info.cluster).unwrap(),
+            x_advance: glyph.x_advance >> 6,
+            x_offset: glyph.x_offset >> 6,
+            y_offset: glyph.y_offset >> 6,
+            face: face_idx,
+        })
+        .collect())
+}
+
+/// The cache to use for `glyph`: caches only hold glyphs from the first face.
+fn face_cache<'a>(
+    glyph: &ShapedGlyph,
+    cache: Option<&'a mut GlyphCache>,
+) -> Option<&'a mut GlyphCache> {
+    match glyph.face {
+        0 => cache,
+        _ => None,
+    }
+}
+
+/// The Y coordinate of the baseline of the `line_idx`th line, relative to the first line's.
+///
+/// FreeType's Y axis points up, so successive lines have lower baselines.
+fn line_baseline(line_idx: usize, line_height: i32) -> std::os::raw::c_int {
+    i32::try_from(line_idx)
+        .ok()
+        .and_then(|idx| idx.checked_mul(line_height))
+        .map(|y| -y)
+        .unwrap()
+}
+
+fn measure_glyph<'a>(
+    face: &mut freetype::FtFace,
+    cache: Option<&'a mut GlyphCache>,
+    codepoint: std::os::raw::c_uint,
+) -> Result<MaybeCachedGlyphMeasures<'a>, RenderError> {
+    if let Some(cache) = cache {
+        let cached_glyph = cache
+            .get_or_render(face, codepoint)
+            .map_err(RenderError::RenderError)?;
+        return Ok(MaybeCachedGlyphMeasures::Cached(cached_glyph.measures()));
+    }
+    log::debug!("Manually measuring glyph {}", codepoint);
+    let mut captured_spans = glyph_rendering::CapturedSpans::new();
+    {
+        let raw_face = face.as_mut_raw();
+        let mut ft_library_lock = face.library().lock().unwrap();
+        let ft_library = ft_library_lock.as_mut_raw();
+        glyph_rendering::render_glyph_raw(ft_library, raw_face, codepoint, &mut captured_spans)
+            .map_err(RenderError::RenderError)?;
+    }
+    let mut measure_builder = GlyphMeasuresBuilder::new();
+    for (y, _) in captured_spans.rows.iter() {
+        measure_builder.measure_y(*y);
+    }
+    for span in captured_spans.spans.iter() {
+        measure_builder.measure_span(*span);
+    }
+    Ok(MaybeCachedGlyphMeasures::Computed(measure_builder.finish()))
 }
 
 #[derive(Debug)]
@@ -190,14 +852,47 @@ enum MeasureInfo {
 }
 
 impl MeasureInfo {
-    fn merge(&mut self, base_x: i32, measures: &GlyphMeasures) {
+    fn merge(&mut self, base_x: i32, base_y: std::os::raw::c_int, measures: &GlyphMeasures) {
+        self.include(
+            base_y.checked_add(measures.min_y).unwrap(),
+            base_y.checked_add(measures.max_y).unwrap(),
+            base_x.checked_add(i32::from(measures.min_x)).unwrap(),
+            base_x.checked_add(i32::from(measures.max_x)).unwrap(),
+        );
+    }
+
+    /// Merge in another set of measures, shifted right by `x_offset`.
+    fn extend(&mut self, x_offset: i32, other: &MeasureInfo) {
+        if let MeasureInfo::Measures {
+            min_y,
+            max_y,
+            global_min_x,
+            global_max_x,
+        } = *other
+        {
+            self.include(
+                min_y,
+                max_y,
+                global_min_x.checked_add(x_offset).unwrap(),
+                global_max_x.checked_add(x_offset).unwrap(),
+            );
+        }
+    }
+
+    fn include(
+        &mut self,
+        this_min_y: std::os::raw::c_int,
+        this_max_y: std::os::raw::c_int,
+        this_min_x: i32,
+        this_max_x: i32,
+    ) {
         match self {
             MeasureInfo::NoneYet => {
                 *self = MeasureInfo::Measures {
-                    min_y: measures.min_y,
-                    max_y: measures.max_y,
-                    global_min_x: base_x.checked_add(i32::from(measures.min_x)).unwrap(),
-                    global_max_x: base_x.checked_add(i32::from(measures.max_x)).unwrap(),
+                    min_y: this_min_y,
+                    max_y: this_max_y,
+                    global_min_x: this_min_x,
+                    global_max_x: this_max_x,
                 }
             }
             MeasureInfo::Measures {
@@ -206,15 +901,38 @@ impl MeasureInfo {
                 global_min_x,
                 global_max_x,
             } => {
-                *min_y = std::cmp::min(*min_y, measures.min_y);
-                *max_y = std::cmp::max(*max_y, measures.max_y);
-                let this_min_x = base_x.checked_add(i32::from(measures.min_x)).unwrap();
-                let this_max_x = base_x.checked_add(i32::from(measures.max_x)).unwrap();
+                *min_y = std::cmp::min(*min_y, this_min_y);
+                *max_y = std::cmp::max(*max_y, this_max_y);
                 *global_min_x = std::cmp::min(*global_min_x, this_min_x);
                 *global_max_x = std::cmp::max(*global_max_x, this_max_x);
             }
         }
     }
+
+    /// The width, in pixels, of an image holding everything measured.
+    fn width(&self) -> u32 {
+        match self.x_extent() {
+            None => 0,
+            Some((min_x, max_x)) => u32::try_from(
+                max_x
+                    .checked_sub(min_x)
+                    .and_then(|v| v.checked_add(1))
+                    .unwrap(),
+            )
+            .unwrap(),
+        }
+    }
+
+    fn x_extent(&self) -> Option<(i32, i32)> {
+        match self {
+            MeasureInfo::NoneYet => None,
+            MeasureInfo::Measures {
+                global_min_x,
+                global_max_x,
+                ..
+            } => Some((*global_min_x, *global_max_x)),
+        }
+    }
 }
 
 struct GlyphMeasures {
@@ -286,10 +1004,14 @@ impl GlyphMeasuresBuilder {
     }
 }
 
-struct RenderInfo {
+struct RenderInfo<'a> {
+    /// The Y coordinate, in FreeType's coordinates, of the top row of the image.
     base_y: std::os::raw::c_int,
-    x: u32,
-    image: SwImage,
+    /// The X coordinate in the image of the current glyph's origin.
+    x: i32,
+    /// The Y coordinate, in FreeType's coordinates, of the current glyph's baseline.
+    y: std::os::raw::c_int,
+    image: &'a mut SwImage,
     color: Pixel,
 }
 
@@ -303,6 +1025,8 @@ pub enum RenderError {
     SpanYComputeFailed(std::os::raw::c_int, std::os::raw::c_int),
     #[error("a span's X coord exceeded the bounds of an i32")]
     SpanXExceedsI32,
+    #[error("the text's origin ({0}, {1}) exceeds the bounds of an i32")]
+    OriginOutOfRange(u32, u32),
     #[error(transparent)]
     Freetype(#[from] freetype::FtError),
     #[error("Harfbuzz buffer allocation failed")]
@@ -326,22 +1050,669 @@ fn render_span(
     y: std::os::raw::c_int,
     span: ft_lib::FT_Span,
 ) -> Result<(), RenderError> {
-    let real_y = match render_info.base_y.checked_sub(y) {
+    let real_y = match y
+        .checked_add(render_info.y)
+        .and_then(|y| render_info.base_y.checked_sub(y))
+    {
         Some(y) => y,
         None => {
             return Err(RenderError::SpanYComputeFailed(render_info.base_y, y));
         }
     };
-    let y = u32::try_from(real_y).unwrap();
+    // Clip anything outside the image:
+    let y = match u32::try_from(real_y) {
+        Ok(y) if y < render_info.image.height() => y,
+        _ => return Ok(()),
+    };
     let color = {
         let mut color = render_info.color;
-        // FIXME: we ignore the specified alpha
-        color.a = span.coverage;
+        let alpha = u16::from(span.coverage) * u16::from(color.a) / 255;
+        color.a = u8::try_from(alpha).unwrap();
         color
     };
     for x in i32::from(span.x)..i32::from(span.x).checked_add(i32::from(span.len)).unwrap() {
-        let x = render_info.x + u32::try_from(x).unwrap();
-        render_info.image.blend_pixel(x, y, color);
+        let x = render_info
+            .x
+            .checked_add(x)
+            .ok_or(RenderError::SpanXExceedsI32)?;
+        match u32::try_from(x) {
+            Ok(x) if x < render_info.image.width() => render_info.image.blend_pixel(x, y, color),
+            _ => (),
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::{FormattedText, TabWidth, TextAlign, TextDirection, TextOptions};
+    use crate::sw_image::{Pixel, SwImage};
+
+    const RED: Pixel = Pixel {
+        r: 255,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+    const GREEN: Pixel = Pixel {
+        r: 0,
+        g: 255,
+        b: 0,
+        a: 255,
+    };
+    const BLUE: Pixel = Pixel {
+        r: 0,
+        g: 0,
+        b: 255,
+        a: 255,
+    };
+
+    fn formatted(s: &str) -> FormattedText {
+        let mut text = FormattedText::new();
+        text.add_str(s, GREEN);
+        text
+    }
+
+    #[test]
+    fn test_render_text_at_two_heights() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let text = formatted("Hello, world.");
+
+        let small = super::render_text(
+            &text,
+            &mut fonts.deja_vu,
+            14 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        let large = super::render_text(
+            &text,
+            &mut fonts.deja_vu,
+            28 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        assert!(small.width() < large.width());
+        assert!(small.height() < large.height());
+    }
+
+    #[test]
+    fn test_render_multiple_lines() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let one_line = super::render_text(
+            &formatted("ccc"),
+            &mut fonts.deja_vu,
+            14 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        let three_lines = super::render_text(
+            &formatted("a\nbb\nccc"),
+            &mut fonts.deja_vu,
+            14 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        let line_height = u32::try_from(fonts.deja_vu.line_height()).unwrap();
+
+        assert!(one_line.width() <= three_lines.width());
+        assert!(2 * line_height < three_lines.height());
+        assert!(three_lines.height() <= 3 * line_height);
+    }
+
+    /// Render "a", "bb", "ccc" on three lines, in red, green & blue respectively, and return the
+    /// (min, max) X coordinates of the pixels drawn for each line.
+    fn line_extents(align: TextAlign) -> Vec<(u32, u32)> {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut text = FormattedText::new();
+        text.add_str("a\n", RED);
+        text.add_str("bb\n", GREEN);
+        text.add_str("ccc", BLUE);
+        let image = super::render_text(
+            &text,
+            &mut fonts.deja_vu,
+            14 << 6,
+            &TextOptions {
+                align,
+                ..TextOptions::default()
+            },
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+
+        let channels: [fn(&Pixel) -> u8; 3] = [|p| p.r, |p| p.g, |p| p.b];
+        channels
+            .iter()
+            .map(|channel| {
+                let xs = image
+                    .pixels()
+                    .enumerate()
+                    .filter(|(_, p)| p.a != 0 && channel(p) != 0)
+                    .map(|(idx, _)| u32::try_from(idx).unwrap() % image.width())
+                    .collect::<Vec<_>>();
+                (*xs.iter().min().unwrap(), *xs.iter().max().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_render_right_aligned() {
+        let extents = line_extents(TextAlign::Right);
+        let (_, expected_max_x) = extents[0];
+        for (_, max_x) in extents {
+            assert!(max_x == expected_max_x);
+        }
+    }
+
+    #[test]
+    fn test_render_center_aligned() {
+        let extents = line_extents(TextAlign::Center);
+        let midpoints = extents
+            .iter()
+            .map(|(min_x, max_x)| min_x + max_x)
+            .collect::<Vec<_>>();
+        let lowest = *midpoints.iter().min().unwrap();
+        let highest = *midpoints.iter().max().unwrap();
+        // We compare doubled midpoints; allow for a pixel of rounding.
+        assert!(highest - lowest <= 2);
+    }
+
+    #[test]
+    fn test_glyphs_cached_on_demand() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        // None of these are in the set of glyphs cached up front; é appears twice.
+        let text = formatted("éàüé");
+
+        let before = fonts.deja_vu_cache.rendered_count();
+        for expected_renders in [3, 3].iter() {
+            super::render_text(
+                &text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &TextOptions::default(),
+                &mut fonts.deja_vu_cache,
+            )
+            .unwrap();
+            assert!(fonts.deja_vu_cache.rendered_count() - before == *expected_renders);
+        }
+    }
+
+    #[test]
+    fn test_kerning_moves_second_glyph() {
+        let mut face = crate::resources::load_third_party_font(
+            true,
+            "deja-vu/dejavu-fonts-ttf-2.37/ttf/DejaVuSans.ttf",
+        )
+        .unwrap();
+        face.set_char_size(28 << 6).unwrap();
+
+        let a_alone = super::shape_lines("A", &mut face).unwrap();
+        let kerned = super::shape_lines("AV", &mut face).unwrap();
+        // Advancing by the unkerned width of "A" alone is where naive placement would put "V".
+        let naive_v_x = a_alone[0][0].x_advance;
+        let v_x = kerned[0][0].x_advance + kerned[0][1].x_offset;
+        assert!(v_x < naive_v_x);
+    }
+
+    #[test]
+    fn test_render_text_with_fallback() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        // DejaVu Sans has Georgian, but DejaVu Sans Mono doesn't.
+        let mut sans = crate::resources::load_third_party_font(
+            true,
+            "deja-vu/dejavu-fonts-ttf-2.37/ttf/DejaVuSans.ttf",
+        )
+        .unwrap();
+        // Not the cache's height, so neither face's glyphs come from it.
+        let height = 20 << 6;
+        fonts.deja_vu.set_char_size(height).unwrap();
+        sans.set_char_size(height).unwrap();
+        let mono_only = super::shape_lines("AႠ", &mut fonts.deja_vu).unwrap();
+        assert!(mono_only[0][1].codepoint == 0);
+        let shaped =
+            super::shape_lines_with_fallback("AႠ", &mut [&mut fonts.deja_vu, &mut sans], false)
+                .unwrap();
+        assert!(shaped[0].iter().map(|g| g.face).eq([0, 1].iter().copied()));
+        assert!(shaped[0][1].codepoint != 0);
+
+        // The character renders just as it does with the second face alone.
+        let text = formatted("Ⴀ");
+        let fallback = super::render_text_with_fallback(
+            &text,
+            &mut [&mut fonts.deja_vu, &mut sans],
+            height,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        let expected = super::render_text(
+            &text,
+            &mut sans,
+            height,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        assert!(0 < expected.width());
+        assert!((fallback.width(), fallback.height()) == (expected.width(), expected.height()));
+        assert!(fallback.pixels().eq(expected.pixels()));
+    }
+
+    #[test]
+    fn test_render_text_into() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut render = |text: &str| {
+            super::render_text(
+                &formatted(text),
+                &mut fonts.deja_vu,
+                14 << 6,
+                &TextOptions::default(),
+                &mut fonts.deja_vu_cache,
+            )
+            .unwrap()
+        };
+        let a_image = render("a");
+        let b_image = render("b");
+        let mut render_into = |dest: &mut SwImage, origin: (u32, u32), text: &str| {
+            super::render_text_into(
+                dest,
+                origin,
+                &formatted(text),
+                &mut fonts.deja_vu,
+                14 << 6,
+                &TextOptions::default(),
+                &mut fonts.deja_vu_cache,
+            )
+            .unwrap()
+        };
+
+        let mut dest = SwImage::new(64, 48);
+        let (a_origin, b_origin) = ((2, 3), (30, 20));
+        render_into(&mut dest, a_origin, "a");
+        render_into(&mut dest, b_origin, "b");
+        let within = |x: u32, y: u32, origin: (u32, u32), image: &SwImage| {
+            (origin.0..origin.0 + image.width()).contains(&x)
+                && (origin.1..origin.1 + image.height()).contains(&y)
+        };
+        for y in 0..dest.height() {
+            for x in 0..dest.width() {
+                let expected = if within(x, y, a_origin, &a_image) {
+                    a_image.get_pixel(x - a_origin.0, y - a_origin.1)
+                } else if within(x, y, b_origin, &b_image) {
+                    b_image.get_pixel(x - b_origin.0, y - b_origin.1)
+                } else {
+                    Pixel {
+                        r: 0,
+                        g: 0,
+                        b: 0,
+                        a: 0,
+                    }
+                };
+                assert!(dest.get_pixel(x, y) == expected);
+            }
+        }
+
+        // Text running off the edges is clipped:
+        let mut small = SwImage::new(8, 8);
+        render_into(&mut small, (4, 4), "Hello, world.");
+        assert!(small.pixels().any(|p| p.a != 0));
+        let mut untouched = SwImage::new(8, 8);
+        render_into(&mut untouched, (100, 100), "Hello, world.");
+        assert!(untouched.pixels().all(|p| p.a == 0));
+    }
+
+    #[test]
+    fn test_render_text_with_letter_spacing() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut width = |s: &str, letter_spacing: i32| {
+            let image = super::render_text(
+                &formatted(s),
+                &mut fonts.deja_vu,
+                14 << 6,
+                &TextOptions {
+                    letter_spacing,
+                    ..TextOptions::default()
+                },
+                &mut fonts.deja_vu_cache,
+            )
+            .unwrap();
+            image.width()
+        };
+        // Each glyph after the first is moved over by the spacing of every glyph before it.
+        for s in ["HHHH", "HHHHHHHH"].iter() {
+            let glyphs = u32::try_from(s.len()).unwrap();
+            assert!(width(s, 3) == width(s, 0) + 3 * (glyphs - 1));
+        }
+        // Glyphs can be pulled together until they're stacked on one another, but no further.
+        let stacked = width("HHHH", -100);
+        assert!(stacked == width("H", 0));
+        assert!(1 <= stacked);
+    }
+
+    #[test]
+    fn test_measure_text() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        for (s, align) in [
+            ("Hello, world.", TextAlign::Left),
+            ("a\nbb\nccc", TextAlign::Left),
+            ("a\nbb\nccc", TextAlign::Center),
+            ("a\nbb\nccc", TextAlign::Right),
+        ]
+        .iter()
+        {
+            let text = formatted(s);
+            let size = super::measure_text(
+                &text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &TextOptions {
+                    align: *align,
+                    ..TextOptions::default()
+                },
+                &mut fonts.deja_vu_cache,
+            )
+            .unwrap();
+            let image = super::render_text(
+                &text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &TextOptions {
+                    align: *align,
+                    ..TextOptions::default()
+                },
+                &mut fonts.deja_vu_cache,
+            )
+            .unwrap();
+            assert!(size == (image.width(), image.height()));
+        }
+    }
+
+    #[test]
+    fn test_render_text_with_alpha() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut render = |color: Pixel| {
+            let mut text = FormattedText::new();
+            text.add_str("Hello, world.", color);
+            super::render_text(
+                &text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &TextOptions::default(),
+                &mut fonts.deja_vu_cache,
+            )
+            .unwrap()
+        };
+        let opaque = render(GREEN);
+        let translucent = render(Pixel { a: 128, ..GREEN });
+
+        // Rendered over a transparent background, the resulting alpha is the glyph's coverage
+        // scaled by the text's alpha.
+        assert!(opaque.pixels().any(|p| p.a == 255));
+        for (full, half) in opaque.pixels().zip(translucent.pixels()) {
+            let expected = i32::from(full.a) * 128 / 255;
+            assert!((i32::from(half.a) - expected).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_render_text_with_background() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let options = TextOptions {
+            background: Some(Pixel {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            }),
+            ..TextOptions::default()
+        };
+        let image = super::render_text(
+            &formatted("Hello,\nworld."),
+            &mut fonts.deja_vu,
+            14 << 6,
+            &options,
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        assert!(image.pixels().all(|p| p.a == 255));
+        // …and the text is still there:
+        assert!(image.pixels().any(|p| p.g != 0));
+    }
+
+    #[test]
+    fn test_tab_stops() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        fonts.deja_vu.set_char_size(14 << 6).unwrap();
+        // The X position of the last glyph in `s`, relative to the start of its line.
+        let mut last_x = |s: &str, tab_width: TabWidth| {
+            let options = TextOptions {
+                tab_width,
+                ..TextOptions::default()
+            };
+            let layout =
+                super::layout_text(&formatted(s), &mut [&mut fonts.deja_vu], &options, None)
+                    .unwrap();
+            let glyphs = layout.lines.last().unwrap();
+            // Tabs aren't drawn as missing glyphs.
+            assert!(glyphs.iter().all(|glyph| glyph.codepoint != 0));
+            let last = glyphs.len() - 1;
+            glyphs[..last]
+                .iter()
+                .map(|glyph| glyph.x_advance)
+                .sum::<i32>()
+        };
+        assert!(last_x("a\tb", TabWidth::Pixels(100)) == 100);
+        assert!(last_x("a\tb\tc", TabWidth::Pixels(100)) == 200);
+
+        // DejaVu Sans Mono's glyphs all advance as far as a space does.
+        let advance = last_x("ab", TabWidth::Spaces(4));
+        assert!(last_x("a\tb", TabWidth::Spaces(4)) == 4 * advance);
+        assert!(last_x("aaaaa\tb", TabWidth::Spaces(4)) == 8 * advance);
+        // A tab right on a stop still advances to the next one.
+        assert!(last_x("aaaa\tb", TabWidth::Spaces(4)) == 8 * advance);
+        // Tab stops are measured from the start of each line.
+        assert!(last_x("aaaaa\na\tb", TabWidth::Spaces(4)) == 4 * advance);
+    }
+
+    #[test]
+    fn test_tab_keeps_colors() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut text = FormattedText::new();
+        text.add_str("a", GREEN);
+        text.add_str("\t", BLUE);
+        text.add_str("b", RED);
+        let image = super::render_text(
+            &text,
+            &mut fonts.deja_vu,
+            14 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        assert!(image.pixels().any(|p| p.a == 255 && p == GREEN));
+        assert!(image.pixels().any(|p| p.a == 255 && p == RED));
+        assert!(!image.pixels().any(|p| p.b != 0));
+    }
+
+    #[test]
+    fn test_layout_glyphs() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let text = formatted("Hello, world.\nab");
+        let placements = super::layout_glyphs(
+            &text,
+            &mut fonts.deja_vu,
+            14 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        let clusters = placements.iter().map(|p| p.cluster).collect::<Vec<_>>();
+        assert!(clusters == (0..13).chain(14..16).collect::<Vec<_>>());
+        let (first_line, second_line) = placements.split_at(13);
+        for pair in first_line.windows(2) {
+            assert!(pair[1].x == pair[0].x + pair[0].advance);
+            assert!(pair[1].y == pair[0].y);
+        }
+        assert!(second_line[0].x == first_line[0].x);
+        assert!(first_line[0].y < second_line[0].y);
+
+        // The advances span the rendered line, give or take the first & last glyphs' bearings.
+        let single_line = formatted("Hello, world.");
+        let placements = super::layout_glyphs(
+            &single_line,
+            &mut fonts.deja_vu,
+            14 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        let image = super::render_text(
+            &single_line,
+            &mut fonts.deja_vu,
+            14 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        let advances: i32 = placements.iter().map(|p| p.advance).sum();
+        let width = i32::try_from(image.width()).unwrap();
+        assert!((advances - width).abs() <= placements[0].advance);
+        // …and the baseline falls within the image.
+        assert!(0 < placements[0].y && placements[0].y < i32::try_from(image.height()).unwrap());
+    }
+
+    #[test]
+    fn test_right_to_left() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut sans = crate::resources::load_third_party_font(
+            true,
+            "deja-vu/dejavu-fonts-ttf-2.37/ttf/DejaVuSans.ttf",
+        )
+        .unwrap();
+        let mut layout = |text: &FormattedText| {
+            // Not the cache's height, so the cache (of another face) isn't used.
+            super::layout_glyphs(
+                text,
+                &mut sans,
+                20 << 6,
+                &TextOptions::default(),
+                &mut fonts.deja_vu_cache,
+            )
+            .unwrap()
+        };
+
+        // "Shalom": 4 letters, of 2 bytes each. The first is placed on the right.
+        let hebrew = formatted("שלום");
+        let placements = layout(&hebrew);
+        assert!(placements.len() == 4);
+        let rightmost = placements.iter().max_by_key(|p| p.x).unwrap();
+        let leftmost = placements.iter().min_by_key(|p| p.x).unwrap();
+        assert!(rightmost.cluster == 0);
+        assert!(leftmost.cluster == 6);
+
+        // The direction can be given explicitly, too.
+        let mut ltr = formatted("שלום");
+        ltr.set_direction(TextDirection::LeftToRight);
+        let placements = layout(&ltr);
+        assert!(placements.iter().min_by_key(|p| p.x).unwrap().cluster == 0);
+
+        // Right-to-left lines are aligned on the right. (Both lines end with the same letter.)
+        let placements = layout(&formatted("שלום\nשל"));
+        let line_left = |line: &[super::GlyphPlacement]| line.iter().map(|p| p.x).min().unwrap();
+        let line_right =
+            |line: &[super::GlyphPlacement]| line.iter().map(|p| p.x + p.advance).max().unwrap();
+        let (first, second) = placements.split_at(4);
+        assert!(line_left(first) < line_left(second));
+        assert!((line_right(first) - line_right(second)).abs() <= 1);
+    }
+
+    #[test]
+    fn test_truncate_with_ellipsis() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        fonts.deja_vu.set_char_size(14 << 6).unwrap();
+        let ellipsis = super::shape_lines("…", &mut fonts.deja_vu).unwrap()[0][0].codepoint;
+        assert!(ellipsis != 0);
+        let text = formatted("This is far too long to fit");
+        let mut layout = |max_width| {
+            let options = TextOptions {
+                max_width,
+                ..TextOptions::default()
+            };
+            super::layout_text(&text, &mut [&mut fonts.deja_vu], &options, None).unwrap()
+        };
+
+        let full = layout(None);
+        let truncated = layout(Some(full.width / 2));
+        assert!(0 < truncated.width && truncated.width <= full.width / 2);
+        let glyphs = &truncated.lines[0];
+        assert!(glyphs.len() < full.lines[0].len());
+        assert!(glyphs.last().unwrap().codepoint == ellipsis);
+
+        // Text that already fits is left alone.
+        let fits = layout(Some(full.width));
+        assert!(fits.width == full.width);
+        let glyphs = &fits.lines[0];
+        assert!(glyphs.iter().all(|glyph| glyph.codepoint != ellipsis));
+
+        // Too narrow for even the ellipsis, nothing is rendered.
+        let nothing = layout(Some(1));
+        assert!(nothing.width == 0 && nothing.lines[0].is_empty());
+    }
+
+    #[test]
+    fn test_render_underlined_text() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let mut underlined = FormattedText::new();
+        underlined.add_underlined_str("ace", GREEN);
+        let mut render = |text: &FormattedText| {
+            super::render_text(
+                text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &TextOptions::default(),
+                &mut fonts.deja_vu_cache,
+            )
+            .unwrap()
+        };
+        let plain = render(&formatted("ace"));
+        let underlined = render(&underlined);
+
+        // The underline is a row that's (nearly) solid across the image; no row of the plain
+        // text is.
+        let has_solid_row = |image: &SwImage| {
+            let row_len = usize::try_from(image.width()).unwrap();
+            let pixels = image.pixels().collect::<Vec<_>>();
+            pixels
+                .chunks(row_len)
+                .any(|row| row.iter().filter(|p| p.a != 0).count() * 10 >= row_len * 9)
+        };
+        assert!(has_solid_row(&underlined));
+        assert!(!has_solid_row(&plain));
+    }
+
+    #[test]
+    fn test_write_rendered_text_png() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let image = super::render_text(
+            &formatted("Hello, world."),
+            &mut fonts.deja_vu,
+            14 << 6,
+            &TextOptions::default(),
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+        let decoded = crate::png::read_png(png.as_slice()).unwrap();
+        assert!(decoded.width() == image.width());
+        assert!(decoded.height() == image.height());
+        assert!(decoded.pixels().eq(image.pixels()));
+    }
+}
@@ -0,0 +1,377 @@
+//! Signed distance fields for glyphs: each glyph is rasterized once, at a large reference height,
+//! & turned into a field of the distance to its outline. Drawn with a shader that thresholds the
+//! (linearly filtered) field at its edge, it stays crisp at any scale, rather than blurring or
+//! going blocky as a rasterized glyph does when scaled.
+
+use std::collections::hash_map::{Entry, HashMap};
+use std::convert::TryFrom;
+use std::os::raw::{c_int, c_uint};
+
+use ::freetype::freetype as ft_lib;
+use ft_lib::FT_F26Dot6;
+
+use super::glyph_rendering::{self, RenderGlyphError};
+use super::{freetype, GlyphMeasuresBuilder};
+use crate::sw_image::{Pixel, SwImage};
+
+/// The height glyphs are rasterized at before being turned into distance fields.
+pub const REFERENCE_HEIGHT: FT_F26Dot6 = 64 << 6;
+
+/// How far from a glyph's outline, in pixels at [`REFERENCE_HEIGHT`], its distance field reaches
+/// before saturating. Each field is padded by this much on every side.
+pub const SPREAD: u32 = 8;
+
+/// A signed distance field: for each pixel, how far its center is from the edge of a shape.
+///
+/// Distances from `-spread` (inside) to `spread` (outside) pixels are stored as values from 1 to
+/// 0, so the edge itself is at 0.5.
+#[derive(Clone, Debug)]
+pub struct DistanceField {
+    width: u32,
+    height: u32,
+    spread: u32,
+    values: Vec<u8>,
+}
+
+impl DistanceField {
+    /// Compute the distance field of an anti-aliased coverage bitmap (row by row, from the top),
+    /// whose edge is where the coverage crosses 50%.
+    ///
+    /// This uses 8SSEDT (the "8-point sequential signed Euclidean distance transform") to find
+    /// each pixel's distance to the nearest pixel on the other side of the edge. Partially covered
+    /// pixels are on the edge itself, so their distance comes from their coverage instead.
+    pub fn from_coverage(coverage: &[u8], width: u32, height: u32, spread: u32) -> DistanceField {
+        let len = usize::try_from(width)
+            .ok()
+            .and_then(|w| w.checked_mul(usize::try_from(height).ok()?))
+            .unwrap();
+        assert!(coverage.len() == len, "the bitmap must be width × height");
+        assert!(0 < spread, "the spread must be positive");
+        let is_inside = |idx: usize| 128 <= coverage[idx];
+        let to_inside = distance_transform(width, height, is_inside);
+        let to_outside = distance_transform(width, height, |idx| !is_inside(idx));
+
+        let spread_f = spread as f32;
+        let values = (0..len)
+            .map(|idx| {
+                // In pixels, positive outside & negative inside.
+                let distance = match coverage[idx] {
+                    0 => to_inside[idx] - 0.5,
+                    255 => 0.5 - to_outside[idx],
+                    partial => 0.5 - f32::from(partial) / 255.,
+                };
+                let value = 0.5 - distance / (2. * spread_f);
+                (value.clamp(0., 1.) * 255.).round() as u8
+            })
+            .collect();
+        DistanceField {
+            width,
+            height,
+            spread,
+            values,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The stored value at `(x, y)`, from 0 to 1: 0.5 on the edge, & more than that inside.
+    pub fn value(&self, x: u32, y: u32) -> f32 {
+        assert!(x < self.width && y < self.height);
+        let idx = usize::try_from(y * self.width + x).unwrap();
+        f32::from(self.values[idx]) / 255.
+    }
+
+    /// The distance, in pixels, from the center of `(x, y)` to the edge: positive outside the
+    /// shape, & negative inside it. Saturates at ± the spread.
+    pub fn distance(&self, x: u32, y: u32) -> f32 {
+        (0.5 - self.value(x, y)) * 2. * self.spread as f32
+    }
+
+    /// The field as an image for uploading to the GPU: white, with the field's values as alpha,
+    /// like a [`GlyphAtlas`](super::atlas::GlyphAtlas)'s glyphs.
+    pub fn to_image(&self) -> SwImage {
+        let mut image = SwImage::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let idx = usize::try_from(y * self.width + x).unwrap();
+                let white = Pixel {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                    a: self.values[idx],
+                };
+                image.set_pixel(x, y, white);
+            }
+        }
+        image
+    }
+}
+
+/// For each pixel, the distance to the nearest pixel that `is_target`, by 8SSEDT: each pixel
+/// tracks the offset to the nearest target found so far, & a pass forward then a pass backward
+/// over the grid propagates offsets from each pixel's 8 neighbors.
+fn distance_transform(width: u32, height: u32, is_target: impl Fn(usize) -> bool) -> Vec<f32> {
+    // Further than any real offset, but not so far its square overflows.
+    const FAR: (i32, i32) = (1 << 15, 1 << 15);
+    let (width, height) = (
+        i32::try_from(width).unwrap(),
+        i32::try_from(height).unwrap(),
+    );
+    let len = usize::try_from(width * height).unwrap();
+    let mut grid = (0..len)
+        .map(|idx| match is_target(idx) {
+            true => (0, 0),
+            false => FAR,
+        })
+        .collect::<Vec<_>>();
+    let length_squared = |(dx, dy): (i32, i32)| i64::from(dx).pow(2) + i64::from(dy).pow(2);
+    let compare = |grid: &mut [(i32, i32)], x: i32, y: i32, (ox, oy): (i32, i32)| {
+        let (nx, ny) = (x + ox, y + oy);
+        if nx < 0 || width <= nx || ny < 0 || height <= ny {
+            return;
+        }
+        let neighbor = grid[usize::try_from(ny * width + nx).unwrap()];
+        if neighbor == FAR {
+            return;
+        }
+        let idx = usize::try_from(y * width + x).unwrap();
+        let candidate = (neighbor.0 + ox, neighbor.1 + oy);
+        if length_squared(candidate) < length_squared(grid[idx]) {
+            grid[idx] = candidate;
+        }
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            for &offset in &[(-1, 0), (0, -1), (-1, -1), (1, -1)] {
+                compare(&mut grid, x, y, offset);
+            }
+        }
+        for x in (0..width).rev() {
+            compare(&mut grid, x, y, (1, 0));
+        }
+    }
+    for y in (0..height).rev() {
+        for x in (0..width).rev() {
+            for &offset in &[(1, 0), (0, 1), (-1, 1), (1, 1)] {
+                compare(&mut grid, x, y, offset);
+            }
+        }
+        for x in 0..width {
+            compare(&mut grid, x, y, (-1, 0));
+        }
+    }
+    grid.into_iter()
+        .map(|offset| (length_squared(offset) as f32).sqrt())
+        .collect()
+}
+
+/// A glyph's distance field, generated at [`REFERENCE_HEIGHT`].
+#[derive(Clone, Debug)]
+pub struct GlyphSdf {
+    pub field: DistanceField,
+    /// The offset of the field's top-left corner from the glyph's origin (the pen position, on the
+    /// baseline), in pixels at `REFERENCE_HEIGHT`, with Y pointing down. This includes the
+    /// field's padding.
+    pub left: i32,
+    pub top: i32,
+}
+
+impl GlyphSdf {
+    /// Generate the distance field of a rendered glyph, given its spans; `None` if it has nothing
+    /// to draw (e.g., a space).
+    pub fn from_spans(spans: impl Iterator<Item = (c_int, ft_lib::FT_Span)>) -> Option<GlyphSdf> {
+        let spans = spans.collect::<Vec<_>>();
+        let measures = GlyphMeasuresBuilder::from_spans(spans.iter().copied())?;
+        let min_x = i32::from(measures.min_x);
+        let width = u32::try_from(i32::from(measures.max_x) - min_x).unwrap() + 2 * SPREAD;
+        let rows = u32::try_from(measures.max_y - measures.min_y + 1).unwrap() + 2 * SPREAD;
+        let mut coverage = vec![0; usize::try_from(width * rows).unwrap()];
+        for (span_y, span) in spans {
+            let row = SPREAD + u32::try_from(measures.max_y - span_y).unwrap();
+            let start = SPREAD + u32::try_from(i32::from(span.x) - min_x).unwrap();
+            for column in start..start + u32::from(span.len) {
+                coverage[usize::try_from(row * width + column).unwrap()] = span.coverage;
+            }
+        }
+        let spread = i32::try_from(SPREAD).unwrap();
+        Some(GlyphSdf {
+            field: DistanceField::from_coverage(&coverage, width, rows, SPREAD),
+            left: min_x - spread,
+            top: -measures.max_y - spread,
+        })
+    }
+}
+
+/// How much to scale glyphs' distance fields by to draw text at `height`.
+pub fn scale_for_height(height: FT_F26Dot6) -> f32 {
+    height as f32 / REFERENCE_HEIGHT as f32
+}
+
+/// The distance fields of a face's glyphs, each generated the first time it's needed.
+///
+/// As the fields scale to any height, one cache serves text of every size.
+pub struct SdfGlyphCache {
+    /// Glyphs with nothing to draw (e.g., spaces) map to `None`.
+    glyphs: HashMap<c_uint, Option<GlyphSdf>>,
+    /// The number of distance fields that have been generated for the cache.
+    generated: usize,
+}
+
+impl SdfGlyphCache {
+    pub fn new() -> SdfGlyphCache {
+        SdfGlyphCache {
+            glyphs: HashMap::new(),
+            generated: 0,
+        }
+    }
+
+    /// Look up a glyph's distance field, generating it & adding it to the cache if it isn't
+    /// already present; `None` if the glyph has nothing to draw.
+    ///
+    /// The face's character size must already be set to [`REFERENCE_HEIGHT`].
+    pub fn get_or_generate(
+        &mut self,
+        face: &mut freetype::FtFace,
+        glyph: c_uint,
+    ) -> Result<Option<&GlyphSdf>, RenderGlyphError> {
+        let sdf = match self.glyphs.entry(glyph) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                log::debug!("Generating a distance field for glyph {}", glyph);
+                let raw_face = face.as_mut_raw();
+                let rendered = {
+                    let mut ft_library_lock = face.library().lock().unwrap();
+                    let ft_library = ft_library_lock.as_mut_raw();
+                    glyph_rendering::render_glyph(ft_library, raw_face, glyph)?
+                };
+                self.generated += 1;
+                entry.insert(GlyphSdf::from_spans(rendered.spans()))
+            }
+        };
+        Ok(sdf.as_ref())
+    }
+
+    /// The number of distance fields generated for the cache over its lifetime.
+    pub fn generated_count(&self) -> usize {
+        self.generated
+    }
+}
+
+impl Default for SdfGlyphCache {
+    fn default() -> Self {
+        SdfGlyphCache::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::freetype::freetype as ft_lib;
+
+    use super::{DistanceField, SdfGlyphCache, REFERENCE_HEIGHT, SPREAD};
+
+    #[test]
+    fn test_distance_field_of_edge() {
+        // Columns 0..10 are covered, column 10 is half covered, & the rest are empty.
+        let (width, height) = (24, 3);
+        let coverage = (0..height)
+            .flat_map(|_| {
+                (0..width).map(|x| match x {
+                    x if x < 10 => 255,
+                    10 => 128,
+                    _ => 0,
+                })
+            })
+            .collect::<Vec<u8>>();
+        let field = DistanceField::from_coverage(&coverage, width, height, 4);
+        for y in 0..height {
+            assert!((field.value(10, y) - 0.5).abs() < 0.01);
+            for x in 0..10 {
+                assert!(0.5 < field.value(x, y));
+            }
+            // The distance grows moving away from the edge, until it saturates at the spread.
+            for x in 11..width {
+                assert!(field.distance(x, y) > field.distance(x - 1, y) || x > 10 + 4);
+                assert!(field.distance(x, y) >= field.distance(x - 1, y));
+            }
+            assert!((field.distance(11, y) - 0.5).abs() < 0.05);
+            assert!((field.distance(12, y) - 1.5).abs() < 0.05);
+            assert!(field.value(width - 1, y) == 0.);
+            assert!(field.value(0, y) > 0.5);
+        }
+    }
+
+    #[test]
+    fn test_distance_field_of_disc() {
+        let (size, radius) = (40u32, 10.);
+        let center = size as f32 / 2.;
+        let true_distance = |x: u32, y: u32| {
+            let (dx, dy) = (x as f32 + 0.5 - center, y as f32 + 0.5 - center);
+            (dx * dx + dy * dy).sqrt() - radius
+        };
+        let coverage = (0..size)
+            .flat_map(|y| (0..size).map(move |x| (x, y)))
+            .map(|(x, y)| match true_distance(x, y) < 0. {
+                true => 255,
+                false => 0,
+            })
+            .collect::<Vec<u8>>();
+        let spread = 6;
+        let field = DistanceField::from_coverage(&coverage, size, size, spread);
+        for y in 0..size {
+            for x in 0..size {
+                let expected = true_distance(x, y).max(-(spread as f32)).min(spread as f32);
+                // The bitmap's edge is only accurate to within a pixel of the true circle's.
+                assert!((field.distance(x, y) - expected).abs() < 1.);
+            }
+        }
+    }
+
+    #[test]
+    fn test_glyph_sdf() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let face = &mut fonts.deja_vu;
+        face.set_char_size(REFERENCE_HEIGHT).unwrap();
+        let glyph_for = |face: &mut crate::text_rendering::freetype::FtFace, ch: char| unsafe {
+            ft_lib::FT_Get_Char_Index(face.as_mut_raw(), ft_lib::FT_ULong::from(ch))
+        };
+        let l = glyph_for(face, 'l');
+        let space = glyph_for(face, ' ');
+
+        let mut cache = SdfGlyphCache::new();
+        let sdf = cache.get_or_generate(face, l).unwrap().unwrap().clone();
+        let field = &sdf.field;
+        // The field is padded by the spread all around.
+        assert!(2 * SPREAD < field.width() && 2 * SPREAD < field.height());
+        for x in 0..field.width() {
+            assert!(field.value(x, 0) < 0.1);
+        }
+
+        // Walk out of the glyph, from the left edge of its stem to the left edge of the field: the
+        // field crosses 0.5 at the stem's edge, & the distance only grows from there on.
+        let mid_y = field.height() / 2;
+        let inside = (0..field.width())
+            .find(|&x| field.value(x, mid_y) > 0.5)
+            .unwrap();
+        let edge = inside - 1;
+        assert!(SPREAD <= edge);
+        assert!((field.value(edge, mid_y) - 0.5).abs() < 0.1);
+        assert!((field.value(inside, mid_y) - 0.5).abs() < 0.1);
+        for x in 0..edge {
+            assert!(field.distance(x, mid_y) > field.distance(x + 1, mid_y));
+        }
+        assert!(field.distance(0, mid_y) > SPREAD as f32 - 1.);
+
+        // Looking the glyph up again doesn't regenerate it.
+        cache.get_or_generate(face, l).unwrap();
+        assert!(cache.generated_count() == 1);
+        assert!(cache.get_or_generate(face, space).unwrap().is_none());
+        assert!(cache.generated_count() == 2);
+    }
+}
@@ -0,0 +1,180 @@
+//! Word wrapping.
+
+use std::ops::Range;
+
+use ::freetype::freetype as ft_lib;
+use ft_lib::FT_F26Dot6;
+
+use super::cache::{GlyphCache, GlyphCaches};
+use super::{freetype, measure_glyph, FormattedText, MeasureInfo, RenderError, ShapedGlyph};
+
+/// Insert line breaks into `text` so that, when rendered at `height`, no line is wider than
+/// `max_width` pixels.
+///
+/// Lines are broken at whitespace where possible; a word too long to fit on a line by itself is
+/// broken between glyphs. Whitespace at a break is left at the end of the earlier line. Colors
+/// are preserved.
+pub fn wrap_text(
+    text: &FormattedText,
+    face: &mut freetype::FtFace,
+    height: FT_F26Dot6,
+    max_width: u32,
+    cache: &mut impl GlyphCaches,
+) -> Result<FormattedText, RenderError> {
+    let mut cache = cache.cache_for_height(height);
+    face.set_char_size(height)?;
+    let lines = super::shape_lines(text.as_str(), face)?;
+    let mut breaks = Vec::new();
+    for glyphs in lines.iter() {
+        let mut line_start = 0;
+        let mut last_word_start = None;
+        let mut idx = 0;
+        while idx < glyphs.len() {
+            if line_start < idx && starts_word(text.as_str(), glyphs, idx) {
+                last_word_start = Some(idx);
+            }
+            let too_wide = line_start < idx
+                && max_width < width(&glyphs[line_start..=idx], face, cache.as_deref_mut())?;
+            // Glyphs sharing a cluster (e.g., a letter & its marks) can't be split apart.
+            if too_wide && glyphs[line_start].cluster < glyphs[idx].cluster {
+                let break_at = last_word_start.take().unwrap_or(idx);
+                breaks.push(glyphs[break_at].cluster);
+                line_start = break_at;
+                // Re-examine this glyph, as the start of the new line.
+                continue;
+            }
+            idx += 1;
+        }
+    }
+
+    let mut wrapped = FormattedText::new();
+    let mut prev = 0;
+    for break_at in breaks {
+        add_range(&mut wrapped, text, prev..break_at);
+        wrapped.add_styled_str(
+            "\n",
+            text.color_for_index(break_at),
+            text.is_underlined(break_at),
+        );
+        prev = break_at;
+    }
+    add_range(&mut wrapped, text, prev..text.as_str().len());
+    Ok(wrapped)
+}
+
+/// Does the glyph at `idx` start a word? (I.e., is it not whitespace, but the previous glyph is?)
+fn starts_word(text: &str, glyphs: &[ShapedGlyph], idx: usize) -> bool {
+    let is_whitespace = |glyph: &ShapedGlyph| {
+        text[glyph.cluster..]
+            .chars()
+            .next()
+            .map_or(false, char::is_whitespace)
+    };
+    0 < idx && !is_whitespace(&glyphs[idx]) && is_whitespace(&glyphs[idx - 1])
+}
+
+/// The width the given glyphs would render at, were they a line by themselves.
+fn width(
+    glyphs: &[ShapedGlyph],
+    face: &mut freetype::FtFace,
+    mut cache: Option<&mut GlyphCache>,
+) -> Result<u32, RenderError> {
+    let mut measure_info = MeasureInfo::NoneYet;
+    let mut base_x: i32 = 0;
+    for glyph in glyphs {
+        let measures = measure_glyph(face, cache.as_deref_mut(), glyph.codepoint)?;
+        if let Some(measures) = measures.as_ref() {
+            measure_info.merge(
+                base_x.checked_add(glyph.x_offset).unwrap(),
+                glyph.y_offset,
+                measures,
+            );
+        }
+        base_x = base_x.checked_add(glyph.x_advance).unwrap();
+    }
+    Ok(measure_info.width())
+}
+
+/// Append `range` of `from` onto `to`, keeping its colors & underlines.
+fn add_range(to: &mut FormattedText, from: &FormattedText, range: Range<usize>) {
+    let mut start = range.start;
+    while start < range.end {
+        let next_span = from
+            .color_spans
+            .iter()
+            .map(|(idx, _)| *idx)
+            .chain(from.underline_spans.iter().map(|(idx, _)| *idx))
+            .filter(|idx| start < *idx)
+            .min()
+            .unwrap_or(range.end);
+        let end = std::cmp::min(next_span, range.end);
+        to.add_styled_str(
+            &from.as_str()[start..end],
+            from.color_for_index(start),
+            from.is_underlined(start),
+        );
+        start = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{FormattedText, TextOptions};
+    use crate::sw_image::Pixel;
+
+    #[test]
+    fn test_wrap_text() {
+        let mut fonts = crate::resources::Fonts::init(true).unwrap();
+        let red = Pixel {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let blue = Pixel {
+            r: 0,
+            g: 0,
+            b: 255,
+            a: 255,
+        };
+        let mut text = FormattedText::new();
+        text.add_str("The quick brown fox jumps over ", red);
+        text.add_str("the lazy dog, supercalifragilisticexpialidociously.", blue);
+
+        let max_width = 100;
+        let wrapped = super::wrap_text(
+            &text,
+            &mut fonts.deja_vu,
+            14 << 6,
+            max_width,
+            &mut fonts.deja_vu_cache,
+        )
+        .unwrap();
+        assert!(wrapped.as_str().replace('\n', "") == text.as_str());
+        assert!(3 < wrapped.as_str().lines().count());
+
+        for line in wrapped.as_str().lines() {
+            let mut line_text = FormattedText::new();
+            line_text.add_str(line, red);
+            let (width, _) = super::super::measure_text(
+                &line_text,
+                &mut fonts.deja_vu,
+                14 << 6,
+                &TextOptions::default(),
+                &mut fonts.deja_vu_cache,
+            )
+            .unwrap();
+            assert!(width <= max_width);
+        }
+
+        // Every character keeps its color:
+        let mut wrapped_idx = 0;
+        for (idx, ch) in text.as_str().char_indices() {
+            while wrapped.as_str()[wrapped_idx..].starts_with('\n') {
+                wrapped_idx += 1;
+            }
+            assert!(wrapped.color_for_index(wrapped_idx) == text.color_for_index(idx));
+            wrapped_idx += ch.len_utf8();
+        }
+    }
+}
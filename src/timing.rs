@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
 pub struct Timer {
@@ -15,4 +16,132 @@ impl Timer {
         let now = Instant::now();
         now - self.started
     }
+
+    /// Restart the timer from now.
+    pub fn reset(&mut self) {
+        self.started = Instant::now();
+    }
+
+    /// The time since the timer started, in seconds.
+    pub fn elapsed_secs_f64(&self) -> f64 {
+        self.mark().as_secs_f64()
+    }
+}
+
+/// Averages the frame rate over the last few frames.
+pub struct FpsCounter {
+    /// The durations of the most recent frames, oldest first; at most `window` of them.
+    frame_times: VecDeque<Duration>,
+    window: usize,
+    /// The sum of `frame_times`.
+    total: Duration,
+}
+
+impl FpsCounter {
+    /// Create a counter averaging over the last `window` frames.
+    pub fn new(window: usize) -> FpsCounter {
+        assert!(
+            0 < window,
+            "an FpsCounter needs a window of at least 1 frame"
+        );
+        FpsCounter {
+            frame_times: VecDeque::with_capacity(window),
+            window,
+            total: Duration::from_secs(0),
+        }
+    }
+
+    /// Record that a frame took `frame_time`, dropping the oldest frame if the window is full.
+    pub fn record(&mut self, frame_time: Duration) {
+        if self.frame_times.len() == self.window {
+            let oldest = self.frame_times.pop_front().unwrap();
+            self.total -= oldest;
+        }
+        self.frame_times.push_back(frame_time);
+        self.total += frame_time;
+    }
+
+    /// The average frames per second over the window; `None` until a frame (taking any time at
+    /// all) has been recorded.
+    pub fn average_fps(&self) -> Option<f64> {
+        let total = self.total.as_secs_f64();
+        if total == 0. {
+            None
+        } else {
+            Some(self.frame_times.len() as f64 / total)
+        }
+    }
+
+    /// The average time a frame took over the window, in milliseconds; `None` until a frame has
+    /// been recorded.
+    pub fn frame_time_ms(&self) -> Option<f64> {
+        if self.frame_times.is_empty() {
+            None
+        } else {
+            Some(self.total.as_secs_f64() * 1000. / self.frame_times.len() as f64)
+        }
+    }
+}
+
+/// How long to sleep after a frame that took `frame_time`, so that frames come no faster than
+/// `max_fps` per second; `None` if no sleep is needed, or `max_fps` is 0 (i.e., uncapped).
+pub fn frame_limit_sleep(frame_time: Duration, max_fps: u32) -> Option<Duration> {
+    if max_fps == 0 {
+        return None;
+    }
+    let budget = Duration::from_secs(1) / max_fps;
+    budget
+        .checked_sub(frame_time)
+        .filter(|sleep| *sleep != Duration::from_secs(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[test]
+    fn test_timer_reset() {
+        let mut timer = super::Timer::start();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(Duration::from_millis(50) <= timer.mark());
+        assert!(0.05 <= timer.elapsed_secs_f64());
+        timer.reset();
+        assert!(timer.mark() < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_fps_counter() {
+        let ms = Duration::from_millis;
+        let mut counter = super::FpsCounter::new(4);
+        assert!(counter.average_fps().is_none());
+        assert!(counter.frame_time_ms().is_none());
+
+        counter.record(ms(10));
+        counter.record(ms(30));
+        assert!((counter.average_fps().unwrap() - 50.).abs() < 1e-9);
+        assert!((counter.frame_time_ms().unwrap() - 20.).abs() < 1e-9);
+
+        // Once the window fills, old frames stop counting:
+        for _ in 0..4 {
+            counter.record(ms(5));
+        }
+        assert!((counter.average_fps().unwrap() - 200.).abs() < 1e-9);
+        assert!((counter.frame_time_ms().unwrap() - 5.).abs() < 1e-9);
+
+        counter.record(ms(25));
+        assert!((counter.frame_time_ms().unwrap() - 10.).abs() < 1e-9);
+        assert!((counter.average_fps().unwrap() - 100.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_frame_limit_sleep() {
+        let ms = Duration::from_millis;
+        assert!(super::frame_limit_sleep(ms(4), 100) == Some(ms(6)));
+        assert!(super::frame_limit_sleep(ms(0), 50) == Some(ms(20)));
+        // Over budget:
+        assert!(super::frame_limit_sleep(ms(10), 100).is_none());
+        assert!(super::frame_limit_sleep(ms(25), 100).is_none());
+        // Uncapped:
+        assert!(super::frame_limit_sleep(ms(4), 0).is_none());
+    }
 }